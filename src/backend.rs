@@ -1,14 +1,79 @@
 //! REST backend client for uploading blobs and performing retrieval.
 
-use crate::{config::Config, indexer::BlobUpload};
+use crate::{
+    config::{Config, RequestOverflowMode, UploadOrder},
+    indexer::{BlobUpload, Checkpoint, hash_blob_name, language_for_extension, parse_blob_path},
+};
 use anyhow::{Result, anyhow};
-use reqwest::Client;
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
+use std::{
+    borrow::Cow,
+    collections::{HashSet, VecDeque},
+    time::Duration,
+};
 
 #[derive(Debug, Serialize)]
 struct BatchUploadPayload<'a> {
-    blobs: &'a [BlobUpload],
+    blobs: Vec<UploadBlob<'a>>,
+}
+
+impl<'a> BatchUploadPayload<'a> {
+    fn new(cfg: &Config, blobs: &'a [BlobUpload]) -> Self {
+        Self {
+            blobs: blobs.iter().map(|b| UploadBlob::new(cfg, b)).collect(),
+        }
+    }
+}
+
+/// Wire form of one blob in `/batch-upload`: `path` and `content`, plus `language` with
+/// `upload_include_language` (the blob's detected language, else by extension). With `upload_strip_chunk_suffix`, `path` is the file path and a
+/// split file's blobs carry 1-based `chunk_index` of `chunk_count` instead of `#chunkNofM`.
+/// With `send_blob_ids`, `id` is the blob name the backend is expected to return for it.
+/// With `upload_include_metrics`, `line_count` and `byte_size` describe the whole source file.
+#[derive(Debug, Serialize)]
+pub struct UploadBlob<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub path: &'a str,
+    pub content: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_index: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_count: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_count: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub byte_size: Option<usize>,
+}
+
+impl<'a> UploadBlob<'a> {
+    pub fn new(cfg: &Config, blob: &'a BlobUpload) -> Self {
+        let (file, chunk) = parse_blob_path(&blob.path);
+        let strip = cfg.settings.upload_strip_chunk_suffix;
+        let metrics = cfg.settings.upload_include_metrics;
+        Self {
+            id: cfg
+                .settings
+                .send_blob_ids
+                .then(|| hash_blob_name(&blob.path, &blob.content)),
+            path: if strip { file } else { &blob.path },
+            content: &blob.content,
+            language: if cfg.settings.upload_include_language {
+                blob.language
+                    .as_deref()
+                    .or_else(|| language_for_extension(file))
+            } else {
+                None
+            },
+            chunk_index: chunk.filter(|_| strip).map(|(n, _)| n),
+            chunk_count: chunk.filter(|_| strip).map(|(_, m)| m),
+            line_count: blob.line_count.filter(|_| metrics),
+            byte_size: blob.byte_size.filter(|_| metrics),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -34,41 +99,346 @@ struct RetrievalPayload<'a> {
     enable_commit_retrieval: bool,
 }
 
+impl<'a> RetrievalPayload<'a> {
+    fn new(
+        cfg: &Config,
+        query: &'a str,
+        blobs: RetrievalBlobs<'a>,
+        dialog: &[serde_json::Value],
+    ) -> Self {
+        Self {
+            information_request: query,
+            blobs,
+            dialog: bounded_dialog(cfg, dialog),
+            max_output_length: cfg.settings.max_output_length,
+            disable_codebase_retrieval: cfg.settings.disable_codebase_retrieval,
+            enable_commit_retrieval: cfg.settings.enable_commit_retrieval,
+        }
+    }
+}
+
+/// The last `max_dialog_turns` turns of `dialog` (all of them when 0), oldest first.
+pub fn bounded_dialog(cfg: &Config, dialog: &[serde_json::Value]) -> Vec<serde_json::Value> {
+    let max = cfg.settings.max_dialog_turns;
+    if max == 0 || dialog.len() <= max {
+        return dialog.to_vec();
+    }
+    let dropped = dialog.len() - max;
+    // summarize_old_dialog 尚未实现摘要，旧轮次与关闭时一样直接丢弃
+    tracing::debug!(
+        dropped,
+        kept = max,
+        summarize = cfg.settings.summarize_old_dialog,
+        "dialog truncated to max_dialog_turns"
+    );
+    dialog[dropped..].to_vec()
+}
+
 #[derive(Debug, Deserialize)]
 struct RetrievalResp {
     #[serde(default)]
     formatted_retrieval: String,
+    #[serde(default)]
+    checkpoint_id: Option<String>,
 }
 
-fn auth_client(timeout_secs: u64) -> Client {
-    Client::builder()
+fn auth_client(cfg: &Config, timeout_secs: u64) -> Result<Client> {
+    // 占位 base_url 的请求必然失败，直接给出配置提示
+    if cfg.unconfigured_fields().contains(&"base_url") {
+        return Err(anyhow!(
+            "{}",
+            cfg.unconfigured_message().unwrap_or_default()
+        ));
+    }
+    let idle_timeout = match cfg.settings.pool_idle_timeout_secs {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    };
+    let mut builder = Client::builder()
         .timeout(Duration::from_secs(timeout_secs))
         .user_agent("augmcp/0.1")
-        .build()
-        .expect("reqwest client")
+        .pool_idle_timeout(idle_timeout);
+    if let Some(n) = cfg.settings.pool_max_idle_per_host {
+        builder = builder.pool_max_idle_per_host(n);
+    }
+    if let Some(path) = &cfg.settings.root_ca_cert_path {
+        let pem = std::fs::read(path)
+            .map_err(|e| anyhow!("failed to read root_ca_cert_path {}: {}", path, e))?;
+        let cert = reqwest::Certificate::from_pem(&pem)
+            .map_err(|e| anyhow!("invalid PEM in root_ca_cert_path {}: {}", path, e))?;
+        builder = builder.add_root_certificate(cert);
+    }
+    if cfg.settings.danger_accept_invalid_certs {
+        tracing::warn!(
+            base_url = %cfg.settings.base_url,
+            "TLS certificate verification is DISABLED (danger_accept_invalid_certs = true); only use this with a trusted local backend"
+        );
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    Ok(builder.build()?)
+}
+
+/// 413 Payload Too Large: retrying the same payload is pointless, the batch must shrink.
+#[derive(Debug, thiserror::Error)]
+#[error("payload too large: {0}")]
+struct PayloadTooLarge(String);
+
+async fn retry<F, Fut, T>(f: F, retries: usize, base_delay_ms: u64) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T>>,
+{
+    retry_within(f, retries, base_delay_ms, &RetryBudget::unlimited()).await
+}
+
+/// Retry time shared by every request of one upload run (`index_total_retry_budget_secs`):
+/// backoff waits and retried attempts of all batches draw from it; first attempts do not.
+struct RetryBudget {
+    budget: Option<Duration>,
+    spent: parking_lot::Mutex<Duration>,
+}
+
+impl RetryBudget {
+    fn unlimited() -> Self {
+        Self {
+            budget: None,
+            spent: parking_lot::Mutex::new(Duration::ZERO),
+        }
+    }
+
+    fn from_config(cfg: &Config) -> Self {
+        Self {
+            budget: match cfg.settings.index_total_retry_budget_secs {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            spent: parking_lot::Mutex::new(Duration::ZERO),
+        }
+    }
+
+    /// Retry time left; None when unlimited.
+    fn remaining(&self) -> Option<Duration> {
+        Some(self.budget?.saturating_sub(*self.spent.lock()))
+    }
+
+    fn charge(&self, d: Duration) {
+        *self.spent.lock() += d;
+    }
+}
+
+/// A retry would have run past `index_total_retry_budget_secs`; carries the last failure.
+#[derive(Debug, thiserror::Error)]
+#[error("retry budget exhausted ({budget_secs}s, index_total_retry_budget_secs): {last:#}")]
+pub struct RetryBudgetExhausted {
+    pub budget_secs: u64,
+    last: anyhow::Error,
 }
 
-async fn retry<F, Fut, T>(mut f: F, retries: usize, base_delay_ms: u64) -> Result<T>
+/// `retry` whose backoff waits and retried attempts are charged to `budget`, failing with
+/// `RetryBudgetExhausted` instead of retrying past it.
+async fn retry_within<F, Fut, T>(
+    mut f: F,
+    retries: usize,
+    base_delay_ms: u64,
+    budget: &RetryBudget,
+) -> Result<T>
 where
     F: FnMut() -> Fut,
     Fut: std::future::Future<Output = Result<T>>,
 {
+    let exhausted = |last: anyhow::Error| -> anyhow::Error {
+        RetryBudgetExhausted {
+            budget_secs: budget.budget.map_or(0, |b| b.as_secs()),
+            last,
+        }
+        .into()
+    };
     let mut last_err: Option<anyhow::Error> = None;
     for attempt in 0..retries {
-        match f().await {
+        let res = match (attempt, budget.remaining()) {
+            // 重试的请求本身也计入预算，超时即放弃
+            (1.., Some(remaining)) => {
+                let started = std::time::Instant::now();
+                let r = tokio::time::timeout(remaining, f()).await;
+                budget.charge(started.elapsed());
+                match r {
+                    Ok(r) => r,
+                    Err(_) => {
+                        let last = last_err.unwrap_or_else(|| anyhow!("retry timed out"));
+                        return Err(exhausted(last));
+                    }
+                }
+            }
+            _ => f().await,
+        };
+        match res {
             Ok(v) => return Ok(v),
             Err(e) => {
-                last_err = Some(e);
+                if e.is::<PayloadTooLarge>() || e.is::<PartialTimeout>() {
+                    return Err(e);
+                }
                 if attempt + 1 < retries {
-                    let delay = base_delay_ms * (1u64 << attempt);
-                    tokio::time::sleep(Duration::from_millis(delay)).await;
+                    let delay = Duration::from_millis(base_delay_ms * (1u64 << attempt));
+                    if budget.remaining().is_some_and(|r| delay >= r) {
+                        return Err(exhausted(e));
+                    }
+                    tokio::time::sleep(delay).await;
+                    budget.charge(delay);
                 }
+                last_err = Some(e);
             }
         }
     }
     Err(last_err.unwrap_or_else(|| anyhow!("retry failed")))
 }
 
+/// Non-success upload response other than 413.
+#[derive(Debug, thiserror::Error)]
+#[error("{status} {body}")]
+struct UploadRejected {
+    status: StatusCode,
+    body: String,
+}
+
+/// An upload batch the backend still refused after retries: which batch, which blobs, and why.
+#[derive(Debug, Clone, Serialize, thiserror::Error)]
+#[error(
+    "upload failed for chunk {chunk_index}/{chunks_total} [{}]: {}{message}",
+    summarize_paths(.paths),
+    .status.map(|s| format!("{s} ")).unwrap_or_default()
+)]
+pub struct UploadError {
+    /// 1-based index of the upload batch
+    pub chunk_index: usize,
+    pub chunks_total: usize,
+    /// Blob paths of the rejected request (a part of the batch once 413 halving kicked in)
+    pub paths: Vec<String>,
+    /// HTTP status; None for transport errors
+    pub status: Option<u16>,
+    /// `message`/`error` of a JSON error body, else the raw body or the transport error
+    pub message: String,
+}
+
+/// First few paths, then how many more.
+fn summarize_paths(paths: &[String]) -> String {
+    const SHOWN: usize = 5;
+    let mut s = paths
+        .iter()
+        .take(SHOWN)
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(", ");
+    if paths.len() > SHOWN {
+        s.push_str(&format!(", +{} more", paths.len() - SHOWN));
+    }
+    s
+}
+
+/// `message` or `error` (string, or object with `message`) of a JSON error body; else the
+/// trimmed body.
+pub fn backend_error_message(body: &str) -> String {
+    let parsed = serde_json::from_str::<serde_json::Value>(body).ok();
+    let field = parsed.as_ref().and_then(|v| {
+        v.get("message")
+            .or_else(|| v.get("error"))
+            .and_then(|f| f.as_str().or_else(|| f.get("message")?.as_str()))
+    });
+    field.unwrap_or(body).trim().to_string()
+}
+
+/// Upload one batch with retries. On 413 the batch is halved and re-sent, down to single
+/// blobs, before giving up. Returned blob names keep the input order.
+/// Failures are `UploadError`s naming `chunk_index` of `chunks_total`.
+async fn upload_batch(
+    client: &Client,
+    url: &str,
+    cfg: &Config,
+    batch: &[BlobUpload],
+    chunk_index: usize,
+    chunks_total: usize,
+    budget: &RetryBudget,
+) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    let mut pending: VecDeque<&[BlobUpload]> = VecDeque::from([batch]);
+    while let Some(part) = pending.pop_front() {
+        let payload = BatchUploadPayload::new(cfg, part);
+        let res: Result<BatchUploadResp> = retry_within(
+            || async {
+                let r = client
+                    .post(url)
+                    .bearer_auth(&cfg.settings.token)
+                    .json(&payload)
+                    .send()
+                    .await?;
+                if r.status() == StatusCode::PAYLOAD_TOO_LARGE {
+                    let t = r.text().await.unwrap_or_default();
+                    return Err(PayloadTooLarge(t).into());
+                }
+                if !r.status().is_success() {
+                    let status = r.status();
+                    let body = r.text().await.unwrap_or_default();
+                    return Err(UploadRejected { status, body }.into());
+                }
+                Ok(r.json::<BatchUploadResp>().await?)
+            },
+            3,
+            1000,
+            budget,
+        )
+        .await;
+        match res {
+            Ok(resp) => names.extend(resp.blob_names),
+            Err(e) if e.is::<PayloadTooLarge>() && part.len() > 1 => {
+                let (a, b) = part.split_at(part.len() / 2);
+                tracing::warn!(
+                    items = part.len(),
+                    split_into = a.len(),
+                    "upload got 413, retrying with smaller batches"
+                );
+                pending.push_front(b);
+                pending.push_front(a);
+            }
+            Err(e) => {
+                let (status, message) = match e.downcast_ref::<UploadRejected>() {
+                    Some(r) => (Some(r.status.as_u16()), backend_error_message(&r.body)),
+                    None => match e.downcast_ref::<PayloadTooLarge>() {
+                        Some(PayloadTooLarge(body)) => (
+                            Some(StatusCode::PAYLOAD_TOO_LARGE.as_u16()),
+                            backend_error_message(body),
+                        ),
+                        None => (None, format!("{:#}", e)),
+                    },
+                };
+                return Err(UploadError {
+                    chunk_index,
+                    chunks_total,
+                    paths: part.iter().map(|b| b.path.clone()).collect(),
+                    status,
+                    message,
+                }
+                .into());
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Send a single zero-blob upload (no retries) to check reachability and the token.
+/// Returns the HTTP status; transport errors (DNS, connect, timeout) are `Err`.
+pub async fn probe_upload(cfg: &Config) -> Result<StatusCode> {
+    let url = format!(
+        "{}/batch-upload",
+        cfg.settings.base_url.trim_end_matches('/')
+    );
+    let r = auth_client(cfg, 10)?
+        .post(url)
+        .bearer_auth(&cfg.settings.token)
+        .json(&BatchUploadPayload::new(cfg, &[]))
+        .send()
+        .await?;
+    Ok(r.status())
+}
+
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct UploadProgress {
     pub chunk_index: usize,
@@ -77,54 +447,151 @@ pub struct UploadProgress {
     pub total_items: usize,
     pub chunk_items: usize,
     pub chunk_bytes: usize,
+    pub uploaded_bytes: usize,
+    pub total_bytes: usize,
+}
+
+impl UploadProgress {
+    /// Share uploaded so far, 0-100, by item count or (`by_bytes`) content bytes.
+    pub fn percent(&self, by_bytes: bool) -> f64 {
+        let (done, total) = if by_bytes {
+            (self.uploaded_bytes, self.total_bytes)
+        } else {
+            (self.uploaded_items, self.total_items)
+        };
+        if total == 0 {
+            100.0
+        } else {
+            done as f64 * 100.0 / total as f64
+        }
+    }
+}
+
+/// Split blobs into upload batches of at most `max_items` blobs and, if `max_bytes > 0`,
+/// at most `max_bytes` of content. A blob larger than `max_bytes` gets a batch of its own.
+pub fn plan_batches(
+    blobs: &[BlobUpload],
+    max_items: usize,
+    max_bytes: usize,
+) -> Vec<&[BlobUpload]> {
+    let max_items = max_items.max(1);
+    let mut batches = Vec::new();
+    let mut start = 0;
+    let mut bytes = 0;
+    for (i, b) in blobs.iter().enumerate() {
+        let len = b.content.len();
+        let full = i - start >= max_items || (max_bytes > 0 && bytes + len > max_bytes);
+        if i > start && full {
+            batches.push(&blobs[start..i]);
+            start = i;
+            bytes = 0;
+        }
+        bytes += len;
+    }
+    if start < blobs.len() {
+        batches.push(&blobs[start..]);
+    }
+    batches
+}
+
+/// Names returned by a blob upload, plus the sent blobs the backend did not confirm.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UploadOutcome {
+    pub blob_names: Vec<String>,
+    /// Client-side names (`hash_blob_name`) of sent blobs missing from the backend's reply;
+    /// they should not be recorded as uploaded
+    pub unconfirmed: Vec<String>,
+}
+
+/// Blobs of `sent` the backend did not confirm when it returned fewer names than it was sent.
+/// Returned names that match client-side names pin down the dropped blobs; if they cannot be
+/// matched, the whole batch counts as unconfirmed.
+fn unconfirmed_blobs(sent: &[BlobUpload], returned: &[String]) -> Vec<String> {
+    if returned.len() >= sent.len() {
+        return Vec::new();
+    }
+    let returned: HashSet<&String> = returned.iter().collect();
+    let missing: Vec<String> = sent
+        .iter()
+        .map(|b| hash_blob_name(&b.path, &b.content))
+        .filter(|n| !returned.contains(n))
+        .collect();
+    tracing::warn!(
+        sent = sent.len(),
+        returned = returned.len(),
+        unconfirmed = missing.len(),
+        "backend confirmed fewer blobs than sent"
+    );
+    missing
+}
+
+/// `new_blobs` in `upload_order`; borrowed unchanged for `walk`. Sorts are stable, and blobs
+/// without a known mtime go last for `recency`.
+fn ordered_for_upload<'a>(cfg: &Config, new_blobs: &'a [BlobUpload]) -> Cow<'a, [BlobUpload]> {
+    let order = cfg.settings.upload_order;
+    if order == UploadOrder::Walk {
+        return Cow::Borrowed(new_blobs);
+    }
+    let mut sorted = new_blobs.to_vec();
+    match order {
+        UploadOrder::Walk => {}
+        UploadOrder::Path => sorted.sort_by(|a, b| a.path.cmp(&b.path)),
+        UploadOrder::SizeDesc => sorted.sort_by_key(|b| std::cmp::Reverse(b.content.len())),
+        UploadOrder::Recency => sorted.sort_by_key(|b| std::cmp::Reverse(b.mtime_ms)),
+    }
+    Cow::Owned(sorted)
+}
+
+/// Runs after batch `done` of `total`: yields, then sleeps `upload_inter_batch_delay_ms`
+/// unless it was the last batch. Both are await points where a stopped task is cancelled.
+async fn between_batches(cfg: &Config, done: usize, total: usize) {
+    // 让出调度，便于任务被及时取消（/api/index/stop）
+    tokio::task::yield_now().await;
+    let delay = cfg.settings.upload_inter_batch_delay_ms;
+    if delay > 0 && done < total {
+        tokio::time::sleep(Duration::from_millis(delay)).await;
+    }
 }
 
 pub async fn upload_new_blobs_with_progress<F>(
     cfg: &Config,
     new_blobs: &[BlobUpload],
     mut on_progress: F,
-) -> Result<Vec<String>>
+) -> Result<UploadOutcome>
 where
     F: FnMut(UploadProgress),
 {
     if new_blobs.is_empty() {
-        return Ok(Vec::new());
+        return Ok(UploadOutcome::default());
     }
     let url = format!(
         "{}/batch-upload",
         cfg.settings.base_url.trim_end_matches('/')
     );
-    let client = auth_client(30);
+    let client = auth_client(cfg, 30)?;
+    let budget = RetryBudget::from_config(cfg);
 
-    let batch_size = cfg.settings.batch_size.max(1);
-    let mut all_blob_names: Vec<String> = Vec::new();
+    let ordered = ordered_for_upload(cfg, new_blobs);
+    let new_blobs = &*ordered;
+    let batches = plan_batches(
+        new_blobs,
+        cfg.settings.batch_size,
+        cfg.settings.batch_max_bytes,
+    );
+    let mut outcome = UploadOutcome::default();
     let total = new_blobs.len();
-    let total_chunks = (total + batch_size - 1) / batch_size;
+    let total_chunks = batches.len();
+    let total_bytes: usize = new_blobs.iter().map(|b| b.content.len()).sum();
+    let mut uploaded_bytes = 0usize;
+    let mut uploaded_cnt = 0usize;
 
-    for (idx, chunk) in new_blobs.chunks(batch_size).enumerate() {
-        let payload = BatchUploadPayload { blobs: chunk };
-        let resp: BatchUploadResp = retry(
-            || async {
-                let r = client
-                    .post(&url)
-                    .bearer_auth(&cfg.settings.token)
-                    .json(&payload)
-                    .send()
-                    .await?;
-                if !r.status().is_success() {
-                    let sc = r.status();
-                    let t = r.text().await.unwrap_or_default();
-                    return Err(anyhow!("upload failed: {} {}", sc, t));
-                }
-                Ok(r.json::<BatchUploadResp>().await?)
-            },
-            3,
-            1000,
-        )
-        .await?;
-        all_blob_names.extend(resp.blob_names);
-        let uploaded_cnt = ((idx + 1) * batch_size).min(total);
+    for (idx, chunk) in batches.into_iter().enumerate() {
+        let names = upload_batch(&client, &url, cfg, chunk, idx + 1, total_chunks, &budget).await?;
+        outcome.unconfirmed.extend(unconfirmed_blobs(chunk, &names));
+        outcome.blob_names.extend(names);
+        uploaded_cnt += chunk.len();
         let chunk_bytes: usize = chunk.iter().map(|b| b.content.len()).sum();
+        uploaded_bytes += chunk_bytes;
         on_progress(UploadProgress {
             chunk_index: idx + 1,
             chunks_total: total_chunks,
@@ -132,57 +599,46 @@ where
             total_items: total,
             chunk_items: chunk.len(),
             chunk_bytes,
+            uploaded_bytes,
+            total_bytes,
         });
-        // 让出调度，便于任务被及时取消（/api/index/stop）
-        tokio::task::yield_now().await;
+        between_batches(cfg, idx + 1, total_chunks).await;
     }
-    Ok(all_blob_names)
+    Ok(outcome)
 }
 
-pub async fn upload_new_blobs(cfg: &Config, new_blobs: &[BlobUpload]) -> Result<Vec<String>> {
+pub async fn upload_new_blobs(cfg: &Config, new_blobs: &[BlobUpload]) -> Result<UploadOutcome> {
     if new_blobs.is_empty() {
-        return Ok(Vec::new());
+        return Ok(UploadOutcome::default());
     }
     let url = format!(
         "{}/batch-upload",
         cfg.settings.base_url.trim_end_matches('/')
     );
-    let client = auth_client(30);
+    let client = auth_client(cfg, 30)?;
+    let budget = RetryBudget::from_config(cfg);
 
+    let ordered = ordered_for_upload(cfg, new_blobs);
+    let new_blobs = &*ordered;
     // 分批上传，避免一次性 payload 过大导致 413（Payload Too Large）
     let batch_size = cfg.settings.batch_size.max(1);
-    let mut all_blob_names: Vec<String> = Vec::new();
+    let batches = plan_batches(new_blobs, batch_size, cfg.settings.batch_max_bytes);
+    let mut outcome = UploadOutcome::default();
     let total = new_blobs.len();
-    let total_chunks = (total + batch_size - 1) / batch_size;
+    let total_chunks = batches.len();
     tracing::info!(
         total_new = total,
         batch_size,
+        batch_max_bytes = cfg.settings.batch_max_bytes,
         chunks = total_chunks,
         "upload start"
     );
-    for (idx, chunk) in new_blobs.chunks(batch_size).enumerate() {
-        let payload = BatchUploadPayload { blobs: chunk };
-        let resp: BatchUploadResp = retry(
-            || async {
-                let r = client
-                    .post(&url)
-                    .bearer_auth(&cfg.settings.token)
-                    .json(&payload)
-                    .send()
-                    .await?;
-                if !r.status().is_success() {
-                    let sc = r.status();
-                    let t = r.text().await.unwrap_or_default();
-                    return Err(anyhow!("upload failed: {} {}", sc, t));
-                }
-                Ok(r.json::<BatchUploadResp>().await?)
-            },
-            3,
-            1000,
-        )
-        .await?;
-        all_blob_names.extend(resp.blob_names);
-        let uploaded_cnt = ((idx + 1) * batch_size).min(total);
+    let mut uploaded_cnt = 0usize;
+    for (idx, chunk) in batches.into_iter().enumerate() {
+        let names = upload_batch(&client, &url, cfg, chunk, idx + 1, total_chunks, &budget).await?;
+        outcome.unconfirmed.extend(unconfirmed_blobs(chunk, &names));
+        outcome.blob_names.extend(names);
+        uploaded_cnt += chunk.len();
         let percent = uploaded_cnt as f64 * 100.0 / total as f64;
         // 估算字节数（可选）
         let chunk_bytes: usize = chunk.iter().map(|b| b.content.len()).sum();
@@ -196,11 +652,214 @@ pub async fn upload_new_blobs(cfg: &Config, new_blobs: &[BlobUpload]) -> Result<
             chunk_bytes,
             "upload progress"
         );
-        // 让出调度，便于任务被及时取消（/api/index/stop）
-        tokio::task::yield_now().await;
+        between_batches(cfg, idx + 1, total_chunks).await;
     }
 
-    Ok(all_blob_names)
+    Ok(outcome)
+}
+
+/// Text returned for an empty retrieval unless `empty_result_message` overrides it.
+pub const DEFAULT_EMPTY_RESULT_MESSAGE: &str = "No relevant code context found for your query.";
+
+/// Outcome of a retrieval: context text, or `Empty` when the backend found nothing relevant.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Retrieved {
+    Context(String),
+    Empty,
+}
+
+impl Retrieved {
+    pub fn is_empty(&self) -> bool {
+        matches!(self, Self::Empty)
+    }
+
+    /// Text form; `Empty` renders `empty_result_message` (default `DEFAULT_EMPTY_RESULT_MESSAGE`).
+    pub fn into_text(self, cfg: &Config) -> String {
+        match self {
+            Self::Context(text) => text,
+            Self::Empty => cfg
+                .settings
+                .empty_result_message
+                .clone()
+                .unwrap_or_else(|| DEFAULT_EMPTY_RESULT_MESSAGE.to_string()),
+        }
+    }
+}
+
+/// Appended when `retrieval_max_chars` cuts the retrieved text (counts toward the limit).
+pub const TRUNCATION_MARKER: &str = "…";
+
+/// Clean retrieved text: remove every match of `retrieval_strip_patterns` (invalid regexes are
+/// logged and skipped), then cut to `retrieval_max_chars` characters (0 = no limit).
+pub fn postprocess_retrieval(cfg: &Config, text: String) -> String {
+    let mut text = text;
+    if cfg.settings.clean_chunk_paths_in_output {
+        text = clean_chunk_paths(&text);
+    }
+    for pat in &cfg.settings.retrieval_strip_patterns {
+        match regex::Regex::new(pat) {
+            Ok(re) => text = re.replace_all(&text, "").into_owned(),
+            Err(e) => {
+                tracing::warn!(pattern = %pat, error = %e, "invalid retrieval strip pattern; ignoring")
+            }
+        }
+    }
+    let max = cfg.settings.retrieval_max_chars;
+    if max > 0 && text.chars().count() > max {
+        let keep = max.saturating_sub(TRUNCATION_MARKER.chars().count());
+        let cut = text.char_indices().nth(keep).map_or(text.len(), |(i, _)| i);
+        text.truncate(cut);
+        text.push_str(TRUNCATION_MARKER);
+    }
+    text
+}
+
+// `#chunkNofM` 紧跟在非空白、非 `#` 字符之后，且数字后不再接字母数字
+static CHUNK_SUFFIX: std::sync::LazyLock<regex::Regex> = std::sync::LazyLock::new(|| {
+    regex::Regex::new(r"([^\s#])(#chunk[0-9]+of[0-9]+)\b").expect("valid regex")
+});
+
+/// Rewrite `path#chunkNofM` to `path` wherever the suffix decodes as a valid chunk
+/// (`1 <= N <= M`); other `#` text, such as `#L10` or `issue #12`, is left alone.
+pub fn clean_chunk_paths(text: &str) -> String {
+    CHUNK_SUFFIX
+        .replace_all(text, |caps: &regex::Captures| {
+            match parse_blob_path(&caps[2]) {
+                ("", Some((n, m))) if n >= 1 && n <= m => caps[1].to_string(),
+                _ => caps[0].to_string(),
+            }
+        })
+        .into_owned()
+}
+
+/// Line prefix the backend puts before each retrieved snippet's blob path.
+pub const RETRIEVAL_PATH_PREFIX: &str = "Path: ";
+
+/// Snippets of one source file, merged across the chunks they were retrieved from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileSnippets {
+    pub path: String,
+    /// 1-based chunk numbers the snippets came from; empty when the file was not split
+    pub chunks: Vec<usize>,
+    pub snippets: Vec<String>,
+}
+
+/// Retrieved text regrouped by source file (`dedupe_by_file`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GroupedRetrieval {
+    /// Text before the first `Path: ` line (usually a heading)
+    pub preamble: String,
+    /// Files in order of first appearance
+    pub files: Vec<FileSnippets>,
+}
+
+impl GroupedRetrieval {
+    /// Split `text` at `Path: ` lines and merge snippets whose blob paths map to the same
+    /// file. None when no such line is found, i.e. the format is not recognized.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut preamble = String::new();
+        let mut sections: Vec<(&str, String)> = Vec::new();
+        for line in text.split_inclusive('\n') {
+            match line.strip_prefix(RETRIEVAL_PATH_PREFIX) {
+                Some(path) => sections.push((path.trim(), String::new())),
+                None => match sections.last_mut() {
+                    Some((_, body)) => body.push_str(line),
+                    None => preamble.push_str(line),
+                },
+            }
+        }
+        if sections.is_empty() {
+            return None;
+        }
+        // 文件按首次出现排序；值为 (chunk 序号, 片段)，未分片的文件序号为 0
+        let mut grouped: Vec<(&str, Vec<(usize, String)>)> = Vec::new();
+        for (blob_path, body) in sections {
+            let (file, chunk) = parse_blob_path(blob_path);
+            let snippet = (chunk.map_or(0, |(n, _)| n), body.trim_end().to_string());
+            match grouped.iter_mut().find(|(f, _)| *f == file) {
+                Some((_, snippets)) => snippets.push(snippet),
+                None => grouped.push((file, vec![snippet])),
+            }
+        }
+        let files = grouped
+            .into_iter()
+            .map(|(file, mut snippets)| {
+                // 同 chunk 内保持原顺序
+                snippets.sort_by_key(|(n, _)| *n);
+                let mut chunks: Vec<usize> = snippets
+                    .iter()
+                    .map(|(n, _)| *n)
+                    .filter(|n| *n > 0)
+                    .collect();
+                chunks.dedup();
+                FileSnippets {
+                    path: file.to_string(),
+                    chunks,
+                    snippets: snippets.into_iter().map(|(_, s)| s).collect(),
+                }
+            })
+            .collect();
+        Some(Self {
+            preamble: preamble.trim_end().to_string(),
+            files,
+        })
+    }
+
+    /// Text form: the preamble, then one `Path: ` heading per file with its snippets.
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        if !self.preamble.is_empty() {
+            out.push_str(&self.preamble);
+            out.push('\n');
+        }
+        for f in &self.files {
+            out.push_str(RETRIEVAL_PATH_PREFIX);
+            out.push_str(&f.path);
+            out.push('\n');
+            out.push_str(&f.snippets.join("\n...\n"));
+            out.push_str("\n\n");
+        }
+        out.truncate(out.trim_end().len());
+        out
+    }
+}
+
+/// Optional query rewriting before it is sent as `information_request` (all off by default).
+#[derive(Debug, Clone, Default)]
+pub struct QueryPreprocess {
+    /// Collapse whitespace runs to one space and strip surrounding quotes
+    pub normalize: bool,
+    /// Caller-supplied context placed on its own line before the query
+    pub prepend_context: Option<String>,
+}
+
+impl QueryPreprocess {
+    pub fn apply(&self, query: &str) -> String {
+        let query = if self.normalize {
+            normalize_query(query)
+        } else {
+            query.to_string()
+        };
+        match self.prepend_context.as_deref().map(str::trim) {
+            Some(ctx) if !ctx.is_empty() => format!("{ctx}\n{query}"),
+            _ => query,
+        }
+    }
+}
+
+/// Collapse whitespace (including newlines) to single spaces, trim, and drop one pair of
+/// matching surrounding quotes (`"`, `'`, `` ` `` or `“”`).
+pub fn normalize_query(query: &str) -> String {
+    let collapsed = query.split_whitespace().collect::<Vec<_>>().join(" ");
+    for (open, close) in [('"', '"'), ('\'', '\''), ('`', '`'), ('“', '”')] {
+        if let Some(inner) = collapsed
+            .strip_prefix(open)
+            .and_then(|s| s.strip_suffix(close))
+        {
+            return inner.trim().to_string();
+        }
+    }
+    collapsed
 }
 
 pub async fn retrieve_formatted(
@@ -208,25 +867,431 @@ pub async fn retrieve_formatted(
     all_blob_names: &[String],
     query: &str,
 ) -> Result<String> {
+    retrieve_formatted_with_dialog(cfg, all_blob_names, query, &[]).await
+}
+
+/// `retrieve_formatted` with prior conversation turns sent as `dialog`, cut to the most
+/// recent `max_dialog_turns`.
+pub async fn retrieve_formatted_with_dialog(
+    cfg: &Config,
+    all_blob_names: &[String],
+    query: &str,
+    dialog: &[serde_json::Value],
+) -> Result<String> {
+    let (retrieved, _, _) = retrieve_inner(cfg, all_blob_names, &[], query, None, dialog).await?;
+    Ok(retrieved.into_text(cfg))
+}
+
+/// Per-call retrieval detail, also logged as one `retrieval completed` event.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RetrievalMeta {
+    /// Characters in the query as sent (`information_request`)
+    pub query_chars: usize,
+    /// Blob names in `added_blobs` of the request that answered (a checkpoint delta when one was used)
+    pub blobs_sent: usize,
+    /// Characters of retrieved text after `retrieval_strip_patterns`/`retrieval_max_chars`
+    pub response_chars: usize,
+    /// Round trip including retries and the checkpoint fallback
+    pub latency_ms: u64,
+}
+
+/// `retrieve_formatted` that also returns the call's `RetrievalMeta`.
+pub async fn retrieve_formatted_with_meta(
+    cfg: &Config,
+    all_blob_names: &[String],
+    query: &str,
+) -> Result<(String, RetrievalMeta)> {
+    let (retrieved, _, meta) = retrieve_inner(cfg, all_blob_names, &[], query, None, &[]).await?;
+    Ok((retrieved.into_text(cfg), meta))
+}
+
+/// Appended to text cut short by the retrieval timeout.
+pub const PARTIAL_MARKER: &str = "(partial, timed out)";
+
+/// Retrieval body timed out after some of `formatted_retrieval` arrived; carries that prefix.
+/// Not retried: a backend that stalled once will likely stall again, and the prefix is usable.
+#[derive(Debug, thiserror::Error)]
+#[error("retrieval timed out after partial response")]
+struct PartialTimeout(String);
+
+async fn post_retrieval(
+    client: &Client,
+    url: &str,
+    cfg: &Config,
+    payload: &RetrievalPayload<'_>,
+    retries: usize,
+) -> Result<RetrievalResp> {
+    let result = retry(
+        || async {
+            let mut r = client
+                .post(url)
+                .bearer_auth(&cfg.settings.token)
+                .json(payload)
+                .send()
+                .await?;
+            if !r.status().is_success() {
+                let sc = r.status();
+                let t = r.text().await.unwrap_or_default();
+                return Err(anyhow!("retrieve failed: {} {}", sc, t));
+            }
+            // 流式读取，超时时仍保留已收到的内容
+            let mut buf = Vec::new();
+            loop {
+                match r.chunk().await {
+                    Ok(Some(c)) => buf.extend_from_slice(&c),
+                    Ok(None) => break,
+                    Err(e) if e.is_timeout() => {
+                        return match partial_formatted_retrieval(&buf) {
+                            Some(p) if !p.trim().is_empty() => Err(PartialTimeout(p).into()),
+                            _ => Err(e.into()),
+                        };
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            Ok(serde_json::from_slice::<RetrievalResp>(&buf)?)
+        },
+        retries,
+        2000,
+    )
+    .await;
+    match result {
+        Err(e) if e.is::<PartialTimeout>() => {
+            let PartialTimeout(text) = e.downcast().expect("checked PartialTimeout");
+            tracing::warn!(
+                received = text.len(),
+                "retrieval timed out; returning partial result"
+            );
+            Ok(RetrievalResp {
+                formatted_retrieval: format!("{}\n\n{}", text, PARTIAL_MARKER),
+                checkpoint_id: None,
+            })
+        }
+        other => other,
+    }
+}
+
+/// Decode the (possibly unterminated) `formatted_retrieval` string from a truncated JSON body.
+fn partial_formatted_retrieval(buf: &[u8]) -> Option<String> {
+    let text = match std::str::from_utf8(buf) {
+        Ok(t) => t,
+        Err(e) => std::str::from_utf8(&buf[..e.valid_up_to()]).ok()?,
+    };
+    const KEY: &str = "\"formatted_retrieval\"";
+    let after_key = &text[text.find(KEY)? + KEY.len()..];
+    let after_colon = after_key.trim_start().strip_prefix(':')?;
+    let body = after_colon.trim_start().strip_prefix('"')?;
+    let mut out = String::new();
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => break,
+            '\\' => match chars.next() {
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('r') => out.push('\r'),
+                Some('b') => out.push('\u{8}'),
+                Some('f') => out.push('\u{c}'),
+                Some('u') => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                        Some(ch) if hex.len() == 4 => out.push(ch),
+                        _ => break,
+                    }
+                }
+                Some(other) => out.push(other),
+                None => break,
+            },
+            c => out.push(c),
+        }
+    }
+    Some(out)
+}
+
+/// Blobs to send against a checkpoint: (added since it, deleted since it).
+pub fn checkpoint_delta(
+    prev: &Checkpoint,
+    all_blob_names: &[String],
+) -> (Vec<String>, Vec<String>) {
+    let current: HashSet<&str> = all_blob_names.iter().map(String::as_str).collect();
+    let added = all_blob_names
+        .iter()
+        .filter(|n| !prev.blob_names.contains(*n))
+        .cloned()
+        .collect();
+    let deleted = prev
+        .blob_names
+        .iter()
+        .filter(|n| !current.contains(n.as_str()))
+        .cloned()
+        .collect();
+    (added, deleted)
+}
+
+/// `names` followed by the `extra` entries it lacks.
+fn with_extra(names: &[String], extra: &[String]) -> Vec<String> {
+    let present: HashSet<&str> = names.iter().map(String::as_str).collect();
+    let mut out = names.to_vec();
+    out.extend(
+        extra
+            .iter()
+            .filter(|n| !present.contains(n.as_str()))
+            .cloned(),
+    );
+    out
+}
+
+/// Serialized size of `names` as a JSON array.
+fn json_array_bytes(names: &[String]) -> usize {
+    2 + names.len().saturating_sub(1)
+        + names
+            .iter()
+            .map(|n| serde_json::to_string(n).map_or(n.len() + 2, |s| s.len()))
+            .sum::<usize>()
+}
+
+/// Runs of `names` sent as `added_blobs` in one retrieval call each: one run unless the list
+/// exceeds `retrieval_max_request_bytes`, then either an error or consecutive runs within it.
+fn request_parts<'a>(cfg: &Config, names: &'a [String]) -> Result<Vec<&'a [String]>> {
+    let cap = cfg.settings.retrieval_max_request_bytes;
+    let total = json_array_bytes(names);
+    if cap == 0 || total <= cap {
+        return Ok(vec![names]);
+    }
+    if cfg.settings.retrieval_overflow_mode == RequestOverflowMode::Error {
+        return Err(anyhow!(
+            "retrieval request too large: added_blobs of {} blobs is {} bytes, over retrieval_max_request_bytes = {}; raise the cap or set retrieval_overflow_mode = \"split\"",
+            names.len(),
+            total,
+            cap
+        ));
+    }
+    let mut parts = Vec::new();
+    let (mut start, mut size) = (0, 2);
+    for (i, n) in names.iter().enumerate() {
+        let item = json_array_bytes(std::slice::from_ref(n)) - 2;
+        if item + 2 > cap {
+            return Err(anyhow!(
+                "retrieval_max_request_bytes = {} is smaller than a single blob name ({} bytes)",
+                cap,
+                item + 2
+            ));
+        }
+        let sep = usize::from(i > start);
+        if size + sep + item > cap {
+            parts.push(&names[start..i]);
+            (start, size) = (i, 2 + item);
+        } else {
+            size += sep + item;
+        }
+    }
+    parts.push(&names[start..]);
+    Ok(parts)
+}
+
+/// Combine the texts of a split retrieval in call order. When every text uses the
+/// `Path: ` layout, sections for the same file are merged and repeated snippets dropped;
+/// otherwise the texts are joined with blank lines.
+fn merge_retrievals(texts: Vec<String>) -> String {
+    let texts: Vec<String> = texts.into_iter().filter(|t| !t.trim().is_empty()).collect();
+    let grouped: Option<Vec<GroupedRetrieval>> =
+        texts.iter().map(|t| GroupedRetrieval::parse(t)).collect();
+    let Some(grouped) = grouped else {
+        return texts.join("\n\n");
+    };
+    let mut merged = GroupedRetrieval {
+        preamble: String::new(),
+        files: Vec::new(),
+    };
+    for g in grouped {
+        if merged.preamble.is_empty() {
+            merged.preamble = g.preamble;
+        }
+        for f in g.files {
+            match merged.files.iter_mut().find(|m| m.path == f.path) {
+                Some(m) => {
+                    for s in f.snippets {
+                        if !m.snippets.contains(&s) {
+                            m.snippets.push(s);
+                        }
+                    }
+                    m.chunks.extend(f.chunks);
+                    m.chunks.sort_unstable();
+                    m.chunks.dedup();
+                }
+                None => merged.files.push(f),
+            }
+        }
+    }
+    merged.to_text()
+}
+
+/// Retrieval that sends only the delta against `prev` when given. Returns the text and the
+/// checkpoint to keep for the next call (`None` if the backend issues no `checkpoint_id`).
+/// A rejected checkpoint falls back to sending the full blob list.
+/// `pinned` blobs are always sent in `added_blobs`, even when the checkpoint already covers them.
+pub async fn retrieve_with_checkpoint(
+    cfg: &Config,
+    all_blob_names: &[String],
+    pinned: &[String],
+    query: &str,
+    prev: Option<&Checkpoint>,
+) -> Result<(Retrieved, Option<Checkpoint>)> {
+    let (retrieved, next, _) =
+        retrieve_inner(cfg, all_blob_names, pinned, query, prev, &[]).await?;
+    Ok((retrieved, next))
+}
+
+async fn retrieve_inner(
+    cfg: &Config,
+    all_blob_names: &[String],
+    pinned: &[String],
+    query: &str,
+    prev: Option<&Checkpoint>,
+    dialog: &[serde_json::Value],
+) -> Result<(Retrieved, Option<Checkpoint>, RetrievalMeta)> {
+    let started = std::time::Instant::now();
+    let all_blob_names = &with_extra(all_blob_names, pinned);
     let url = format!(
         "{}/agents/codebase-retrieval",
         cfg.settings.base_url.trim_end_matches('/')
     );
-    let client = auth_client(60);
-    let payload = RetrievalPayload {
-        information_request: query,
-        blobs: RetrievalBlobs {
-            checkpoint_id: None,
-            added_blobs: all_blob_names,
-            deleted_blobs: vec![],
-        },
-        dialog: vec![],
-        max_output_length: cfg.settings.max_output_length,
-        disable_codebase_retrieval: cfg.settings.disable_codebase_retrieval,
-        enable_commit_retrieval: cfg.settings.enable_commit_retrieval,
+    let client = auth_client(cfg, cfg.settings.retrieval_timeout_secs)?;
+    let mut resp = None;
+    let mut blobs_sent = all_blob_names.len();
+    if let Some(cp) = prev {
+        let (added, deleted) = checkpoint_delta(cp, all_blob_names);
+        let added = with_extra(&added, pinned);
+        let delta_len = added.len();
+        let cap = cfg.settings.retrieval_max_request_bytes;
+        if cap > 0 && json_array_bytes(&added) > cap {
+            // 增量本身超出上限：改走完整列表（按 retrieval_overflow_mode 拆分或报错）
+            tracing::debug!(
+                delta_len,
+                "checkpoint delta exceeds retrieval_max_request_bytes; sending the full blob list"
+            );
+        } else {
+            let blobs = RetrievalBlobs {
+                checkpoint_id: Some(cp.checkpoint_id.clone()),
+                added_blobs: &added,
+                deleted_blobs: deleted,
+            };
+            match post_retrieval(
+                &client,
+                &url,
+                cfg,
+                &RetrievalPayload::new(cfg, query, blobs, dialog),
+                1,
+            )
+            .await
+            {
+                Ok(r) => {
+                    resp = Some(r);
+                    blobs_sent = delta_len;
+                }
+                Err(e) => tracing::warn!(
+                    error = %e,
+                    "checkpointed retrieval failed; retrying with full blob list"
+                ),
+            }
+        }
+    }
+    let resp = match resp {
+        Some(r) => r,
+        None => {
+            let parts = request_parts(cfg, all_blob_names)?;
+            if parts.len() > 1 {
+                tracing::info!(
+                    blobs = all_blob_names.len(),
+                    calls = parts.len(),
+                    "blob list exceeds retrieval_max_request_bytes; splitting retrieval"
+                );
+            }
+            let mut texts = Vec::with_capacity(parts.len());
+            let mut single = None;
+            for part in &parts {
+                let blobs = RetrievalBlobs {
+                    checkpoint_id: None,
+                    added_blobs: part,
+                    deleted_blobs: vec![],
+                };
+                let r = post_retrieval(
+                    &client,
+                    &url,
+                    cfg,
+                    &RetrievalPayload::new(cfg, query, blobs, dialog),
+                    3,
+                )
+                .await?;
+                if parts.len() == 1 {
+                    single = Some(r);
+                } else {
+                    texts.push(r.formatted_retrieval);
+                }
+            }
+            // 拆分后的各次调用各自对应部分 blob，不保留 checkpoint
+            single.unwrap_or_else(|| RetrievalResp {
+                formatted_retrieval: merge_retrievals(texts),
+                checkpoint_id: None,
+            })
+        }
+    };
+
+    let next = resp.checkpoint_id.map(|checkpoint_id| Checkpoint {
+        checkpoint_id,
+        blob_names: all_blob_names.iter().cloned().collect(),
+    });
+    let text = postprocess_retrieval(cfg, resp.formatted_retrieval);
+    let meta = RetrievalMeta {
+        query_chars: query.chars().count(),
+        blobs_sent,
+        response_chars: text.chars().count(),
+        latency_ms: started.elapsed().as_millis() as u64,
+    };
+    tracing::info!(
+        query_chars = meta.query_chars,
+        blobs_sent = meta.blobs_sent,
+        response_chars = meta.response_chars,
+        latency_ms = meta.latency_ms,
+        "retrieval completed"
+    );
+    let retrieved = if text.trim().is_empty() {
+        Retrieved::Empty
+    } else {
+        Retrieved::Context(text)
     };
+    Ok((retrieved, next, meta))
+}
 
-    let resp: RetrievalResp = retry(
+#[derive(Debug, Serialize)]
+struct VerifyPayload<'a> {
+    blob_names: &'a [String],
+}
+
+#[derive(Debug, Deserialize)]
+struct VerifyResp {
+    #[serde(default)]
+    missing_blob_names: Vec<String>,
+}
+
+/// Ask `verify_endpoint` which of `blob_names` the backend no longer has: POSTs
+/// `{"blob_names": [...]}` and reads `{"missing_blob_names": [...]}`. None when no
+/// `verify_endpoint` is configured.
+pub async fn find_missing_blobs(
+    cfg: &Config,
+    blob_names: &[String],
+) -> Result<Option<Vec<String>>> {
+    let Some(endpoint) = cfg.settings.verify_endpoint.as_deref() else {
+        return Ok(None);
+    };
+    let url = format!(
+        "{}/{}",
+        cfg.settings.base_url.trim_end_matches('/'),
+        endpoint.trim_start_matches('/')
+    );
+    let client = auth_client(cfg, 30)?;
+    let payload = VerifyPayload { blob_names };
+    let resp: VerifyResp = retry(
         || async {
             let r = client
                 .post(&url)
@@ -237,18 +1302,54 @@ pub async fn retrieve_formatted(
             if !r.status().is_success() {
                 let sc = r.status();
                 let t = r.text().await.unwrap_or_default();
-                return Err(anyhow!("retrieve failed: {} {}", sc, t));
+                return Err(anyhow!("verify failed: {} {}", sc, t));
             }
-            Ok(r.json::<RetrievalResp>().await?)
+            Ok(r.json().await?)
         },
         3,
-        2000,
+        1000,
     )
     .await?;
+    // 只采信确实在请求中的名字
+    let asked: HashSet<&String> = blob_names.iter().collect();
+    Ok(Some(
+        resp.missing_blob_names
+            .into_iter()
+            .filter(|n| asked.contains(n))
+            .collect(),
+    ))
+}
 
-    if resp.formatted_retrieval.trim().is_empty() {
-        Ok("No relevant code context found for your query.".to_string())
-    } else {
-        Ok(resp.formatted_retrieval)
+/// Header carrying `sha256=<hex>`: HMAC-SHA256 of the webhook body keyed by the backend token.
+pub const WEBHOOK_SIGNATURE_HEADER: &str = "X-Augmcp-Signature";
+
+pub fn webhook_signature(token: &str, body: &[u8]) -> String {
+    use hmac::{Hmac, Mac};
+    let mut mac =
+        Hmac::<sha2::Sha256>::new_from_slice(token.as_bytes()).expect("HMAC accepts any key");
+    mac.update(body);
+    let digest = mac.finalize().into_bytes();
+    let hex: String = digest.iter().map(|b| format!("{b:02x}")).collect();
+    format!("sha256={hex}")
+}
+
+/// POST a signed JSON notification to `url` (single attempt).
+pub async fn post_webhook(cfg: &Config, url: &str, body: Vec<u8>) -> Result<()> {
+    // 第三方地址：不沿用后端的 TLS 放宽/自定义 CA 与 base_url 检查
+    let client = Client::builder()
+        .timeout(Duration::from_secs(10))
+        .user_agent("augmcp/0.1")
+        .build()?;
+    let signature = webhook_signature(&cfg.settings.token, &body);
+    let r = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(WEBHOOK_SIGNATURE_HEADER, signature)
+        .body(body)
+        .send()
+        .await?;
+    if !r.status().is_success() {
+        return Err(anyhow!("webhook returned {}", r.status()));
     }
+    Ok(())
 }
@@ -0,0 +1,100 @@
+//! Blocking wrappers around `service` for callers without an async runtime (feature `blocking`).
+//!
+//! Every call runs on one shared, lazily built tokio runtime. Calling these from inside a
+//! tokio runtime (an async fn, a `#[tokio::test]`) returns an error instead of blocking a
+//! worker thread; use the async `service` functions there.
+
+use crate::{
+    backend::Retrieved,
+    config::Config,
+    service::{self, IndexReport, RetrievalLimiter},
+};
+use anyhow::{Result, anyhow};
+use std::{future::Future, sync::OnceLock};
+use tokio::runtime::{Builder, Runtime};
+
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+// 首次 search_blocking 的配置决定检索并发上限
+static LIMITER: OnceLock<RetrievalLimiter> = OnceLock::new();
+
+fn runtime() -> Result<&'static Runtime> {
+    if let Some(rt) = RUNTIME.get() {
+        return Ok(rt);
+    }
+    let rt = Builder::new_multi_thread()
+        .worker_threads(2)
+        .thread_name("augmcp-blocking")
+        .enable_all()
+        .build()?;
+    // 并发首次调用时只保留一个运行时
+    Ok(RUNTIME.get_or_init(|| rt))
+}
+
+/// Drive `fut` to completion on the shared runtime.
+pub fn block_on<F: Future>(fut: F) -> Result<F::Output> {
+    if tokio::runtime::Handle::try_current().is_ok() {
+        return Err(anyhow!(
+            "blocking API called from within a tokio runtime; use the async service API"
+        ));
+    }
+    Ok(runtime()?.block_on(fut))
+}
+
+/// Blocking `service::index_and_persist`.
+pub fn index_blocking(
+    cfg: &Config,
+    project_key: &str,
+    path: &str,
+    force_full: bool,
+) -> Result<IndexReport> {
+    block_on(service::index_and_persist(
+        cfg,
+        project_key,
+        path,
+        force_full,
+    ))?
+}
+
+/// Blocking `service::ensure_indexed`.
+pub fn ensure_indexed_blocking(
+    cfg: &Config,
+    project_key: &str,
+    path: &str,
+    skip_index_if_indexed: bool,
+) -> Result<Vec<String>> {
+    block_on(service::ensure_indexed(
+        cfg,
+        project_key,
+        path,
+        skip_index_if_indexed,
+    ))?
+}
+
+/// Blocking `service::retrieve`.
+pub fn retrieve_blocking(
+    cfg: &Config,
+    project_key: &str,
+    all_blob_names: &[String],
+    query: &str,
+) -> Result<Retrieved> {
+    block_on(service::retrieve(cfg, project_key, all_blob_names, query))?
+}
+
+/// Blocking `service::ensure_index_then_retrieve`: index if needed, then search. All calls
+/// share one `max_concurrent_retrievals` limit, taken from the first call's `cfg`.
+pub fn search_blocking(
+    cfg: &Config,
+    project_key: &str,
+    path: &str,
+    query: &str,
+    skip_index_if_indexed: bool,
+) -> Result<String> {
+    block_on(service::ensure_index_then_retrieve(
+        cfg,
+        LIMITER.get_or_init(|| RetrievalLimiter::from_config(cfg)),
+        project_key,
+        path,
+        query,
+        skip_index_if_indexed,
+    ))?
+}
@@ -2,45 +2,278 @@
 //!
 //! Reads `~/.augmcp/settings.toml`, creates with defaults on first run.
 
+use crate::indexer::ChunkNumbering;
 use anyhow::{Result, anyhow};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::env;
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashMap, HashSet},
     fs,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
 };
 use toml;
 
 const ROOT_DIR_NAME: &str = ".augmcp";
 
+/// Prefix of hashed project keys (`hash_project_keys`).
+pub const HASHED_KEY_PREFIX: &str = "sha256:";
+
+/// Whether a search may (re)index the project first. Ordered from most to least restrictive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchIndexPolicy {
+    /// Never index on search; projects must be indexed explicitly first
+    Never,
+    /// Index only projects without a stored index
+    IfMissing,
+    /// Incrementally re-index before every search (per-call `skip_index_if_indexed` narrows
+    /// this to `if_missing`)
+    #[default]
+    Always,
+}
+
+impl SearchIndexPolicy {
+    /// The policy for one search: `skip_index_if_indexed` can narrow it to `IfMissing`, never widen.
+    pub fn narrowed(self, skip_index_if_indexed: bool) -> Self {
+        if skip_index_if_indexed {
+            self.min(Self::IfMissing)
+        } else {
+            self
+        }
+    }
+}
+
+/// Where index state (projects, index metadata, checkpoints, aliases) is stored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StateScope {
+    /// `~/.augmcp/data` (aliases in `~/.augmcp`)
+    #[default]
+    Global,
+    /// A `.augmcp/` directory in the project containing the working directory
+    Project,
+}
+
+/// What a search does when `max_concurrent_retrievals` slots are all taken.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetrievalOverflow {
+    /// Wait for a free slot
+    #[default]
+    Queue,
+    /// Fail at once with a `busy` status
+    Reject,
+}
+
+/// What a retrieval does when its blob list exceeds `retrieval_max_request_bytes`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestOverflowMode {
+    /// Send the blob list over several retrieval calls and merge their results
+    #[default]
+    Split,
+    /// Fail without calling the backend
+    Error,
+}
+
+/// Order in which new blobs are sent to `/batch-upload`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadOrder {
+    /// As collected by the directory walk
+    #[default]
+    Walk,
+    /// By blob path, lexically
+    Path,
+    /// Largest content first
+    SizeDesc,
+    /// Most recently modified file first
+    Recency,
+}
+
+/// How project paths are shown in listings; stored paths stay absolute.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisplayPaths {
+    /// The stored absolute path
+    #[default]
+    Absolute,
+    /// Paths under the home directory as `~/...`
+    HomeRelative,
+}
+
+/// Backend credentials for one project (`project_backends`); unset fields use the global ones.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProjectBackend {
+    pub base_url: Option<String>,
+    pub token: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Settings {
     #[serde(alias = "BATCH_SIZE")]
     pub batch_size: usize,
+    /// Upload batches also stop at this many content bytes (0 = item count only)
+    pub batch_max_bytes: usize,
+    /// Order new blobs are uploaded in: `walk` (default), `path`, `size_desc` or `recency`
+    pub upload_order: UploadOrder,
+    /// Pause between upload batches, for backends that rate-limit `/batch-upload` (0 = none)
+    pub upload_inter_batch_delay_ms: u64,
+    /// Total seconds of backoff and retried requests allowed across one upload run before it
+    /// fails with "retry budget exhausted"; 0 = only the per-request retry limit applies
+    pub index_total_retry_budget_secs: u64,
     #[serde(alias = "MAX_LINES_PER_BLOB")]
     pub max_lines_per_blob: usize,
+    /// Per-extension override of `max_lines_per_blob`, keyed like `.md`
+    pub max_lines_per_ext: HashMap<String, usize>,
     #[serde(alias = "BASE_URL")]
     pub base_url: String,
     #[serde(alias = "TOKEN")]
     pub token: String,
+    /// Per-project `base_url` / `token`, keyed by alias or project path, for multi-tenant backends
+    pub project_backends: BTreeMap<String, ProjectBackend>,
     #[serde(alias = "TEXT_EXTENSIONS")]
     pub text_extensions: Vec<String>,
     #[serde(alias = "EXCLUDE_PATTERNS")]
     pub exclude_patterns: Vec<String>,
+    /// Built-in exclude lists merged with `exclude_patterns`: `rust`, `node`, `python`, `java`
+    pub exclude_presets: Vec<String>,
+    /// Skip files whose average line length exceeds this many bytes (minified bundles); 0 disables
+    pub max_avg_line_bytes: usize,
+    /// Skip empty and whitespace-only files, and whitespace-only chunks of larger files
+    pub skip_empty_files: bool,
+    /// Skip files modified less than this many seconds ago (possibly half-written); 0 disables
+    pub min_file_age_secs: u64,
+    /// Skip files not modified for more than this many days; 0 disables
+    pub max_file_age_days: u64,
+    /// Ignore .gitignore files above the project root, as if the root were its own repository
+    pub treat_root_as_repo: bool,
     // Retrieval tuning
     pub max_output_length: u32,
     pub disable_codebase_retrieval: bool,
     pub enable_commit_retrieval: bool,
+    /// Max retrievals issued concurrently for one batched search_context call
+    pub max_concurrent_queries: usize,
+    /// Searches retrieving from the backend at once, server-wide; 0 = unlimited
+    pub max_concurrent_retrievals: usize,
+    /// `queue` (default) waits for a free retrieval slot, `reject` answers `busy`
+    pub retrieval_overflow: RetrievalOverflow,
+    /// Compute async task progress percent from uploaded bytes instead of item count
+    pub progress_percent_by_bytes: bool,
+    /// Max index runs (sync or async, HTTP or MCP) at once per server (0 = unlimited); others queue
+    pub max_concurrent_indexes: usize,
+    /// Re-index every known project incrementally this often on the HTTP server; 0 disables
+    pub reindex_interval_secs: u64,
+    /// Remove known projects whose directory is gone during periodic re-index
+    pub reindex_prune_missing: bool,
+    /// Base directory for relative project paths; unset = current working directory
+    pub project_base_dir: Option<String>,
+    /// Track content-only hashes so renamed files are reported as moved rather than new
+    pub detect_moves: bool,
+    /// Named content transforms applied before hashing/upload, in order
+    /// (`redact-env-values`, `strip-block-comments`)
+    pub content_filters: Vec<String>,
+    /// How long a project found to have no indexable files is trusted to stay empty before
+    /// searches walk it again
+    pub empty_index_ttl_secs: u64,
+    /// Age after which a stored index is re-indexed incrementally even when the search asks to
+    /// skip indexing (0 = never stale)
+    pub index_ttl_secs: u64,
+    /// Skip TLS certificate verification for the backend (self-signed dev setups only)
+    pub danger_accept_invalid_certs: bool,
+    /// Extra PEM root certificate trusted for the backend
+    pub root_ca_cert_path: Option<String>,
+    /// Persist salted hashes of project paths as keys instead of plaintext paths
+    pub hash_project_keys: bool,
+    /// Write the rolling `augmcp.log` under the log dir; false = console only
+    pub file_logging: bool,
+    /// Idle keep-alive connections kept per backend host; unset = unlimited (reqwest default)
+    pub pool_max_idle_per_host: Option<usize>,
+    /// Seconds an idle pooled connection is kept before closing; 0 = never expire
+    pub pool_idle_timeout_secs: u64,
+    /// Total time allowed per retrieval request; on expiry any partially received text is returned
+    pub retrieval_timeout_secs: u64,
+    /// Most recent `dialog` turns sent with a retrieval; older ones are dropped (0 = all)
+    pub max_dialog_turns: usize,
+    /// Reserved: fold turns beyond `max_dialog_turns` into one summary turn (currently dropped)
+    pub summarize_old_dialog: bool,
+    /// Cap on the serialized `added_blobs` array of one retrieval request, in bytes; 0 = no cap
+    pub retrieval_max_request_bytes: usize,
+    /// Over `retrieval_max_request_bytes`: `split` (default) across several calls, or `error`
+    pub retrieval_overflow_mode: RequestOverflowMode,
+    /// Namespace applied to bare aliases, stored as `<namespace>:<name>`; unset = no prefix
+    pub alias_namespace: Option<String>,
+    /// Alias used when a call names neither alias nor path; takes precedence over `default_project_path`
+    pub default_alias: Option<String>,
+    /// Project path used when a call names neither alias nor path and `default_alias` is unset
+    pub default_project_path: Option<String>,
+    /// `padded` (default): `#chunk02of10`; `plain`: `#chunk2of10`
+    pub chunk_numbering: ChunkNumbering,
+    /// POSTed `{project_key, path, total_blobs, new_blobs, duration_ms}` after each successful index
+    pub index_webhook_url: Option<String>,
+    /// Backend path (e.g. `/find-missing`) answering which stored blob names it no longer has;
+    /// unset = blob verification not supported
+    pub verify_endpoint: Option<String>,
+    /// Globs for generated code (e.g. `*.pb.go`): excluded like `exclude_patterns`, counted separately
+    pub generated_patterns: Vec<String>,
+    /// Globs always collected even if .gitignore, exclude or generated patterns drop them
+    pub force_include: Vec<String>,
+    /// Let `force_include` files bypass the `text_extensions` and .gitattributes checks too
+    pub force_include_any_extension: bool,
+    /// Honor `.augmcpkeep` files (costs one extra walk that ignores .gitignore per collection)
+    pub honor_keep_files: bool,
+    /// In git repositories, collect only files listed by `git ls-files`; other roots walk as usual
+    pub only_git_tracked: bool,
+    /// Retrieval-only deployment: never index, upload or bind aliases; mutating HTTP routes 403
+    pub read_only: bool,
+    /// Text returned when retrieval finds nothing; unset = the built-in English message
+    pub empty_result_message: Option<String>,
+    /// Regexes whose matches are removed from retrieved text (backend headers/footers)
+    pub retrieval_strip_patterns: Vec<String>,
+    /// Cut retrieved text to this many characters, ending in `…`; 0 = no limit
+    pub retrieval_max_chars: usize,
+    /// Rewrite `path#chunkNofM` in retrieved text to the bare file path
+    pub clean_chunk_paths_in_output: bool,
+    /// Project paths in `/api/projects` and MCP resource names: `absolute` (default) or `home_relative`
+    pub display_paths: DisplayPaths,
+    /// Project-relative files whose blobs are sent with every retrieval, on top of the normal set
+    pub pinned_paths: Vec<String>,
+    /// Server-wide limit on indexing during search: `never`, `if_missing` or `always` (default)
+    pub search_index_policy: SearchIndexPolicy,
+    /// Send a `language` field (detected from the extension) with each uploaded blob
+    pub upload_include_language: bool,
+    /// Upload blob paths without `#chunkNofM`, sending `chunk_index`/`chunk_count` fields instead
+    pub upload_strip_chunk_suffix: bool,
+    /// Send each blob's name as an `id` field so the backend can dedupe re-uploads
+    pub send_blob_ids: bool,
+    /// Send the source file's `line_count` and `byte_size` with each uploaded blob
+    pub upload_include_metrics: bool,
+    /// HTTP requests allowed per client IP per minute (token bucket, `/healthz` exempt); 0 = unlimited
+    pub rate_limit_per_min: u32,
+    /// `global` (default): state under `~/.augmcp`; `project`: in the nearest `.augmcp/` above the working directory
+    pub state_scope: StateScope,
+    /// Append `{timestamp_ms, query, result_len, blob_count}` per retrieval to the project's history.jsonl
+    pub record_history: bool,
+    /// Also store the retrieved text in history entries (off by default for privacy)
+    pub history_include_result: bool,
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Self {
             batch_size: 10,
+            batch_max_bytes: 0,
+            upload_order: UploadOrder::default(),
+            upload_inter_batch_delay_ms: 0,
+            index_total_retry_budget_secs: 0,
             max_lines_per_blob: 800,
+            max_lines_per_ext: HashMap::new(),
             base_url: "https://api.example.com".to_string(),
             token: "your-token-here".to_string(),
+            project_backends: BTreeMap::new(),
             text_extensions: vec![
                 ".py", ".js", ".ts", ".jsx", ".tsx", ".java", ".go", ".rs", ".cpp", ".c", ".h",
                 ".hpp", ".cs", ".rb", ".php", ".md", ".txt", ".json", ".yaml", ".yml", ".toml",
@@ -85,13 +318,80 @@ impl Default for Settings {
             .into_iter()
             .map(|s| s.to_string())
             .collect(),
+            exclude_presets: vec![],
+            max_avg_line_bytes: 0,
+            skip_empty_files: true,
+            min_file_age_secs: 0,
+            max_file_age_days: 0,
+            treat_root_as_repo: false,
             max_output_length: 0,
             disable_codebase_retrieval: false,
             enable_commit_retrieval: false,
+            max_concurrent_queries: 4,
+            max_concurrent_retrievals: 0,
+            retrieval_overflow: RetrievalOverflow::default(),
+            progress_percent_by_bytes: false,
+            max_concurrent_indexes: 2,
+            reindex_interval_secs: 0,
+            reindex_prune_missing: false,
+            project_base_dir: None,
+            detect_moves: false,
+            content_filters: Vec::new(),
+            empty_index_ttl_secs: 300,
+            index_ttl_secs: 0,
+            danger_accept_invalid_certs: false,
+            root_ca_cert_path: None,
+            hash_project_keys: false,
+            file_logging: true,
+            pool_max_idle_per_host: None,
+            pool_idle_timeout_secs: 90,
+            retrieval_timeout_secs: 60,
+            max_dialog_turns: 0,
+            summarize_old_dialog: false,
+            retrieval_max_request_bytes: 0,
+            retrieval_overflow_mode: RequestOverflowMode::default(),
+            alias_namespace: None,
+            default_alias: None,
+            default_project_path: None,
+            chunk_numbering: ChunkNumbering::default(),
+            index_webhook_url: None,
+            verify_endpoint: None,
+            generated_patterns: Vec::new(),
+            force_include: Vec::new(),
+            force_include_any_extension: false,
+            honor_keep_files: false,
+            only_git_tracked: false,
+            read_only: false,
+            empty_result_message: None,
+            retrieval_strip_patterns: Vec::new(),
+            retrieval_max_chars: 0,
+            clean_chunk_paths_in_output: false,
+            display_paths: DisplayPaths::default(),
+            pinned_paths: Vec::new(),
+            search_index_policy: SearchIndexPolicy::default(),
+            upload_include_language: false,
+            upload_strip_chunk_suffix: false,
+            send_blob_ids: false,
+            upload_include_metrics: false,
+            rate_limit_per_min: 0,
+            state_scope: StateScope::default(),
+            record_history: false,
+            history_include_result: false,
         }
     }
 }
 
+/// Resolved on-disk locations for the current config (`augmcp paths`, `GET /api/paths`).
+#[derive(Debug, Clone, Serialize)]
+pub struct ResolvedPaths {
+    pub settings_path: PathBuf,
+    pub data_dir: PathBuf,
+    pub projects_file: PathBuf,
+    pub index_meta_file: PathBuf,
+    pub aliases_file: PathBuf,
+    pub log_dir: PathBuf,
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub settings: Settings,
@@ -106,9 +406,7 @@ impl Config {
             .ok_or_else(|| anyhow!("failed to resolve home dir"))?
             .join(ROOT_DIR_NAME);
         let cfg_dir = root_dir.clone();
-        let data_dir = root_dir.join("data");
         fs::create_dir_all(&cfg_dir)?;
-        fs::create_dir_all(&data_dir)?;
         let settings_path = cfg_dir.join("settings.toml");
 
         let mut settings = if settings_path.exists() {
@@ -127,30 +425,70 @@ impl Config {
         if let Ok(v) = env::var("AUGMCP_TOKEN") {
             settings.token = v;
         }
-        if let Ok(v) = env::var("AUGMCP_BATCH_SIZE") {
-            if let Ok(n) = v.parse::<usize>() {
-                settings.batch_size = n;
-            }
+        if let Ok(v) = env::var("AUGMCP_BATCH_SIZE")
+            && let Ok(n) = v.parse::<usize>()
+        {
+            settings.batch_size = n;
         }
-        if let Ok(v) = env::var("AUGMCP_MAX_LINES_PER_BLOB") {
-            if let Ok(n) = v.parse::<usize>() {
-                settings.max_lines_per_blob = n;
-            }
+        if let Ok(v) = env::var("AUGMCP_BATCH_MAX_BYTES")
+            && let Ok(n) = v.parse::<usize>()
+        {
+            settings.batch_max_bytes = n;
         }
-        if let Ok(v) = env::var("AUGMCP_MAX_OUTPUT_LENGTH") {
-            if let Ok(n) = v.parse::<u32>() {
-                settings.max_output_length = n;
-            }
+        if let Ok(v) = env::var("AUGMCP_MAX_LINES_PER_BLOB")
+            && let Ok(n) = v.parse::<usize>()
+        {
+            settings.max_lines_per_blob = n;
         }
-        if let Ok(v) = env::var("AUGMCP_DISABLE_CODEBASE_RETRIEVAL") {
-            if let Ok(b) = v.parse::<bool>() {
-                settings.disable_codebase_retrieval = b;
-            }
+        if let Ok(v) = env::var("AUGMCP_MAX_AVG_LINE_BYTES")
+            && let Ok(n) = v.parse::<usize>()
+        {
+            settings.max_avg_line_bytes = n;
         }
-        if let Ok(v) = env::var("AUGMCP_ENABLE_COMMIT_RETRIEVAL") {
-            if let Ok(b) = v.parse::<bool>() {
-                settings.enable_commit_retrieval = b;
-            }
+        if let Ok(v) = env::var("AUGMCP_MAX_OUTPUT_LENGTH")
+            && let Ok(n) = v.parse::<u32>()
+        {
+            settings.max_output_length = n;
+        }
+        if let Ok(v) = env::var("AUGMCP_DISABLE_CODEBASE_RETRIEVAL")
+            && let Ok(b) = v.parse::<bool>()
+        {
+            settings.disable_codebase_retrieval = b;
+        }
+        if let Ok(v) = env::var("AUGMCP_ENABLE_COMMIT_RETRIEVAL")
+            && let Ok(b) = v.parse::<bool>()
+        {
+            settings.enable_commit_retrieval = b;
+        }
+        if let Ok(v) = env::var("AUGMCP_MAX_CONCURRENT_QUERIES")
+            && let Ok(n) = v.parse::<usize>()
+        {
+            settings.max_concurrent_queries = n;
+        }
+        if let Ok(v) = env::var("AUGMCP_MAX_CONCURRENT_INDEXES")
+            && let Ok(n) = v.parse::<usize>()
+        {
+            settings.max_concurrent_indexes = n;
+        }
+        if let Ok(v) = env::var("AUGMCP_NO_FILE_LOG")
+            && matches!(v.trim(), "1" | "true")
+        {
+            settings.file_logging = false;
+        }
+        if let Ok(v) = env::var("AUGMCP_PROJECT_BASE_DIR")
+            && !v.trim().is_empty()
+        {
+            settings.project_base_dir = Some(v);
+        }
+        if let Ok(v) = env::var("AUGMCP_READ_ONLY")
+            && let Ok(b) = v.parse::<bool>()
+        {
+            settings.read_only = b;
+        }
+        if let Ok(v) = env::var("AUGMCP_ALIAS_NAMESPACE")
+            && !v.trim().is_empty()
+        {
+            settings.alias_namespace = Some(v);
         }
         if let Ok(v) = env::var("AUGMCP_TEXT_EXTENSIONS") {
             let vec = v
@@ -180,6 +518,12 @@ impl Config {
             settings.token = t;
         }
 
+        let data_dir = match settings.state_scope {
+            StateScope::Global => root_dir.join("data"),
+            StateScope::Project => Self::project_state_dir(&env::current_dir()?, &root_dir),
+        };
+        fs::create_dir_all(&data_dir)?;
+
         Ok(Self {
             settings,
             root_dir,
@@ -188,6 +532,24 @@ impl Config {
         })
     }
 
+    /// State directory for `state_scope = "project"`: the nearest existing `.augmcp/` at or
+    /// above `start` (other than the global `global_root`), else `.augmcp/` in the nearest
+    /// ancestor holding `.git`, else in `start` itself.
+    pub fn project_state_dir(start: &Path, global_root: &Path) -> PathBuf {
+        let existing = start
+            .ancestors()
+            .map(|d| d.join(ROOT_DIR_NAME))
+            .find(|d| d.is_dir() && d.as_path() != global_root);
+        if let Some(dir) = existing {
+            return dir;
+        }
+        let root = start
+            .ancestors()
+            .find(|d| d.join(".git").exists())
+            .unwrap_or(start);
+        root.join(ROOT_DIR_NAME)
+    }
+
     pub fn text_extensions_set(&self) -> HashSet<String> {
         self.settings.text_extensions.iter().cloned().collect()
     }
@@ -196,6 +558,29 @@ impl Config {
         self.data_dir.join("projects.json")
     }
 
+    pub fn index_meta_file(&self) -> PathBuf {
+        self.data_dir.join("index_meta.json")
+    }
+
+    pub fn checkpoints_file(&self) -> PathBuf {
+        self.data_dir.join("checkpoints.json")
+    }
+
+    pub fn project_meta_file(&self) -> PathBuf {
+        self.data_dir.join("project_meta.json")
+    }
+
+    /// `<data_dir>/history`, holding one query log per project.
+    pub fn history_dir(&self) -> PathBuf {
+        self.data_dir.join("history")
+    }
+
+    /// `<data_dir>/history/<sha256(project_key)>.jsonl` (`record_history`).
+    pub fn history_file(&self, project_key: &str) -> PathBuf {
+        let digest = Sha256::digest(project_key.as_bytes());
+        self.history_dir().join(format!("{:x}.jsonl", digest))
+    }
+
     pub fn save(&self) -> Result<()> {
         let text = toml::to_string_pretty(&self.settings)?;
         if let Some(parent) = self.settings_path.parent() {
@@ -209,14 +594,315 @@ impl Config {
         self.root_dir.join("log")
     }
 
+    /// `aliases.json` next to `settings.toml`, or in the project's `.augmcp/` with
+    /// `state_scope = "project"`.
     pub fn aliases_file(&self) -> PathBuf {
-        self.root_dir.join("aliases.json")
+        match self.settings.state_scope {
+            StateScope::Global => self.root_dir.join("aliases.json"),
+            StateScope::Project => self.data_dir.join("aliases.json"),
+        }
+    }
+
+    /// Resolve a user-supplied project path to its normalized key. Relative paths are
+    /// joined onto `project_base_dir` (or the current directory) first, so the same
+    /// project maps to the same key regardless of where it was referenced from.
+    pub fn resolve_project_path(&self, p: &str) -> Result<String> {
+        normalize_path(self.join_project_base(p)?)
+    }
+
+    /// `resolve_project_path` without touching the filesystem (no symlink resolution), for
+    /// paths that may no longer exist, e.g. finding the cached index of a moved project.
+    pub fn lexical_project_path(&self, p: &str) -> Result<String> {
+        Ok(lexical_normalize(&self.join_project_base(p)?))
+    }
+
+    fn join_project_base(&self, p: &str) -> Result<PathBuf> {
+        let path = Path::new(p);
+        if path.is_absolute() {
+            return Ok(path.to_path_buf());
+        }
+        let base = match &self.settings.project_base_dir {
+            Some(dir) => PathBuf::from(dir),
+            None => env::current_dir()?,
+        };
+        Ok(base.join(path))
+    }
+
+    /// Per-install salt for hashed project keys, created on first use.
+    pub fn key_salt(&self) -> Result<String> {
+        let path = self.root_dir.join("key_salt");
+        if let Ok(salt) = fs::read_to_string(&path)
+            && !salt.trim().is_empty()
+        {
+            return Ok(salt.trim().to_string());
+        }
+        let mut hasher = Sha256::new();
+        hasher.update(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos()
+                .to_le_bytes(),
+        );
+        hasher.update(std::process::id().to_le_bytes());
+        hasher.update(self.root_dir.to_string_lossy().as_bytes());
+        let salt = format!("{:x}", hasher.finalize());
+        fs::create_dir_all(&self.root_dir)?;
+        fs::write(&path, &salt)?;
+        Ok(salt)
+    }
+
+    /// Key under which a normalized project path is persisted: the path itself, or
+    /// `sha256:<hex>` of the salted path when `hash_project_keys` is on.
+    pub fn project_key(&self, normalized_path: &str) -> Result<String> {
+        if !self.settings.hash_project_keys {
+            return Ok(normalized_path.to_string());
+        }
+        let mut hasher = Sha256::new();
+        hasher.update(self.key_salt()?.as_bytes());
+        hasher.update(b"\n");
+        hasher.update(normalized_path.as_bytes());
+        Ok(format!("{}{:x}", HASHED_KEY_PREFIX, hasher.finalize()))
+    }
+
+    /// `resolve_project_path` + `project_key`: returns (project_key, absolute path).
+    pub fn resolve_project(&self, p: &str) -> Result<(String, String)> {
+        let path = self.resolve_project_path(p)?;
+        Ok((self.project_key(&path)?, path))
+    }
+
+    /// Alias as stored in `aliases.json`: `<namespace>:<name>`. An alias already containing
+    /// `:` is taken as qualified; otherwise `namespace` (or `alias_namespace`) is prefixed.
+    pub fn qualify_alias(&self, alias: &str, namespace: Option<&str>) -> String {
+        if alias.contains(':') {
+            return alias.to_string();
+        }
+        let ns = namespace
+            .or(self.settings.alias_namespace.as_deref())
+            .map(str::trim)
+            .filter(|ns| !ns.is_empty());
+        match ns {
+            Some(ns) => format!("{ns}:{alias}"),
+            None => alias.to_string(),
+        }
+    }
+
+    /// This config with `backend`'s non-empty `base_url` / `token` in place of the global ones.
+    pub fn with_backend(&self, backend: &ProjectBackend) -> Config {
+        let mut cfg = self.clone();
+        fn set(v: &Option<String>) -> Option<&str> {
+            v.as_deref().map(str::trim).filter(|v| !v.is_empty())
+        }
+        if let Some(url) = set(&backend.base_url) {
+            cfg.settings.base_url = url.to_string();
+        }
+        if let Some(token) = set(&backend.token) {
+            cfg.settings.token = token.to_string();
+        }
+        cfg
+    }
+
+    /// `path` as shown in listings per `display_paths`. The home directory is the parent of
+    /// `root_dir` (`~/.augmcp`).
+    pub fn display_path(&self, path: &str) -> String {
+        if self.settings.display_paths == DisplayPaths::HomeRelative
+            && let Some(home) = self.root_dir.parent()
+            && let Ok(rest) = Path::new(path).strip_prefix(home)
+        {
+            let rest = rest.to_string_lossy().replace('\\', "/");
+            return if rest.is_empty() {
+                "~".into()
+            } else {
+                format!("~/{rest}")
+            };
+        }
+        path.to_string()
+    }
+
+    /// Backend settings still holding their placeholder defaults (or empty): `base_url`,
+    /// `token`. Empty when the backend is configured.
+    pub fn unconfigured_fields(&self) -> Vec<&'static str> {
+        let defaults = Settings::default();
+        let mut fields = Vec::new();
+        let url = self.settings.base_url.trim();
+        if url.is_empty() || url == defaults.base_url {
+            fields.push("base_url");
+        }
+        let token = self.settings.token.trim();
+        if token.is_empty() || token == defaults.token {
+            fields.push("token");
+        }
+        fields
+    }
+
+    /// Explains which of `unconfigured_fields` to set and where; None when configured.
+    pub fn unconfigured_message(&self) -> Option<String> {
+        let fields = self.unconfigured_fields();
+        if fields.is_empty() {
+            return None;
+        }
+        Some(format!(
+            "augmcp is not configured: {} still {}; set {} in {} (or via AUGMCP_BASE_URL / AUGMCP_TOKEN, --base-url / --token)",
+            fields.join(" and "),
+            if fields.len() == 1 {
+                "holds its placeholder default"
+            } else {
+                "hold their placeholder defaults"
+            },
+            if fields.len() == 1 { "it" } else { "them" },
+            self.settings_path.display()
+        ))
+    }
+
+    pub fn resolved_paths(&self) -> ResolvedPaths {
+        ResolvedPaths {
+            settings_path: self.settings_path.clone(),
+            data_dir: self.data_dir.clone(),
+            projects_file: self.projects_file(),
+            index_meta_file: self.index_meta_file(),
+            aliases_file: self.aliases_file(),
+            log_dir: self.log_dir(),
+        }
+    }
+
+    /// Fingerprint of the settings that determine blob names and where they live
+    /// (backend, splitting, file selection). Used to validate shared manifests.
+    pub fn settings_fingerprint(&self) -> String {
+        let mut exts = self.settings.text_extensions.clone();
+        exts.sort();
+        let mut excludes = self.settings.exclude_patterns.clone();
+        excludes.sort();
+        let mut hasher = Sha256::new();
+        hasher.update(self.settings.base_url.trim_end_matches('/').as_bytes());
+        hasher.update(b"\n");
+        hasher.update(self.settings.max_lines_per_blob.to_string().as_bytes());
+        hasher.update(b"\n");
+        let mut per_ext: Vec<_> = self.settings.max_lines_per_ext.iter().collect();
+        per_ext.sort();
+        for (ext, n) in per_ext {
+            hasher.update(format!("{}={};", ext, n).as_bytes());
+        }
+        hasher.update(b"\n");
+        hasher.update(exts.join(",").as_bytes());
+        hasher.update(b"\n");
+        hasher.update(excludes.join(",").as_bytes());
+        if !self.settings.content_filters.is_empty() {
+            hasher.update(b"\n");
+            hasher.update(self.settings.content_filters.join(",").as_bytes());
+        }
+        if !self.settings.exclude_presets.is_empty() {
+            let mut presets: Vec<String> = self
+                .settings
+                .exclude_presets
+                .iter()
+                .map(|p| p.trim().to_lowercase())
+                .collect();
+            presets.sort();
+            hasher.update(b"\nexclude_presets=");
+            hasher.update(presets.join(",").as_bytes());
+        }
+        if !self.settings.generated_patterns.is_empty() {
+            let mut generated = self.settings.generated_patterns.clone();
+            generated.sort();
+            hasher.update(b"\ngenerated=");
+            hasher.update(generated.join(",").as_bytes());
+        }
+        if !self.settings.force_include.is_empty() {
+            let mut forced = self.settings.force_include.clone();
+            forced.sort();
+            hasher.update(b"\nforce_include=");
+            hasher.update(forced.join(",").as_bytes());
+            if self.settings.force_include_any_extension {
+                hasher.update(b"\nforce_include_any_extension");
+            }
+        }
+        if self.settings.only_git_tracked {
+            hasher.update(b"\nonly_git_tracked");
+        }
+        if self.settings.honor_keep_files {
+            hasher.update(b"\nhonor_keep_files");
+        }
+        if self.settings.treat_root_as_repo {
+            hasher.update(b"\ntreat_root_as_repo");
+        }
+        // Skipping empty files drops blobs that older manifests list; keeping them keeps the old fingerprint
+        if self.settings.skip_empty_files {
+            hasher.update(b"\nskip_empty_files");
+        }
+        // Padded numbering renames blobs of files with 10+ chunks; plain keeps the old fingerprint
+        if self.settings.chunk_numbering == ChunkNumbering::Padded {
+            hasher.update(b"\nchunk_numbering=padded");
+        }
+        format!("{:x}", hasher.finalize())
     }
 }
 
 /// Normalize a path to an absolute forward-slash representation.
 pub fn normalize_path<P: AsRef<Path>>(p: P) -> Result<String> {
     let abs = dunce::canonicalize(p)?;
-    let s = abs.to_string_lossy().replace('\\', "/");
-    Ok(s)
+    Ok(path_key_form(&abs.to_string_lossy()))
+}
+
+/// Forward-slash form used for keys, without Windows verbatim prefixes:
+/// `\\server\share` and `\\?\UNC\server\share` become `//server/share`, `\\?\C:\x` becomes
+/// `C:/x`. dunce keeps the verbatim prefix for paths over 260 chars, so it is stripped here.
+pub fn path_key_form(path: &str) -> String {
+    let s = path.replace('\\', "/");
+    if let Some(rest) = s.strip_prefix("//?/UNC/") {
+        return format!("//{rest}");
+    }
+    match s.strip_prefix("//?/") {
+        Some(rest) => rest.to_string(),
+        None => s,
+    }
+}
+
+/// Longest accepted explicit `project_key`.
+pub const MAX_PROJECT_KEY_LEN: usize = 128;
+
+/// Validate an explicit `project_key` (used verbatim instead of the path-derived key).
+/// Allowed: ASCII letters, digits, `.`, `_`, `-` and `/`, starting with a letter or digit and
+/// without `..` segments. Without `:` or a leading `/`, it never collides with a path or hashed key.
+pub fn validate_project_key(key: &str) -> Result<String> {
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(anyhow!("project_key must not be empty"));
+    }
+    if key.len() > MAX_PROJECT_KEY_LEN {
+        return Err(anyhow!(
+            "project_key is longer than {} characters",
+            MAX_PROJECT_KEY_LEN
+        ));
+    }
+    let allowed = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '/');
+    if !is_explicit_project_key(key)
+        || !key.chars().all(allowed)
+        || key.split('/').any(|seg| seg == ".." || seg.is_empty())
+    {
+        return Err(anyhow!(
+            "invalid project_key '{}': use letters, digits, '.', '_', '-' and '/'",
+            key
+        ));
+    }
+    Ok(key.to_string())
+}
+
+/// Whether a stored key is an explicit `project_key` rather than a path or hashed key.
+pub fn is_explicit_project_key(key: &str) -> bool {
+    key.starts_with(|c: char| c.is_ascii_alphanumeric()) && !key.contains(':')
+}
+
+/// Normalize without touching the filesystem: drop `.`, fold `..`, use `/` separators.
+fn lexical_normalize(p: &Path) -> String {
+    let mut out = PathBuf::new();
+    for c in p.components() {
+        match c {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            other => out.push(other),
+        }
+    }
+    path_key_form(&out.to_string_lossy())
 }
@@ -0,0 +1,218 @@
+//! `augmcp doctor`: setup diagnostics (settings, backend reachability/auth, permissions,
+//! optional dry-run collection).
+
+use crate::{
+    backend,
+    config::{Config, Settings},
+    indexer::{CollectOptions, collect_blobs_with_progress, root_gitignored},
+};
+use reqwest::{StatusCode, Url};
+use std::{fmt, fs, path::Path};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tag = match self.status {
+            CheckStatus::Pass => "PASS",
+            CheckStatus::Warn => "WARN",
+            CheckStatus::Fail => "FAIL",
+        };
+        write!(f, "[{}] {}: {}", tag, self.name, self.detail)
+    }
+}
+
+/// True if any check is a hard failure.
+pub fn has_failures(checks: &[Check]) -> bool {
+    checks.iter().any(|c| c.status == CheckStatus::Fail)
+}
+
+/// Run all checks. `index_path` enables a dry-run collection (nothing is uploaded or persisted).
+pub async fn run(cfg: &Config, index_path: Option<&str>) -> Vec<Check> {
+    let mut checks = vec![check_settings_file(cfg)];
+    let url_check = check_base_url(cfg);
+    let url_ok = url_check.status == CheckStatus::Pass;
+    checks.push(url_check);
+    if cfg.settings.token.trim().is_empty() || cfg.settings.token == Settings::default().token {
+        checks.push(Check::new(
+            "token",
+            CheckStatus::Fail,
+            "token is empty or the placeholder; set `token` in settings.toml, AUGMCP_TOKEN or --token",
+        ));
+    } else if url_ok {
+        checks.push(check_backend(cfg).await);
+    }
+    checks.push(check_writable("data_dir", &cfg.data_dir));
+    checks.push(check_writable("log_dir", &cfg.log_dir()));
+    if let Some(path) = index_path {
+        checks.push(check_dry_run(cfg, path));
+    }
+    checks
+}
+
+fn check_settings_file(cfg: &Config) -> Check {
+    let path = &cfg.settings_path;
+    let text = match fs::read_to_string(path) {
+        Ok(t) => t,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Check::new(
+                "settings",
+                CheckStatus::Warn,
+                format!("{} not found; using defaults", path.display()),
+            );
+        }
+        Err(e) => {
+            return Check::new(
+                "settings",
+                CheckStatus::Fail,
+                format!("cannot read {}: {}", path.display(), e),
+            );
+        }
+    };
+    match toml::from_str::<Settings>(&text) {
+        Ok(_) => Check::new("settings", CheckStatus::Pass, path.display().to_string()),
+        Err(e) => Check::new(
+            "settings",
+            CheckStatus::Fail,
+            format!(
+                "{} is invalid (defaults are silently used instead): {}",
+                path.display(),
+                e.message()
+            ),
+        ),
+    }
+}
+
+fn check_base_url(cfg: &Config) -> Check {
+    match Url::parse(&cfg.settings.base_url) {
+        Ok(u) if matches!(u.scheme(), "http" | "https") => {
+            Check::new("base_url", CheckStatus::Pass, u.to_string())
+        }
+        Ok(u) => Check::new(
+            "base_url",
+            CheckStatus::Fail,
+            format!("unsupported scheme `{}`; use http(s)://", u.scheme()),
+        ),
+        Err(e) => Check::new(
+            "base_url",
+            CheckStatus::Fail,
+            format!("`{}` is not a valid URL: {}", cfg.settings.base_url, e),
+        ),
+    }
+}
+
+async fn check_backend(cfg: &Config) -> Check {
+    match backend::probe_upload(cfg).await {
+        Ok(sc) if sc.is_success() => Check::new(
+            "backend",
+            CheckStatus::Pass,
+            "reachable; token accepted by batch-upload",
+        ),
+        Ok(sc) if sc == StatusCode::UNAUTHORIZED || sc == StatusCode::FORBIDDEN => Check::new(
+            "backend",
+            CheckStatus::Fail,
+            format!("token rejected ({}); check `token`", sc),
+        ),
+        Ok(sc) => Check::new(
+            "backend",
+            CheckStatus::Warn,
+            format!("reachable but probe returned {}", sc),
+        ),
+        Err(e) => Check::new(
+            "backend",
+            CheckStatus::Fail,
+            format!("unreachable: {}; check `base_url` and network/proxy", e),
+        ),
+    }
+}
+
+fn check_writable(name: &'static str, dir: &Path) -> Check {
+    let probe = dir.join(".doctor-probe");
+    let res = fs::create_dir_all(dir)
+        .and_then(|_| fs::write(&probe, b"ok"))
+        .and_then(|_| fs::remove_file(&probe));
+    match res {
+        Ok(()) => Check::new(
+            name,
+            CheckStatus::Pass,
+            format!("{} is writable", dir.display()),
+        ),
+        Err(e) => Check::new(
+            name,
+            CheckStatus::Fail,
+            format!("{} is not writable: {}", dir.display(), e),
+        ),
+    }
+}
+
+fn check_dry_run(cfg: &Config, path: &str) -> Check {
+    let root = match cfg.resolve_project_path(path) {
+        Ok(p) => p,
+        Err(e) => {
+            return Check::new(
+                "dry_run",
+                CheckStatus::Fail,
+                format!("cannot resolve {}: {}", path, e),
+            );
+        }
+    };
+    let mut files_seen = 0;
+    let res =
+        collect_blobs_with_progress(Path::new(&root), &CollectOptions::from_config(cfg), |p| {
+            files_seen = p.files_seen
+        });
+    match res {
+        Ok(blobs) if blobs.is_empty() => {
+            let hint = if !cfg.settings.treat_root_as_repo && root_gitignored(Path::new(&root)) {
+                "project root is gitignored; set treat_root_as_repo = true to index it"
+            } else {
+                "check text_extensions/exclude_patterns"
+            };
+            Check::new(
+                "dry_run",
+                CheckStatus::Warn,
+                format!(
+                    "{}: no text files collected ({} candidates); {}",
+                    root, files_seen, hint
+                ),
+            )
+        }
+        Ok(blobs) => {
+            let bytes: usize = blobs.iter().map(|b| b.content.len()).sum();
+            Check::new(
+                "dry_run",
+                CheckStatus::Pass,
+                format!(
+                    "{}: {} files, {} blobs, {} bytes",
+                    root,
+                    files_seen,
+                    blobs.len(),
+                    bytes
+                ),
+            )
+        }
+        Err(e) => Check::new("dry_run", CheckStatus::Fail, format!("{}: {}", root, e)),
+    }
+}
@@ -0,0 +1,69 @@
+//! Content filters: deterministic transforms applied to file content before hashing/upload.
+
+use regex::Regex;
+use std::sync::LazyLock;
+
+/// Values of assignments like `API_KEY=...`, `db_password: ...`, `export GITHUB_TOKEN="..."`.
+static ENV_SECRET: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?mi)^([ \t]*(?:export[ \t]+)?[a-z0-9_.-]*(?:key|secret|token|password|passwd)[a-z0-9_.-]*[ \t]*[=:][ \t]*)\S[^\r\n]*",
+    )
+    .expect("valid regex")
+});
+
+/// C-style `/* ... */` block comments (non-nested).
+static BLOCK_COMMENT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)/\*.*?\*/").expect("valid regex"));
+
+pub const REDACTED: &str = "***";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentFilter {
+    /// Mask values assigned to secret-looking keys
+    RedactEnvValues,
+    /// Remove `/* ... */` block comments
+    StripBlockComments,
+}
+
+impl ContentFilter {
+    pub const ALL: &[ContentFilter] = &[Self::RedactEnvValues, Self::StripBlockComments];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::RedactEnvValues => "redact-env-values",
+            Self::StripBlockComments => "strip-block-comments",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|f| f.name() == name.trim())
+    }
+
+    pub fn apply(self, content: &str) -> String {
+        match self {
+            Self::RedactEnvValues => ENV_SECRET
+                .replace_all(content, format!("${{1}}{}", REDACTED))
+                .into_owned(),
+            Self::StripBlockComments => BLOCK_COMMENT.replace_all(content, "").into_owned(),
+        }
+    }
+}
+
+/// Parse filter names from settings; unknown names are logged and skipped.
+pub fn parse_filters(names: &[String]) -> Vec<ContentFilter> {
+    names
+        .iter()
+        .filter_map(|n| {
+            let f = ContentFilter::parse(n);
+            if f.is_none() {
+                tracing::warn!(filter = %n, "unknown content filter; ignoring");
+            }
+            f
+        })
+        .collect()
+}
+
+/// Apply filters in order.
+pub fn apply_all(filters: &[ContentFilter], content: String) -> String {
+    filters.iter().fold(content, |acc, f| f.apply(&acc))
+}
@@ -1,13 +1,23 @@
-use crate::{server::AugServer, service, tasks::TaskManager};
+use crate::{
+    config::Config,
+    rate_limit::RateLimiter,
+    server::AugServer,
+    service,
+    tasks::{TaskManager, TaskProgress},
+};
 use axum::{
     Json, Router,
-    extract::State,
+    extract::{ConnectInfo, MatchedPath, Request, State},
+    http::{HeaderMap, Method, StatusCode, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
     routing::{get, post},
 };
 use rmcp::transport::streamable_http_server::{
     StreamableHttpService, session::local::LocalSessionManager,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 #[derive(Clone)]
 pub struct AppState {
@@ -15,6 +25,175 @@ pub struct AppState {
     pub tasks: TaskManager,
 }
 
+/// Body of `GET`/`POST /api/projects/{key}/meta`.
+#[derive(Serialize)]
+struct ProjectMetaResp {
+    status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<String>,
+    meta: BTreeMap<String, String>,
+}
+
+/// Project selector shared by request bodies (flattened into them).
+#[derive(Debug, Deserialize)]
+struct TargetReq {
+    project_root_path: Option<String>,
+    alias: Option<String>,
+    /// Namespace for a bare `alias`; defaults to `alias_namespace`
+    namespace: Option<String>,
+    /// Explicit cache key used verbatim instead of the path-derived key
+    project_key: Option<String>,
+}
+
+impl TargetReq {
+    /// `(alias, path, project_key)` as the `service::resolve_target*` functions take them, with
+    /// the alias qualified by `namespace`.
+    fn parts(&self, cfg: &Config) -> (Option<String>, Option<String>, Option<&str>) {
+        let alias = self
+            .alias
+            .as_deref()
+            .map(|a| cfg.qualify_alias(a, self.namespace.as_deref()));
+        (
+            alias,
+            self.project_root_path.clone(),
+            self.project_key.as_deref(),
+        )
+    }
+
+    /// 解析为 (project_key, path)，见 service::resolve_target_keyed。
+    fn resolve(&self, cfg: &Config) -> anyhow::Result<(String, String)> {
+        let (alias, path, project_key) = self.parts(cfg);
+        service::resolve_target_keyed(cfg, alias, path, project_key)
+    }
+}
+
+/// Entries returned by `GET /api/projects/{key}/history` without `limit`.
+const DEFAULT_HISTORY_LIMIT: usize = 50;
+
+/// Route templates (as registered, e.g. `/api/projects/{key}/...`) whose `POST` uploads,
+/// persists or wipes state; answered with 403 when `read_only` is set.
+pub const MUTATING_ROUTES: &[&str] = &[
+    "/api/index",
+    "/api/index/bulk",
+    "/api/index/stop",
+    "/api/reset",
+    "/api/projects/compact",
+    "/api/projects/merge",
+    "/api/projects/{key}/meta",
+    "/api/projects/{key}/verify",
+];
+
+/// 只读模式下拒绝会触发上传或修改本地状态的端点（按匹配到的路由模板判断）。
+async fn reject_mutations_if_read_only(
+    State(app): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let mutating = req.method() == Method::POST
+        && req
+            .extensions()
+            .get::<MatchedPath>()
+            .is_some_and(|p| MUTATING_ROUTES.contains(&p.as_str()));
+    if mutating && app.server.get_cfg().settings.read_only {
+        let body = serde_json::json!({"status": "error", "result": "server is read-only"});
+        return (StatusCode::FORBIDDEN, Json(body)).into_response();
+    }
+    next.run(req).await
+}
+
+/// Weak ETag (`W/"<hex>"`) over a serialized response body.
+pub fn weak_etag(body: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    let digest = format!("{:x}", Sha256::digest(body));
+    format!("W/\"{}\"", &digest[..16])
+}
+
+/// JSON response carrying a weak ETag of its body; 304 without a body when `If-None-Match`
+/// lists that tag (weak comparison) or is `*`.
+fn json_with_etag<T: Serialize>(headers: &HeaderMap, value: &T) -> Response {
+    let body = match serde_json::to_vec(value) {
+        Ok(b) => b,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let etag = weak_etag(&body);
+    let opaque = |t: &str| t.trim().trim_start_matches("W/").to_string();
+    let matches = headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|t| t.trim() == "*" || opaque(t) == opaque(&etag));
+    if matches {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+    }
+    (
+        [
+            (header::ETAG, etag),
+            (header::CONTENT_TYPE, "application/json".to_string()),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+/// Routes never counted against `rate_limit_per_min`.
+pub const RATE_LIMIT_EXEMPT_ROUTES: &[&str] = &["/healthz"];
+
+/// 按客户端 IP 限流；超限返回 429 并附带 Retry-After（秒）。
+async fn rate_limit(
+    State((app, limiter)): State<(AppState, RateLimiter)>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let per_min = app.server.get_cfg().settings.rate_limit_per_min;
+    if per_min == 0 || RATE_LIMIT_EXEMPT_ROUTES.contains(&req.uri().path()) {
+        return next.run(req).await;
+    }
+    // 未通过 into_make_service_with_connect_info 提供地址时（如测试）共用一个桶
+    let client = req
+        .extensions()
+        .get::<ConnectInfo<std::net::SocketAddr>>()
+        .map(|ci| ci.0.ip().to_string())
+        .unwrap_or_else(|| "unknown".into());
+    if let Err(wait) = limiter.check(&client, per_min) {
+        let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+        let body = serde_json::json!({"status": "error", "result": "rate limit exceeded"});
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, retry_after.to_string())],
+            Json(body),
+        )
+            .into_response();
+    }
+    next.run(req).await
+}
+
+/// Await `fut`, giving up after `timeout_secs` when set (None or 0 = no deadline beyond the
+/// backend client's own timeouts). None when the deadline passed; `fut` is dropped.
+async fn within<T>(
+    timeout_secs: Option<u64>,
+    fut: impl std::future::Future<Output = T>,
+) -> Option<T> {
+    match timeout_secs.filter(|s| *s > 0) {
+        Some(secs) => tokio::time::timeout(std::time::Duration::from_secs(secs), fut)
+            .await
+            .ok(),
+        None => Some(fut.await),
+    }
+}
+
+fn timeout_message(what: &str, timeout_secs: Option<u64>) -> String {
+    format!(
+        "{what} did not finish within timeout_secs={}; the backend may be slow, retry later or raise the timeout",
+        timeout_secs.unwrap_or_default()
+    )
+}
+
+/// 504 Gateway Timeout carrying `body`.
+fn timed_out(body: impl IntoResponse) -> Response {
+    (StatusCode::GATEWAY_TIMEOUT, body).into_response()
+}
+
 pub fn build_router(app_state: AppState) -> Router {
     // MCP service under /mcp
     let srv_factory = app_state.server.clone();
@@ -33,35 +212,168 @@ pub fn build_router(app_state: AppState) -> Router {
 
     #[derive(Debug, Deserialize)]
     struct SearchReq {
-        project_root_path: Option<String>,
-        alias: Option<String>,
+        #[serde(flatten)]
+        target: TargetReq,
         query: String,
         skip_index_if_indexed: Option<bool>,
+        quick: Option<bool>,
+        normalize_query: Option<bool>,
+        prepend_context: Option<String>,
+        path_prefix: Option<String>,
+        languages: Option<Vec<String>>,
+        /// Answer 504 if indexing plus retrieval take longer than this
+        timeout_secs: Option<u64>,
     }
     #[derive(Debug, Serialize)]
     struct SearchResp {
         status: String,
         result: String,
+        /// True when retrieval succeeded but found no relevant context
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        empty: bool,
     }
 
     #[derive(Deserialize)]
     struct IndexReq {
-        project_root_path: Option<String>,
-        alias: Option<String>,
+        #[serde(flatten)]
+        target: TargetReq,
         force_full: Option<bool>,
         #[serde(rename = "async")]
         r#async: Option<bool>,
+        /// Async only: coalesce bursts, run after this many ms without a new request
+        debounce_ms: Option<u64>,
+        /// Sync only: answer 504 if indexing takes longer than this
+        timeout_secs: Option<u64>,
     }
     #[derive(Serialize)]
     struct IndexResp {
         status: String,
         result: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        scheduled_at_ms: Option<u64>,
+        /// Async only: id for `GET /api/tasks/{task_id}`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        task_id: Option<String>,
+        /// Rejected upload batch: chunk, blob paths and backend message
+        #[serde(skip_serializing_if = "Option::is_none")]
+        upload_error: Option<crate::backend::UploadError>,
+    }
+    impl IndexResp {
+        fn new(status: &str, result: String) -> Self {
+            IndexResp {
+                status: status.into(),
+                result,
+                scheduled_at_ms: None,
+                task_id: None,
+                upload_error: None,
+            }
+        }
+        fn error(result: String) -> Self {
+            Self::new("error", result)
+        }
+        fn accepted(result: String, task_id: Option<String>) -> Self {
+            IndexResp {
+                task_id,
+                ..Self::new("accepted", result)
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct BulkIndexReq {
+        projects: Vec<BulkEntry>,
+        #[serde(rename = "async")]
+        r#async: Option<bool>,
+    }
+    #[derive(Deserialize)]
+    struct BulkEntry {
+        #[serde(flatten)]
+        target: TargetReq,
+        force_full: Option<bool>,
+    }
+    #[derive(Serialize)]
+    struct BulkIndexResp {
+        status: String,
+        /// One entry per requested project, in request order
+        results: Vec<BulkItem>,
+    }
+    #[derive(Serialize)]
+    struct BulkItem {
+        status: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        project: Option<String>,
+        result: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        task_id: Option<String>,
+    }
+    impl BulkItem {
+        fn error(project: Option<String>, result: String) -> Self {
+            BulkItem {
+                status: "error".into(),
+                project,
+                result,
+                task_id: None,
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct DiffReq {
+        #[serde(flatten)]
+        target: TargetReq,
+    }
+    #[derive(Serialize)]
+    struct DiffResp {
+        status: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        result: Option<String>,
+        #[serde(flatten)]
+        diff: Option<service::IndexDiff>,
+    }
+
+    #[derive(Deserialize)]
+    struct MergeReq {
+        src_key: String,
+        dst_key: String,
+        /// Drop the source project's local state after merging
+        #[serde(default)]
+        remove_source: bool,
+    }
+    #[derive(Serialize)]
+    struct MergeResp {
+        status: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        result: Option<String>,
+        #[serde(flatten)]
+        report: Option<service::MergeReport>,
+    }
+
+    #[derive(Serialize)]
+    struct CompactResp {
+        status: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        result: Option<String>,
+        #[serde(flatten)]
+        report: Option<service::CompactReport>,
+    }
+
+    #[derive(Deserialize, Default)]
+    struct ResetReq {
+        keep_aliases: Option<bool>,
+    }
+    #[derive(Serialize)]
+    struct ResetResp {
+        status: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        result: Option<String>,
+        cleared: Vec<String>,
+        aborted_tasks: usize,
     }
 
     #[derive(Deserialize)]
     struct StopReq {
-        project_root_path: Option<String>,
-        alias: Option<String>,
+        #[serde(flatten)]
+        target: TargetReq,
     }
     #[derive(Serialize)]
     struct StopResp {
@@ -80,47 +392,93 @@ pub fn build_router(app_state: AppState) -> Router {
                 })
             }),
         )
+        .route(
+            "/api/paths",
+            get(|State(app): State<AppState>| async move {
+                Json(app.server.get_cfg().resolved_paths())
+            }),
+        )
         .route(
             "/api/search",
             post(
                 |State(app): State<AppState>, Json(req): Json<SearchReq>| async move {
                     let cfg = app.server.get_cfg();
-                    let (project_key, path) = match service::resolve_target(
-                        &cfg,
-                        req.alias.clone(),
-                        req.project_root_path.clone(),
-                    ) {
-                        Ok(v) => v,
-                        Err(e) => {
-                            return Json(SearchResp {
-                                status: "error".into(),
-                                result: e.to_string(),
-                            });
-                        }
-                    };
+                    let (alias, path, project_key) = req.target.parts(&cfg);
+                    let (project_key, path, cfg) =
+                        match service::resolve_search_target(&cfg, alias, path, project_key) {
+                            Ok(v) => v,
+                            Err(e) => {
+                                return Json(SearchResp {
+                                    status: "error".into(),
+                                    result: e.to_string(),
+                                    empty: false,
+                                })
+                                .into_response();
+                            }
+                        };
                     if app.tasks.is_running(&project_key) {
                         return Json(SearchResp {
                             status: "accepted".into(),
                             result: "indexing in progress; please retry later".into(),
-                        });
+                            empty: false,
+                        })
+                        .into_response();
                     }
                     let skip = req.skip_index_if_indexed.unwrap_or(true);
-                    let result = match service::ensure_index_then_retrieve(
-                        &cfg,
-                        &project_key,
-                        &path,
-                        &req.query,
-                        skip,
-                    )
-                    .await
-                    {
-                        Ok(s) => s,
-                        Err(e) => format!("Error: {}", e),
+                    let query = crate::backend::QueryPreprocess {
+                        normalize: req.normalize_query.unwrap_or(false),
+                        prepend_context: req.prepend_context.clone(),
+                    }
+                    .apply(&req.query);
+                    let search = async {
+                        let names = if req.quick.unwrap_or(false) {
+                            service::quick_index(&cfg, &project_key, &path).await
+                        } else {
+                            service::ensure_indexed(&cfg, &project_key, &path, skip).await
+                        }
+                        .and_then(|names| match &req.path_prefix {
+                            Some(prefix) => {
+                                service::filter_blobs_by_prefix(&cfg, &project_key, &names, prefix)
+                            }
+                            None => Ok(names),
+                        })
+                        .and_then(|names| match &req.languages {
+                            Some(langs) => service::filter_blobs_by_languages(
+                                &cfg,
+                                &project_key,
+                                &names,
+                                langs,
+                            ),
+                            None => Ok(names),
+                        })?;
+                        let _permit = app.server.retrieval_limiter().acquire().await?;
+                        service::retrieve(&cfg, &project_key, &names, &query).await
+                    };
+                    let Some(result) = within(req.timeout_secs, search).await else {
+                        return timed_out(Json(SearchResp {
+                            status: "timeout".into(),
+                            result: timeout_message("search", req.timeout_secs),
+                            empty: false,
+                        }));
+                    };
+                    let (empty, result) = match result {
+                        Ok(r) => (r.is_empty(), r.into_text(&cfg)),
+                        Err(e) if e.is::<service::RetrievalBusy>() => {
+                            return Json(SearchResp {
+                                status: "busy".into(),
+                                result: e.to_string(),
+                                empty: false,
+                            })
+                            .into_response();
+                        }
+                        Err(e) => (false, format!("Error: {}", e)),
                     };
                     Json(SearchResp {
                         status: "success".into(),
                         result,
+                        empty,
                     })
+                    .into_response()
                 },
             ),
         )
@@ -131,140 +489,291 @@ pub fn build_router(app_state: AppState) -> Router {
                     let req: IndexReq = match serde_json::from_value(req) {
                         Ok(v) => v,
                         Err(e) => {
-                            return Json(IndexResp {
-                                status: "error".into(),
-                                result: e.to_string(),
-                            });
+                            return Json(IndexResp::error(e.to_string())).into_response();
                         }
                     };
                     let cfg = app.server.get_cfg();
-                    use crate::indexer::Aliases;
-                    let mut aliases = Aliases::load(&cfg.aliases_file()).unwrap_or_default();
-                    let path = match (req.alias.clone(), req.project_root_path.clone()) {
-                        (Some(a), Some(p)) => {
-                            let norm = match crate::config::normalize_path(&p) {
-                                Ok(s) => s,
-                                Err(e) => {
-                                    return Json(IndexResp {
-                                        status: "error".into(),
-                                        result: e.to_string(),
-                                    });
-                                }
-                            };
-                            aliases.set(a, norm);
-                            let _ = aliases.save(&cfg.aliases_file());
-                            p
-                        }
-                        (Some(a), None) => match aliases.resolve(&a) {
-                            Some(p) => p.clone(),
-                            None => {
-                                return Json(IndexResp {
-                                    status: "error".into(),
-                                    result: "alias not found and no path provided".into(),
-                                });
-                            }
-                        },
-                        (None, Some(p)) => p,
-                        (None, None) => {
-                            return Json(IndexResp {
-                                status: "error".into(),
-                                result: "provide project_root_path or alias".into(),
-                            });
-                        }
-                    };
-                    let project_key = match crate::config::normalize_path(&path) {
+                    let (project_key, path) = match req.target.resolve(&cfg) {
                         Ok(x) => x,
                         Err(e) => {
-                            return Json(IndexResp {
-                                status: "error".into(),
-                                result: e.to_string(),
-                            });
+                            return Json(IndexResp::error(e.to_string())).into_response();
                         }
                     };
+                    let cfg = service::project_config(&cfg, &path);
 
                     let run_async = req.r#async.unwrap_or(false);
+                    let force_full = req.force_full.unwrap_or(false);
+                    if run_async && let Some(ms) = req.debounce_ms.filter(|ms| *ms > 0) {
+                        let app_bg = app.clone();
+                        let key_bg = project_key.clone();
+                        let path_bg = path.clone();
+                        let scheduled_at = app.tasks.debounce(
+                            &project_key,
+                            std::time::Duration::from_millis(ms),
+                            move || {
+                                if service::start_index_task(
+                                    &app_bg.tasks,
+                                    cfg,
+                                    key_bg,
+                                    path_bg,
+                                    force_full,
+                                )
+                                .is_none()
+                                {
+                                    tracing::info!("debounced index skipped: already running");
+                                }
+                            },
+                        );
+                        return Json(IndexResp {
+                            scheduled_at_ms: Some(scheduled_at),
+                            ..IndexResp::accepted(format!("indexing debounced for {}", &path), None)
+                        })
+                        .into_response();
+                    }
                     if run_async {
-                        if app.tasks.is_running(&project_key) {
-                            return Json(IndexResp {
-                                status: "accepted".into(),
-                                result: format!("indexing already in progress for {}", &path),
-                            });
+                        let Some(task_id) = service::start_index_task(
+                            &app.tasks,
+                            cfg,
+                            project_key.clone(),
+                            path.clone(),
+                            force_full,
+                        ) else {
+                            return Json(IndexResp::accepted(
+                                format!("indexing already in progress for {}", &path),
+                                app.tasks.task_id(&project_key),
+                            ))
+                            .into_response();
+                        };
+                        return Json(IndexResp::accepted(
+                            format!("async indexing started for {}", &path),
+                            Some(task_id),
+                        ))
+                        .into_response();
+                    }
+
+                    // 同步索引同样受全局 max_concurrent_indexes 限制（排队时间计入 timeout）
+                    let index = async {
+                        let _permit = app.tasks.acquire_index_permit(&project_key).await;
+                        service::index_and_persist(&cfg, &project_key, &path, force_full).await
+                    };
+                    let Some(indexed) = within(req.timeout_secs, index).await else {
+                        return timed_out(Json(IndexResp::new(
+                            "timeout",
+                            timeout_message("index", req.timeout_secs),
+                        )));
+                    };
+                    match indexed {
+                        Ok(report) => {
+                            Json(IndexResp::new("success", report.summary())).into_response()
                         }
-                        if !app.tasks.begin(&project_key) {
-                            return Json(IndexResp {
+                        Err(e) => Json(IndexResp {
+                            upload_error: e.downcast_ref::<crate::backend::UploadError>().cloned(),
+                            ..IndexResp::error(e.to_string())
+                        })
+                        .into_response(),
+                    }
+                },
+            ),
+        )
+        .route(
+            "/api/index/bulk",
+            post(
+                |State(app): State<AppState>, Json(req): Json<BulkIndexReq>| async move {
+                    let cfg = app.server.get_cfg();
+                    let run_async = req.r#async.unwrap_or(false);
+                    let mut results: Vec<Option<BulkItem>> = Vec::new();
+                    let mut seen = std::collections::HashSet::new();
+                    let mut set = tokio::task::JoinSet::new();
+                    for (i, entry) in req.projects.into_iter().enumerate() {
+                        results.push(None);
+                        let (alias, path, project_key) = entry.target.parts(&cfg);
+                        let (key, path, cfg) = match service::resolve_target_with_config(
+                            &cfg,
+                            alias,
+                            path,
+                            project_key,
+                        ) {
+                            Ok(v) => v,
+                            Err(e) => {
+                                results[i] = Some(BulkItem::error(
+                                    entry.target.project_root_path,
+                                    e.to_string(),
+                                ));
+                                continue;
+                            }
+                        };
+                        // 同一请求内重复的项目只索引一次
+                        if !seen.insert(key.clone()) {
+                            results[i] = Some(BulkItem::error(
+                                Some(path),
+                                "duplicate project in request".into(),
+                            ));
+                            continue;
+                        }
+                        let force_full = entry.force_full.unwrap_or(false);
+                        if run_async || app.tasks.is_running(&key) {
+                            let started = service::start_index_task(
+                                &app.tasks,
+                                cfg.clone(),
+                                key.clone(),
+                                path.clone(),
+                                force_full,
+                            );
+                            let result = match started {
+                                Some(_) => format!("async indexing started for {}", &path),
+                                None => format!("indexing already in progress for {}", &path),
+                            };
+                            results[i] = Some(BulkItem {
                                 status: "accepted".into(),
-                                result: format!("indexing already in progress for {}", &path),
+                                result,
+                                task_id: started.or_else(|| app.tasks.task_id(&key)),
+                                project: Some(path),
                             });
+                            continue;
                         }
-                        let cfg_bg = cfg.clone();
-                        let path_bg = path.clone();
-                        let key_bg = project_key.clone();
-                        let tasks_bg = app.tasks.clone();
-                        let force_full = req.force_full.unwrap_or(false);
-                        let handle = tokio::spawn(async move {
-                            tasks_bg.set_phase(&key_bg, "collecting");
-                            let mut totals_set = false;
-                            match service::index_and_persist_with_progress(
-                                &cfg_bg,
-                                &key_bg,
-                                &path_bg,
-                                force_full,
-                                |p| {
-                                    if !totals_set {
-                                        tasks_bg.set_upload_totals(
-                                            &key_bg,
-                                            p.total_items,
-                                            p.chunks_total,
-                                            p.total_items,
-                                        );
-                                        totals_set = true;
-                                    }
-                                    tasks_bg.on_chunk(
-                                        &key_bg,
-                                        p.uploaded_items,
-                                        p.chunk_index,
-                                        p.chunk_bytes,
-                                    );
+                        // 同步模式：各项目并发执行，但受全局 max_concurrent_indexes 限制
+                        let (cfg, tasks) = (cfg.clone(), app.tasks.clone());
+                        set.spawn(async move {
+                            let _permit = tasks.acquire_index_permit(&key).await;
+                            let res =
+                                service::index_and_persist(&cfg, &key, &path, force_full).await;
+                            let item = match res {
+                                Ok(report) => BulkItem {
+                                    status: "success".into(),
+                                    project: Some(path),
+                                    result: report.summary(),
+                                    task_id: None,
                                 },
-                            )
-                            .await
-                            {
-                                Ok((_total, _newn, _existing, _all)) => {
-                                    tasks_bg.finish(&key_bg);
-                                }
-                                Err(e) => {
-                                    tasks_bg.fail(&key_bg, e.to_string());
-                                }
-                            }
-                        });
-                        app.tasks.set_handle(&project_key, handle);
-                        return Json(IndexResp {
-                            status: "accepted".into(),
-                            result: format!("async indexing started for {}", &path),
+                                Err(e) => BulkItem::error(Some(path), e.to_string()),
+                            };
+                            (i, item)
                         });
                     }
-
-                    match service::index_and_persist(
+                    while let Some(joined) = set.join_next().await {
+                        match joined {
+                            Ok((i, item)) => results[i] = Some(item),
+                            Err(e) => tracing::warn!(error = %e, "bulk index task failed"),
+                        }
+                    }
+                    let results = results
+                        .into_iter()
+                        .map(|r| {
+                            r.unwrap_or_else(|| BulkItem::error(None, "index task failed".into()))
+                        })
+                        .collect();
+                    Json(BulkIndexResp {
+                        status: "success".into(),
+                        results,
+                    })
+                },
+            ),
+        )
+        .route(
+            "/api/index/diff",
+            post(
+                |State(app): State<AppState>, Json(req): Json<DiffReq>| async move {
+                    let cfg = app.server.get_cfg();
+                    let res = req
+                        .target
+                        .resolve(&cfg)
+                        .and_then(|(key, path)| service::diff_index(&cfg, &key, &path));
+                    match res {
+                        Ok(diff) => Json(DiffResp {
+                            status: "success".into(),
+                            result: None,
+                            diff: Some(diff),
+                        }),
+                        Err(e) => Json(DiffResp {
+                            status: "error".into(),
+                            result: Some(e.to_string()),
+                            diff: None,
+                        }),
+                    }
+                },
+            ),
+        )
+        .route(
+            "/api/projects/merge",
+            post(
+                |State(app): State<AppState>, Json(req): Json<MergeReq>| async move {
+                    let cfg = app.server.get_cfg();
+                    // 合并期间任一项目正在索引会覆盖结果
+                    if app.tasks.is_running(&req.src_key) || app.tasks.is_running(&req.dst_key) {
+                        return (
+                            StatusCode::CONFLICT,
+                            Json(MergeResp {
+                                status: "error".into(),
+                                result: Some("indexing in progress; please retry later".into()),
+                                report: None,
+                            }),
+                        );
+                    }
+                    match service::merge_projects(
                         &cfg,
-                        &project_key,
-                        &path,
-                        req.force_full.unwrap_or(false),
-                    )
-                    .await
-                    {
-                        Ok((total, newn, existing, _)) => {
-                            let msg = format!(
-                                "Index complete: total_blobs={}, new_blobs={}, existing_blobs={}",
-                                total, newn, existing
-                            );
-                            Json(IndexResp {
+                        &req.src_key,
+                        &req.dst_key,
+                        req.remove_source,
+                    ) {
+                        Ok(report) => (
+                            StatusCode::OK,
+                            Json(MergeResp {
                                 status: "success".into(),
-                                result: msg,
-                            })
-                        }
-                        Err(e) => Json(IndexResp {
+                                result: None,
+                                report: Some(report),
+                            }),
+                        ),
+                        Err(e) => (
+                            StatusCode::BAD_REQUEST,
+                            Json(MergeResp {
+                                status: "error".into(),
+                                result: Some(e.to_string()),
+                                report: None,
+                            }),
+                        ),
+                    }
+                },
+            ),
+        )
+        .route(
+            "/api/projects/compact",
+            post(|State(app): State<AppState>| async move {
+                let cfg = app.server.get_cfg();
+                match service::compact_projects(&cfg) {
+                    Ok(report) => Json(CompactResp {
+                        status: "success".into(),
+                        result: None,
+                        report: Some(report),
+                    }),
+                    Err(e) => Json(CompactResp {
+                        status: "error".into(),
+                        result: Some(e.to_string()),
+                        report: None,
+                    }),
+                }
+            }),
+        )
+        .route(
+            "/api/reset",
+            post(
+                |State(app): State<AppState>, req: Option<Json<ResetReq>>| async move {
+                    let req = req.map(|Json(r)| r).unwrap_or_default();
+                    let aborted_tasks = app.tasks.abort_all();
+                    let cfg = app.server.get_cfg();
+                    match service::reset_local_state(&cfg, req.keep_aliases.unwrap_or(false)) {
+                        Ok(cleared) => Json(ResetResp {
+                            status: "success".into(),
+                            result: None,
+                            cleared: cleared
+                                .iter()
+                                .map(|p| p.to_string_lossy().to_string())
+                                .collect(),
+                            aborted_tasks,
+                        }),
+                        Err(e) => Json(ResetResp {
                             status: "error".into(),
-                            result: e.to_string(),
+                            result: Some(e.to_string()),
+                            cleared: vec![],
+                            aborted_tasks,
                         }),
                     }
                 },
@@ -274,6 +783,7 @@ pub fn build_router(app_state: AppState) -> Router {
             "/api/tasks",
             get(
                 |State(app): State<AppState>,
+                 headers: HeaderMap,
                  axum::extract::Query(params): axum::extract::Query<
                     std::collections::HashMap<String, String>,
                 >| async move {
@@ -281,43 +791,330 @@ pub fn build_router(app_state: AppState) -> Router {
                     struct TaskResp {
                         status: String,
                         running: bool,
-                        progress: Option<crate::tasks::TaskProgress>,
+                        progress: Option<TaskProgress>,
                         eta_secs: Option<u64>,
                     }
                     let cfg = app.server.get_cfg();
-                    let alias = params.get("alias").cloned();
+                    let alias = params
+                        .get("alias")
+                        .map(|a| cfg.qualify_alias(a, params.get("namespace").map(String::as_str)));
                     let path = params.get("project_root_path").cloned();
-                    let (key, _p) = match service::resolve_target(&cfg, alias, path) {
-                        Ok(v) => v,
-                        Err(_) => {
-                            return axum::Json(TaskResp {
-                                status: "error".into(),
-                                running: false,
-                                progress: None,
-                                eta_secs: None,
-                            });
-                        }
-                    };
+                    let project_key = params.get("project_key").map(String::as_str);
+                    let (key, _p) =
+                        match service::resolve_target_keyed(&cfg, alias, path, project_key) {
+                            Ok(v) => v,
+                            Err(_) => {
+                                return axum::Json(TaskResp {
+                                    status: "error".into(),
+                                    running: false,
+                                    progress: None,
+                                    eta_secs: None,
+                                })
+                                .into_response();
+                            }
+                        };
                     let running = app.tasks.is_running(&key);
                     let progress = app.tasks.get(&key);
-                    let mut eta = None;
-                    if let Some(p) = &progress {
-                        if p.chunk_index > 0 && p.chunks_total > 0 && p.updated_at >= p.started_at {
-                            let elapsed = p.updated_at.saturating_sub(p.started_at);
-                            let remaining_chunks = p.chunks_total.saturating_sub(p.chunk_index);
-                            if elapsed > 0 && remaining_chunks > 0 {
-                                let avg = elapsed / (p.chunk_index as u64).max(1);
-                                eta = Some(avg.saturating_mul(remaining_chunks as u64));
-                            }
+                    let eta = progress.as_ref().and_then(TaskProgress::eta_secs);
+                    // 进度（含 updated_at）不变时响应体不变，轮询方得到 304
+                    json_with_etag(
+                        &headers,
+                        &TaskResp {
+                            status: "success".into(),
+                            running,
+                            progress,
+                            eta_secs: eta,
+                        },
+                    )
+                },
+            ),
+        )
+        .route(
+            "/api/projects",
+            get(
+                |State(app): State<AppState>, headers: HeaderMap| async move {
+                    #[derive(Serialize)]
+                    struct ProjectEntry {
+                        project_key: String,
+                        path: String,
+                        blobs: usize,
+                        #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+                        meta: BTreeMap<String, String>,
+                    }
+                    #[derive(Serialize)]
+                    struct ProjectsResp {
+                        status: String,
+                        projects: Vec<ProjectEntry>,
+                    }
+                    let cfg = app.server.get_cfg();
+                    let index = service::load_projects(&cfg);
+                    let mut meta = service::all_project_meta(&cfg);
+                    let projects = service::known_projects(&cfg)
+                        .into_iter()
+                        .map(|(project_key, path)| ProjectEntry {
+                            blobs: index.0.get(&project_key).map_or(0, Vec::len),
+                            meta: meta.remove(&project_key).unwrap_or_default(),
+                            path: cfg.display_path(&path),
+                            project_key,
+                        })
+                        .collect();
+                    json_with_etag(
+                        &headers,
+                        &ProjectsResp {
+                            status: "success".into(),
+                            projects,
+                        },
+                    )
+                },
+            ),
+        )
+        .route(
+            "/api/projects/{key}/history",
+            get(
+                |State(app): State<AppState>,
+                 axum::extract::Path(key): axum::extract::Path<String>,
+                 axum::extract::Query(params): axum::extract::Query<
+                    std::collections::HashMap<String, String>,
+                >| async move {
+                    #[derive(Serialize)]
+                    struct HistoryResp {
+                        status: String,
+                        #[serde(skip_serializing_if = "Option::is_none")]
+                        result: Option<String>,
+                        entries: Vec<service::HistoryEntry>,
+                    }
+                    let cfg = app.server.get_cfg();
+                    let limit = match params.get("limit").map(|l| l.parse::<usize>()) {
+                        None => DEFAULT_HISTORY_LIMIT,
+                        Some(Ok(n)) => n,
+                        Some(Err(_)) => {
+                            return (
+                                StatusCode::BAD_REQUEST,
+                                Json(HistoryResp {
+                                    status: "error".into(),
+                                    result: Some("limit must be a non-negative integer".into()),
+                                    entries: vec![],
+                                }),
+                            );
                         }
+                    };
+                    match service::read_history(&cfg, &key, limit) {
+                        Ok(entries) => (
+                            StatusCode::OK,
+                            Json(HistoryResp {
+                                status: "success".into(),
+                                result: None,
+                                entries,
+                            }),
+                        ),
+                        Err(e) => (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            Json(HistoryResp {
+                                status: "error".into(),
+                                result: Some(e.to_string()),
+                                entries: vec![],
+                            }),
+                        ),
                     }
-                    axum::Json(TaskResp {
+                },
+            ),
+        )
+        .route(
+            "/api/projects/{key}/meta",
+            get(
+                |State(app): State<AppState>,
+                 axum::extract::Path(key): axum::extract::Path<String>| async move {
+                    let cfg = app.server.get_cfg();
+                    Json(ProjectMetaResp {
                         status: "success".into(),
-                        running,
-                        progress,
-                        eta_secs: eta,
+                        result: None,
+                        meta: service::get_project_meta(&cfg, &key),
                     })
                 },
+            )
+            .post(
+                |State(app): State<AppState>,
+                 axum::extract::Path(key): axum::extract::Path<String>,
+                 Json(req): Json<serde_json::Value>| async move {
+                    let cfg = app.server.get_cfg();
+                    let res = service::parse_project_meta(&req).and_then(|meta| {
+                        service::set_project_meta(&cfg, &key, meta.clone()).map(|_| meta)
+                    });
+                    match res {
+                        Ok(meta) => (
+                            StatusCode::OK,
+                            Json(ProjectMetaResp {
+                                status: "success".into(),
+                                result: None,
+                                meta,
+                            }),
+                        ),
+                        Err(e) => (
+                            StatusCode::BAD_REQUEST,
+                            Json(ProjectMetaResp {
+                                status: "error".into(),
+                                result: Some(e.to_string()),
+                                meta: BTreeMap::new(),
+                            }),
+                        ),
+                    }
+                },
+            ),
+        )
+        .route(
+            "/api/projects/{key}/verify",
+            post(
+                |State(app): State<AppState>,
+                 axum::extract::Path(key): axum::extract::Path<String>| async move {
+                    #[derive(Serialize)]
+                    struct VerifyResp {
+                        status: String,
+                        #[serde(skip_serializing_if = "Option::is_none")]
+                        result: Option<String>,
+                        #[serde(flatten)]
+                        report: Option<service::VerifyReport>,
+                    }
+                    let cfg = app.server.get_cfg();
+                    let (status, result, report) = match service::verify_blobs(&cfg, &key).await {
+                        Ok(Some(report)) => ("success", None, Some(report)),
+                        Ok(None) => (
+                            "not_supported",
+                            Some("not supported: set verify_endpoint".to_string()),
+                            None,
+                        ),
+                        Err(e) => ("error", Some(e.to_string()), None),
+                    };
+                    Json(VerifyResp {
+                        status: status.into(),
+                        result,
+                        report,
+                    })
+                },
+            ),
+        )
+        .route(
+            "/api/estimate",
+            get(
+                |State(app): State<AppState>,
+                 axum::extract::Query(params): axum::extract::Query<
+                    std::collections::HashMap<String, String>,
+                >| async move {
+                    #[derive(Serialize)]
+                    struct EstimateResp {
+                        status: String,
+                        #[serde(skip_serializing_if = "Option::is_none")]
+                        result: Option<String>,
+                        #[serde(flatten)]
+                        estimate: Option<crate::indexer::IndexEstimate>,
+                    }
+                    let cfg = app.server.get_cfg();
+                    let alias = params
+                        .get("alias")
+                        .map(|a| cfg.qualify_alias(a, params.get("namespace").map(String::as_str)));
+                    let res = match (alias, params.get("path").cloned()) {
+                        (None, None) => Err(anyhow::anyhow!("provide path or alias")),
+                        (alias, path) => service::resolve_target(&cfg, alias, path)
+                            .and_then(|(_key, root)| service::estimate_index(&cfg, &root)),
+                    };
+                    match res {
+                        Ok(estimate) => Json(EstimateResp {
+                            status: "success".into(),
+                            result: None,
+                            estimate: Some(estimate),
+                        }),
+                        Err(e) => Json(EstimateResp {
+                            status: "error".into(),
+                            result: Some(e.to_string()),
+                            estimate: None,
+                        }),
+                    }
+                },
+            ),
+        )
+        .route(
+            "/api/explain",
+            get(
+                |State(app): State<AppState>,
+                 axum::extract::Query(params): axum::extract::Query<
+                    std::collections::HashMap<String, String>,
+                >| async move {
+                    #[derive(Serialize)]
+                    struct ExplainResp {
+                        status: String,
+                        #[serde(skip_serializing_if = "Option::is_none")]
+                        result: Option<String>,
+                        included: bool,
+                        #[serde(flatten)]
+                        reason: Option<crate::indexer::ExclusionReason>,
+                    }
+                    let cfg = app.server.get_cfg();
+                    let alias = params
+                        .get("alias")
+                        .map(|a| cfg.qualify_alias(a, params.get("namespace").map(String::as_str)));
+                    let project = params.get("project").cloned();
+                    let res = match params.get("path") {
+                        Some(rel) => service::resolve_target(&cfg, alias, project).and_then(
+                            |(_key, root)| {
+                                crate::indexer::explain_path(&cfg, std::path::Path::new(&root), rel)
+                            },
+                        ),
+                        None => Err(anyhow::anyhow!("provide path")),
+                    };
+                    match res {
+                        Ok(reason) => Json(ExplainResp {
+                            status: "success".into(),
+                            result: None,
+                            included: reason.is_included(),
+                            reason: Some(reason),
+                        }),
+                        Err(e) => Json(ExplainResp {
+                            status: "error".into(),
+                            result: Some(e.to_string()),
+                            included: false,
+                            reason: None,
+                        }),
+                    }
+                },
+            ),
+        )
+        .route(
+            "/api/tasks/{task_id}",
+            get(
+                |State(app): State<AppState>,
+                 axum::extract::Path(task_id): axum::extract::Path<String>| async move {
+                    #[derive(Serialize)]
+                    struct TaskByIdResp {
+                        status: String,
+                        #[serde(skip_serializing_if = "Option::is_none")]
+                        project: Option<String>,
+                        running: bool,
+                        progress: Option<TaskProgress>,
+                        eta_secs: Option<u64>,
+                    }
+                    let Some((key, progress)) = app.tasks.get_by_task_id(&task_id) else {
+                        return (
+                            StatusCode::NOT_FOUND,
+                            axum::Json(TaskByIdResp {
+                                status: "not_found".into(),
+                                project: None,
+                                running: false,
+                                progress: None,
+                                eta_secs: None,
+                            }),
+                        );
+                    };
+                    let eta = progress.eta_secs();
+                    (
+                        StatusCode::OK,
+                        axum::Json(TaskByIdResp {
+                            status: "success".into(),
+                            running: app.tasks.is_running(&key),
+                            project: Some(key),
+                            progress: Some(progress),
+                            eta_secs: eta,
+                        }),
+                    )
+                },
             ),
         )
         .route(
@@ -334,28 +1131,7 @@ pub fn build_router(app_state: AppState) -> Router {
                         }
                     };
                     let cfg = app.server.get_cfg();
-                    use crate::indexer::Aliases;
-                    let aliases = Aliases::load(&cfg.aliases_file()).unwrap_or_default();
-                    let path = match (req.alias.clone(), req.project_root_path.clone()) {
-                        (Some(_), Some(p)) => p,
-                        (Some(a), None) => match aliases.resolve(&a) {
-                            Some(p) => p.clone(),
-                            None => {
-                                return Json(StopResp {
-                                    status: "error".into(),
-                                    result: "alias not found and no path provided".into(),
-                                });
-                            }
-                        },
-                        (None, Some(p)) => p,
-                        (None, None) => {
-                            return Json(StopResp {
-                                status: "error".into(),
-                                result: "provide project_root_path or alias".into(),
-                            });
-                        }
-                    };
-                    let project_key = match crate::config::normalize_path(&path) {
+                    let (project_key, _path) = match req.target.resolve(&cfg) {
                         Ok(x) => x,
                         Err(e) => {
                             return Json(StopResp {
@@ -377,5 +1153,38 @@ pub fn build_router(app_state: AppState) -> Router {
                 },
             ),
         )
+        .layer(middleware::from_fn_with_state(
+            server_state.clone(),
+            reject_mutations_if_read_only,
+        ))
+        .layer(middleware::from_fn_with_state(
+            (server_state.clone(), RateLimiter::new()),
+            rate_limit,
+        ))
         .with_state(server_state)
 }
+
+/// Serve `router` on a Unix domain socket at `path` until `shutdown` resolves, then remove the
+/// socket file. A stale socket left by a previous run is replaced; any other file is an error.
+#[cfg(unix)]
+pub async fn serve_unix<F>(
+    router: Router,
+    path: &std::path::Path,
+    shutdown: F,
+) -> anyhow::Result<()>
+where
+    F: std::future::Future<Output = ()> + Send + 'static,
+{
+    use std::os::unix::fs::FileTypeExt;
+    match std::fs::symlink_metadata(path) {
+        Ok(m) if m.file_type().is_socket() => std::fs::remove_file(path)?,
+        Ok(_) => anyhow::bail!("{} exists and is not a socket", path.display()),
+        Err(_) => {}
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    let served = axum::serve(listener, router)
+        .with_graceful_shutdown(shutdown)
+        .await;
+    let _ = std::fs::remove_file(path);
+    Ok(served?)
+}
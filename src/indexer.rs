@@ -1,5 +1,7 @@
 //! Indexer: collect files, honor .gitignore, split large files, hash path+content.
 
+use crate::config::Config;
+use crate::filters::{ContentFilter, apply_all, parse_filters};
 use anyhow::{Context, Result, anyhow};
 use encoding_rs::Encoding;
 use globset::{Glob, GlobSet, GlobSetBuilder};
@@ -7,21 +9,173 @@ use ignore::WalkBuilder;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fs,
     path::{Path, PathBuf},
+    time::SystemTime,
 };
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
 pub struct ProjectsIndex(pub HashMap<String, Vec<String>>);
 
+/// On-disk shapes of `projects.json`: the current versioned wrapper, or the legacy bare map.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ProjectsFile {
+    Versioned {
+        version: u32,
+        projects: HashMap<String, Vec<String>>,
+    },
+    Legacy(HashMap<String, Vec<String>>),
+}
+
+#[derive(Serialize)]
+struct ProjectsFileRef<'a> {
+    version: u32,
+    projects: &'a HashMap<String, Vec<String>>,
+}
+
 impl ProjectsIndex {
+    /// Schema version written by `save`.
+    pub const VERSION: u32 = 1;
+
+    /// Load either the versioned (`{"version":1,"projects":{...}}`) or legacy bare-map format.
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(Self::load_with_version(path)?.0)
+    }
+
+    /// Like `load`, also returning the on-disk schema version (0 = legacy bare map).
+    fn load_with_version(path: &Path) -> Result<(Self, u32)> {
+        if !path.exists() {
+            return Ok((Self::default(), Self::VERSION));
+        }
+        let text = fs::read_to_string(path)?;
+        let loaded = match serde_json::from_str::<ProjectsFile>(&text) {
+            Ok(ProjectsFile::Versioned { version, projects }) => (Self(projects), version),
+            Ok(ProjectsFile::Legacy(projects)) => (Self(projects), 0),
+            Err(_) => (Self::default(), Self::VERSION),
+        };
+        Ok(loaded)
+    }
+
+    /// Rewrite a legacy file in the versioned format; returns true if it was migrated.
+    pub fn migrate_if_needed(path: &Path) -> Result<bool> {
+        let (index, version) = Self::load_with_version(path)?;
+        if version >= Self::VERSION {
+            return Ok(false);
+        }
+        index.save(path)?;
+        tracing::info!(file = %path.display(), "migrated projects index to version {}", Self::VERSION);
+        Ok(true)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let text = serde_json::to_string_pretty(&ProjectsFileRef {
+            version: Self::VERSION,
+            projects: &self.0,
+        })?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+/// Per-project bookkeeping for quick (mtime-based) re-indexing.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ProjectMeta {
+    /// Unix millis when the last index started; files modified before it are considered indexed
+    pub indexed_at_ms: u64,
+    /// Settings fingerprint the blobs were produced with
+    pub fingerprint: String,
+    /// rel file path -> blob names (chunk order)
+    pub files: BTreeMap<String, Vec<String>>,
+    /// Content-only hashes of all blobs, recorded when `detect_moves` is on
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub content_hashes: BTreeSet<String>,
+    /// rel file path -> detected language, for files whose language is known
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub languages: BTreeMap<String, String>,
+}
+
+impl ProjectMeta {
+    /// Build from parallel `blobs` / `blob_names` as produced by `incremental_plan`.
+    pub fn from_blobs(
+        blobs: &[BlobUpload],
+        blob_names: &[String],
+        fingerprint: String,
+        indexed_at_ms: u64,
+    ) -> Self {
+        let mut files: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        let mut languages = BTreeMap::new();
+        for (b, name) in blobs.iter().zip(blob_names) {
+            let file = blob_file_path(&b.path);
+            files
+                .entry(file.to_string())
+                .or_default()
+                .push(name.clone());
+            if let Some(lang) = &b.language {
+                languages.insert(file.to_string(), lang.clone());
+            }
+        }
+        Self {
+            indexed_at_ms,
+            fingerprint,
+            files,
+            content_hashes: BTreeSet::new(),
+            languages,
+        }
+    }
+
+    /// Record content-only hashes of `blobs` so later runs can recognize moved content.
+    pub fn with_content_hashes(mut self, blobs: &[BlobUpload]) -> Self {
+        self.content_hashes = blobs.iter().map(|b| hash_content(&b.content)).collect();
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct IndexMeta(pub HashMap<String, ProjectMeta>);
+
+impl IndexMeta {
     pub fn load(path: &Path) -> Result<Self> {
         if !path.exists() {
             return Ok(Self::default());
         }
         let text = fs::read_to_string(path)?;
-        let v = serde_json::from_str::<HashMap<String, Vec<String>>>(&text).unwrap_or_default();
+        let v = serde_json::from_str::<HashMap<String, ProjectMeta>>(&text).unwrap_or_default();
+        Ok(Self(v))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let text = serde_json::to_string_pretty(&self.0)?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+/// Backend retrieval checkpoint and the blob set it covers.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct Checkpoint {
+    pub checkpoint_id: String,
+    pub blob_names: BTreeSet<String>,
+}
+
+/// `checkpoints.json`: project_key -> last retrieval checkpoint.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Checkpoints(pub HashMap<String, Checkpoint>);
+
+impl Checkpoints {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = fs::read_to_string(path)?;
+        let v = serde_json::from_str::<HashMap<String, Checkpoint>>(&text).unwrap_or_default();
         Ok(Self(v))
     }
 
@@ -63,15 +217,280 @@ impl Aliases {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// `project_meta.json`: project_key -> user-supplied labels (team, description, ...).
+/// Independent of indexing, so re-indexing leaves it untouched.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ProjectTags(pub BTreeMap<String, BTreeMap<String, String>>);
+
+impl ProjectTags {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = fs::read_to_string(path)?;
+        let v = serde_json::from_str(&text).unwrap_or_default();
+        Ok(Self(v))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let text = serde_json::to_string_pretty(&self.0)?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+/// Directory inside a project root holding shareable augmcp state (never indexed).
+pub const PROJECT_STATE_DIR: &str = ".augmcp";
+
+/// Shareable blob manifest stored at `<root>/.augmcp/manifest.json`.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Manifest {
+    pub version: u32,
+    /// Settings fingerprint the blob names were computed with
+    pub fingerprint: String,
+    pub blob_names: Vec<String>,
+}
+
+impl Manifest {
+    pub const VERSION: u32 = 1;
+
+    pub fn path_for(project_root: &Path) -> PathBuf {
+        project_root.join(PROJECT_STATE_DIR).join("manifest.json")
+    }
+
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let text = fs::read_to_string(path)?;
+        let m = serde_json::from_str::<Manifest>(&text)
+            .with_context(|| format!("invalid manifest: {}", path.display()))?;
+        Ok(Some(m))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let text = serde_json::to_string_pretty(self)?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct BlobUpload {
     pub path: String,
     pub content: String,
+    /// Language of the source file (`detect_language`); not part of the blob name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Source file mtime (Unix millis) when known; orders `upload_order = "recency"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime_ms: Option<u64>,
+    /// Lines in the whole source file (every chunk carries the file's total); sent with
+    /// `upload_include_metrics`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line_count: Option<usize>,
+    /// Size of the whole source file's (filtered) content in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub byte_size: Option<usize>,
+}
+
+/// Languages `detect_language` can report; values of the `languages` search filter.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    Rust,
+    Python,
+    Javascript,
+    Typescript,
+    Java,
+    Kotlin,
+    Go,
+    C,
+    Cpp,
+    Csharp,
+    Ruby,
+    Php,
+    Perl,
+    Swift,
+    Markdown,
+    Json,
+    Yaml,
+    Toml,
+    Xml,
+    Html,
+    Css,
+    Scss,
+    Sql,
+    Shell,
+}
+
+impl Language {
+    pub const ALL: [Language; 24] = [
+        Self::Rust,
+        Self::Python,
+        Self::Javascript,
+        Self::Typescript,
+        Self::Java,
+        Self::Kotlin,
+        Self::Go,
+        Self::C,
+        Self::Cpp,
+        Self::Csharp,
+        Self::Ruby,
+        Self::Php,
+        Self::Perl,
+        Self::Swift,
+        Self::Markdown,
+        Self::Json,
+        Self::Yaml,
+        Self::Toml,
+        Self::Xml,
+        Self::Html,
+        Self::Css,
+        Self::Scss,
+        Self::Sql,
+        Self::Shell,
+    ];
+
+    /// Name as stored in index metadata and sent as a blob's `language`.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Rust => "rust",
+            Self::Python => "python",
+            Self::Javascript => "javascript",
+            Self::Typescript => "typescript",
+            Self::Java => "java",
+            Self::Kotlin => "kotlin",
+            Self::Go => "go",
+            Self::C => "c",
+            Self::Cpp => "cpp",
+            Self::Csharp => "csharp",
+            Self::Ruby => "ruby",
+            Self::Php => "php",
+            Self::Perl => "perl",
+            Self::Swift => "swift",
+            Self::Markdown => "markdown",
+            Self::Json => "json",
+            Self::Yaml => "yaml",
+            Self::Toml => "toml",
+            Self::Xml => "xml",
+            Self::Html => "html",
+            Self::Css => "css",
+            Self::Scss => "scss",
+            Self::Sql => "sql",
+            Self::Shell => "shell",
+        }
+    }
+}
+
+impl std::fmt::Display for Language {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for Language {
+    type Err = String;
+
+    /// Case-insensitive, like the `languages` filter always was.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let wanted = s.trim().to_lowercase();
+        Self::ALL
+            .into_iter()
+            .find(|l| l.as_str() == wanted)
+            .ok_or_else(|| {
+                let known: Vec<&str> = Self::ALL.iter().map(|l| l.as_str()).collect();
+                format!(
+                    "unknown language {:?}; expected one of {}",
+                    s,
+                    known.join(", ")
+                )
+            })
+    }
+}
+
+impl<'de> Deserialize<'de> for Language {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        String::deserialize(d)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Language name for a file path by its extension (`src/lib.rs` -> `rust`); None if unknown.
+pub fn language_for_extension(path: &str) -> Option<&'static str> {
+    let ext = Path::new(path).extension()?.to_str()?.to_lowercase();
+    let lang = match ext.as_str() {
+        "rs" => Language::Rust,
+        "py" | "pyi" => Language::Python,
+        "js" | "jsx" | "mjs" | "cjs" => Language::Javascript,
+        "ts" | "tsx" => Language::Typescript,
+        "java" => Language::Java,
+        "kt" | "kts" => Language::Kotlin,
+        "go" => Language::Go,
+        "c" | "h" => Language::C,
+        "cpp" | "cc" | "cxx" | "hpp" | "hh" | "hxx" => Language::Cpp,
+        "cs" => Language::Csharp,
+        "rb" => Language::Ruby,
+        "php" => Language::Php,
+        "swift" => Language::Swift,
+        "md" => Language::Markdown,
+        "json" => Language::Json,
+        "yaml" | "yml" => Language::Yaml,
+        "toml" => Language::Toml,
+        "xml" => Language::Xml,
+        "html" | "htm" => Language::Html,
+        "css" => Language::Css,
+        "scss" => Language::Scss,
+        "sql" => Language::Sql,
+        "sh" | "bash" | "zsh" => Language::Shell,
+        _ => return None,
+    };
+    Some(lang.as_str())
+}
+
+/// Language from a `#!` first line (`#!/usr/bin/env python3` -> `python`); None if absent
+/// or unknown.
+fn language_for_shebang(content: &str) -> Option<&'static str> {
+    let line = content.lines().next()?.strip_prefix("#!")?;
+    let mut words = line.split_whitespace();
+    let mut interpreter = words.next()?.rsplit('/').next()?;
+    if interpreter == "env" {
+        interpreter = words.find(|w| !w.starts_with('-') && !w.contains('='))?;
+    }
+    // python3.11 -> python
+    let name = interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    let lang = match name {
+        "python" => Language::Python,
+        "node" | "nodejs" => Language::Javascript,
+        "deno" | "ts-node" | "bun" => Language::Typescript,
+        "sh" | "bash" | "zsh" | "dash" | "ksh" => Language::Shell,
+        "ruby" => Language::Ruby,
+        "perl" => Language::Perl,
+        "php" => Language::Php,
+        _ => return None,
+    };
+    Some(lang.as_str())
+}
+
+/// Language of a file: by extension (`language_for_extension`), else by its shebang line.
+pub fn detect_language(path: &str, content: &str) -> Option<String> {
+    language_for_extension(path)
+        .or_else(|| language_for_shebang(content))
+        .map(str::to_string)
 }
 
 /// Read file bytes and decode using multiple encodings (UTF-8 -> GBK -> GB2312 -> ISO-8859-1),
 /// fall back to UTF-8 lossy if all failed.
-fn read_text_with_encodings(p: &Path) -> Result<String> {
+pub fn read_text_with_encodings(p: &Path) -> Result<String> {
     let bytes = fs::read(p).with_context(|| format!("read file bytes: {}", p.display()))?;
     // try UTF-8
     if let Ok(s) = std::str::from_utf8(&bytes) {
@@ -88,6 +507,66 @@ fn read_text_with_encodings(p: &Path) -> Result<String> {
     Ok(String::from_utf8_lossy(&bytes).into_owned())
 }
 
+/// Built-in `exclude_presets`: well-known build output and cache directories per ecosystem.
+pub const EXCLUDE_PRESETS: &[(&str, &[&str])] = &[
+    ("rust", &["target", "*.rs.bk"]),
+    (
+        "node",
+        &[
+            "node_modules",
+            "dist",
+            "build",
+            "coverage",
+            ".next",
+            ".nuxt",
+            ".turbo",
+            ".parcel-cache",
+            "*.min.js",
+            "*.min.css",
+        ],
+    ),
+    (
+        "python",
+        &[
+            "__pycache__",
+            ".venv",
+            "venv",
+            ".tox",
+            ".eggs",
+            "*.egg-info",
+            ".pytest_cache",
+            ".mypy_cache",
+            ".ruff_cache",
+            "*.pyc",
+            "build",
+            "dist",
+        ],
+    ),
+    ("java", &["target", "build", "out", ".gradle", "*.class"]),
+];
+
+/// Patterns of the built-in exclude preset `name` (case-insensitive); None if unknown.
+pub fn exclude_preset(name: &str) -> Option<&'static [&'static str]> {
+    let name = name.trim().to_lowercase();
+    EXCLUDE_PRESETS
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, pats)| *pats)
+}
+
+/// `patterns` followed by the patterns of each preset in `presets`; unknown presets are
+/// logged and skipped.
+fn with_presets(patterns: &[String], presets: &[String]) -> Vec<String> {
+    let mut all = patterns.to_vec();
+    for name in presets {
+        match exclude_preset(name) {
+            Some(pats) => all.extend(pats.iter().map(|p| p.to_string())),
+            None => tracing::warn!(preset = %name, "unknown exclude preset"),
+        }
+    }
+    all
+}
+
 fn build_exclude_globset(patterns: &[String]) -> Result<GlobSet> {
     let mut b = GlobSetBuilder::new();
     for pat in patterns {
@@ -109,7 +588,69 @@ fn build_exclude_globset(patterns: &[String]) -> Result<GlobSet> {
     Ok(b.build()?)
 }
 
+/// Per-directory file listing globs, relative to its directory, of files indexed even when
+/// .gitignore, exclude or generated patterns would drop them (like `force_include`).
+pub const KEEP_FILE: &str = ".augmcpkeep";
+
+/// Globs of every `.augmcpkeep` under `project_root`, anchored at the file's directory so they
+/// only match within that subtree; empty unless `honor_keep_files`. The discovery walk ignores
+/// .gitignore: keep files usually sit inside ignored directories such as `vendor/`. Invalid
+/// globs are skipped with a warning.
+fn keep_globset(project_root: &Path, opts: &CollectOptions) -> GlobSet {
+    let mut b = GlobSetBuilder::new();
+    if opts.honor_keep_files && project_root.is_dir() {
+        let mut walk = WalkBuilder::new(project_root);
+        walk.standard_filters(false)
+            .filter_entry(|e| e.file_name() != ".git" && e.file_name() != PROJECT_STATE_DIR);
+        for dent in walk.build().filter_map(|d| d.ok()) {
+            if dent.file_name() != KEEP_FILE || !dent.file_type().is_some_and(|t| t.is_file()) {
+                continue;
+            }
+            let dir = dent.path().parent().unwrap_or(project_root);
+            let prefix = pathdiff::diff_paths(dir, project_root)
+                .map(|d| d.to_string_lossy().replace('\\', "/"))
+                .filter(|d| !d.is_empty())
+                .map(|d| format!("{d}/"))
+                .unwrap_or_default();
+            let Ok(text) = fs::read_to_string(dent.path()) else {
+                continue;
+            };
+            for line in text.lines().map(str::trim) {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let pat = format!(
+                    "{prefix}{}",
+                    line.trim_start_matches("./").trim_matches('/')
+                );
+                // 与 exclude_patterns 一致：目录名同时匹配其下所有文件
+                for glob in [pat.clone(), format!("{pat}/**")] {
+                    match Glob::new(&glob) {
+                        Ok(g) => {
+                            b.add(g);
+                        }
+                        Err(e) => {
+                            tracing::warn!(file = %dent.path().display(), pattern = line, error = %e, "skip invalid keep pattern");
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    b.build().unwrap_or_else(|_| GlobSet::empty())
+}
+
+/// `text_extensions` match: the lowercased `.ext` of `path`, or its whole file name for
+/// entries such as `Makefile`, `Dockerfile`, `CMakeLists.txt` or `.env.local`.
 fn is_text_ext(path: &Path, text_exts: &HashSet<String>) -> bool {
+    if path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .is_some_and(|name| text_exts.contains(name))
+    {
+        return true;
+    }
     if let Some(ext) = path.extension().and_then(|s| s.to_str()) {
         let dot = format!(".{}", ext.to_lowercase());
         return text_exts.contains(&dot);
@@ -117,17 +658,221 @@ fn is_text_ext(path: &Path, text_exts: &HashSet<String>) -> bool {
     false
 }
 
+/// Minimal `.gitattributes` reader: only the text/binary classification (`text`, `-text`,
+/// `binary`, `diff`, `-diff`). Later lines override earlier ones, as in git.
+#[derive(Debug, Default)]
+struct GitAttributes {
+    rules: Vec<(globset::GlobMatcher, bool)>, // (pattern, is_text)
+}
+
+impl GitAttributes {
+    fn load(project_root: &Path) -> Self {
+        let Ok(text) = fs::read_to_string(project_root.join(".gitattributes")) else {
+            return Self::default();
+        };
+        let mut rules = Vec::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let Some(pat) = parts.next() else { continue };
+            let mut is_text = None;
+            for attr in parts {
+                match attr {
+                    "binary" | "-text" | "-diff" => is_text = Some(false),
+                    "diff" => is_text = Some(true),
+                    a if a == "text" || a.starts_with("text=") => is_text = Some(true),
+                    _ => {}
+                }
+            }
+            let Some(is_text) = is_text else { continue };
+            let glob = if let Some(anchored) = pat.strip_prefix('/') {
+                anchored.to_string()
+            } else if pat.contains('/') {
+                pat.to_string()
+            } else {
+                format!("**/{}", pat)
+            };
+            match Glob::new(&glob) {
+                Ok(g) => rules.push((g.compile_matcher(), is_text)),
+                Err(e) => {
+                    tracing::debug!(pattern = pat, error = %e, "skip invalid gitattributes pattern")
+                }
+            }
+        }
+        Self { rules }
+    }
+
+    /// Some(true) = declared text, Some(false) = declared binary, None = unspecified.
+    fn classify(&self, rel: &str) -> Option<bool> {
+        self.rules
+            .iter()
+            .rev()
+            .find(|(m, _)| m.is_match(rel))
+            .map(|(_, is_text)| *is_text)
+    }
+}
+
 fn should_exclude(rel: &str, globset: &GlobSet) -> bool {
     globset.is_match(rel)
 }
 
-fn hash_blob_name(path: &str, content: &str) -> String {
+/// Type check of `visit_candidates`: .gitattributes text/binary declarations take precedence
+/// over the extension; forced files skip both with `force_include_any_extension`.
+fn passes_type_check(
+    p: &Path,
+    rel: &str,
+    attrs: &GitAttributes,
+    opts: &CollectOptions,
+    forced: bool,
+) -> bool {
+    if forced && opts.force_include_any_extension {
+        return true;
+    }
+    match attrs.classify(rel) {
+        Some(is_text) => is_text,
+        None => is_text_ext(p, &opts.text_exts),
+    }
+}
+
+/// Client-side blob name: `sha256(path + content)` in hex.
+pub fn hash_blob_name(path: &str, content: &str) -> String {
     let mut hasher = Sha256::new();
     hasher.update(path.as_bytes());
     hasher.update(content.as_bytes());
     format!("{:x}", hasher.finalize())
 }
 
+/// How the `N` and `M` of a `#chunkNofM` blob suffix are written (both 1-based).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChunkNumbering {
+    /// `N` zero-padded to the width of `M` (`chunk02of10`), so lexical order is numeric order
+    #[default]
+    Padded,
+    /// Unpadded (`chunk2of10`), the pre-padding format
+    Plain,
+}
+
+impl ChunkNumbering {
+    pub fn suffix(self, n: usize, total: usize) -> String {
+        match self {
+            Self::Padded => {
+                let width = total.to_string().len();
+                format!("#chunk{n:0width$}of{total}")
+            }
+            Self::Plain => format!("#chunk{n}of{total}"),
+        }
+    }
+}
+
+const SECS_PER_DAY: u64 = 86_400;
+
+/// Options controlling which files are collected and how they are split.
+#[derive(Debug, Clone, Default)]
+pub struct CollectOptions {
+    pub text_exts: HashSet<String>,
+    pub max_lines: usize,
+    /// Per-extension override of `max_lines` (lowercase, with leading dot)
+    pub max_lines_per_ext: HashMap<String, usize>,
+    pub exclude_patterns: Vec<String>,
+    /// Names of built-in exclude lists (`EXCLUDE_PRESETS`) added to `exclude_patterns`
+    pub exclude_presets: Vec<String>,
+    /// Generated-code globs: skipped like excludes but counted in `VisitStats::generated_skipped`
+    pub generated_patterns: Vec<String>,
+    /// Globs collected even when .gitignore, exclude or generated patterns would drop them
+    pub force_include: Vec<String>,
+    /// Forced files also bypass the extension and .gitattributes checks
+    pub force_include_any_extension: bool,
+    /// Treat files listed in `.augmcpkeep` files like `force_include`
+    pub honor_keep_files: bool,
+    /// Only collect files tracked by git (`git ls-files`) when the root is in a git repository
+    pub only_git_tracked: bool,
+    /// Skip files whose average line length (bytes / lines) exceeds this, e.g. minified bundles; 0 disables
+    pub max_avg_line_bytes: usize,
+    /// Drop empty/whitespace-only files and whitespace-only chunks
+    pub skip_empty_files: bool,
+    /// Skip files modified less than this many seconds ago; 0 disables
+    pub min_file_age_secs: u64,
+    /// Skip files not modified for more than this many days; 0 disables
+    pub max_file_age_days: u64,
+    /// Skip .gitignore files in directories above the project root
+    pub treat_root_as_repo: bool,
+    /// Transforms applied to file content before splitting/hashing
+    pub content_filters: Vec<ContentFilter>,
+    pub chunk_numbering: ChunkNumbering,
+}
+
+impl CollectOptions {
+    pub fn from_config(cfg: &Config) -> Self {
+        Self {
+            text_exts: cfg.text_extensions_set(),
+            max_lines: cfg.settings.max_lines_per_blob,
+            max_lines_per_ext: cfg
+                .settings
+                .max_lines_per_ext
+                .iter()
+                .map(|(ext, n)| (ext.to_lowercase(), *n))
+                .collect(),
+            exclude_patterns: cfg.settings.exclude_patterns.clone(),
+            exclude_presets: cfg.settings.exclude_presets.clone(),
+            generated_patterns: cfg.settings.generated_patterns.clone(),
+            force_include: cfg.settings.force_include.clone(),
+            force_include_any_extension: cfg.settings.force_include_any_extension,
+            honor_keep_files: cfg.settings.honor_keep_files,
+            only_git_tracked: cfg.settings.only_git_tracked,
+            max_avg_line_bytes: cfg.settings.max_avg_line_bytes,
+            skip_empty_files: cfg.settings.skip_empty_files,
+            min_file_age_secs: cfg.settings.min_file_age_secs,
+            max_file_age_days: cfg.settings.max_file_age_days,
+            treat_root_as_repo: cfg.settings.treat_root_as_repo,
+            content_filters: parse_filters(&cfg.settings.content_filters),
+            chunk_numbering: cfg.settings.chunk_numbering,
+        }
+    }
+
+    /// Whether either file age filter is enabled.
+    fn filters_by_age(&self) -> bool {
+        self.min_file_age_secs > 0 || self.max_file_age_days > 0
+    }
+
+    /// `min_file_age_secs` / `max_file_age_days` verdict for a file last modified at `mtime`.
+    fn age_exclusion(&self, mtime: SystemTime) -> Option<ExclusionReason> {
+        // 修改时间在未来（时钟偏差）按刚修改处理
+        let age_secs = SystemTime::now()
+            .duration_since(mtime)
+            .unwrap_or_default()
+            .as_secs();
+        if self.min_file_age_secs > 0 && age_secs < self.min_file_age_secs {
+            return Some(ExclusionReason::TooRecent {
+                age_secs,
+                min_age_secs: self.min_file_age_secs,
+            });
+        }
+        if self.max_file_age_days > 0 && age_secs > self.max_file_age_days * SECS_PER_DAY {
+            return Some(ExclusionReason::TooOld {
+                age_days: age_secs / SECS_PER_DAY,
+                max_age_days: self.max_file_age_days,
+            });
+        }
+        None
+    }
+
+    /// Lines per blob for a file: its extension's override, else the global limit.
+    pub fn max_lines_for(&self, path: &Path) -> usize {
+        path.extension()
+            .and_then(|s| s.to_str())
+            .and_then(|ext| {
+                self.max_lines_per_ext
+                    .get(&format!(".{}", ext.to_lowercase()))
+            })
+            .copied()
+            .unwrap_or(self.max_lines)
+    }
+}
+
 /// Collect blobs from a project directory with .gitignore and exclude patterns.
 pub fn collect_blobs(
     project_root: &Path,
@@ -135,19 +880,224 @@ pub fn collect_blobs(
     max_lines: usize,
     exclude_patterns: &[String],
 ) -> Result<Vec<BlobUpload>> {
+    let opts = CollectOptions {
+        text_exts: text_exts.clone(),
+        max_lines,
+        exclude_patterns: exclude_patterns.to_vec(),
+        ..Default::default()
+    };
+    collect_blobs_with(project_root, &opts)
+}
+
+/// Same as `collect_blobs`, driven by `CollectOptions`.
+pub fn collect_blobs_with(project_root: &Path, opts: &CollectOptions) -> Result<Vec<BlobUpload>> {
+    collect_blobs_with_progress(project_root, opts, |_| {})
+}
+
+/// Report collection progress every this many files seen.
+pub const COLLECT_PROGRESS_EVERY: usize = 100;
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct CollectProgress {
+    /// Files visited by the walk so far (total is unknown upfront)
+    pub files_seen: usize,
+    /// Blobs produced so far (after filtering and splitting)
+    pub blobs: usize,
+    /// Otherwise-indexable files skipped by `generated_patterns` so far
+    pub generated_skipped: usize,
+}
+
+/// Counters of a `visit_candidates` walk.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VisitStats {
+    /// Files visited by the walk
+    pub files_seen: usize,
+    /// Files passing every other filter but matching `generated_patterns`
+    pub generated_skipped: usize,
+}
+
+/// Same as `collect_blobs_with`, invoking `on_progress` every `COLLECT_PROGRESS_EVERY` files
+/// and once at the end.
+pub fn collect_blobs_with_progress<F>(
+    project_root: &Path,
+    opts: &CollectOptions,
+    mut on_progress: F,
+) -> Result<Vec<BlobUpload>>
+where
+    F: FnMut(CollectProgress),
+{
+    let mut blobs = Vec::new();
+    let mut last_reported = 0usize;
+    let stats = visit_candidates(project_root, opts, |p, rel, stats| {
+        if stats.files_seen - last_reported >= COLLECT_PROGRESS_EVERY {
+            last_reported = stats.files_seen;
+            on_progress(CollectProgress {
+                files_seen: stats.files_seen,
+                blobs: blobs.len(),
+                generated_skipped: stats.generated_skipped,
+            });
+        }
+        if let Some(mut b) = file_blobs(p, &rel, opts) {
+            blobs.append(&mut b);
+        }
+    })?;
+    on_progress(CollectProgress {
+        files_seen: stats.files_seen,
+        blobs: blobs.len(),
+        generated_skipped: stats.generated_skipped,
+    });
+    Ok(blobs)
+}
+
+/// Assumed bytes per line when `estimate_blobs` turns file sizes into line counts.
+pub const ESTIMATE_AVG_LINE_BYTES: u64 = 40;
+
+/// Metadata-only preview of a collection run (`estimate_blobs`).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct IndexEstimate {
+    /// Files that pass the path, extension and size filters
+    pub files: usize,
+    /// Their total size on disk
+    pub total_bytes: u64,
+    /// Approximate blob count: each file's size / `ESTIMATE_AVG_LINE_BYTES` lines, split by
+    /// its `max_lines`
+    pub estimated_blobs: usize,
+    /// Files visited by the walk
+    pub files_seen: usize,
+    /// Otherwise-indexable files skipped by `generated_patterns`
+    pub generated_skipped: usize,
+}
+
+/// Estimate what `collect_blobs_with` would produce from file metadata only: content is never
+/// read, so content checks (`max_avg_line_bytes`, whitespace-only files) are not applied.
+/// Zero-byte files are skipped when `skip_empty_files` is set.
+pub fn estimate_blobs(project_root: &Path, opts: &CollectOptions) -> Result<IndexEstimate> {
+    let mut est = IndexEstimate::default();
+    let stats = visit_candidates(project_root, opts, |p, _rel, _| {
+        let Ok(size) = fs::metadata(p).map(|m| m.len()) else {
+            return;
+        };
+        if size == 0 && opts.skip_empty_files {
+            return;
+        }
+        let lines = size.div_ceil(ESTIMATE_AVG_LINE_BYTES).max(1);
+        est.files += 1;
+        est.total_bytes += size;
+        est.estimated_blobs += lines.div_ceil(opts.max_lines_for(p).max(1) as u64) as usize;
+    })?;
+    est.files_seen = stats.files_seen;
+    est.generated_skipped = stats.generated_skipped;
+    Ok(est)
+}
+
+/// Files tracked by git under `dir`, relative to it with forward slashes (`git ls-files`).
+/// None when `dir` is not inside a git work tree or git cannot be run.
+pub fn git_tracked_files(dir: &Path) -> Option<HashSet<String>> {
+    let out = std::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["ls-files", "-z", "--cached"])
+        .output()
+        .ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    Some(
+        out.stdout
+            .split(|b| *b == 0)
+            .filter(|p| !p.is_empty())
+            .map(|p| String::from_utf8_lossy(p).replace('\\', "/"))
+            .collect(),
+    )
+}
+
+/// `git_tracked_files` for `only_git_tracked`; warns and returns None (walk everything) when
+/// `dir` is not a git repository.
+fn tracked_filter(dir: &Path, opts: &CollectOptions) -> Option<HashSet<String>> {
+    if !opts.only_git_tracked {
+        return None;
+    }
+    let tracked = git_tracked_files(dir);
+    if tracked.is_none() {
+        tracing::warn!(
+            dir = %dir.display(),
+            "only_git_tracked: not a git repository (or git unavailable); indexing all files"
+        );
+    }
+    tracked
+}
+
+/// Whether .gitignore rules above `project_root` hide every file under it, e.g. the root sits
+/// in a parent repository that ignores it. Such a root indexes nothing unless
+/// `treat_root_as_repo` is set.
+pub fn root_gitignored(project_root: &Path) -> bool {
+    let has_files = |parents: bool| {
+        let mut walk = WalkBuilder::new(project_root);
+        walk.git_ignore(true)
+            .git_global(true)
+            .git_exclude(true)
+            .parents(parents)
+            .hidden(false);
+        walk.build()
+            .filter_map(|d| d.ok())
+            .any(|d| d.file_type().is_some_and(|t| t.is_file()))
+    };
+    project_root.is_dir() && !has_files(true) && has_files(false)
+}
+
+/// Whether the age filters reject the walked file (mtime from the entry's metadata; files
+/// without one are kept).
+fn too_old_or_recent(dent: &ignore::DirEntry, opts: &CollectOptions) -> bool {
+    opts.filters_by_age()
+        && dent
+            .metadata()
+            .ok()
+            .and_then(|m| m.modified().ok())
+            .and_then(|mtime| opts.age_exclusion(mtime))
+            .is_some()
+}
+
+/// Walk the project and call `f(abs_path, rel_path, stats_so_far)` for every file passing the
+/// path-based filters (.gitignore, exclude and generated patterns, .gitattributes, extensions).
+/// Files matching `force_include` override the ignore and pattern filters.
+/// Content is not read here. Returns the final counters.
+pub fn visit_candidates<F>(
+    project_root: &Path,
+    opts: &CollectOptions,
+    mut f: F,
+) -> Result<VisitStats>
+where
+    F: FnMut(&Path, String, &VisitStats),
+{
     if !project_root.exists() {
         return Err(anyhow!(
             "project root not found: {}",
             project_root.display()
         ));
     }
-    let globset = build_exclude_globset(exclude_patterns)?;
-    let mut blobs = Vec::new();
+    // 单文件作为项目根：只索引该文件，相对路径为文件名
+    let base_dir = if project_root.is_file() {
+        project_root.parent().unwrap_or(project_root)
+    } else {
+        project_root
+    };
+    let globset =
+        build_exclude_globset(&with_presets(&opts.exclude_patterns, &opts.exclude_presets))?;
+    let generated = build_exclude_globset(&opts.generated_patterns)?;
+    let forced = build_exclude_globset(&opts.force_include)?;
+    let kept = keep_globset(project_root, opts);
+    let forced_or_kept = |rel: &str| should_exclude(rel, &forced) || kept.is_match(rel);
+    let attrs = GitAttributes::load(base_dir);
+    let tracked = tracked_filter(base_dir, opts);
+    let mut stats = VisitStats::default();
+    // 第一遍已判定过的强制包含文件，第二遍不再重复
+    let mut forced_seen: HashSet<String> = HashSet::new();
 
     let mut walk = WalkBuilder::new(project_root);
     walk.git_ignore(true)
         .git_global(true)
         .git_exclude(true)
+        .parents(!opts.treat_root_as_repo)
         .hidden(false);
 
     for dent in walk.build() {
@@ -159,44 +1109,364 @@ pub fn collect_blobs(
         if p.is_dir() {
             continue;
         }
+        stats.files_seen += 1;
         // relative string with forward slashes
-        let rel = pathdiff::diff_paths(p, project_root).unwrap_or_else(|| PathBuf::from(""));
+        let rel = pathdiff::diff_paths(p, base_dir).unwrap_or_else(|| PathBuf::from(""));
         let rel_str = rel.to_string_lossy().replace('\\', "/");
-        if rel_str.is_empty() {
+        if rel_str.is_empty() || rel_str.starts_with(&format!("{PROJECT_STATE_DIR}/")) {
             continue;
         }
 
-        if should_exclude(&rel_str, &globset) {
+        let is_forced = forced_or_kept(&rel_str);
+        if is_forced {
+            forced_seen.insert(rel_str.clone());
+        }
+        if !is_forced && tracked.as_ref().is_some_and(|t| !t.contains(&rel_str)) {
+            continue;
+        }
+        if !is_forced && should_exclude(&rel_str, &globset) {
             continue;
         }
-        if !is_text_ext(p, text_exts) {
+        if !passes_type_check(p, &rel_str, &attrs, opts, is_forced) {
             continue;
         }
+        if too_old_or_recent(&dent, opts) {
+            continue;
+        }
+        if !is_forced && should_exclude(&rel_str, &generated) {
+            stats.generated_skipped += 1;
+            continue;
+        }
+        f(p, rel_str, &stats);
+    }
+    if stats.files_seen == 0 && !opts.treat_root_as_repo && root_gitignored(project_root) {
+        tracing::warn!(
+            root = %project_root.display(),
+            "project root is gitignored; set treat_root_as_repo = true to index it"
+        );
+    }
 
-        let content = match read_text_with_encodings(p) {
-            Ok(s) => s,
-            Err(_) => continue,
-        };
-        // split by max_lines
-        let lines: Vec<&str> = content.split_inclusive(['\n', '\r']).collect();
-        if lines.len() <= max_lines {
-            blobs.push(BlobUpload {
-                path: rel_str,
-                content,
-            });
-        } else {
-            let total = (lines.len() + max_lines - 1) / max_lines;
-            for (i, chunk) in lines.chunks(max_lines).enumerate() {
-                let chunk_content = chunk.concat();
-                let chunk_path = format!("{}#chunk{}of{}", rel_str, i + 1, total);
-                blobs.push(BlobUpload {
-                    path: chunk_path,
-                    content: chunk_content,
-                });
+    if opts.force_include.is_empty() && kept.is_empty() {
+        return Ok(stats);
+    }
+    // 强制包含（含 .augmcpkeep）：不读 ignore 规则再走一遍，补上被 .gitignore 挡掉的文件
+    let mut walk = WalkBuilder::new(project_root);
+    walk.standard_filters(false)
+        .filter_entry(|e| e.file_name() != ".git" && e.file_name() != PROJECT_STATE_DIR);
+    for dent in walk.build() {
+        let Ok(dent) = dent else { continue };
+        let p = dent.path();
+        if p.is_dir() {
+            continue;
+        }
+        let rel = pathdiff::diff_paths(p, base_dir).unwrap_or_else(|| PathBuf::from(""));
+        let rel_str = rel.to_string_lossy().replace('\\', "/");
+        if rel_str.is_empty()
+            || !forced_or_kept(&rel_str)
+            || forced_seen.contains(&rel_str)
+            || !passes_type_check(p, &rel_str, &attrs, opts, true)
+            || too_old_or_recent(&dent, opts)
+        {
+            continue;
+        }
+        stats.files_seen += 1;
+        f(p, rel_str, &stats);
+    }
+    Ok(stats)
+}
+
+/// Why `explain_path` would skip a file, or `Included` with its chunk count.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum ExclusionReason {
+    /// Not excluded: indexed as this many blobs
+    Included {
+        chunks: usize,
+    },
+    NotFound,
+    /// The path escapes the project root
+    OutsideProject,
+    Directory,
+    /// Skipped by .gitignore, .git/info/exclude or the global gitignore
+    Gitignored,
+    /// Not listed by `git ls-files` while `only_git_tracked` is on
+    Untracked,
+    /// Inside the project's `.augmcp/` state directory
+    StateDir,
+    ExcludePattern {
+        pattern: String,
+    },
+    /// Declared binary in .gitattributes
+    GitattributesBinary,
+    /// Extension not in `text_extensions` (empty when the file has none)
+    Extension {
+        extension: String,
+    },
+    GeneratedPattern {
+        pattern: String,
+    },
+    Unreadable {
+        error: String,
+    },
+    /// Average line length above `max_avg_line_bytes`
+    Minified {
+        avg_line_bytes: usize,
+        limit: usize,
+    },
+    /// Empty or whitespace-only content while `skip_empty_files` is on
+    Empty,
+    /// Modified more recently than `min_file_age_secs`
+    TooRecent {
+        age_secs: u64,
+        min_age_secs: u64,
+    },
+    /// Not modified for longer than `max_file_age_days`
+    TooOld {
+        age_days: u64,
+        max_age_days: u64,
+    },
+}
+
+impl ExclusionReason {
+    pub fn is_included(&self) -> bool {
+        matches!(self, Self::Included { .. })
+    }
+}
+
+/// First pattern in `patterns` (as configured) matching `rel`.
+fn matching_pattern(rel: &str, patterns: &[String]) -> Result<Option<String>> {
+    for pat in patterns {
+        if should_exclude(rel, &build_exclude_globset(std::slice::from_ref(pat))?) {
+            return Ok(Some(pat.clone()));
+        }
+    }
+    Ok(None)
+}
+
+/// `explain_path_with` using the collection options from `cfg`.
+pub fn explain_path(cfg: &Config, project_root: &Path, rel_path: &str) -> Result<ExclusionReason> {
+    explain_path_with(project_root, rel_path, &CollectOptions::from_config(cfg))
+}
+
+/// Run the `visit_candidates` + `file_blobs` filters for one project-relative path and report
+/// the first one that rejects it.
+pub fn explain_path_with(
+    project_root: &Path,
+    rel_path: &str,
+    opts: &CollectOptions,
+) -> Result<ExclusionReason> {
+    let base_dir = if project_root.is_file() {
+        project_root.parent().unwrap_or(project_root)
+    } else {
+        project_root
+    };
+    let rel = rel_path.replace('\\', "/");
+    let rel = rel.trim_start_matches("./").trim_matches('/');
+    if rel.is_empty() || Path::new(rel).is_absolute() || rel.split('/').any(|c| c == "..") {
+        return Ok(ExclusionReason::OutsideProject);
+    }
+    let target = base_dir.join(rel);
+    if !target.exists() {
+        return Ok(ExclusionReason::NotFound);
+    }
+    if target.is_dir() {
+        return Ok(ExclusionReason::Directory);
+    }
+    if project_root.is_file() && target != project_root {
+        return Ok(ExclusionReason::OutsideProject);
+    }
+
+    let is_forced = should_exclude(rel, &build_exclude_globset(&opts.force_include)?)
+        || keep_globset(project_root, opts).is_match(rel);
+
+    // 与 visit_candidates 相同的遍历设置，只进入目标的祖先目录，以复用各层 .gitignore
+    let mut walk = WalkBuilder::new(project_root);
+    walk.git_ignore(true)
+        .git_global(true)
+        .git_exclude(true)
+        .parents(!opts.treat_root_as_repo)
+        .hidden(false);
+    let target_bg = target.clone();
+    walk.filter_entry(move |e| target_bg.starts_with(e.path()));
+    let visited = walk
+        .build()
+        .filter_map(|d| d.ok())
+        .any(|d| d.path() == target);
+    if !visited && !is_forced {
+        return Ok(ExclusionReason::Gitignored);
+    }
+
+    if rel.starts_with(&format!("{PROJECT_STATE_DIR}/")) {
+        return Ok(ExclusionReason::StateDir);
+    }
+    if !is_forced && tracked_filter(base_dir, opts).is_some_and(|t| !t.contains(rel)) {
+        return Ok(ExclusionReason::Untracked);
+    }
+    let excludes = with_presets(&opts.exclude_patterns, &opts.exclude_presets);
+    if !is_forced && let Some(pattern) = matching_pattern(rel, &excludes)? {
+        return Ok(ExclusionReason::ExcludePattern { pattern });
+    }
+    let type_check = if is_forced && opts.force_include_any_extension {
+        Some(true)
+    } else {
+        GitAttributes::load(base_dir).classify(rel)
+    };
+    match type_check {
+        Some(false) => return Ok(ExclusionReason::GitattributesBinary),
+        Some(true) => {}
+        None => {
+            if !is_text_ext(&target, &opts.text_exts) {
+                let extension = target
+                    .extension()
+                    .map(|e| format!(".{}", e.to_string_lossy().to_lowercase()))
+                    .unwrap_or_default();
+                return Ok(ExclusionReason::Extension { extension });
             }
         }
     }
-    Ok(blobs)
+    if opts.filters_by_age()
+        && let Ok(mtime) = fs::metadata(&target).and_then(|m| m.modified())
+        && let Some(reason) = opts.age_exclusion(mtime)
+    {
+        return Ok(reason);
+    }
+    if !is_forced && let Some(pattern) = matching_pattern(rel, &opts.generated_patterns)? {
+        return Ok(ExclusionReason::GeneratedPattern { pattern });
+    }
+    let content = match read_text_with_encodings(&target) {
+        Ok(c) => apply_all(&opts.content_filters, c),
+        Err(e) => {
+            return Ok(ExclusionReason::Unreadable {
+                error: format!("{:#}", e),
+            });
+        }
+    };
+    if opts.skip_empty_files && content.trim().is_empty() {
+        return Ok(ExclusionReason::Empty);
+    }
+    match file_blobs(&target, rel, opts) {
+        Some(blobs) => Ok(ExclusionReason::Included {
+            chunks: blobs.len(),
+        }),
+        None => {
+            let lines = content.split_inclusive(['\n', '\r']).count().max(1);
+            Ok(ExclusionReason::Minified {
+                avg_line_bytes: content.len() / lines,
+                limit: opts.max_avg_line_bytes,
+            })
+        }
+    }
+}
+
+/// Read one file and split it into blobs; None if unreadable or filtered by content.
+pub fn file_blobs(p: &Path, rel_str: &str, opts: &CollectOptions) -> Option<Vec<BlobUpload>> {
+    let max_lines = opts.max_lines_for(p).max(1);
+    let content = apply_all(&opts.content_filters, read_text_with_encodings(p).ok()?);
+    if opts.skip_empty_files && content.trim().is_empty() {
+        tracing::debug!(path = %rel_str, "skip empty file");
+        return None;
+    }
+    // average line length, to skip minified-like files
+    let lines: Vec<&str> = content.split_inclusive(['\n', '\r']).collect();
+    if opts.max_avg_line_bytes > 0 && !lines.is_empty() {
+        let avg = content.len() / lines.len();
+        if avg > opts.max_avg_line_bytes {
+            tracing::debug!(path = %rel_str, avg_line_bytes = avg, "skip minified-like file");
+            return None;
+        }
+    }
+    let mtime_ms = fs::metadata(p)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_millis() as u64);
+    let mut blobs = text_blobs(rel_str, content, max_lines, opts.chunk_numbering, mtime_ms);
+    // 其余分块保持原编号，空白分块不上传
+    if opts.skip_empty_files {
+        blobs.retain(|b| !b.content.trim().is_empty());
+    }
+    Some(blobs)
+}
+
+/// Split in-memory `content` into blobs for the virtual path `rel_str`, chunked and named
+/// exactly as `file_blobs` does for a file on disk.
+pub fn text_blobs(
+    rel_str: &str,
+    content: String,
+    max_lines: usize,
+    chunk_numbering: ChunkNumbering,
+    mtime_ms: Option<u64>,
+) -> Vec<BlobUpload> {
+    let max_lines = max_lines.max(1);
+    let language = detect_language(rel_str, &content);
+    let (line_count, byte_size) = (Some(content.lines().count()), Some(content.len()));
+    let lines: Vec<&str> = content.split_inclusive(['\n', '\r']).collect();
+    if lines.len() <= max_lines {
+        return vec![BlobUpload {
+            path: rel_str.to_string(),
+            content,
+            language,
+            mtime_ms,
+            line_count,
+            byte_size,
+        }];
+    }
+    let total = lines.len().div_ceil(max_lines);
+    lines
+        .chunks(max_lines)
+        .enumerate()
+        .map(|(i, chunk)| BlobUpload {
+            path: format!("{}{}", rel_str, chunk_numbering.suffix(i + 1, total)),
+            content: chunk.concat(),
+            language: language.clone(),
+            mtime_ms,
+            line_count,
+            byte_size,
+        })
+        .collect()
+}
+
+/// Split content into `max_lines`-line chunks the same way indexing does.
+pub fn split_chunks(content: &str, max_lines: usize) -> Vec<String> {
+    let lines: Vec<&str> = content.split_inclusive(['\n', '\r']).collect();
+    if lines.len() <= max_lines.max(1) {
+        return vec![content.to_string()];
+    }
+    lines.chunks(max_lines.max(1)).map(|c| c.concat()).collect()
+}
+
+/// Decode a blob path into its file path and optional `(chunk, total)` from `#chunkNofM`.
+pub fn parse_blob_path(blob_path: &str) -> (&str, Option<(usize, usize)>) {
+    let Some((file, suffix)) = blob_path.rsplit_once("#chunk") else {
+        return (blob_path, None);
+    };
+    let parsed = suffix
+        .split_once("of")
+        .and_then(|(n, m)| Some((n.parse().ok()?, m.parse().ok()?)));
+    match parsed {
+        Some(nm) => (file, Some(nm)),
+        None => (blob_path, None),
+    }
+}
+
+/// Strip the `#chunkNofM` suffix from a blob path, yielding the file's relative path.
+pub fn blob_file_path(blob_path: &str) -> &str {
+    blob_path.split("#chunk").next().unwrap_or(blob_path)
+}
+
+/// Content-only hash, stable across renames (unlike `hash_blob_name`).
+pub fn hash_content(content: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(content.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Count new blobs whose content was already indexed under another path (renamed/moved files).
+pub fn count_moved(new_blobs: &[BlobUpload], known_content: &BTreeSet<String>) -> usize {
+    new_blobs
+        .iter()
+        .filter(|b| known_content.contains(&hash_content(&b.content)))
+        .count()
 }
 
 /// Compute incremental upload set: returns (new_blobs, all_blob_names).
@@ -217,12 +1487,29 @@ pub fn incremental_plan(
     for b in blobs {
         let h = hash_blob_name(&b.path, &b.content);
         if !existing.contains(&h) {
-            new_blobs.push(BlobUpload {
-                path: b.path.clone(),
-                content: b.content.clone(),
-            });
+            new_blobs.push(b.clone());
         }
         all_blob_names.push(h);
     }
     (new_blobs, all_blob_names)
 }
+
+/// Stored blob names for `project_key` that are absent from `all_blob_names` (deleted/changed content).
+pub fn removed_blobs(
+    project_key: &str,
+    projects: &ProjectsIndex,
+    all_blob_names: &[String],
+) -> Vec<String> {
+    let current: HashSet<&String> = all_blob_names.iter().collect();
+    projects
+        .0
+        .get(project_key)
+        .map(|stored| {
+            stored
+                .iter()
+                .filter(|h| !current.contains(h))
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default()
+}
@@ -2,14 +2,25 @@
 //!
 //! This crate exposes:
 //! - `config`: load/save configuration from `~/.augmcp/settings.toml`.
+//! - `doctor`: setup diagnostics behind `augmcp doctor`.
+//! - `filters`: content transforms (redaction, comment stripping) applied before upload.
 //! - `indexer`: incremental indexing with .gitignore and exclude patterns.
 //! - `backend`: REST calls to upload blobs and perform retrieval.
+//! - `blocking` (feature `blocking`): synchronous wrappers around `service`.
 //! - `server`: rmcp server with a `search_context` tool.
+//! - `prompts`: MCP prompt templates expanding into `search_context` queries.
+//! - `rate_limit`: per-client token buckets for the HTTP API.
 
 pub mod backend;
+#[cfg(feature = "blocking")]
+pub mod blocking;
 pub mod config;
+pub mod doctor;
+pub mod filters;
 pub mod http_router;
 pub mod indexer;
+pub mod prompts;
+pub mod rate_limit;
 pub mod server;
 pub mod service;
 pub mod tasks;
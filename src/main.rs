@@ -1,16 +1,92 @@
-use augmcp::backend;
 use augmcp::service;
 use augmcp::{AppState, AugServer, config::Config};
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
 use rmcp::serve_server;
 //
 use tracing_appender::rolling;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use tracing_subscriber::{Layer, layer::SubscriberExt, util::SubscriberInitExt};
 
 #[derive(Debug, Clone, ValueEnum)]
 enum TransportKind {
     Stdio,
     Http,
+    /// HTTP (REST + /mcp) over a Unix domain socket
+    Uds,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Index a project and write its shareable blob manifest to <path>/.augmcp/manifest.json
+    Export {
+        /// Project root path
+        path: String,
+    },
+    /// Check settings, backend reachability/token and permissions; optionally dry-run a project
+    Doctor {
+        /// Project path to collect (dry run: nothing is uploaded or persisted)
+        #[arg(long)]
+        path: Option<String>,
+    },
+    /// Delete local index state (projects, index metadata, aliases); settings.toml is kept
+    Reset {
+        /// Keep aliases.json
+        #[arg(long, default_value_t = false)]
+        keep_aliases: bool,
+        /// Do not ask for confirmation
+        #[arg(long, default_value_t = false)]
+        yes: bool,
+    },
+    /// Search text piped on stdin as a one-off ephemeral project (not saved to projects.json)
+    SearchStdin {
+        /// Query
+        query: String,
+        /// Virtual file name for the piped content (drives language detection and blob paths)
+        #[arg(long, default_value = "stdin")]
+        name: String,
+    },
+    /// Index a project once, then answer queries read line by line from stdin
+    /// (`:reindex` refreshes the index, `:quit` or EOF exits)
+    Repl {
+        /// Project alias (bound to --path when both are given)
+        #[arg(long)]
+        alias: Option<String>,
+        /// Project root path
+        #[arg(long)]
+        path: Option<String>,
+    },
+    /// Manage aliases
+    Alias {
+        #[command(subcommand)]
+        action: AliasCommand,
+    },
+    /// Shrink each indexed project's stored blob list to the blobs still present on disk
+    Compact,
+    /// Print the resolved settings/data/log paths
+    Paths {
+        /// Output format
+        #[arg(long, value_enum, default_value = "text")]
+        output: OutputFormat,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum AliasCommand {
+    /// Bind an alias to a project path (namespaced by alias_namespace)
+    Add {
+        /// Alias name
+        name: String,
+        /// Project root path
+        path: String,
+        /// Print the normalized path and project key without writing aliases.json
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+    },
 }
 
 #[derive(Parser, Debug)]
@@ -20,12 +96,17 @@ enum TransportKind {
     about = "MCP server for code indexing + retrieval"
 )]
 struct Cli {
-    /// Transport: stdio or http
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Transport: stdio, http or uds
     #[arg(long, value_enum, default_value = "http")]
     transport: TransportKind,
     /// HTTP bind address when transport=http
     #[arg(long, default_value = "127.0.0.1:8888")]
     bind: String,
+    /// Socket path when transport=uds
+    #[arg(long, default_value = "/tmp/augmcp.sock")]
+    uds_path: std::path::PathBuf,
     /// Override BASE_URL
     #[arg(long)]
     base_url: Option<String>,
@@ -41,6 +122,16 @@ struct Cli {
     /// One-shot run without MCP: query
     #[arg(long)]
     oneshot_query: Option<String>,
+    /// One-shot run: emit upload progress as JSON lines on stderr
+    #[arg(long, default_value_t = false)]
+    progress: bool,
+    /// stdio transport: no startup banner and no console logs (the file log is kept)
+    #[arg(long, default_value_t = false)]
+    quiet: bool,
+    /// Serve even while base_url/token are the placeholder defaults (backend calls then fail
+    /// with a configuration hint)
+    #[arg(long, default_value_t = false)]
+    allow_unconfigured: bool,
 }
 
 #[tokio::main]
@@ -48,61 +139,292 @@ async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
     let cfg = Config::load_with_overrides(cli.base_url, cli.token)?;
 
-    // Setup logging: console (info) + rolling file (debug)
+    // Setup logging: console (info) + rolling file (debug), file layer optional
     let log_dir = cfg.log_dir();
-    std::fs::create_dir_all(&log_dir).ok();
-    let file_appender = rolling::daily(&log_dir, "augmcp.log");
-    let (file_writer, _guard) = tracing_appender::non_blocking(file_appender);
+    let (file_layer, _guard) = if cfg.settings.file_logging {
+        std::fs::create_dir_all(&log_dir).ok();
+        let file_appender = rolling::daily(&log_dir, "augmcp.log");
+        let (file_writer, guard) = tracing_appender::non_blocking(file_appender);
+        let layer = tracing_subscriber::fmt::layer()
+            .with_ansi(false)
+            .with_writer(file_writer);
+        (Some(layer), Some(guard))
+    } else {
+        (None, None)
+    };
+    // stdio 模式下 stdout 只能承载 MCP 帧：控制台日志改写到 stderr，--quiet 时关闭
+    // search-stdin / repl / alias 的 stdout 只输出结果，日志同样改写到 stderr
+    let stdio = (matches!(cli.transport, TransportKind::Stdio) && cli.command.is_none())
+        || matches!(
+            cli.command,
+            Some(Command::SearchStdin { .. } | Command::Repl { .. } | Command::Alias { .. })
+        );
+    let console_layer = if stdio {
+        (!cli.quiet).then(|| {
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(std::io::stderr)
+                .boxed()
+        })
+    } else {
+        Some(tracing_subscriber::fmt::layer().with_ansi(true).boxed())
+    };
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()),
         )
-        .with(tracing_subscriber::fmt::layer().with_ansi(true))
-        .with(
-            tracing_subscriber::fmt::layer()
-                .with_ansi(false)
-                .with_writer(file_writer),
-        )
+        .with(console_layer)
+        .with(file_layer)
         .init();
 
     if cli.persist_config {
         cfg.save()?;
     }
-    tracing::info!(config_file = %cfg.settings_path.display(), data_dir = %cfg.data_dir.display(), log_file = %log_dir.join("augmcp.log").display(), "paths initialized");
+    if cfg.settings.file_logging {
+        tracing::info!(config_file = %cfg.settings_path.display(), data_dir = %cfg.data_dir.display(), log_file = %log_dir.join("augmcp.log").display(), "paths initialized");
+    } else {
+        tracing::info!(config_file = %cfg.settings_path.display(), data_dir = %cfg.data_dir.display(), "paths initialized; file logging disabled (console only)");
+    }
+
+    if let Err(e) = augmcp::indexer::ProjectsIndex::migrate_if_needed(&cfg.projects_file()) {
+        tracing::warn!(error = %e, "failed to migrate projects index");
+    }
+    if let Err(e) = service::migrate_project_keys(&cfg) {
+        tracing::warn!(error = %e, "failed to migrate project keys");
+    }
+
+    if let Some(cmd) = cli.command {
+        match cmd {
+            Command::Export { path } => {
+                let (project_key, path) = cfg.resolve_project(&path)?;
+                let out = service::export_manifest(&cfg, &project_key, &path).await?;
+                println!("manifest written: {}", out.display());
+            }
+            Command::Doctor { path } => {
+                let checks = augmcp::doctor::run(&cfg, path.as_deref()).await;
+                for c in &checks {
+                    println!("{}", c);
+                }
+                if augmcp::doctor::has_failures(&checks) {
+                    anyhow::bail!("doctor found failing checks");
+                }
+            }
+            Command::Reset { keep_aliases, yes } => {
+                if !yes {
+                    eprint!(
+                        "This deletes all local index state under {}. Continue? [y/N] ",
+                        cfg.root_dir.display()
+                    );
+                    let mut answer = String::new();
+                    std::io::stdin().read_line(&mut answer)?;
+                    if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+                        println!("reset cancelled");
+                        return Ok(());
+                    }
+                }
+                let cleared = service::reset_local_state(&cfg, keep_aliases)?;
+                if cleared.is_empty() {
+                    println!("nothing to clear");
+                }
+                for p in cleared {
+                    println!("removed: {}", p.display());
+                }
+            }
+            Command::SearchStdin { query, name } => {
+                let mut content = String::new();
+                std::io::Read::read_to_string(&mut std::io::stdin(), &mut content)?;
+                let result = service::search_text(&cfg, &name, content, &query).await?;
+                println!("{}", result.into_text(&cfg));
+            }
+            Command::Repl { alias, path } => {
+                let (project_key, path, cfg) =
+                    service::resolve_target_with_config(&cfg, alias, path, None)?;
+                run_repl(&cfg, &project_key, &path).await?;
+            }
+            Command::Alias {
+                action:
+                    AliasCommand::Add {
+                        name,
+                        path,
+                        dry_run,
+                    },
+            } => {
+                let binding = service::bind_alias(&cfg, &name, &path, dry_run)?;
+                println!("alias: {}", binding.alias);
+                println!("path: {}", binding.path);
+                println!("project_key: {}", binding.project_key);
+                if let Some(old) = &binding.replaces {
+                    println!("replaces: {}", old);
+                }
+                if dry_run {
+                    println!("dry run: {} not modified", cfg.aliases_file().display());
+                }
+            }
+            Command::Compact => {
+                let report = service::compact_projects(&cfg)?;
+                for p in &report.compacted {
+                    println!(
+                        "compacted: {} ({} -> {} entries)",
+                        p.project_key, p.entries_before, p.entries_after
+                    );
+                }
+                for key in &report.skipped {
+                    println!("skipped (directory missing or unreadable): {}", key);
+                }
+                println!(
+                    "entries removed: {}, bytes reclaimed: {}",
+                    report.entries_removed, report.bytes_reclaimed
+                );
+            }
+            Command::Paths { output } => {
+                let paths = cfg.resolved_paths();
+                match output {
+                    OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&paths)?),
+                    OutputFormat::Text => {
+                        println!("settings_path: {}", paths.settings_path.display());
+                        println!("data_dir: {}", paths.data_dir.display());
+                        println!("projects_file: {}", paths.projects_file.display());
+                        println!("index_meta_file: {}", paths.index_meta_file.display());
+                        println!("aliases_file: {}", paths.aliases_file.display());
+                        println!("log_dir: {}", paths.log_dir.display());
+                    }
+                }
+            }
+        }
+        return Ok(());
+    }
 
     // One-shot direct execution (no MCP) for quick testing
     if let (Some(path), Some(query)) = (cli.oneshot_path.clone(), cli.oneshot_query.clone()) {
-        let project_key = augmcp::config::normalize_path(&path)?;
-        let (_total, _newn, _existing, all_blob_names) =
-            service::index_and_persist(&cfg, &project_key, &path, false).await?;
-        let result = backend::retrieve_formatted(&cfg, &all_blob_names, &query).await?;
-        println!("{}", result);
+        let (project_key, path, cfg) =
+            service::resolve_search_target(&cfg, None, Some(path), None)?;
+        let report = if cli.progress {
+            service::index_and_persist_with_progress(
+                &cfg,
+                &project_key,
+                &path,
+                false,
+                |_| {},
+                |p| {
+                    let line = serde_json::json!({
+                        "uploaded_items": p.uploaded_items,
+                        "total_items": p.total_items,
+                        "chunk_index": p.chunk_index,
+                        "chunks_total": p.chunks_total,
+                        "percent": p.percent(false),
+                    });
+                    eprintln!("{}", line);
+                },
+            )
+            .await?
+        } else {
+            service::index_and_persist(&cfg, &project_key, &path, false).await?
+        };
+        let result = service::retrieve(&cfg, &project_key, &report.all_blob_names, &query).await?;
+        println!("{}", result.into_text(&cfg));
         return Ok(());
     }
 
+    // 未配置 base_url/token 时拒绝启动服务，除非显式 --allow-unconfigured
+    if let Some(msg) = cfg.unconfigured_message() {
+        if !cli.allow_unconfigured {
+            anyhow::bail!("{msg}; pass --allow-unconfigured to start anyway");
+        }
+        tracing::warn!("{msg}; starting anyway (--allow-unconfigured)");
+    }
+
     let server = AugServer::new(cfg.clone());
+    // 定时增量重建索引只随 HTTP 服务运行
+    if !matches!(cli.transport, TransportKind::Stdio) {
+        service::spawn_reindex_scheduler(cfg.clone(), server.tasks().clone());
+    }
 
     match cli.transport {
         TransportKind::Stdio => {
-            println!("augmcp stdio server started");
+            if !cli.quiet {
+                eprintln!("augmcp stdio server started");
+            }
             let io = (tokio::io::stdin(), tokio::io::stdout());
-            serve_server(server, io).await?;
+            // 等待服务结束（stdin 关闭）；直接丢弃 RunningService 会立即取消服务
+            serve_server(server, io).await?.waiting().await?;
         }
         TransportKind::Http => {
             let app_state = AppState {
                 server: server.clone(),
-                tasks: augmcp::tasks::TaskManager::new(),
+                tasks: server.tasks().clone(),
             };
             let router = augmcp::http_router::build_router(app_state);
             let listener = tokio::net::TcpListener::bind(&cli.bind).await?;
             tracing::info!("augmcp http server listening on {}", &cli.bind);
-            axum::serve(listener, router)
-                .with_graceful_shutdown(async {
+            axum::serve(
+                listener,
+                router.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+            )
+            .with_graceful_shutdown(async {
+                let _ = tokio::signal::ctrl_c().await;
+            })
+            .await?;
+        }
+        TransportKind::Uds => {
+            #[cfg(unix)]
+            {
+                let app_state = AppState {
+                    server: server.clone(),
+                    tasks: server.tasks().clone(),
+                };
+                let router = augmcp::http_router::build_router(app_state);
+                tracing::info!("augmcp http server listening on {}", cli.uds_path.display());
+                augmcp::http_router::serve_unix(router, &cli.uds_path, async {
                     let _ = tokio::signal::ctrl_c().await;
                 })
                 .await?;
+            }
+            #[cfg(not(unix))]
+            anyhow::bail!(
+                "--transport uds is not supported on this platform (Unix domain sockets need Unix); use --transport http"
+            );
         }
     }
 
     Ok(())
 }
+
+/// REPL 主循环：索引一次，之后复用 blob 列表逐行检索；单条查询失败只打印错误。
+async fn run_repl(cfg: &Config, project_key: &str, path: &str) -> anyhow::Result<()> {
+    use std::io::IsTerminal;
+    use tokio::io::AsyncBufReadExt;
+
+    let mut names = service::ensure_indexed(cfg, project_key, path, true).await?;
+    let interactive = std::io::stdin().is_terminal();
+    if interactive {
+        eprintln!(
+            "indexed {} blobs; type a query, :reindex to refresh, :quit to exit",
+            names.len()
+        );
+    }
+    let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        if interactive {
+            eprint!("> ");
+        }
+        let Some(line) = lines.next_line().await? else {
+            break;
+        };
+        match line.trim() {
+            "" => {}
+            ":quit" | ":q" => break,
+            ":reindex" => match service::index_and_persist(cfg, project_key, path, false).await {
+                Ok(report) => {
+                    names = report.all_blob_names;
+                    eprintln!("reindexed: {} blobs", names.len());
+                }
+                Err(e) => eprintln!("error: {e:#}"),
+            },
+            query => match service::retrieve(cfg, project_key, &names, query).await {
+                Ok(result) => println!("{}", result.into_text(cfg)),
+                Err(e) => eprintln!("error: {e:#}"),
+            },
+        }
+    }
+    Ok(())
+}
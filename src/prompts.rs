@@ -0,0 +1,90 @@
+//! Prompt templates that expand into `search_context` queries.
+
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+
+pub struct PromptTemplate {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// (argument name, description, required)
+    pub arguments: &'static [(&'static str, &'static str, bool)],
+    /// Query template; `{arg}` placeholders are replaced by argument values
+    pub template: &'static str,
+}
+
+/// Arguments accepted by every template to select the target project.
+pub const TARGET_ARGS: &[(&str, &str, bool)] = &[
+    (
+        "project_root_path",
+        "Absolute path to the project root (optional when alias is provided)",
+        false,
+    ),
+    ("alias", "Project alias registered previously", false),
+];
+
+pub const TEMPLATES: &[PromptTemplate] = &[
+    PromptTemplate {
+        name: "explain-file",
+        description: "Explain what a file does, its main types/functions and how it fits into the project",
+        arguments: &[("path", "File path relative to the project root", true)],
+        template: "Explain the file `{path}`: its purpose, the main types and functions it defines, and how it is used by the rest of the codebase.",
+    },
+    PromptTemplate {
+        name: "find-usages",
+        description: "Find definitions and call sites of a symbol",
+        arguments: &[(
+            "symbol",
+            "Function, type, or variable name to look up",
+            true,
+        )],
+        template: "Find the definition of `{symbol}` and all places where it is used or called, including how it is constructed and passed around.",
+    },
+    PromptTemplate {
+        name: "summarize-module",
+        description: "Summarize a module or directory: responsibilities, public API, dependencies",
+        arguments: &[(
+            "module",
+            "Module name or directory path relative to the project root",
+            true,
+        )],
+        template: "Summarize the module `{module}`: its responsibilities, public API, key data structures, and which other modules it depends on.",
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static PromptTemplate> {
+    TEMPLATES.iter().find(|t| t.name == name)
+}
+
+/// Expand a template into a `search_context` query string.
+pub fn expand_query(tpl: &PromptTemplate, args: &HashMap<String, String>) -> Result<String> {
+    let mut out = tpl.template.to_string();
+    for (name, _, required) in tpl.arguments {
+        let value = args.get(*name).map(|s| s.trim()).unwrap_or_default();
+        if value.is_empty() && *required {
+            return Err(anyhow!("missing required argument: {}", name));
+        }
+        out = out.replace(&format!("{{{}}}", name), value);
+    }
+    Ok(out)
+}
+
+/// Render the full prompt message instructing the client to call `search_context`.
+pub fn render(name: &str, args: &HashMap<String, String>) -> Result<String> {
+    let tpl = find(name).ok_or_else(|| anyhow!("unknown prompt: {}", name))?;
+    let query = expand_query(tpl, args)?;
+    let mut target = Vec::new();
+    for (arg, _, _) in TARGET_ARGS {
+        if let Some(v) = args.get(*arg).filter(|v| !v.trim().is_empty()) {
+            target.push(format!("{}=\"{}\"", arg, v.trim()));
+        }
+    }
+    let target = if target.is_empty() {
+        String::new()
+    } else {
+        format!(" ({})", target.join(", "))
+    };
+    Ok(format!(
+        "Use the search_context tool{} with the following query, then answer based on the retrieved code context.\n\nquery: {}",
+        target, query
+    ))
+}
@@ -0,0 +1,62 @@
+//! Per-client token buckets for the HTTP API (`rate_limit_per_min`).
+
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Buckets kept before idle, fully refilled ones are dropped.
+const PRUNE_ABOVE: usize = 1024;
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// Token buckets keyed by client (IP). Each holds up to `per_min` tokens and refills
+/// continuously at `per_min` per minute.
+#[derive(Clone, Default)]
+pub struct RateLimiter {
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take one token for `key`. Err carries how long until a token is available.
+    pub fn check(&self, key: &str, per_min: u32) -> Result<(), Duration> {
+        self.check_at(key, per_min, Instant::now())
+    }
+
+    fn check_at(&self, key: &str, per_min: u32, now: Instant) -> Result<(), Duration> {
+        if per_min == 0 {
+            return Ok(());
+        }
+        let capacity = per_min as f64;
+        let per_sec = capacity / 60.0;
+        let mut buckets = self.buckets.lock();
+        if buckets.len() > PRUNE_ABOVE {
+            buckets.retain(|_, b| {
+                b.tokens + now.duration_since(b.refilled_at).as_secs_f64() * per_sec < capacity
+            });
+        }
+        let b = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            refilled_at: now,
+        });
+        let elapsed = now.duration_since(b.refilled_at).as_secs_f64();
+        b.tokens = (b.tokens + elapsed * per_sec).min(capacity);
+        b.refilled_at = now;
+        if b.tokens >= 1.0 {
+            b.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - b.tokens) / per_sec))
+        }
+    }
+}
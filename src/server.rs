@@ -1,18 +1,41 @@
-//! rmcp server exposing `search_context` tool.
+//! rmcp server exposing `search_context` tool and indexed projects as resources.
 
-use crate::config::Config;
+use crate::{config::Config, service::RetrievalLimiter, tasks::TaskManager};
 use anyhow::Result;
 use parking_lot::Mutex;
 use rmcp::{
-    ErrorData as McpError, ServerHandler,
+    ErrorData as McpError, Peer, RoleServer, ServerHandler,
     handler::server::{router::tool::ToolRouter, wrapper::Parameters},
     model::{
-        CallToolResult, Content, Implementation, ProtocolVersion, ServerCapabilities, ServerInfo,
+        AnnotateAble, CallToolResult, Content, GetPromptRequestParam, GetPromptResult,
+        Implementation, InitializeRequestParam, InitializeResult, ListPromptsResult,
+        ListResourceTemplatesResult, ListResourcesResult, Meta, PaginatedRequestParam,
+        ProgressNotificationParam, ProgressToken, Prompt, PromptArgument, PromptMessage,
+        PromptMessageRole, ProtocolVersion, RawResource, RawResourceTemplate,
+        ReadResourceRequestParam, ReadResourceResult, ResourceContents, ServerCapabilities,
+        ServerInfo,
     },
-    schemars, tool, tool_handler, tool_router,
+    schemars,
+    service::RequestContext,
+    tool, tool_handler, tool_router,
 };
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::{collections::BTreeMap, path::Path, sync::Arc};
+
+const PROJECTS_URI_PREFIX: &str = "augmcp://projects/";
+
+/// Newest MCP protocol version advertised: the latest one the linked rmcp implements.
+pub const SUPPORTED_PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion::LATEST;
+
+/// Version to answer `initialize` with: the client's when it is older than ours (so 2024-11-05
+/// clients keep working), otherwise ours. Versions are ISO dates, so they order as strings.
+pub fn negotiate_protocol_version(client: &ProtocolVersion) -> ProtocolVersion {
+    if *client < SUPPORTED_PROTOCOL_VERSION {
+        client.clone()
+    } else {
+        SUPPORTED_PROTOCOL_VERSION
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
 pub struct SearchArgs {
@@ -25,8 +48,50 @@ pub struct SearchArgs {
     /// When true (default), skip indexing if project already has cached blobs
     #[serde(skip_serializing_if = "Option::is_none")]
     pub skip_index_if_indexed: Option<bool>,
+    /// Quick mode: re-read only files modified since the last index (by mtime), then search. Overrides skip_index_if_indexed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quick: Option<bool>,
     /// Natural language query
-    pub query: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+    /// Multiple queries answered against the same index (run concurrently); results are returned as JSON keyed by query
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queries: Option<Vec<String>>,
+    /// Collapse whitespace and strip surrounding quotes from queries before sending (default false)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub normalize_query: Option<bool>,
+    /// Text prefixed to every query as extra context, e.g. "Rust web service"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prepend_context: Option<String>,
+    /// Only search files whose project-relative path starts with this, e.g. "services/auth/"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path_prefix: Option<String>,
+    /// Only search files in these languages, e.g. ["rust"] (detected from extension or shebang; case-insensitive)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub languages: Option<Vec<crate::indexer::Language>>,
+    /// Explicit cache key used instead of the path-derived one, e.g. "myorg/api"; lets the same code mounted at different paths share an index
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub project_key: Option<String>,
+    /// Merge snippets from different chunks of the same file under one `Path:` heading and list them per file in the structured result (default false)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dedupe_by_file: Option<bool>,
+}
+
+/// Regroup `text` by file when asked; the text is kept as is when its format is not recognized.
+fn group_retrieval(
+    text: String,
+    dedupe: bool,
+) -> (String, Option<crate::backend::GroupedRetrieval>) {
+    if !dedupe {
+        return (text, None);
+    }
+    match crate::backend::GroupedRetrieval::parse(&text) {
+        Some(g) => (g.to_text(), Some(g)),
+        None => {
+            tracing::debug!("retrieval format not recognized; skipping dedupe_by_file");
+            (text, None)
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
@@ -40,18 +105,70 @@ pub struct IndexArgs {
     /// Force full re-index (ignore cache)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub force_full: Option<bool>,
+    /// Explicit cache key used instead of the path-derived one, e.g. "myorg/api"; lets the same code mounted at different paths share an index
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub project_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct IndexStatusArgs {
+    /// Task id returned by index_project_async
+    pub task_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct ReadFileArgs {
+    /// Absolute path to the project root (use forward slashes on Windows). Optional if alias resolves
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub project_root_path: Option<String>,
+    /// Optional project alias registered previously
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    /// File path relative to the project root; a `#chunkNofM` suffix from search output selects that chunk
+    pub path: String,
+    /// 1-based chunk number (as in `#chunkNofM`); overrides the suffix. Omit for the whole file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct ProjectMetaArgs {
+    /// Absolute path to the project root (use forward slashes on Windows). Optional if alias resolves
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub project_root_path: Option<String>,
+    /// Optional project alias registered previously
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    /// Explicit cache key the project was indexed under, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub project_key: Option<String>,
+    /// set_project_meta only: flat string map replacing the stored metadata, e.g. {"team": "search"}; {} clears it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<BTreeMap<String, String>>,
 }
 
 #[derive(Clone)]
 pub struct AugServer {
     cfg: Arc<Mutex<Config>>, // runtime overrides supported
+    tasks: TaskManager,
+    retrievals: RetrievalLimiter,
     tool_router: ToolRouter<AugServer>,
 }
 
 impl AugServer {
+    /// Server with its own `TaskManager` limited by `max_concurrent_indexes`.
     pub fn new(cfg: Config) -> Self {
+        let tasks = TaskManager::with_limit(cfg.settings.max_concurrent_indexes);
+        Self::with_tasks(cfg, tasks)
+    }
+
+    /// Server sharing `tasks` with other front ends (e.g. the HTTP `AppState`), so async
+    /// indexes started over MCP and HTTP are tracked together.
+    pub fn with_tasks(cfg: Config, tasks: TaskManager) -> Self {
         Self {
+            retrievals: RetrievalLimiter::from_config(&cfg),
             cfg: Arc::new(Mutex::new(cfg)),
+            tasks,
             tool_router: Self::tool_router(),
         }
     }
@@ -59,6 +176,202 @@ impl AugServer {
     pub fn get_cfg(&self) -> Config {
         self.cfg.lock().clone()
     }
+
+    pub fn tasks(&self) -> &TaskManager {
+        &self.tasks
+    }
+
+    /// Slots shared by every search of this server (`max_concurrent_retrievals`).
+    pub fn retrieval_limiter(&self) -> &RetrievalLimiter {
+        &self.retrievals
+    }
+}
+
+/// Percent-encode a project key so it fits into a single URI path segment.
+fn encode_key(key: &str) -> String {
+    let mut out = String::with_capacity(key.len());
+    for b in key.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+fn decode_key(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3)?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+pub fn project_resource_uri(project_key: &str) -> String {
+    format!("{}{}", PROJECTS_URI_PREFIX, encode_key(project_key))
+}
+
+/// Parse `augmcp://projects/<key>[/files/<rel>]` into (project_key, Option<rel_path>).
+fn parse_resource_uri(uri: &str) -> Option<(String, Option<String>)> {
+    let rest = uri.strip_prefix(PROJECTS_URI_PREFIX)?;
+    match rest.split_once("/files/") {
+        Some((key, rel)) if !rel.is_empty() => Some((decode_key(key)?, Some(decode_key(rel)?))),
+        Some(_) => None,
+        None => Some((decode_key(rest.trim_end_matches('/'))?, None)),
+    }
+}
+
+/// Build the JSON listing (files + stats) for an indexed project.
+fn project_listing(cfg: &Config, project_key: &str, indexed_blobs: usize) -> serde_json::Value {
+    let mut files: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+    let collected = crate::indexer::collect_blobs_with(
+        Path::new(project_key),
+        &crate::indexer::CollectOptions::from_config(cfg),
+    );
+    let error = match collected {
+        Ok(blobs) => {
+            for b in blobs {
+                let rel = crate::indexer::blob_file_path(&b.path).to_string();
+                let e = files.entry(rel).or_insert((0, 0));
+                e.0 += 1;
+                e.1 += b.content.len();
+            }
+            None
+        }
+        Err(e) => Some(e.to_string()),
+    };
+    let file_list: Vec<serde_json::Value> = files
+        .iter()
+        .map(|(path, (chunks, bytes))| {
+            serde_json::json!({
+                "path": path,
+                "chunks": chunks,
+                "bytes": bytes,
+                "uri": format!("{}/files/{}", project_resource_uri(project_key), path),
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "project_key": project_key,
+        "indexed_blobs": indexed_blobs,
+        "total_files": file_list.len(),
+        "total_bytes": files.values().map(|(_, b)| *b).sum::<usize>(),
+        "files": file_list,
+        "error": error,
+    })
+}
+
+/// Read one `#chunkN` region of a project file, split exactly as indexing does
+/// (content filters applied, per-extension `max_lines_per_blob`).
+fn read_project_chunk(
+    cfg: &Config,
+    project_key: &str,
+    root: &str,
+    rel: &str,
+    chunk: usize,
+) -> Result<String> {
+    let content = read_project_file(cfg, project_key, root, rel)?;
+    let opts = crate::indexer::CollectOptions::from_config(cfg);
+    let mut chunks = crate::indexer::split_chunks(&content, opts.max_lines_for(Path::new(rel)));
+    let total = chunks.len();
+    if chunk == 0 || chunk > total {
+        return Err(anyhow::anyhow!(
+            "chunk {} out of range: {} has {} chunk(s)",
+            chunk,
+            rel,
+            total
+        ));
+    }
+    Ok(chunks.swap_remove(chunk - 1))
+}
+
+/// Read a file of an indexed project as it was indexed (content filters applied). Refuses
+/// unindexed projects, paths escaping the root and files collection would skip (gitignored,
+/// excluded, state dir, ...).
+fn read_project_file(cfg: &Config, project_key: &str, root: &str, rel: &str) -> Result<String> {
+    if !crate::service::load_projects(cfg)
+        .0
+        .contains_key(project_key)
+    {
+        return Err(anyhow::anyhow!("project not indexed: {}", root));
+    }
+    let root_path = dunce::canonicalize(root)?;
+    let full = dunce::canonicalize(root_path.join(rel))
+        .map_err(|_| anyhow::anyhow!("file not found in project: {}", rel))?;
+    if !full.starts_with(&root_path) || !full.is_file() {
+        return Err(anyhow::anyhow!("file not found in project: {}", rel));
+    }
+    let opts = crate::indexer::CollectOptions::from_config(cfg);
+    if !crate::indexer::explain_path_with(&root_path, rel, &opts)?.is_included() {
+        return Err(anyhow::anyhow!("file not indexed: {}", rel));
+    }
+    let raw = crate::indexer::read_text_with_encodings(&full)?;
+    Ok(crate::filters::apply_all(&opts.content_filters, raw))
+}
+
+/// `index_and_persist_with_progress` that sends each upload step to the client as an MCP
+/// progress notification (`progress` = percent, `total` = 100), ending at 100 on success.
+async fn index_reporting_progress(
+    cfg: &Config,
+    project_key: &str,
+    path: &str,
+    force_full: bool,
+    peer: Peer<RoleServer>,
+    token: ProgressToken,
+) -> Result<crate::service::IndexReport> {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<(f64, String)>();
+    // 回调是同步的，通知经通道交给单独的任务按序发送
+    let forward = tokio::spawn(async move {
+        while let Some((progress, message)) = rx.recv().await {
+            let _ = peer
+                .notify_progress(ProgressNotificationParam {
+                    progress_token: token.clone(),
+                    progress,
+                    total: Some(100.0),
+                    message: Some(message),
+                })
+                .await;
+        }
+    });
+    let by_bytes = cfg.settings.progress_percent_by_bytes;
+    let mut last = 0.0;
+    let res = crate::service::index_and_persist_with_progress(
+        cfg,
+        project_key,
+        path,
+        force_full,
+        |_| {},
+        |p| {
+            let percent = p.percent(by_bytes);
+            // progress 必须递增
+            if percent > last {
+                last = percent;
+                let _ = tx.send((
+                    percent,
+                    format!("uploaded {}/{} blobs", p.uploaded_items, p.total_items),
+                ));
+            }
+        },
+    )
+    .await;
+    if let Ok(report) = &res
+        && last < 100.0
+    {
+        let _ = tx.send((100.0, format!("indexed {} blobs", report.total_blobs)));
+    }
+    drop(tx);
+    let _ = forward.await;
+    res
 }
 
 #[tool_router]
@@ -73,10 +386,11 @@ impl AugServer {
         Parameters(args): Parameters<SearchArgs>,
     ) -> Result<CallToolResult, McpError> {
         let cfg = self.get_cfg();
-        let (project_key, path) = match crate::service::resolve_target(
+        let (project_key, path, cfg) = match crate::service::resolve_search_target(
             &cfg,
             args.alias.clone(),
             args.project_root_path.clone(),
+            args.project_key.as_deref(),
         ) {
             Ok(v) => v,
             Err(e) => {
@@ -88,32 +402,125 @@ impl AugServer {
         };
         tracing::info!(path = %path, "search_context invoked");
         let skip = args.skip_index_if_indexed.unwrap_or(true);
-        let formatted = match crate::service::ensure_index_then_retrieve(
+        let dedupe = args.dedupe_by_file.unwrap_or(false);
+        let mut queries: Vec<String> = args.query.into_iter().collect();
+        let batched = args.queries.as_ref().is_some_and(|q| !q.is_empty());
+        queries.extend(args.queries.unwrap_or_default());
+        queries.retain(|q| !q.trim().is_empty());
+        if queries.is_empty() {
+            return Ok(CallToolResult::success(vec![Content::text(
+                "Error: provide query or queries",
+            )]));
+        }
+        let indexed = if args.quick.unwrap_or(false) {
+            crate::service::quick_index(&cfg, &project_key, &path).await
+        } else {
+            crate::service::ensure_indexed(&cfg, &project_key, &path, skip).await
+        };
+        let names = match indexed
+            .and_then(|names| match &args.path_prefix {
+                Some(prefix) => {
+                    crate::service::filter_blobs_by_prefix(&cfg, &project_key, &names, prefix)
+                }
+                None => Ok(names),
+            })
+            .and_then(|names| match &args.languages {
+                Some(langs) => {
+                    let langs: Vec<String> = langs.iter().map(|l| l.to_string()).collect();
+                    crate::service::filter_blobs_by_languages(&cfg, &project_key, &names, &langs)
+                }
+                None => Ok(names),
+            }) {
+            Ok(v) => v,
+            Err(e) => {
+                return Ok(CallToolResult::success(vec![Content::text(format!(
+                    "Error: {}",
+                    e
+                ))]));
+            }
+        };
+        let prep = crate::backend::QueryPreprocess {
+            normalize: args.normalize_query.unwrap_or(false),
+            prepend_context: args.prepend_context,
+        };
+        let sent: Vec<String> = queries.iter().map(|q| prep.apply(q)).collect();
+        if !batched {
+            let _permit = match self.retrievals.acquire().await {
+                Ok(p) => p,
+                Err(e) => {
+                    return Ok(CallToolResult::success(vec![Content::text(format!(
+                        "Error: {}",
+                        e
+                    ))]));
+                }
+            };
+            let retrieved = crate::service::retrieve(&cfg, &project_key, &names, &sent[0]).await;
+            return Ok(match retrieved {
+                Ok(r) => {
+                    let empty = r.is_empty();
+                    let (text, grouped) = group_retrieval(r.into_text(&cfg), dedupe && !empty);
+                    let mut res = CallToolResult::success(vec![Content::text(text)]);
+                    // 结构化信号：调用方无需匹配提示文本即可识别“无结果”
+                    let mut structured = serde_json::json!({ "empty": empty });
+                    if let Some(g) = grouped {
+                        structured["files"] = serde_json::json!(g.files);
+                    }
+                    res.structured_content = Some(structured);
+                    res
+                }
+                Err(e) => CallToolResult::success(vec![Content::text(format!("Error: {}", e))]),
+            });
+        }
+        // 结果按输入顺序返回，沿用调用方原始的 query 作为键；每条查询各占一个检索名额
+        let results: Vec<serde_json::Value> = crate::service::retrieve_many(
             &cfg,
+            &self.retrievals,
             &project_key,
-            &path,
-            &args.query,
-            skip,
+            names,
+            sent,
         )
         .await
-        {
-            Ok(s) => s,
-            Err(e) => format!("Error: {}", e),
-        };
-        Ok(CallToolResult::success(vec![Content::text(formatted)]))
+        .into_iter()
+        .zip(queries)
+        .map(|((_, r), query)| match r {
+            Ok(r) => {
+                let empty = r.is_empty();
+                let (text, grouped) = group_retrieval(r.into_text(&cfg), dedupe && !empty);
+                let mut entry = serde_json::json!({
+                    "query": query,
+                    "status": "success",
+                    "empty": empty,
+                    "result": text,
+                });
+                if let Some(g) = grouped {
+                    entry["files"] = serde_json::json!(g.files);
+                }
+                entry
+            }
+            Err(e) => {
+                serde_json::json!({"query": query, "status": "error", "result": e.to_string()})
+            }
+        })
+        .collect();
+        let text = serde_json::to_string_pretty(&serde_json::json!({ "results": results }))
+            .unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(text)]))
     }
     #[tool(
-        description = "Index a project and persist cache. Optionally bind an alias or force full re-index."
+        description = "Index a project and persist cache. Optionally bind an alias or force full re-index. Sends progress notifications when the request carries a progressToken."
     )]
     pub async fn index_project(
         &self,
         Parameters(args): Parameters<IndexArgs>,
+        meta: Meta,
+        peer: Peer<RoleServer>,
     ) -> Result<CallToolResult, McpError> {
         let cfg = self.get_cfg();
-        let (project_key, path) = match crate::service::resolve_target(
+        let (project_key, path, cfg) = match crate::service::resolve_target_with_config(
             &cfg,
             args.alias.clone(),
             args.project_root_path.clone(),
+            args.project_key.as_deref(),
         ) {
             Ok(v) => v,
             Err(e) => {
@@ -125,14 +532,187 @@ impl AugServer {
         };
         let force_full = args.force_full.unwrap_or(false);
         tracing::info!(path = %path, force_full, "index_project invoked");
-        match crate::service::index_and_persist(&cfg, &project_key, &path, force_full).await {
-            Ok((total, newn, existing, _)) => {
-                let stats = format!(
-                    "Index complete: total_blobs={}, new_blobs={}, existing_blobs={}",
-                    total, newn, existing
-                );
-                Ok(CallToolResult::success(vec![Content::text(stats)]))
+        // 与异步任务共用 max_concurrent_indexes 名额
+        let _permit = self.tasks().acquire_index_permit(&project_key).await;
+        // 客户端带 progressToken 时逐批发送上传进度通知，否则静默索引
+        let indexed = match meta.get_progress_token() {
+            Some(token) => {
+                index_reporting_progress(&cfg, &project_key, &path, force_full, peer, token).await
+            }
+            None => crate::service::index_and_persist(&cfg, &project_key, &path, force_full).await,
+        };
+        match indexed {
+            Ok(report) => Ok(CallToolResult::success(vec![Content::text(
+                report.summary(),
+            )])),
+            Err(e) => Ok(CallToolResult::success(vec![Content::text(format!(
+                "Error: {}",
+                e
+            ))])),
+        }
+    }
+
+    /// Start indexing in the background and return a task id at once; poll `get_index_status`.
+    #[tool(
+        description = "Start indexing a project in the background and return a task_id immediately. Poll get_index_status with it until phase is done, failed or aborted."
+    )]
+    pub async fn index_project_async(
+        &self,
+        Parameters(args): Parameters<IndexArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let cfg = self.get_cfg();
+        let (project_key, path, cfg) = match crate::service::resolve_target_with_config(
+            &cfg,
+            args.alias.clone(),
+            args.project_root_path.clone(),
+            args.project_key.as_deref(),
+        ) {
+            Ok(v) => v,
+            Err(e) => {
+                return Ok(CallToolResult::success(vec![Content::text(format!(
+                    "Error: {}",
+                    e
+                ))]));
+            }
+        };
+        let force_full = args.force_full.unwrap_or(false);
+        tracing::info!(path = %path, force_full, "index_project_async invoked");
+        let started = crate::service::start_index_task(
+            &self.tasks,
+            cfg,
+            project_key.clone(),
+            path.clone(),
+            force_full,
+        );
+        let result = match started {
+            Some(_) => format!("async indexing started for {}", &path),
+            None => format!("indexing already in progress for {}", &path),
+        };
+        let text = serde_json::to_string_pretty(&serde_json::json!({
+            "status": "accepted",
+            "result": result,
+            "task_id": self.tasks.task_id(&project_key),
+        }))
+        .unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(text)]))
+    }
+
+    /// Progress of a task started by `index_project_async`.
+    #[tool(
+        description = "Get the progress of an index_project_async task: phase (queued, collecting, uploading, done, failed, aborted), counters, percent and eta_secs."
+    )]
+    pub async fn get_index_status(
+        &self,
+        Parameters(args): Parameters<IndexStatusArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let Some((key, progress)) = self.tasks.get_by_task_id(&args.task_id) else {
+            return Ok(CallToolResult::success(vec![Content::text(format!(
+                "Error: unknown task_id {}",
+                args.task_id
+            ))]));
+        };
+        let text = serde_json::to_string_pretty(&serde_json::json!({
+            "status": "success",
+            "running": self.tasks.is_running(&key),
+            "project": key,
+            "eta_secs": progress.eta_secs(),
+            "progress": progress,
+        }))
+        .unwrap_or_default();
+        Ok(CallToolResult::success(vec![Content::text(text)]))
+    }
+
+    /// Replace the free-form metadata (team, description, ...) attached to an indexed project.
+    #[tool(
+        description = "Attach metadata to an indexed project as a flat string map (e.g. team, language, description), replacing what was stored; {} clears it. Does not affect indexing."
+    )]
+    pub async fn set_project_meta(
+        &self,
+        Parameters(args): Parameters<ProjectMetaArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let cfg = self.get_cfg();
+        let res = crate::service::resolve_target_keyed(
+            &cfg,
+            args.alias.clone(),
+            args.project_root_path.clone(),
+            args.project_key.as_deref(),
+        )
+        .and_then(|(project_key, _)| {
+            let meta = args
+                .meta
+                .clone()
+                .ok_or_else(|| anyhow::anyhow!("provide meta"))?;
+            crate::service::set_project_meta(&cfg, &project_key, meta.clone())?;
+            Ok((project_key, meta))
+        });
+        let text = match res {
+            Ok((project_key, meta)) => serde_json::to_string_pretty(&serde_json::json!({
+                "status": "success",
+                "project_key": project_key,
+                "meta": meta,
+            }))
+            .unwrap_or_default(),
+            Err(e) => format!("Error: {}", e),
+        };
+        Ok(CallToolResult::success(vec![Content::text(text)]))
+    }
+
+    /// Metadata attached to a project with `set_project_meta`.
+    #[tool(
+        description = "Get the metadata attached to a project with set_project_meta ({} when none)."
+    )]
+    pub async fn get_project_meta(
+        &self,
+        Parameters(args): Parameters<ProjectMetaArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let cfg = self.get_cfg();
+        let text = match crate::service::resolve_target_keyed(
+            &cfg,
+            args.alias.clone(),
+            args.project_root_path.clone(),
+            args.project_key.as_deref(),
+        ) {
+            Ok((project_key, _)) => serde_json::to_string_pretty(&serde_json::json!({
+                "status": "success",
+                "meta": crate::service::get_project_meta(&cfg, &project_key),
+                "project_key": project_key,
+            }))
+            .unwrap_or_default(),
+            Err(e) => format!("Error: {}", e),
+        };
+        Ok(CallToolResult::success(vec![Content::text(text)]))
+    }
+
+    /// Return the full content of a project file (or one chunk of it) read from local disk.
+    #[tool(
+        description = "Read a file (or a #chunkNofM region) from an indexed project's local disk, e.g. to expand a search_context snippet."
+    )]
+    pub async fn read_project_file(
+        &self,
+        Parameters(args): Parameters<ReadFileArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let cfg = self.get_cfg();
+        let (key, root) = match crate::service::resolve_target(
+            &cfg,
+            args.alias.clone(),
+            args.project_root_path.clone(),
+        ) {
+            Ok(v) => v,
+            Err(e) => {
+                return Ok(CallToolResult::success(vec![Content::text(format!(
+                    "Error: {}",
+                    e
+                ))]));
             }
+        };
+        let (rel, suffix) = crate::indexer::parse_blob_path(&args.path);
+        let chunk = args.chunk.or(suffix.map(|(n, _)| n));
+        let res = match chunk {
+            Some(n) => read_project_chunk(&cfg, &key, &root, rel, n),
+            None => read_project_file(&cfg, &key, &root, rel),
+        };
+        match res {
+            Ok(text) => Ok(CallToolResult::success(vec![Content::text(text)])),
             Err(e) => Ok(CallToolResult::success(vec![Content::text(format!(
                 "Error: {}",
                 e
@@ -145,13 +725,167 @@ impl AugServer {
 impl ServerHandler for AugServer {
     fn get_info(&self) -> ServerInfo {
         ServerInfo {
-            protocol_version: ProtocolVersion::V_2024_11_05,
-            capabilities: ServerCapabilities::builder().enable_tools().build(),
+            protocol_version: SUPPORTED_PROTOCOL_VERSION,
+            capabilities: ServerCapabilities::builder()
+                .enable_tools()
+                .enable_resources()
+                .enable_prompts()
+                .build(),
             server_info: Implementation::from_build_env(),
             instructions: Some(
-                "augmcp tools: search_context(project_root_path?|alias?, query, skip_index_if_indexed?=true); index_project(project_root_path?|alias?, force_full?=false). Use forward slashes on Windows."
+                "augmcp tools: search_context(project_root_path?|alias?, query|queries[], skip_index_if_indexed?=true, quick?=false); index_project(project_root_path?|alias?, force_full?=false); index_project_async(same args) -> task_id, then poll get_index_status(task_id); read_project_file(project_root_path?|alias?, path, chunk?); set_project_meta(project_root_path?|alias?, meta{}) / get_project_meta(project_root_path?|alias?). Resources: augmcp://projects/<key> (file list + stats), augmcp://projects/<key>/files/<rel> (file content). Prompts: explain-file, find-usages, summarize-module. Use forward slashes on Windows."
                     .to_string(),
             ),
         }
     }
+
+    // Negotiate here rather than relying on the transport: stateless HTTP sessions return the
+    // initialize result as-is.
+    async fn initialize(
+        &self,
+        request: InitializeRequestParam,
+        context: RequestContext<RoleServer>,
+    ) -> Result<InitializeResult, McpError> {
+        let protocol_version = negotiate_protocol_version(&request.protocol_version);
+        tracing::info!(
+            client = %request.protocol_version,
+            negotiated = %protocol_version,
+            "mcp initialize"
+        );
+        if context.peer.peer_info().is_none() {
+            context.peer.set_peer_info(request);
+        }
+        Ok(InitializeResult {
+            protocol_version,
+            ..self.get_info()
+        })
+    }
+
+    async fn list_resources(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListResourcesResult, McpError> {
+        let cfg = self.get_cfg();
+        let projects = crate::service::load_projects(&cfg);
+        let resources = crate::service::known_projects(&cfg)
+            .into_iter()
+            .map(|(key, path)| {
+                let mut r = RawResource::new(project_resource_uri(&path), cfg.display_path(&path));
+                r.description = Some(format!(
+                    "Indexed project ({} blobs): file list and stats",
+                    projects.0.get(&key).map_or(0, |b| b.len())
+                ));
+                r.mime_type = Some("application/json".into());
+                r.no_annotation()
+            })
+            .collect();
+        Ok(ListResourcesResult::with_all_items(resources))
+    }
+
+    async fn list_resource_templates(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListResourceTemplatesResult, McpError> {
+        let template = RawResourceTemplate {
+            uri_template: format!("{}{{project_key}}/files/{{path}}", PROJECTS_URI_PREFIX),
+            name: "project-file".into(),
+            title: None,
+            description: Some(
+                "Content of a file inside an indexed project (project_key is percent-encoded)"
+                    .into(),
+            ),
+            mime_type: Some("text/plain".into()),
+        };
+        Ok(ListResourceTemplatesResult::with_all_items(vec![
+            template.no_annotation(),
+        ]))
+    }
+
+    async fn read_resource(
+        &self,
+        request: ReadResourceRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ReadResourceResult, McpError> {
+        let uri = request.uri;
+        let (project_path, rel) = parse_resource_uri(&uri).ok_or_else(|| {
+            McpError::invalid_params(format!("unsupported resource uri: {}", uri), None)
+        })?;
+        let cfg = self.get_cfg();
+        let projects = crate::service::load_projects(&cfg);
+        let key = cfg.project_key(&project_path).ok();
+        let blobs = key.as_ref().and_then(|key| projects.0.get(key));
+        let (Some(key), Some(blobs)) = (key.as_ref(), blobs) else {
+            return Err(McpError::resource_not_found(
+                format!("project not indexed: {}", project_path),
+                None,
+            ));
+        };
+        let contents = match rel {
+            None => {
+                let listing = project_listing(&cfg, &project_path, blobs.len());
+                ResourceContents::TextResourceContents {
+                    uri,
+                    mime_type: Some("application/json".into()),
+                    text: serde_json::to_string_pretty(&listing).unwrap_or_default(),
+                    meta: None,
+                }
+            }
+            Some(rel) => match read_project_file(&cfg, key, &project_path, &rel) {
+                Ok(text) => ResourceContents::text(text, uri),
+                Err(e) => return Err(McpError::resource_not_found(e.to_string(), None)),
+            },
+        };
+        Ok(ReadResourceResult {
+            contents: vec![contents],
+        })
+    }
+
+    async fn list_prompts(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListPromptsResult, McpError> {
+        let prompts = crate::prompts::TEMPLATES
+            .iter()
+            .map(|t| {
+                let arguments = t
+                    .arguments
+                    .iter()
+                    .chain(crate::prompts::TARGET_ARGS)
+                    .map(|(name, desc, required)| PromptArgument {
+                        name: name.to_string(),
+                        title: None,
+                        description: Some(desc.to_string()),
+                        required: Some(*required),
+                    })
+                    .collect();
+                Prompt::new(t.name, Some(t.description), Some(arguments))
+            })
+            .collect();
+        Ok(ListPromptsResult::with_all_items(prompts))
+    }
+
+    async fn get_prompt(
+        &self,
+        request: GetPromptRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<GetPromptResult, McpError> {
+        let args: std::collections::HashMap<String, String> = request
+            .arguments
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(k, v)| match v {
+                serde_json::Value::String(s) => (k, s),
+                other => (k, other.to_string()),
+            })
+            .collect();
+        let text = crate::prompts::render(&request.name, &args)
+            .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+        Ok(GetPromptResult {
+            description: crate::prompts::find(&request.name).map(|t| t.description.to_string()),
+            messages: vec![PromptMessage::new_text(PromptMessageRole::User, text)],
+        })
+    }
 }
@@ -1,175 +1,1702 @@
 use crate::{
-    backend::{self, UploadProgress},
-    config::{self, Config},
-    indexer::{Aliases, ProjectsIndex, collect_blobs, incremental_plan},
+    backend::{self, Retrieved, UploadProgress},
+    config::{self, Config, RetrievalOverflow, SearchIndexPolicy},
+    filters::apply_all,
+    indexer::{
+        Aliases, BlobUpload, Checkpoints, CollectOptions, CollectProgress, IndexEstimate,
+        IndexMeta, Manifest, ProjectMeta, ProjectTags, ProjectsIndex, blob_file_path,
+        collect_blobs_with, collect_blobs_with_progress, count_moved, estimate_blobs, file_blobs,
+        hash_blob_name, hash_content, incremental_plan, removed_blobs, text_blobs,
+        visit_candidates,
+    },
+    tasks::TaskManager,
 };
 use anyhow::{Result, anyhow};
 use parking_lot::Mutex;
-use std::path::Path;
-use std::sync::OnceLock;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    task::JoinSet,
+    time::MissedTickBehavior,
+};
 
 // 全局互斥锁，保护 projects.json 的读/改/写，避免并发覆盖
 static PROJECTS_MUTEX: OnceLock<Mutex<()>> = OnceLock::new();
 
-/// 解析 alias 与路径，返回 (normalized_project_key, path_string)。
+/// 只读模式（read_only）下拒绝上传与持久化。
+fn ensure_writable(cfg: &Config, what: &str) -> Result<()> {
+    if cfg.settings.read_only {
+        return Err(anyhow!("read-only mode: {} is disabled", what));
+    }
+    Ok(())
+}
+
+/// 解析 alias 与路径，返回 (project_key, path_string)。
+/// 相对路径基于 `project_base_dir`（默认当前目录）解析，返回的 path 为绝对路径；
+/// project_key 为规范化路径，开启 hash_project_keys 时为其加盐哈希。
 /// 若同时提供 alias 和 path，则绑定 alias -> normalized_path 并持久化。
+/// 不含 `:` 的 alias 会加上 `alias_namespace` 前缀（若已配置）。
 pub fn resolve_target(
     cfg: &Config,
     alias: Option<String>,
     path: Option<String>,
 ) -> Result<(String, String)> {
+    let alias = alias.map(|a| cfg.qualify_alias(&a, None));
     let mut aliases = Aliases::load(&cfg.aliases_file()).unwrap_or_default();
     let path = match (alias.clone(), path.clone()) {
         (Some(a), Some(p)) => {
-            let norm = config::normalize_path(&p)?;
-            aliases.set(a, norm);
-            let _ = aliases.save(&cfg.aliases_file());
-            p
+            let norm = cfg.resolve_project_path(&p)?;
+            if aliases.resolve(&a) != Some(&norm) {
+                ensure_writable(cfg, "alias binding")?;
+                aliases.set(a, norm.clone());
+                let _ = aliases.save(&cfg.aliases_file());
+            }
+            norm
         }
         (Some(a), None) => aliases
             .resolve(&a)
             .cloned()
             .ok_or_else(|| anyhow!("alias not found and no path provided"))?,
-        (None, Some(p)) => p,
-        (None, None) => return Err(anyhow!("provide project_root_path or alias")),
+        (None, Some(p)) => cfg.resolve_project_path(&p)?,
+        (None, None) => default_project_path(cfg)?,
     };
-    let project_key = config::normalize_path(&path)?;
-    Ok((project_key, path))
+    cfg.resolve_project(&path)
 }
 
-/// 收集 -> 增量计划 -> 可选上传 -> 持久化项目索引，返回 (total_blobs, new_blobs, existing_blobs, all_blob_names)
-pub async fn index_and_persist(
+/// An alias binding as `bind_alias` stores (or would store) it.
+#[derive(Debug, Clone, Serialize)]
+pub struct AliasBinding {
+    /// Alias as stored, with its namespace
+    pub alias: String,
+    /// Normalized absolute path
+    pub path: String,
+    /// Key the path is indexed under in `projects.json`
+    pub project_key: String,
+    /// Path the alias was bound to before, when different
+    pub replaces: Option<String>,
+}
+
+/// 绑定 alias -> 规范化路径；dry_run 时只返回解析结果，不写 aliases.json。
+pub fn bind_alias(cfg: &Config, alias: &str, path: &str, dry_run: bool) -> Result<AliasBinding> {
+    let alias = cfg.qualify_alias(alias, None);
+    let (project_key, norm) = cfg.resolve_project(path)?;
+    let previous = Aliases::load(&cfg.aliases_file())
+        .unwrap_or_default()
+        .resolve(&alias)
+        .cloned();
+    if !dry_run {
+        resolve_target(cfg, Some(alias.clone()), Some(path.to_string()))?;
+    }
+    Ok(AliasBinding {
+        replaces: previous.filter(|p| *p != norm),
+        alias,
+        path: norm,
+        project_key,
+    })
+}
+
+/// 未提供 alias 与路径时的回退：先 `default_alias`，再 `default_project_path`，都未配置则报错。
+pub fn default_project_path(cfg: &Config) -> Result<String> {
+    if let Some(alias) = &cfg.settings.default_alias {
+        let alias = cfg.qualify_alias(alias, None);
+        return Aliases::load(&cfg.aliases_file())
+            .unwrap_or_default()
+            .resolve(&alias)
+            .cloned()
+            .ok_or_else(|| anyhow!("default_alias {} is not bound to a path", alias));
+    }
+    match &cfg.settings.default_project_path {
+        Some(p) => cfg.resolve_project_path(p),
+        None => Err(anyhow!("provide project_root_path or alias")),
+    }
+}
+
+/// 同 resolve_target；显式给出 project_key 时原样作为缓存键（路径仍用于收集文件），
+/// 使同一代码挂载在不同路径下时共享缓存。
+pub fn resolve_target_keyed(
+    cfg: &Config,
+    alias: Option<String>,
+    path: Option<String>,
+    project_key: Option<&str>,
+) -> Result<(String, String)> {
+    with_project_key(resolve_target(cfg, alias, path)?, project_key)
+}
+
+/// 同 resolve_target_keyed，另返回套用该项目 `project_backends` 覆盖后的配置（见 project_config）。
+pub fn resolve_target_with_config(
+    cfg: &Config,
+    alias: Option<String>,
+    path: Option<String>,
+    project_key: Option<&str>,
+) -> Result<(String, String, Config)> {
+    let (key, path) = resolve_target_keyed(cfg, alias, path, project_key)?;
+    let cfg = project_config(cfg, &path);
+    Ok((key, path, cfg))
+}
+
+/// 检索用的 resolve_target_with_config：项目目录已不存在（被移动/删除）但其原路径下仍有
+/// 非空缓存时，以词法规范化的路径为键继续，ensure_indexed 随后退回缓存。不绑定 alias；
+/// 没有缓存时返回原来的解析错误。
+pub fn resolve_search_target(
+    cfg: &Config,
+    alias: Option<String>,
+    path: Option<String>,
+    project_key: Option<&str>,
+) -> Result<(String, String, Config)> {
+    let err = match resolve_target_with_config(cfg, alias.clone(), path.clone(), project_key) {
+        Ok(v) => return Ok(v),
+        Err(e) => e,
+    };
+    let missing = match (alias, path) {
+        (_, Some(p)) => Some(p),
+        (Some(a), None) => Aliases::load(&cfg.aliases_file())
+            .unwrap_or_default()
+            .resolve(&cfg.qualify_alias(&a, None))
+            .cloned(),
+        (None, None) => None,
+    };
+    let cached = missing
+        .and_then(|p| cfg.lexical_project_path(&p).ok())
+        .filter(|norm| !Path::new(norm).exists())
+        .and_then(|norm| {
+            let key = cfg.project_key(&norm).ok()?;
+            with_project_key((key, norm), project_key).ok()
+        })
+        .filter(|(key, _)| load_projects(cfg).0.get(key).is_some_and(|b| !b.is_empty()));
+    match cached {
+        Some((key, norm)) => {
+            let cfg = project_config(cfg, &norm);
+            Ok((key, norm, cfg))
+        }
+        None => Err(err),
+    }
+}
+
+/// 项目 `path`（规范化路径）的有效配置：`project_backends` 中指向该路径的条目覆盖
+/// base_url / token。条目键先按 alias 匹配，再按路径匹配；无匹配时使用全局配置。
+pub fn project_config(cfg: &Config, path: &str) -> Config {
+    let backends = &cfg.settings.project_backends;
+    if backends.is_empty() {
+        return cfg.clone();
+    }
+    let aliases = Aliases::load(&cfg.aliases_file()).unwrap_or_default();
+    let by_alias = backends.iter().find(|(k, _)| {
+        aliases
+            .resolve(&cfg.qualify_alias(k, None))
+            .is_some_and(|p| p == path)
+    });
+    let by_path = || {
+        backends
+            .iter()
+            .find(|(k, _)| cfg.resolve_project_path(k).is_ok_and(|p| p == path))
+    };
+    match by_alias.or_else(by_path) {
+        Some((_, backend)) => cfg.with_backend(backend),
+        None => cfg.clone(),
+    }
+}
+
+/// 用显式 project_key（校验后）替换推导出的键。
+pub fn with_project_key(
+    (key, path): (String, String),
+    project_key: Option<&str>,
+) -> Result<(String, String)> {
+    match project_key {
+        Some(k) => Ok((config::validate_project_key(k)?, path)),
+        None => Ok((key, path)),
+    }
+}
+
+/// 已索引项目的 (project_key, path)。开启 hash_project_keys 时路径无法从键还原，
+/// 只返回能通过 alias 找回路径的项目。
+pub fn known_projects(cfg: &Config) -> Vec<(String, String)> {
+    let projects = load_projects(cfg);
+    let mut out: Vec<(String, String)> = if cfg.settings.hash_project_keys {
+        let aliases = Aliases::load(&cfg.aliases_file()).unwrap_or_default();
+        let paths: BTreeSet<&String> = aliases.0.values().collect();
+        paths
+            .into_iter()
+            .filter_map(|p| Some((cfg.project_key(p).ok()?, p.clone())))
+            .filter(|(k, _)| projects.0.contains_key(k))
+            .collect()
+    } else {
+        // 显式 project_key 无法还原路径
+        projects
+            .0
+            .keys()
+            .filter(|k| !config::is_explicit_project_key(k))
+            .map(|k| (k.clone(), k.clone()))
+            .collect()
+    };
+    out.sort_by(|a, b| a.1.cmp(&b.1));
+    out
+}
+
+/// 开启 hash_project_keys 后，将 projects.json / index_meta.json 中的明文路径键一次性替换为
+/// 哈希键，返回迁移的项目数。
+pub fn migrate_project_keys(cfg: &Config) -> Result<usize> {
+    if !cfg.settings.hash_project_keys {
+        return Ok(0);
+    }
+    let m = PROJECTS_MUTEX.get_or_init(|| Mutex::new(()));
+    let _g = m.lock();
+    let is_plain = |k: &String| {
+        !k.starts_with(config::HASHED_KEY_PREFIX) && !config::is_explicit_project_key(k)
+    };
+    let mut projects = ProjectsIndex::load(&cfg.projects_file()).unwrap_or_default();
+    let plain: Vec<String> = projects.0.keys().filter(|k| is_plain(k)).cloned().collect();
+    for k in &plain {
+        if let Some(v) = projects.0.remove(k) {
+            projects.0.insert(cfg.project_key(k)?, v);
+        }
+    }
+    let mut metas = IndexMeta::load(&cfg.index_meta_file()).unwrap_or_default();
+    let plain_meta: Vec<String> = metas.0.keys().filter(|k| is_plain(k)).cloned().collect();
+    for k in &plain_meta {
+        if let Some(v) = metas.0.remove(k) {
+            metas.0.insert(cfg.project_key(k)?, v);
+        }
+    }
+    if !plain.is_empty() {
+        projects.save(&cfg.projects_file())?;
+    }
+    if !plain_meta.is_empty() {
+        metas.save(&cfg.index_meta_file())?;
+    }
+    if !plain.is_empty() {
+        tracing::info!(
+            projects = plain.len(),
+            "migrated project keys to hashed form"
+        );
+    }
+    Ok(plain.len())
+}
+
+/// 在锁内读取 projects.json 的快照。
+pub fn load_projects(cfg: &Config) -> ProjectsIndex {
+    let m = PROJECTS_MUTEX.get_or_init(|| Mutex::new(()));
+    let _g = m.lock();
+    ProjectsIndex::load(&cfg.projects_file()).unwrap_or_default()
+}
+
+/// 项目的自定义元数据（`project_meta.json`）；未设置时为空表。
+pub fn get_project_meta(cfg: &Config, project_key: &str) -> BTreeMap<String, String> {
+    let m = PROJECTS_MUTEX.get_or_init(|| Mutex::new(()));
+    let _g = m.lock();
+    ProjectTags::load(&cfg.project_meta_file())
+        .unwrap_or_default()
+        .0
+        .remove(project_key)
+        .unwrap_or_default()
+}
+
+/// 所有项目的自定义元数据，供项目列表使用。
+pub fn all_project_meta(cfg: &Config) -> BTreeMap<String, BTreeMap<String, String>> {
+    let m = PROJECTS_MUTEX.get_or_init(|| Mutex::new(()));
+    let _g = m.lock();
+    ProjectTags::load(&cfg.project_meta_file())
+        .unwrap_or_default()
+        .0
+}
+
+/// Check that `value` is a flat JSON object of string values, e.g. `{"team": "search"}`.
+pub fn parse_project_meta(value: &serde_json::Value) -> Result<BTreeMap<String, String>> {
+    let obj = value
+        .as_object()
+        .ok_or_else(|| anyhow!("meta must be a JSON object of string values"))?;
+    obj.iter()
+        .map(|(k, v)| match v.as_str() {
+            Some(s) => Ok((k.clone(), s.to_string())),
+            None => Err(anyhow!(
+                "meta value for {:?} must be a string, got {}",
+                k,
+                v
+            )),
+        })
+        .collect()
+}
+
+/// 替换已索引项目的元数据；空表删除该项目的记录。不影响索引。
+pub fn set_project_meta(
     cfg: &Config,
     project_key: &str,
-    path: &str,
+    meta: BTreeMap<String, String>,
+) -> Result<()> {
+    ensure_writable(cfg, "set_project_meta")?;
+    let m = PROJECTS_MUTEX.get_or_init(|| Mutex::new(()));
+    let _g = m.lock();
+    let indexed = ProjectsIndex::load(&cfg.projects_file())
+        .unwrap_or_default()
+        .0
+        .contains_key(project_key);
+    if !indexed {
+        return Err(anyhow!("project not indexed: {}", project_key));
+    }
+    let mut tags = ProjectTags::load(&cfg.project_meta_file()).unwrap_or_default();
+    if meta.is_empty() {
+        tags.0.remove(project_key);
+    } else {
+        tags.0.insert(project_key.to_string(), meta);
+    }
+    tags.save(&cfg.project_meta_file())
+}
+
+/// 读取与计算增量在锁内，确保与其他并发写一致。
+/// 若项目根目录存在 `.augmcp/manifest.json` 且设置指纹一致，其中的 blob 视为已上传。
+fn plan_upload(
+    cfg: &Config,
+    project_key: &str,
+    root: &Path,
+    blobs: &[BlobUpload],
     force_full: bool,
-) -> Result<(usize, usize, usize, Vec<String>)> {
-    let p = Path::new(path);
-    let blobs = collect_blobs(
-        p,
-        &cfg.text_extensions_set(),
-        cfg.settings.max_lines_per_blob,
-        &cfg.settings.exclude_patterns,
-    )?;
-    if blobs.is_empty() {
-        return Err(anyhow!("No text files found in project"));
+) -> (Vec<BlobUpload>, Vec<String>) {
+    let m = PROJECTS_MUTEX.get_or_init(|| Mutex::new(()));
+    let _g = m.lock();
+    let mut projects = ProjectsIndex::load(&cfg.projects_file()).unwrap_or_default();
+    if force_full {
+        projects.0.remove(project_key);
+    } else if let Some(trusted) = trusted_manifest_blobs(cfg, root) {
+        projects
+            .0
+            .entry(project_key.to_string())
+            .or_default()
+            .extend(trusted);
     }
-    // 读取与计算增量在锁内，确保与其他并发写一致
-    let (new_blobs, all_names) = {
-        let m = PROJECTS_MUTEX.get_or_init(|| Mutex::new(()));
-        let _g = m.lock();
-        let mut projects = ProjectsIndex::load(&cfg.projects_file()).unwrap_or_default();
-        if force_full {
-            projects.0.remove(project_key);
+    incremental_plan(project_key, blobs, &projects)
+}
+
+fn trusted_manifest_blobs(cfg: &Config, root: &Path) -> Option<Vec<String>> {
+    let path = Manifest::path_for(root);
+    let manifest = match Manifest::load(&path) {
+        Ok(Some(m)) => m,
+        Ok(None) => return None,
+        Err(e) => {
+            tracing::warn!(error = %e, "ignoring unreadable manifest");
+            return None;
         }
-        incremental_plan(project_key, &blobs, &projects)
     };
-    let total = all_names.len();
-    let newn = new_blobs.len();
-    let existing = total.saturating_sub(newn);
-    if !new_blobs.is_empty() {
-        tracing::info!(uploading = new_blobs.len(), "uploading new blobs (service)");
-        let _ = backend::upload_new_blobs(cfg, &new_blobs).await?;
+    if manifest.version != Manifest::VERSION || manifest.fingerprint != cfg.settings_fingerprint() {
+        tracing::warn!(
+            manifest = %path.display(),
+            "manifest settings fingerprint mismatch; not trusting its blobs"
+        );
+        return None;
     }
-    // 保存在锁内，避免并发覆盖
-    {
+    tracing::info!(
+        blobs = manifest.blob_names.len(),
+        "trusting blobs from project manifest"
+    );
+    Some(manifest.blob_names)
+}
+
+/// 索引项目后将 blob 列表与设置指纹写入 `<root>/.augmcp/manifest.json`，返回清单路径。
+pub async fn export_manifest(cfg: &Config, project_key: &str, path: &str) -> Result<PathBuf> {
+    let all_names = index_and_persist(cfg, project_key, path, false)
+        .await?
+        .all_blob_names;
+    let manifest = Manifest {
+        version: Manifest::VERSION,
+        fingerprint: cfg.settings_fingerprint(),
+        blob_names: all_names,
+    };
+    let out = Manifest::path_for(Path::new(path));
+    manifest.save(&out)?;
+    Ok(out)
+}
+
+/// Wall-clock time spent in each index phase.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct IndexDurations {
+    pub collect_ms: u64,
+    pub upload_ms: u64,
+    pub total_ms: u64,
+}
+
+/// Outcome of an index run.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct IndexReport {
+    pub total_blobs: usize,
+    pub new_blobs: usize,
+    pub existing_blobs: usize,
+    /// Uploaded blobs whose content was already indexed under another path
+    /// (only counted with `detect_moves`; not included in `new_blobs`)
+    pub moved_blobs: usize,
+    /// Sum of content bytes of the uploaded (new) blobs
+    pub uploaded_bytes: usize,
+    /// Files skipped because they match `generated_patterns`
+    pub generated_skipped: usize,
+    /// Uploaded blobs the backend did not confirm; left out of the index and retried next time
+    pub unconfirmed_blobs: usize,
+    pub durations: IndexDurations,
+    #[serde(skip)]
+    pub all_blob_names: Vec<String>,
+}
+
+impl IndexReport {
+    /// One-line stats string returned by the MCP tool and REST API.
+    pub fn summary(&self) -> String {
+        let mut s = format!(
+            "Index complete: total_blobs={}, new_blobs={}, existing_blobs={}",
+            self.total_blobs, self.new_blobs, self.existing_blobs
+        );
+        if self.moved_blobs > 0 {
+            s.push_str(&format!(", moved_blobs={}", self.moved_blobs));
+        }
+        if self.generated_skipped > 0 {
+            s.push_str(&format!(", generated_skipped={}", self.generated_skipped));
+        }
+        if self.unconfirmed_blobs > 0 {
+            s.push_str(&format!(", unconfirmed_blobs={}", self.unconfirmed_blobs));
+        }
+        s
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// 在锁内保存项目 blob 列表与快速索引元数据，避免并发覆盖。
+fn persist_index(
+    cfg: &Config,
+    project_key: &str,
+    all_names: &[String],
+    meta: ProjectMeta,
+) -> Result<()> {
+    let m = PROJECTS_MUTEX.get_or_init(|| Mutex::new(()));
+    let _g = m.lock();
+    let mut projects = ProjectsIndex::load(&cfg.projects_file()).unwrap_or_default();
+    projects
+        .0
+        .insert(project_key.to_string(), all_names.to_vec());
+    projects.save(&cfg.projects_file())?;
+    let mut metas = IndexMeta::load(&cfg.index_meta_file()).unwrap_or_default();
+    metas.0.insert(project_key.to_string(), meta);
+    metas.save(&cfg.index_meta_file())?;
+    Ok(())
+}
+
+const NO_INDEXABLE_FILES: &str = "project has no indexable files";
+
+/// 记录“已索引但为空”：index_meta 中保留无文件的条目及时间戳，供 TTL 内的搜索直接返回。
+fn mark_empty(cfg: &Config, project_key: &str, started: u64) -> anyhow::Error {
+    let meta = ProjectMeta {
+        indexed_at_ms: started,
+        fingerprint: cfg.settings_fingerprint(),
+        ..Default::default()
+    };
+    if let Err(e) = persist_index(cfg, project_key, &[], meta) {
+        tracing::warn!(error = %e, "failed to record empty project");
+    }
+    anyhow!(NO_INDEXABLE_FILES)
+}
+
+/// 若项目在 TTL 内被确认为空（且设置未变），返回确认时间（unix ms）。
+fn empty_since(cfg: &Config, project_key: &str) -> Option<u64> {
+    let meta = {
         let m = PROJECTS_MUTEX.get_or_init(|| Mutex::new(()));
         let _g = m.lock();
-        let mut projects = ProjectsIndex::load(&cfg.projects_file()).unwrap_or_default();
-        projects
+        IndexMeta::load(&cfg.index_meta_file())
+            .unwrap_or_default()
             .0
-            .insert(project_key.to_string(), all_names.clone());
-        projects.save(&cfg.projects_file())?;
+            .remove(project_key)?
+    };
+    let ttl_ms = cfg.settings.empty_index_ttl_secs.saturating_mul(1000);
+    (meta.files.is_empty()
+        && meta.fingerprint == cfg.settings_fingerprint()
+        && now_ms().saturating_sub(meta.indexed_at_ms) < ttl_ms)
+        .then_some(meta.indexed_at_ms)
+}
+
+/// 设置了 index_ttl_secs 且上次索引（index_meta 中的 indexed_at_ms）已超过 TTL 时返回其年龄（秒）。
+/// 缺少索引元数据视为过期；只读模式从不过期。
+fn stale_index_age_secs(cfg: &Config, project_key: &str) -> Option<u64> {
+    let ttl_ms = cfg.settings.index_ttl_secs.saturating_mul(1000);
+    if ttl_ms == 0 || cfg.settings.read_only {
+        return None;
+    }
+    let indexed_at = load_project_meta(cfg, project_key).map_or(0, |m| m.indexed_at_ms);
+    let age_ms = now_ms().saturating_sub(indexed_at);
+    (age_ms >= ttl_ms).then_some(age_ms / 1000)
+}
+
+/// 开启 detect_moves 时，统计新 blob 中内容已在上次索引中出现过（文件被移动/重命名）的数量。
+fn moved_count(cfg: &Config, project_key: &str, new_blobs: &[BlobUpload]) -> usize {
+    if !cfg.settings.detect_moves || new_blobs.is_empty() {
+        return 0;
     }
-    Ok((total, newn, existing, all_names))
+    let m = PROJECTS_MUTEX.get_or_init(|| Mutex::new(()));
+    let _g = m.lock();
+    IndexMeta::load(&cfg.index_meta_file())
+        .unwrap_or_default()
+        .0
+        .get(project_key)
+        .map(|meta| count_moved(new_blobs, &meta.content_hashes))
+        .unwrap_or(0)
 }
 
-/// 与 index_and_persist 类似，但允许传入上传进度回调。
-pub async fn index_and_persist_with_progress<F>(
+/// 去掉后端未确认的 blob（及其名字），使其不被记为已上传，下次索引时重新上传。
+fn drop_unconfirmed(blobs: &mut Vec<BlobUpload>, names: &mut Vec<String>, unconfirmed: &[String]) {
+    if unconfirmed.is_empty() {
+        return;
+    }
+    let dropped: HashSet<&String> = unconfirmed.iter().collect();
+    let mut keep = names.iter().map(|n| !dropped.contains(n));
+    blobs.retain(|_| keep.next().unwrap_or(true));
+    names.retain(|n| !dropped.contains(n));
+}
+
+fn build_meta(cfg: &Config, blobs: &[BlobUpload], names: &[String], started: u64) -> ProjectMeta {
+    let meta = ProjectMeta::from_blobs(blobs, names, cfg.settings_fingerprint(), started);
+    if cfg.settings.detect_moves {
+        meta.with_content_hashes(blobs)
+    } else {
+        meta
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct IndexWebhook<'a> {
+    project_key: &'a str,
+    path: &'a str,
+    total_blobs: usize,
+    new_blobs: usize,
+    duration_ms: u64,
+}
+
+/// 索引成功后向 index_webhook_url 发送通知；投递失败只记录日志，不影响索引结果。
+async fn notify_indexed(cfg: &Config, project_key: &str, path: &str, report: &IndexReport) {
+    let Some(url) = cfg.settings.index_webhook_url.as_deref() else {
+        return;
+    };
+    let payload = IndexWebhook {
+        project_key,
+        path,
+        total_blobs: report.total_blobs,
+        new_blobs: report.new_blobs,
+        duration_ms: report.durations.total_ms,
+    };
+    let res = match serde_json::to_vec(&payload) {
+        Ok(body) => backend::post_webhook(cfg, url, body).await,
+        Err(e) => Err(e.into()),
+    };
+    if let Err(e) = res {
+        tracing::warn!(url, error = %e, "index webhook delivery failed");
+    }
+}
+
+/// 收集 -> 增量计划 -> 可选上传 -> 持久化项目索引，返回 IndexReport。
+pub async fn index_and_persist(
+    cfg: &Config,
+    project_key: &str,
+    path: &str,
+    force_full: bool,
+) -> Result<IndexReport> {
+    index_and_persist_with_progress(cfg, project_key, path, force_full, |_| {}, |_| {}).await
+}
+
+/// 与 index_and_persist 类似，但允许传入收集进度与上传进度回调。
+pub async fn index_and_persist_with_progress<C, F>(
     cfg: &Config,
     project_key: &str,
     path: &str,
     force_full: bool,
-    mut on_progress: F,
-) -> Result<(usize, usize, usize, Vec<String>)>
+    on_collect: C,
+    on_progress: F,
+) -> Result<IndexReport>
 where
+    C: FnMut(CollectProgress),
     F: FnMut(UploadProgress),
 {
+    ensure_writable(cfg, "indexing")?;
+    let started = now_ms();
+    let clock = Instant::now();
     let p = Path::new(path);
-    let blobs = collect_blobs(
-        p,
-        &cfg.text_extensions_set(),
-        cfg.settings.max_lines_per_blob,
-        &cfg.settings.exclude_patterns,
-    )?;
+    let mut generated_skipped = 0;
+    let mut on_collect = on_collect;
+    let mut blobs = collect_blobs_with_progress(p, &CollectOptions::from_config(cfg), |c| {
+        generated_skipped = c.generated_skipped;
+        on_collect(c)
+    })?;
     if blobs.is_empty() {
-        return Err(anyhow!("No text files found in project"));
+        return Err(mark_empty(cfg, project_key, started));
     }
-    // 在锁内读取与计算增量
-    let (new_blobs, all_names) = {
-        let m = PROJECTS_MUTEX.get_or_init(|| Mutex::new(()));
-        let _g = m.lock();
-        let mut projects = ProjectsIndex::load(&cfg.projects_file()).unwrap_or_default();
-        if force_full {
-            projects.0.remove(project_key);
-        }
-        incremental_plan(project_key, &blobs, &projects)
-    };
-    let total = all_names.len();
-    let newn = new_blobs.len();
-    let existing = total.saturating_sub(newn);
+    let collect_ms = clock.elapsed().as_millis() as u64;
+    let (new_blobs, mut all_names) = plan_upload(cfg, project_key, p, &blobs, force_full);
+    let moved = moved_count(cfg, project_key, &new_blobs);
+    let existing_blobs = all_names.len().saturating_sub(new_blobs.len());
+    let upload_clock = Instant::now();
+    let mut unconfirmed = Vec::new();
     if !new_blobs.is_empty() {
         tracing::info!(
             uploading = new_blobs.len(),
             "uploading new blobs (service+progress)"
         );
-        let _ =
-            backend::upload_new_blobs_with_progress(cfg, &new_blobs, |p| on_progress(p)).await?;
+        unconfirmed = backend::upload_new_blobs_with_progress(cfg, &new_blobs, on_progress)
+            .await?
+            .unconfirmed;
+    }
+    let upload_ms = upload_clock.elapsed().as_millis() as u64;
+    drop_unconfirmed(&mut blobs, &mut all_names, &unconfirmed);
+    let meta = build_meta(cfg, &blobs, &all_names, started);
+    persist_index(cfg, project_key, &all_names, meta)?;
+    let report = IndexReport {
+        total_blobs: all_names.len(),
+        new_blobs: new_blobs.len() - moved,
+        existing_blobs,
+        moved_blobs: moved,
+        unconfirmed_blobs: unconfirmed.len(),
+        uploaded_bytes: new_blobs.iter().map(|b| b.content.len()).sum(),
+        generated_skipped,
+        durations: IndexDurations {
+            collect_ms,
+            upload_ms,
+            total_ms: clock.elapsed().as_millis() as u64,
+        },
+        all_blob_names: all_names,
+    };
+    notify_indexed(cfg, project_key, path, &report).await;
+    Ok(report)
+}
+
+/// Search on a project that is not indexed under `search_index_policy = "never"`.
+pub const NOT_INDEXED_POLICY_NEVER: &str =
+    "project not indexed; call index first (search_index_policy = \"never\")";
+
+/// 若需要索引则先索引（可跳过已有缓存），返回用于检索的 blob 名称列表。
+/// `search_index_policy` 限定是否允许索引，`skip_index_if_indexed` 只能进一步收紧。
+pub async fn ensure_indexed(
+    cfg: &Config,
+    project_key: &str,
+    path: &str,
+    skip_index_if_indexed: bool,
+) -> Result<Vec<String>> {
+    let projects = load_projects(cfg);
+    let policy = cfg
+        .settings
+        .search_index_policy
+        .narrowed(skip_index_if_indexed);
+    if policy == SearchIndexPolicy::Never {
+        return match projects.0.get(project_key) {
+            Some(existing) if !existing.is_empty() => Ok(existing.clone()),
+            _ => Err(anyhow!(NOT_INDEXED_POLICY_NEVER)),
+        };
+    }
+    // 只读模式：只使用已有索引，从不触发索引
+    let skip_index_if_indexed = policy == SearchIndexPolicy::IfMissing || cfg.settings.read_only;
+    if skip_index_if_indexed
+        && let Some(existing) = projects.0.get(project_key)
+        && !existing.is_empty()
+    {
+        match stale_index_age_secs(cfg, project_key) {
+            Some(age_secs) => tracing::info!(
+                age_secs,
+                index_ttl_secs = cfg.settings.index_ttl_secs,
+                "existing index older than index_ttl_secs; re-indexing"
+            ),
+            None => {
+                tracing::info!(
+                    blobs = existing.len(),
+                    "using existing index (skip_index_if_indexed=true)"
+                );
+                return Ok(existing.clone());
+            }
+        }
+    }
+    if skip_index_if_indexed && let Some(at) = empty_since(cfg, project_key) {
+        return Err(anyhow!(
+            "{} (checked {}s ago; rechecked after empty_index_ttl_secs)",
+            NO_INDEXABLE_FILES,
+            now_ms().saturating_sub(at) / 1000
+        ));
+    }
+    match index_and_persist(cfg, project_key, path, false).await {
+        Ok(report) => Ok(report.all_blob_names),
+        // 项目目录已不存在（被移动/删除）：有非空缓存时退回缓存继续检索
+        Err(e) if !Path::new(path).exists() => match projects.0.get(project_key) {
+            Some(cached) if !cached.is_empty() => {
+                tracing::warn!(
+                    path,
+                    error = %e,
+                    blobs = cached.len(),
+                    "project directory missing; using cached index"
+                );
+                Ok(cached.clone())
+            }
+            _ => Err(e),
+        },
+        Err(e) => Err(e),
+    }
+}
+
+fn load_project_meta(cfg: &Config, project_key: &str) -> Option<ProjectMeta> {
+    let m = PROJECTS_MUTEX.get_or_init(|| Mutex::new(()));
+    let _g = m.lock();
+    IndexMeta::load(&cfg.index_meta_file())
+        .unwrap_or_default()
+        .0
+        .remove(project_key)
+}
+
+/// 仅保留源文件相对路径以 `prefix` 开头的 blob（依据 index_meta 中的 路径 -> blob 映射）。
+/// 前缀以 `/` 结尾时只匹配该目录；没有匹配的 blob 时返回错误。
+pub fn filter_blobs_by_prefix(
+    cfg: &Config,
+    project_key: &str,
+    all_blob_names: &[String],
+    prefix: &str,
+) -> Result<Vec<String>> {
+    let prefix = prefix.trim().replace('\\', "/");
+    let prefix = prefix.trim_start_matches("./").trim_start_matches('/');
+    if prefix.is_empty() {
+        return Ok(all_blob_names.to_vec());
+    }
+    let meta = load_project_meta(cfg, project_key)
+        .ok_or_else(|| anyhow!("path_prefix needs index metadata; re-index the project first"))?;
+    let wanted: HashSet<&String> = meta
+        .files
+        .iter()
+        .filter(|(rel, _)| rel.starts_with(prefix))
+        .flat_map(|(_, names)| names)
+        .collect();
+    let names: Vec<String> = all_blob_names
+        .iter()
+        .filter(|n| wanted.contains(n))
+        .cloned()
+        .collect();
+    if names.is_empty() {
+        return Err(anyhow!("no indexed files match path_prefix '{}'", prefix));
+    }
+    Ok(names)
+}
+
+/// 仅保留所属文件语言（index_meta 中记录的 `detect_language` 结果，忽略大小写）在
+/// `languages` 中的 blob；列表为空时不过滤，没有匹配的 blob 时返回错误。
+pub fn filter_blobs_by_languages(
+    cfg: &Config,
+    project_key: &str,
+    all_blob_names: &[String],
+    languages: &[String],
+) -> Result<Vec<String>> {
+    let wanted_langs: HashSet<String> = languages
+        .iter()
+        .map(|l| l.trim().to_lowercase())
+        .filter(|l| !l.is_empty())
+        .collect();
+    if wanted_langs.is_empty() {
+        return Ok(all_blob_names.to_vec());
+    }
+    let meta = load_project_meta(cfg, project_key)
+        .ok_or_else(|| anyhow!("languages needs index metadata; re-index the project first"))?;
+    let wanted: HashSet<&String> = meta
+        .files
+        .iter()
+        .filter(|(rel, _)| {
+            meta.languages
+                .get(*rel)
+                .is_some_and(|l| wanted_langs.contains(l))
+        })
+        .flat_map(|(_, names)| names)
+        .collect();
+    let names: Vec<String> = all_blob_names
+        .iter()
+        .filter(|n| wanted.contains(n))
+        .cloned()
+        .collect();
+    if names.is_empty() {
+        let mut sorted: Vec<_> = wanted_langs.into_iter().collect();
+        sorted.sort();
+        return Err(anyhow!(
+            "no indexed files match languages [{}]",
+            sorted.join(", ")
+        ));
+    }
+    Ok(names)
+}
+
+/// `pinned_paths` 对应的 blob（依据 index_meta 的 路径 -> blob 映射）；未索引的路径记录警告后跳过。
+pub fn pinned_blob_names(cfg: &Config, project_key: &str) -> Vec<String> {
+    if cfg.settings.pinned_paths.is_empty() {
+        return vec![];
+    }
+    let files = load_project_meta(cfg, project_key)
+        .map(|meta| meta.files)
+        .unwrap_or_default();
+    let mut names = vec![];
+    for pinned in &cfg.settings.pinned_paths {
+        let rel = pinned.trim().replace('\\', "/");
+        let rel = rel.trim_start_matches("./").trim_start_matches('/');
+        match files.get(rel) {
+            Some(blobs) => names.extend(blobs.iter().cloned()),
+            None => tracing::warn!(
+                project = project_key,
+                path = rel,
+                "pinned path is not indexed; skipping"
+            ),
+        }
+    }
+    names
+}
+
+/// 快速模式：只重新读取自上次索引以来修改过（mtime）或新出现的文件，未变文件复用记录的
+/// blob，已删除文件被移除。无元数据或设置指纹变化时退回普通增量索引。
+/// `search_index_policy` 不是 `always` 时等同于 `ensure_indexed(.., true)`。
+pub async fn quick_index(cfg: &Config, project_key: &str, path: &str) -> Result<Vec<String>> {
+    if cfg.settings.read_only || cfg.settings.search_index_policy < SearchIndexPolicy::Always {
+        return ensure_indexed(cfg, project_key, path, true).await;
+    }
+    let meta = {
+        let m = PROJECTS_MUTEX.get_or_init(|| Mutex::new(()));
+        let _g = m.lock();
+        IndexMeta::load(&cfg.index_meta_file())
+            .unwrap_or_default()
+            .0
+            .remove(project_key)
+    };
+    let Some(meta) = meta.filter(|m| m.fingerprint == cfg.settings_fingerprint()) else {
+        tracing::info!("no usable quick-index metadata; running incremental index");
+        return Ok(index_and_persist(cfg, project_key, path, false)
+            .await?
+            .all_blob_names);
+    };
+    let started = now_ms();
+    let opts = CollectOptions::from_config(cfg);
+    let mut files: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut languages: BTreeMap<String, String> = BTreeMap::new();
+    let mut changed: Vec<BlobUpload> = Vec::new();
+    visit_candidates(Path::new(path), &opts, |p, rel, _| {
+        let modified_ms = fs::metadata(p)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_millis() as u64);
+        if modified_ms.is_some_and(|ms| ms < meta.indexed_at_ms)
+            && let Some(names) = meta.files.get(&rel)
+        {
+            if let Some(lang) = meta.languages.get(&rel) {
+                languages.insert(rel.clone(), lang.clone());
+            }
+            files.insert(rel, names.clone());
+            return;
+        }
+        if let Some(mut b) = file_blobs(p, &rel, &opts) {
+            changed.append(&mut b);
+        }
+    })?;
+    let deleted = meta
+        .files
+        .keys()
+        .filter(|k| !files.contains_key(*k))
+        .count();
+    if changed.is_empty() && deleted == 0 {
+        let all: Vec<String> = files.into_values().flatten().collect();
+        if all.is_empty() {
+            return Err(anyhow!(NO_INDEXABLE_FILES));
+        }
+        tracing::info!(
+            blobs = all.len(),
+            "quick index: no changes since last index"
+        );
+        return Ok(all);
+    }
+    let projects = load_projects(cfg);
+    let (new_blobs, changed_names) = incremental_plan(project_key, &changed, &projects);
+    for (b, name) in changed.iter().zip(&changed_names) {
+        let file = blob_file_path(&b.path);
+        files
+            .entry(file.to_string())
+            .or_default()
+            .push(name.clone());
+        if let Some(lang) = &b.language {
+            languages.insert(file.to_string(), lang.clone());
+        }
+    }
+    tracing::info!(
+        changed_blobs = changed.len(),
+        new_blobs = new_blobs.len(),
+        "quick index: re-read modified files"
+    );
+    if !new_blobs.is_empty() {
+        let unconfirmed = backend::upload_new_blobs(cfg, &new_blobs)
+            .await?
+            .unconfirmed;
+        if !unconfirmed.is_empty() {
+            let dropped: HashSet<&String> = unconfirmed.iter().collect();
+            files.retain(|_, names| {
+                names.retain(|n| !dropped.contains(n));
+                !names.is_empty()
+            });
+        }
     }
-    // 保存在锁内
+    let all_names: Vec<String> = files.values().flatten().cloned().collect();
+    if all_names.is_empty() {
+        return Err(mark_empty(cfg, project_key, started));
+    }
+    let mut content_hashes = meta.content_hashes;
+    if cfg.settings.detect_moves {
+        content_hashes.extend(changed.iter().map(|b| hash_content(&b.content)));
+    }
+    let meta = ProjectMeta {
+        indexed_at_ms: started,
+        fingerprint: cfg.settings_fingerprint(),
+        files,
+        content_hashes,
+        languages,
+    };
+    persist_index(cfg, project_key, &all_names, meta)?;
+    Ok(all_names)
+}
+
+/// Blob-level difference between the stored index and a fresh collection.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct IndexDiff {
+    /// File paths with new/changed content
+    pub added: Vec<String>,
+    /// File paths whose stored blobs are gone (blob name if no path is recorded)
+    pub removed: Vec<String>,
+    pub unchanged_count: usize,
+}
+
+/// 只读文件元数据估算索引规模（文件数、总字节、约略 blob 数），不读取内容、不上传。
+pub fn estimate_index(cfg: &Config, path: &str) -> Result<IndexEstimate> {
+    estimate_blobs(Path::new(path), &CollectOptions::from_config(cfg))
+}
+
+/// 对比已存储索引与当前文件（不上传、不持久化），返回新增/移除的文件路径。
+pub fn diff_index(cfg: &Config, project_key: &str, path: &str) -> Result<IndexDiff> {
+    let blobs = collect_blobs_with(Path::new(path), &CollectOptions::from_config(cfg))?;
+    let (projects, meta) = {
+        let m = PROJECTS_MUTEX.get_or_init(|| Mutex::new(()));
+        let _g = m.lock();
+        (
+            ProjectsIndex::load(&cfg.projects_file()).unwrap_or_default(),
+            IndexMeta::load(&cfg.index_meta_file())
+                .unwrap_or_default()
+                .0
+                .remove(project_key)
+                .unwrap_or_default(),
+        )
+    };
+    let (new_blobs, all_names) = incremental_plan(project_key, &blobs, &projects);
+    let name_to_path: HashMap<&String, &String> = meta
+        .files
+        .iter()
+        .flat_map(|(file, names)| names.iter().map(move |n| (n, file)))
+        .collect();
+    let added: BTreeSet<String> = new_blobs
+        .iter()
+        .map(|b| blob_file_path(&b.path).to_string())
+        .collect();
+    let removed: BTreeSet<String> = removed_blobs(project_key, &projects, &all_names)
+        .into_iter()
+        .map(|h| name_to_path.get(&h).map(|p| (*p).clone()).unwrap_or(h))
+        .collect();
+    Ok(IndexDiff {
+        added: added.into_iter().collect(),
+        removed: removed.into_iter().collect(),
+        unchanged_count: all_names.len() - new_blobs.len(),
+    })
+}
+
+/// One project rewritten by `compact_projects`.
+#[derive(Debug, Clone, Serialize)]
+pub struct CompactedProject {
+    pub project_key: String,
+    pub entries_before: usize,
+    pub entries_after: usize,
+}
+
+/// Outcome of `compact_projects`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CompactReport {
+    /// Projects whose stored blob list shrank
+    pub compacted: Vec<CompactedProject>,
+    /// Projects left as they were because their directory could not be walked
+    pub skipped: Vec<String>,
+    pub entries_removed: usize,
+    /// Size of `projects.json` before minus after
+    pub bytes_reclaimed: u64,
+}
+
+/// 重新遍历每个已知项目，将 projects.json 中的条目收缩为“仍在磁盘上且已上传”的 blob
+/// （去重、保持原顺序），并同步清理 index_meta 中已失效的文件记录。不上传新内容，也不通知后端。
+pub fn compact_projects(cfg: &Config) -> Result<CompactReport> {
+    ensure_writable(cfg, "compact")?;
+    let opts = CollectOptions::from_config(cfg);
+    let mut report = CompactReport::default();
+    // 遍历在锁外进行，写回时再与最新条目求交集
+    let mut current: Vec<(String, HashSet<String>)> = Vec::new();
+    for (key, path) in known_projects(cfg) {
+        if !Path::new(&path).exists() {
+            report.skipped.push(key);
+            continue;
+        }
+        match collect_blobs_with(Path::new(&path), &opts) {
+            Ok(blobs) => {
+                let names = blobs
+                    .iter()
+                    .map(|b| hash_blob_name(&b.path, &b.content))
+                    .collect();
+                current.push((key, names));
+            }
+            Err(e) => {
+                tracing::warn!(project = %key, error = %e, "compact: cannot walk project; skipping");
+                report.skipped.push(key);
+            }
+        }
+    }
+
+    let m = PROJECTS_MUTEX.get_or_init(|| Mutex::new(()));
+    let _g = m.lock();
+    let bytes_before = fs::metadata(cfg.projects_file()).map_or(0, |m| m.len());
+    let mut projects = ProjectsIndex::load(&cfg.projects_file()).unwrap_or_default();
+    let mut metas = IndexMeta::load(&cfg.index_meta_file()).unwrap_or_default();
+    for (key, names) in current {
+        let Some(stored) = projects.0.get_mut(&key) else {
+            continue;
+        };
+        let before = stored.len();
+        let mut seen = HashSet::new();
+        stored.retain(|n| names.contains(n) && seen.insert(n.clone()));
+        if stored.len() == before {
+            continue;
+        }
+        if let Some(meta) = metas.0.get_mut(&key) {
+            meta.files.retain(|_, file_names| {
+                file_names.retain(|n| seen.contains(n));
+                !file_names.is_empty()
+            });
+            meta.languages
+                .retain(|file, _| meta.files.contains_key(file));
+        }
+        report.entries_removed += before - stored.len();
+        report.compacted.push(CompactedProject {
+            project_key: key,
+            entries_before: before,
+            entries_after: stored.len(),
+        });
+    }
+    if !report.compacted.is_empty() {
+        projects.save(&cfg.projects_file())?;
+        metas.save(&cfg.index_meta_file())?;
+    }
+    let bytes_after = fs::metadata(cfg.projects_file()).map_or(0, |m| m.len());
+    report.bytes_reclaimed = bytes_before.saturating_sub(bytes_after);
+    tracing::info!(
+        projects = report.compacted.len(),
+        entries_removed = report.entries_removed,
+        bytes_reclaimed = report.bytes_reclaimed,
+        "projects compacted"
+    );
+    Ok(report)
+}
+
+/// Outcome of `verify_blobs`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct VerifyReport {
+    /// Stored blob names the backend was asked about
+    pub checked: usize,
+    /// Names the backend no longer has; dropped from `projects.json`
+    pub missing: Vec<String>,
+    /// Blobs uploaded again by the follow-up incremental index
+    pub reuploaded: usize,
+}
+
+/// Outcome of `merge_projects`.
+#[derive(Debug, Clone, Serialize)]
+pub struct MergeReport {
+    pub src_key: String,
+    pub dst_key: String,
+    /// Blobs stored for the destination before the merge
+    pub dst_blobs_before: usize,
+    /// After the merge: the deduplicated union of both lists
+    pub dst_blobs_after: usize,
+    /// Whether the source project's state was removed
+    pub source_removed: bool,
+}
+
+/// 将 src 的 blob 列表并入 dst（去重、保持顺序），同步合并 index_meta 的文件记录并丢弃 dst 的
+/// 检索 checkpoint；remove_source 时删除 src 的全部本地状态。不上传、不通知后端。
+/// blob 名包含路径，不同根目录下的同名文件仍各自保留。
+pub fn merge_projects(
+    cfg: &Config,
+    src_key: &str,
+    dst_key: &str,
+    remove_source: bool,
+) -> Result<MergeReport> {
+    ensure_writable(cfg, "merge")?;
+    if src_key == dst_key {
+        return Err(anyhow!("cannot merge a project into itself"));
+    }
+    let m = PROJECTS_MUTEX.get_or_init(|| Mutex::new(()));
+    let _g = m.lock();
+    let mut projects = ProjectsIndex::load(&cfg.projects_file()).unwrap_or_default();
+    let src = projects
+        .0
+        .get(src_key)
+        .cloned()
+        .ok_or_else(|| anyhow!("project not indexed: {}", src_key))?;
+    let dst = projects
+        .0
+        .get_mut(dst_key)
+        .ok_or_else(|| anyhow!("project not indexed: {}", dst_key))?;
+    let dst_blobs_before = dst.len();
+    let mut seen: HashSet<String> = dst.iter().cloned().collect();
+    dst.extend(src.into_iter().filter(|n| seen.insert(n.clone())));
+    let dst_blobs_after = dst.len();
+
+    let mut metas = IndexMeta::load(&cfg.index_meta_file()).unwrap_or_default();
+    let src_meta = if remove_source {
+        metas.0.remove(src_key)
+    } else {
+        metas.0.get(src_key).cloned()
+    };
+    if let Some(src_meta) = src_meta {
+        let dst_meta = metas.0.entry(dst_key.to_string()).or_default();
+        for (file, names) in src_meta.files {
+            let entry = dst_meta.files.entry(file).or_default();
+            for n in names {
+                if !entry.contains(&n) {
+                    entry.push(n);
+                }
+            }
+        }
+        for (file, lang) in src_meta.languages {
+            dst_meta.languages.entry(file).or_insert(lang);
+        }
+        dst_meta.content_hashes.extend(src_meta.content_hashes);
+    }
+    let mut cps = Checkpoints::load(&cfg.checkpoints_file()).unwrap_or_default();
+    let mut cps_changed = cps.0.remove(dst_key).is_some();
+    if remove_source {
+        projects.0.remove(src_key);
+        cps_changed |= cps.0.remove(src_key).is_some();
+        let mut tags = ProjectTags::load(&cfg.project_meta_file()).unwrap_or_default();
+        if tags.0.remove(src_key).is_some() {
+            tags.save(&cfg.project_meta_file())?;
+        }
+    }
+    projects.save(&cfg.projects_file())?;
+    metas.save(&cfg.index_meta_file())?;
+    if cps_changed {
+        cps.save(&cfg.checkpoints_file())?;
+    }
+    tracing::info!(
+        src = %src_key,
+        dst = %dst_key,
+        added = dst_blobs_after - dst_blobs_before,
+        remove_source,
+        "projects merged"
+    );
+    Ok(MergeReport {
+        src_key: src_key.to_string(),
+        dst_key: dst_key.to_string(),
+        dst_blobs_before,
+        dst_blobs_after,
+        source_removed: remove_source,
+    })
+}
+
+/// 询问后端（verify_endpoint）项目已存储的 blob 是否仍存在：缺失的从 projects.json 删除、
+/// 丢弃该项目的检索 checkpoint，再对能找回路径的项目做一次增量索引以重新上传。
+/// 未配置 verify_endpoint 时返回 None（不支持）。
+pub async fn verify_blobs(cfg: &Config, project_key: &str) -> Result<Option<VerifyReport>> {
+    ensure_writable(cfg, "verify")?;
+    let Some(stored) = load_projects(cfg).0.remove(project_key) else {
+        return Err(anyhow!("project not indexed: {}", project_key));
+    };
+    let path = known_projects(cfg)
+        .into_iter()
+        .find(|(k, _)| k == project_key)
+        .map(|(_, p)| p);
+    let project_cfg = match &path {
+        Some(p) => project_config(cfg, p),
+        None => cfg.clone(),
+    };
+    let cfg = &project_cfg;
+    let Some(missing) = backend::find_missing_blobs(cfg, &stored).await? else {
+        return Ok(None);
+    };
+    let mut report = VerifyReport {
+        checked: stored.len(),
+        missing,
+        reuploaded: 0,
+    };
+    if report.missing.is_empty() {
+        return Ok(Some(report));
+    }
+    tracing::warn!(
+        project = %project_key,
+        missing = report.missing.len(),
+        "backend no longer has some stored blobs; pruning"
+    );
     {
+        let gone: HashSet<&String> = report.missing.iter().collect();
         let m = PROJECTS_MUTEX.get_or_init(|| Mutex::new(()));
         let _g = m.lock();
         let mut projects = ProjectsIndex::load(&cfg.projects_file()).unwrap_or_default();
-        projects
-            .0
-            .insert(project_key.to_string(), all_names.clone());
+        if let Some(names) = projects.0.get_mut(project_key) {
+            names.retain(|n| !gone.contains(n));
+        }
         projects.save(&cfg.projects_file())?;
+        let mut cps = Checkpoints::load(&cfg.checkpoints_file()).unwrap_or_default();
+        if cps.0.remove(project_key).is_some() {
+            cps.save(&cfg.checkpoints_file())?;
+        }
+    }
+    match path.filter(|p| Path::new(p).exists()) {
+        Some(path) => {
+            report.reuploaded = index_and_persist(cfg, project_key, &path, false)
+                .await?
+                .new_blobs;
+        }
+        None => tracing::warn!(
+            project = %project_key,
+            "project path unknown or missing; pruned without re-uploading"
+        ),
+    }
+    Ok(Some(report))
+}
+
+/// 清空本地状态（projects.json、index_meta.json、checkpoints.json、project_meta.json、查询历史目录，
+/// 可选 aliases.json），保留 settings.toml。
+/// 返回实际删除的文件。调用方负责先中止运行中的任务。
+pub fn reset_local_state(cfg: &Config, keep_aliases: bool) -> Result<Vec<PathBuf>> {
+    let m = PROJECTS_MUTEX.get_or_init(|| Mutex::new(()));
+    let _g = m.lock();
+    let mut targets = vec![
+        cfg.projects_file(),
+        cfg.index_meta_file(),
+        cfg.checkpoints_file(),
+        cfg.project_meta_file(),
+    ];
+    if !keep_aliases {
+        targets.push(cfg.aliases_file());
+    }
+    let mut cleared = Vec::new();
+    for path in targets {
+        match fs::remove_file(&path) {
+            Ok(()) => cleared.push(path),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(anyhow!("failed to remove {}: {}", path.display(), e)),
+        }
     }
-    Ok((total, newn, existing, all_names))
+    let history = cfg.history_dir();
+    match fs::remove_dir_all(&history) {
+        Ok(()) => cleared.push(history),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(anyhow!("failed to remove {}: {}", history.display(), e)),
+    }
+    tracing::info!(cleared = cleared.len(), keep_aliases, "local state reset");
+    Ok(cleared)
 }
 
-/// 若需要索引则先索引（可跳过已有缓存），随后检索并返回格式化文本。
+/// 启动后台索引任务并返回 task_id；若该项目已有任务在运行则返回 None。
+/// HTTP 异步索引与 MCP `index_project_async` 共用。
+pub fn start_index_task(
+    tasks: &TaskManager,
+    cfg: Config,
+    project_key: String,
+    path: String,
+    force_full: bool,
+) -> Option<String> {
+    if tasks.is_running(&project_key) || !tasks.begin(&project_key) {
+        return None;
+    }
+    tasks.set_percent_by_bytes(&project_key, cfg.settings.progress_percent_by_bytes);
+    let tasks_bg = tasks.clone();
+    let key_bg = project_key.clone();
+    let handle = tokio::spawn(async move {
+        let _permit = tasks_bg.acquire_index_permit(&key_bg).await;
+        tasks_bg.set_phase(&key_bg, "collecting");
+        let mut totals_set = false;
+        match index_and_persist_with_progress(
+            &cfg,
+            &key_bg,
+            &path,
+            force_full,
+            |c| tasks_bg.on_collect(&key_bg, c.files_seen),
+            |p| {
+                if !totals_set {
+                    tasks_bg.set_upload_totals(
+                        &key_bg,
+                        p.total_items,
+                        p.chunks_total,
+                        p.total_items,
+                        p.total_bytes,
+                    );
+                    totals_set = true;
+                }
+                tasks_bg.on_chunk(&key_bg, p.uploaded_items, p.chunk_index, p.chunk_bytes);
+            },
+        )
+        .await
+        {
+            Ok(_report) => {
+                tasks_bg.finish(&key_bg);
+            }
+            Err(e) => {
+                tasks_bg.fail(&key_bg, e.to_string());
+            }
+        }
+    });
+    tasks.set_handle(&project_key, handle);
+    tasks.task_id(&project_key)
+}
+
+/// Outcome of one `reindex_known_projects` cycle.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReindexCycle {
+    /// Projects whose incremental index task was started (or queued)
+    pub started: Vec<String>,
+    /// Projects skipped because an index task for them is already running
+    pub busy: Vec<String>,
+    /// Projects whose directory no longer exists
+    pub missing: Vec<String>,
+    /// Missing projects removed from local state (`reindex_prune_missing`)
+    pub pruned: Vec<String>,
+}
+
+/// 为每个已知项目启动增量索引任务（与异步索引共用 max_concurrent_indexes 排队），
+/// 跳过目录已不存在的项目；reindex_prune_missing 时删除它们的本地状态。
+pub fn reindex_known_projects(cfg: &Config, tasks: &TaskManager) -> Result<ReindexCycle> {
+    ensure_writable(cfg, "reindex")?;
+    let mut cycle = ReindexCycle::default();
+    for (key, path) in known_projects(cfg) {
+        if !Path::new(&path).exists() {
+            cycle.missing.push(key);
+            continue;
+        }
+        let project_cfg = project_config(cfg, &path);
+        match start_index_task(tasks, project_cfg, key.clone(), path, false) {
+            Some(_) => cycle.started.push(key),
+            None => cycle.busy.push(key),
+        }
+    }
+    if cfg.settings.reindex_prune_missing && !cycle.missing.is_empty() {
+        forget_projects(cfg, &cycle.missing)?;
+        cycle.pruned = cycle.missing.clone();
+    }
+    tracing::info!(
+        started = cycle.started.len(),
+        busy = cycle.busy.len(),
+        missing = cycle.missing.len(),
+        pruned = cycle.pruned.len(),
+        "periodic re-index cycle"
+    );
+    Ok(cycle)
+}
+
+/// 删除项目的全部本地状态（blob 列表、索引元数据、检索 checkpoint、标签）。
+fn forget_projects(cfg: &Config, keys: &[String]) -> Result<()> {
+    let m = PROJECTS_MUTEX.get_or_init(|| Mutex::new(()));
+    let _g = m.lock();
+    let mut projects = ProjectsIndex::load(&cfg.projects_file()).unwrap_or_default();
+    let mut metas = IndexMeta::load(&cfg.index_meta_file()).unwrap_or_default();
+    let mut cps = Checkpoints::load(&cfg.checkpoints_file()).unwrap_or_default();
+    let mut tags = ProjectTags::load(&cfg.project_meta_file()).unwrap_or_default();
+    let (mut metas_changed, mut cps_changed, mut tags_changed) = (false, false, false);
+    for key in keys {
+        projects.0.remove(key);
+        metas_changed |= metas.0.remove(key).is_some();
+        cps_changed |= cps.0.remove(key).is_some();
+        tags_changed |= tags.0.remove(key).is_some();
+    }
+    projects.save(&cfg.projects_file())?;
+    if metas_changed {
+        metas.save(&cfg.index_meta_file())?;
+    }
+    if cps_changed {
+        cps.save(&cfg.checkpoints_file())?;
+    }
+    if tags_changed {
+        tags.save(&cfg.project_meta_file())?;
+    }
+    Ok(())
+}
+
+/// Spawn the `reindex_interval_secs` loop running `reindex_known_projects` once per interval,
+/// starting one interval after launch. None when the interval is 0 or in `read_only` mode.
+pub fn spawn_reindex_scheduler(
+    cfg: Config,
+    tasks: TaskManager,
+) -> Option<tokio::task::JoinHandle<()>> {
+    let secs = cfg.settings.reindex_interval_secs;
+    if secs == 0 {
+        return None;
+    }
+    if cfg.settings.read_only {
+        tracing::warn!("reindex_interval_secs is ignored in read-only mode");
+        return None;
+    }
+    Some(tokio::spawn(async move {
+        let period = Duration::from_secs(secs);
+        let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        // 上一轮任务仍在排队时不补发错过的周期
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = reindex_known_projects(&cfg, &tasks) {
+                tracing::warn!(error = %e, "periodic re-index failed");
+            }
+        }
+    }))
+}
+
+/// `retrieval_overflow = "reject"` 且检索名额已满时返回的错误。
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("busy: {limit} retrievals already running (max_concurrent_retrievals); retry later")]
+pub struct RetrievalBusy {
+    pub limit: usize,
+}
+
+/// Server-wide cap on concurrent searches hitting the backend (`max_concurrent_retrievals`).
+/// Clones share the same slots.
+#[derive(Clone, Default)]
+pub struct RetrievalLimiter {
+    // None 表示不限制
+    permits: Option<Arc<Semaphore>>,
+    limit: usize,
+    overflow: RetrievalOverflow,
+}
+
+impl RetrievalLimiter {
+    pub fn from_config(cfg: &Config) -> Self {
+        let limit = cfg.settings.max_concurrent_retrievals;
+        Self {
+            permits: (limit > 0).then(|| Arc::new(Semaphore::new(limit))),
+            limit,
+            overflow: cfg.settings.retrieval_overflow,
+        }
+    }
+
+    /// Take a retrieval slot, released when the permit is dropped. With `queue` this waits
+    /// for a free slot; with `reject` a full limiter returns `RetrievalBusy`.
+    pub async fn acquire(&self) -> Result<Option<OwnedSemaphorePermit>, RetrievalBusy> {
+        let Some(sem) = self.permits.clone() else {
+            return Ok(None);
+        };
+        match self.overflow {
+            RetrievalOverflow::Queue => Ok(sem.acquire_owned().await.ok()),
+            RetrievalOverflow::Reject => sem
+                .try_acquire_owned()
+                .map(Some)
+                .map_err(|_| RetrievalBusy { limit: self.limit }),
+        }
+    }
+}
+
+/// 若需要索引则先索引（可跳过已有缓存），随后在 `limiter` 的名额内检索并返回格式化文本。
 pub async fn ensure_index_then_retrieve(
     cfg: &Config,
+    limiter: &RetrievalLimiter,
     project_key: &str,
     path: &str,
     query: &str,
     skip_index_if_indexed: bool,
 ) -> Result<String> {
-    let projects = {
+    let all_blob_names = ensure_indexed(cfg, project_key, path, skip_index_if_indexed).await?;
+    let _permit = limiter.acquire().await?;
+    Ok(retrieve(cfg, project_key, &all_blob_names, query)
+        .await?
+        .into_text(cfg))
+}
+
+/// 检索项目：若有上次的检查点则只发送新增/删除的 blob，并保存后端返回的新检查点；
+/// 后端不支持检查点时始终发送完整列表。`pinned_paths` 的 blob 每次都会附带发送。
+pub async fn retrieve(
+    cfg: &Config,
+    project_key: &str,
+    all_blob_names: &[String],
+    query: &str,
+) -> Result<Retrieved> {
+    let prev = {
         let m = PROJECTS_MUTEX.get_or_init(|| Mutex::new(()));
         let _g = m.lock();
-        ProjectsIndex::load(&cfg.projects_file()).unwrap_or_default()
-    };
-    let mut all_blob_names: Vec<String> = Vec::new();
-    let mut need_index = true;
-    if skip_index_if_indexed {
-        if let Some(existing) = projects.0.get(project_key) {
-            if !existing.is_empty() {
-                all_blob_names = existing.clone();
-                need_index = false;
-                tracing::info!(
-                    blobs = all_blob_names.len(),
-                    "using existing index (skip_index_if_indexed=true)"
-                );
-            }
+        Checkpoints::load(&cfg.checkpoints_file())
+            .unwrap_or_default()
+            .0
+            .remove(project_key)
+    };
+    let pinned = pinned_blob_names(cfg, project_key);
+    let (retrieved, next) =
+        backend::retrieve_with_checkpoint(cfg, all_blob_names, &pinned, query, prev.as_ref())
+            .await?;
+    if next != prev {
+        let m = PROJECTS_MUTEX.get_or_init(|| Mutex::new(()));
+        let _g = m.lock();
+        let mut cps = Checkpoints::load(&cfg.checkpoints_file()).unwrap_or_default();
+        match next {
+            Some(cp) => cps.0.insert(project_key.to_string(), cp),
+            None => cps.0.remove(project_key),
+        };
+        if let Err(e) = cps.save(&cfg.checkpoints_file()) {
+            tracing::warn!(error = %e, "failed to save retrieval checkpoint");
+        }
+    }
+    record_history(cfg, project_key, query, all_blob_names.len(), &retrieved);
+    Ok(retrieved)
+}
+
+/// 临时项目：把内存中的文本（如 stdin）作为单个虚拟文件 `name` 分块、上传并检索。
+/// 只写后端，不写 projects.json / checkpoints / history；后端没有删除接口，
+/// 本地不留记录即视为清理完成。
+pub async fn search_text(
+    cfg: &Config,
+    name: &str,
+    content: String,
+    query: &str,
+) -> Result<Retrieved> {
+    ensure_writable(cfg, "search-stdin")?;
+    let opts = CollectOptions::from_config(cfg);
+    let content = apply_all(&opts.content_filters, content);
+    if content.trim().is_empty() {
+        return Err(anyhow!("no content to search: input is empty"));
+    }
+    let ephemeral_key = format!("ephemeral:{}", &hash_content(&content)[..12]);
+    let max_lines = opts.max_lines_for(Path::new(name));
+    let blobs = text_blobs(name, content, max_lines, opts.chunk_numbering, None);
+    tracing::info!(project_key = %ephemeral_key, blobs = blobs.len(), "indexing ephemeral content");
+    let unconfirmed = backend::upload_new_blobs(cfg, &blobs).await?.unconfirmed;
+    let names: Vec<String> = blobs
+        .iter()
+        .map(|b| hash_blob_name(&b.path, &b.content))
+        .filter(|n| !unconfirmed.contains(n))
+        .collect();
+    if names.is_empty() {
+        return Err(anyhow!("backend confirmed none of the uploaded blobs"));
+    }
+    let (retrieved, _) = backend::retrieve_with_checkpoint(cfg, &names, &[], query, None).await?;
+    Ok(retrieved)
+}
+
+/// `history.jsonl` 中的一条检索记录。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub timestamp_ms: u64,
+    pub query: String,
+    /// Characters of retrieved text; 0 for an empty result
+    pub result_len: usize,
+    pub blob_count: usize,
+    /// Retrieved text, only with `history_include_result`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<String>,
+}
+
+/// 开启 `record_history` 时向项目的 history.jsonl 追加一条记录；写入失败只记录警告。
+fn record_history(
+    cfg: &Config,
+    project_key: &str,
+    query: &str,
+    blob_count: usize,
+    retrieved: &Retrieved,
+) {
+    if !cfg.settings.record_history {
+        return;
+    }
+    let text = match retrieved {
+        Retrieved::Context(text) => Some(text.as_str()),
+        Retrieved::Empty => None,
+    };
+    let entry = HistoryEntry {
+        timestamp_ms: now_ms(),
+        query: query.to_string(),
+        result_len: text.map_or(0, |t| t.chars().count()),
+        blob_count,
+        result: text
+            .filter(|_| cfg.settings.history_include_result)
+            .map(str::to_string),
+    };
+    let path = cfg.history_file(project_key);
+    let res = (|| -> Result<()> {
+        use std::io::Write;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
         }
+        let line = format!("{}\n", serde_json::to_string(&entry)?);
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?
+            .write_all(line.as_bytes())?;
+        Ok(())
+    })();
+    if let Err(e) = res {
+        tracing::warn!(error = %e, file = %path.display(), "failed to record retrieval history");
     }
-    if need_index {
-        let (_t, _n, _e, all) = index_and_persist(cfg, project_key, path, false).await?;
-        all_blob_names = all;
+}
+
+/// 项目最近的 `limit` 条检索记录（按时间顺序）；无记录时为空。
+pub fn read_history(cfg: &Config, project_key: &str, limit: usize) -> Result<Vec<HistoryEntry>> {
+    let text = match fs::read_to_string(cfg.history_file(project_key)) {
+        Ok(t) => t,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let lines: Vec<&str> = text.lines().filter(|l| !l.trim().is_empty()).collect();
+    lines[lines.len().saturating_sub(limit)..]
+        .iter()
+        .map(|l| serde_json::from_str(l).map_err(Into::into))
+        .collect()
+}
+
+/// 使用同一份 blob 列表（附带 `pinned_paths` 的 blob）并发执行多个检索
+/// （并发数受 max_concurrent_queries 限制，每条检索另占 `limiter` 的一个名额），
+/// 按输入顺序返回 (query, result)。
+pub async fn retrieve_many(
+    cfg: &Config,
+    limiter: &RetrievalLimiter,
+    project_key: &str,
+    all_blob_names: Vec<String>,
+    queries: Vec<String>,
+) -> Vec<(String, Result<Retrieved>)> {
+    let sem = Arc::new(Semaphore::new(cfg.settings.max_concurrent_queries.max(1)));
+    let names = Arc::new(all_blob_names);
+    let pinned = Arc::new(pinned_blob_names(cfg, project_key));
+    let mut set = JoinSet::new();
+    for (i, q) in queries.iter().cloned().enumerate() {
+        let sem = sem.clone();
+        let names = names.clone();
+        let pinned = pinned.clone();
+        let cfg = cfg.clone();
+        let project_key = project_key.to_string();
+        let limiter = limiter.clone();
+        set.spawn(async move {
+            let _permit = sem.acquire_owned().await;
+            let _slot = match limiter.acquire().await {
+                Ok(slot) => slot,
+                Err(e) => return (i, Err(e.into())),
+            };
+            let r = backend::retrieve_with_checkpoint(&cfg, &names, &pinned, &q, None).await;
+            if let Ok((retrieved, _)) = &r {
+                record_history(&cfg, &project_key, &q, names.len(), retrieved);
+            }
+            (i, r.map(|(retrieved, _)| retrieved))
+        });
+    }
+    let mut results: Vec<Option<Result<Retrieved>>> = (0..queries.len()).map(|_| None).collect();
+    while let Some(joined) = set.join_next().await {
+        match joined {
+            Ok((i, r)) => results[i] = Some(r),
+            Err(e) => tracing::warn!(error = %e, "retrieval task failed"),
+        }
     }
-    let formatted = backend::retrieve_formatted(cfg, &all_blob_names, query).await?;
-    Ok(formatted)
+    queries
+        .into_iter()
+        .zip(results)
+        .map(|(q, r)| {
+            (
+                q,
+                r.unwrap_or_else(|| Err(anyhow!("retrieval task aborted"))),
+            )
+        })
+        .collect()
 }
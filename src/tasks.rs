@@ -2,19 +2,32 @@ use parking_lot::Mutex;
 use serde::Serialize;
 use std::{
     collections::HashMap,
-    sync::Arc,
-    time::{SystemTime, UNIX_EPOCH},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 
 #[derive(Clone, Debug, Serialize, Default)]
 pub struct TaskProgress {
+    /// Unique per run; query with `GET /api/tasks/{task_id}`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub task_id: Option<String>,
     pub phase: String,
+    /// Files visited during the collecting phase
+    pub files_seen: usize,
     pub total: usize,
     pub new_total: usize,
     pub uploaded: usize,
     pub chunks_total: usize,
     pub chunk_index: usize,
     pub chunk_bytes: usize,
+    pub uploaded_bytes: usize,
+    pub total_bytes: usize,
+    /// When true, `percent` is computed from bytes instead of item count
+    pub percent_by_bytes: bool,
     pub percent: f32,
     pub started_at: u64,
     pub updated_at: u64,
@@ -36,12 +49,41 @@ impl TaskProgress {
             ..Default::default()
         }
     }
+
+    /// Seconds left, estimated from the average time per finished chunk; None before the
+    /// first chunk or when nothing is left.
+    pub fn eta_secs(&self) -> Option<u64> {
+        if self.chunk_index == 0 || self.chunks_total == 0 || self.updated_at < self.started_at {
+            return None;
+        }
+        let elapsed = self.updated_at.saturating_sub(self.started_at);
+        let remaining_chunks = self.chunks_total.saturating_sub(self.chunk_index);
+        if elapsed == 0 || remaining_chunks == 0 {
+            return None;
+        }
+        let avg = elapsed / (self.chunk_index as u64).max(1);
+        Some(avg.saturating_mul(remaining_chunks as u64))
+    }
+
+    fn is_terminal(&self) -> bool {
+        matches!(self.phase.as_str(), "done" | "failed" | "aborted")
+    }
 }
 
+/// (generation, pending timer task)
+type DebounceTimer = (u64, tokio::task::JoinHandle<()>);
+
 #[derive(Clone, Default)]
 pub struct TaskManager {
     statuses: Arc<Mutex<HashMap<String, TaskProgress>>>,
     handles: Arc<Mutex<HashMap<String, tokio::task::JoinHandle<()>>>>,
+    timers: Arc<Mutex<HashMap<String, DebounceTimer>>>,
+    timer_seq: Arc<AtomicU64>,
+    // task_id -> project key（仅保留每个 key 最近一次运行）
+    task_ids: Arc<Mutex<HashMap<String, String>>>,
+    task_seq: Arc<AtomicU64>,
+    // 全局索引并发上限；None 表示不限制
+    index_permits: Option<Arc<Semaphore>>,
 }
 
 impl TaskManager {
@@ -49,15 +91,63 @@ impl TaskManager {
         Self::default()
     }
 
+    /// At most `max_concurrent_indexes` index tasks run at once (0 = unlimited); others queue.
+    pub fn with_limit(max_concurrent_indexes: usize) -> Self {
+        Self {
+            index_permits: (max_concurrent_indexes > 0)
+                .then(|| Arc::new(Semaphore::new(max_concurrent_indexes))),
+            ..Self::default()
+        }
+    }
+
+    /// Wait for a global index slot, reporting the `queued` phase while waiting.
+    /// The slot is released when the returned permit is dropped.
+    pub async fn acquire_index_permit(&self, key: &str) -> Option<OwnedSemaphorePermit> {
+        let sem = self.index_permits.clone()?;
+        if let Ok(p) = sem.clone().try_acquire_owned() {
+            return Some(p);
+        }
+        self.set_phase(key, "queued");
+        sem.acquire_owned().await.ok()
+    }
+
     pub fn begin(&self, key: &str) -> bool {
         let mut map = self.statuses.lock();
-        if map.contains_key(key) {
+        if map.get(key).is_some_and(|st| !st.is_terminal()) {
             return false;
         }
-        map.insert(key.to_string(), TaskProgress::new_start());
+        let task_id = format!(
+            "{:x}-{}",
+            TaskProgress::now(),
+            self.task_seq.fetch_add(1, Ordering::Relaxed)
+        );
+        let mut ids = self.task_ids.lock();
+        if let Some(old) = map.get(key).and_then(|st| st.task_id.as_ref()) {
+            ids.remove(old);
+        }
+        ids.insert(task_id.clone(), key.to_string());
+        map.insert(
+            key.to_string(),
+            TaskProgress {
+                task_id: Some(task_id),
+                ..TaskProgress::new_start()
+            },
+        );
         true
     }
 
+    /// Task id of the latest run for `key`.
+    pub fn task_id(&self, key: &str) -> Option<String> {
+        self.statuses.lock().get(key)?.task_id.clone()
+    }
+
+    /// Look up (project key, progress) by task id.
+    pub fn get_by_task_id(&self, task_id: &str) -> Option<(String, TaskProgress)> {
+        let key = self.task_ids.lock().get(task_id)?.clone();
+        let progress = self.get(&key)?;
+        Some((key, progress))
+    }
+
     pub fn set_handle(&self, key: &str, h: tokio::task::JoinHandle<()>) {
         self.handles.lock().insert(key.to_string(), h);
     }
@@ -69,31 +159,53 @@ impl TaskManager {
         }
     }
 
+    pub fn on_collect(&self, key: &str, files_seen: usize) {
+        if let Some(st) = self.statuses.lock().get_mut(key) {
+            st.phase = "collecting".into();
+            st.files_seen = files_seen;
+            st.updated_at = TaskProgress::now();
+        }
+    }
+
     pub fn set_upload_totals(
         &self,
         key: &str,
         new_total: usize,
         chunks_total: usize,
         total: usize,
+        total_bytes: usize,
     ) {
         if let Some(st) = self.statuses.lock().get_mut(key) {
             st.new_total = new_total;
             st.chunks_total = chunks_total;
             st.total = total;
+            st.total_bytes = total_bytes;
             st.updated_at = TaskProgress::now();
         }
     }
 
+    pub fn set_percent_by_bytes(&self, key: &str, by_bytes: bool) {
+        if let Some(st) = self.statuses.lock().get_mut(key) {
+            st.percent_by_bytes = by_bytes;
+        }
+    }
+
     pub fn on_chunk(&self, key: &str, uploaded: usize, chunk_index: usize, chunk_bytes: usize) {
         if let Some(st) = self.statuses.lock().get_mut(key) {
             st.phase = "uploading".into();
             st.uploaded = uploaded;
             st.chunk_index = chunk_index;
             st.chunk_bytes = chunk_bytes;
-            st.percent = if st.new_total == 0 {
+            st.uploaded_bytes += chunk_bytes;
+            let (done, total) = if st.percent_by_bytes {
+                (st.uploaded_bytes, st.total_bytes)
+            } else {
+                (uploaded, st.new_total)
+            };
+            st.percent = if total == 0 {
                 100.0
             } else {
-                (uploaded as f32) * 100.0 / (st.new_total as f32)
+                (done as f32) * 100.0 / (total as f32)
             };
             st.updated_at = TaskProgress::now();
         }
@@ -129,6 +241,23 @@ impl TaskManager {
         false
     }
 
+    /// Abort every running task and pending debounce timer; returns how many tasks were aborted.
+    pub fn abort_all(&self) -> usize {
+        for (_, (_, timer)) in self.timers.lock().drain() {
+            timer.abort();
+        }
+        let handles: Vec<_> = self.handles.lock().drain().collect();
+        let mut statuses = self.statuses.lock();
+        for (key, h) in &handles {
+            h.abort();
+            if let Some(st) = statuses.get_mut(key) {
+                st.phase = "aborted".into();
+                st.updated_at = TaskProgress::now();
+            }
+        }
+        handles.len()
+    }
+
     pub fn is_running(&self, key: &str) -> bool {
         self.handles.lock().contains_key(key)
     }
@@ -136,4 +265,41 @@ impl TaskManager {
     pub fn get(&self, key: &str) -> Option<TaskProgress> {
         self.statuses.lock().get(key).cloned()
     }
+
+    /// Run `job` once `delay` has passed without another `debounce` call for the same key.
+    /// A pending timer for the key is cancelled and replaced. Returns the scheduled run time
+    /// (unix millis).
+    pub fn debounce<F>(&self, key: &str, delay: Duration, job: F) -> u64
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let id = self.timer_seq.fetch_add(1, Ordering::Relaxed);
+        let timers = self.timers.clone();
+        let key_bg = key.to_string();
+        // 持锁插入，避免定时器在登记前就触发
+        let mut map = self.timers.lock();
+        let handle = tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            {
+                let mut map = timers.lock();
+                if map.get(&key_bg).is_some_and(|(gen_id, _)| *gen_id == id) {
+                    map.remove(&key_bg);
+                }
+            }
+            job();
+        });
+        if let Some((_, old)) = map.insert(key.to_string(), (id, handle)) {
+            old.abort();
+        }
+        drop(map);
+        SystemTime::now()
+            .checked_add(delay)
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default()
+            .as_millis() as u64
+    }
+
+    pub fn has_pending_timer(&self, key: &str) -> bool {
+        self.timers.lock().contains_key(key)
+    }
 }
@@ -1,7 +1,9 @@
+mod common;
+
 use augmcp::{
     backend,
-    config::{Config, Settings},
-    indexer::BlobUpload,
+    config::{Config, RequestOverflowMode, Settings, UploadOrder},
+    indexer::{BlobUpload, hash_blob_name},
 };
 use axum::{Json, Router, routing::post};
 use serde::{Deserialize, Serialize};
@@ -31,7 +33,7 @@ struct RetrievalResp {
     formatted_retrieval: String,
 }
 
-async fn start_stub_server() -> (SocketAddr, tokio::task::JoinHandle<()>) {
+async fn start_stub_server() -> SocketAddr {
     let app = Router::new()
         .route(
             "/batch-upload",
@@ -56,12 +58,7 @@ async fn start_stub_server() -> (SocketAddr, tokio::task::JoinHandle<()>) {
             }),
         );
 
-    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
-    let addr = listener.local_addr().unwrap();
-    let handle = tokio::spawn(async move {
-        let _ = axum::serve(listener, app).await;
-    });
-    (addr, handle)
+    common::serve(app).await
 }
 
 fn test_config(base_url: String) -> Config {
@@ -81,6 +78,7 @@ fn test_config(base_url: String) -> Config {
             max_output_length: 0,
             disable_codebase_retrieval: false,
             enable_commit_retrieval: false,
+            ..Settings::default()
         },
         root_dir: root_dir.clone(),
         data_dir: data_dir.clone(),
@@ -90,7 +88,7 @@ fn test_config(base_url: String) -> Config {
 
 #[tokio::test(flavor = "multi_thread")]
 async fn upload_progress_and_retrieval() {
-    let (addr, _h) = start_stub_server().await;
+    let addr = start_stub_server().await;
     let base_url = format!("http://{}:{}", addr.ip(), addr.port());
     let cfg = test_config(base_url);
 
@@ -99,6 +97,7 @@ async fn upload_progress_and_retrieval() {
         .map(|i| BlobUpload {
             path: format!("f{i}.txt"),
             content: format!("c{i}"),
+            ..Default::default()
         })
         .collect();
 
@@ -117,6 +116,8 @@ async fn upload_progress_and_retrieval() {
         "expected 3 chunks for 5 items with batch_size=2"
     );
     assert_eq!(got.last().unwrap().uploaded_items, 5);
+    assert_eq!(got.last().unwrap().uploaded_bytes, 10);
+    assert!(got.iter().all(|p| p.total_bytes == 10));
 
     // Retrieval
     let ans = backend::retrieve_formatted(&cfg, &[], "hello")
@@ -124,3 +125,720 @@ async fn upload_progress_and_retrieval() {
         .unwrap();
     assert!(ans.starts_with("OK: hello"));
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn retrieve_many_preserves_query_order() {
+    let addr = start_stub_server().await;
+    let base_url = format!("http://{}:{}", addr.ip(), addr.port());
+    let mut cfg = test_config(base_url);
+    cfg.settings.max_concurrent_queries = 2;
+
+    let queries: Vec<String> = (0..5).map(|i| format!("q{i}")).collect();
+    let results = augmcp::service::retrieve_many(
+        &cfg,
+        &augmcp::service::RetrievalLimiter::default(),
+        "/proj",
+        vec![],
+        queries.clone(),
+    )
+    .await;
+    assert_eq!(results.len(), 5);
+    for ((q, r), expected) in results.into_iter().zip(queries) {
+        assert_eq!(q, expected);
+        assert_eq!(
+            r.unwrap(),
+            backend::Retrieved::Context(format!("OK: {expected}"))
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn retrieve_many_takes_a_retrieval_slot_per_query() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let peak = Arc::new(AtomicUsize::new(0));
+    let (in_flight_bg, peak_bg) = (in_flight.clone(), peak.clone());
+    let app = Router::new().route(
+        "/agents/codebase-retrieval",
+        post(move |Json(p): Json<RetrievalPayload>| {
+            let (in_flight, peak) = (in_flight_bg.clone(), peak_bg.clone());
+            async move {
+                let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                Json(RetrievalResp {
+                    formatted_retrieval: format!("OK: {}", p.information_request),
+                })
+            }
+        }),
+    );
+    let addr = common::serve(app).await;
+    let mut cfg = test_config(format!("http://{addr}"));
+    cfg.settings.max_concurrent_queries = 4;
+    cfg.settings.max_concurrent_retrievals = 1;
+
+    let queries: Vec<String> = (0..4).map(|i| format!("q{i}")).collect();
+    let limiter = augmcp::service::RetrievalLimiter::from_config(&cfg);
+    let results = augmcp::service::retrieve_many(&cfg, &limiter, "/proj", vec![], queries).await;
+    assert!(results.iter().all(|(_, r)| r.is_ok()));
+    assert_eq!(peak.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn upload_splits_batches_on_413() {
+    // Stub rejects any payload carrying more than 10 content bytes
+    let app = Router::new().route(
+        "/batch-upload",
+        post(|Json(p): Json<UploadPayload>| async move {
+            let bytes: usize = p.blobs.iter().map(|b| b.content.len()).sum();
+            if bytes > 10 {
+                return Err(axum::http::StatusCode::PAYLOAD_TOO_LARGE);
+            }
+            Ok(Json(UploadResp {
+                blob_names: p.blobs.into_iter().map(|b| b.path).collect(),
+            }))
+        }),
+    );
+    let addr = common::serve(app).await;
+    let mut cfg = test_config(format!("http://{}", addr));
+    cfg.settings.batch_size = 4;
+
+    let blobs: Vec<BlobUpload> = (0..4)
+        .map(|i| BlobUpload {
+            path: format!("f{i}.txt"),
+            content: "x".repeat(8),
+            ..Default::default()
+        })
+        .collect();
+    let outcome = backend::upload_new_blobs(&cfg, &blobs).await.unwrap();
+    assert_eq!(
+        outcome.blob_names,
+        vec!["f0.txt", "f1.txt", "f2.txt", "f3.txt"]
+    );
+    assert!(outcome.unconfirmed.is_empty());
+
+    // A single blob that is still too large fails
+    let huge = vec![BlobUpload {
+        path: "big.txt".into(),
+        content: "x".repeat(50),
+        ..Default::default()
+    }];
+    assert!(backend::upload_new_blobs(&cfg, &huge).await.is_err());
+}
+
+#[test]
+fn plan_batches_respects_item_and_byte_limits() {
+    let blob = |n: usize| BlobUpload {
+        path: format!("f{n}"),
+        content: "x".repeat(n),
+        ..Default::default()
+    };
+    let blobs = vec![
+        blob(10),
+        blob(10),
+        blob(10),
+        blob(50),
+        blob(5),
+        blob(5),
+        blob(5),
+    ];
+    let sizes = |b: Vec<&[BlobUpload]>| b.iter().map(|s| s.len()).collect::<Vec<_>>();
+
+    // Item cap only
+    assert_eq!(sizes(backend::plan_batches(&blobs, 3, 0)), vec![3, 3, 1]);
+    // Byte budget 25: oversized blob alone; item cap 2 still applies
+    let batches = backend::plan_batches(&blobs, 2, 25);
+    assert_eq!(sizes(batches.clone()), vec![2, 1, 1, 2, 1]);
+    for b in &batches {
+        let bytes: usize = b.iter().map(|x| x.content.len()).sum();
+        assert!(b.len() == 1 || bytes <= 25);
+    }
+    assert_eq!(batches.iter().map(|b| b.len()).sum::<usize>(), blobs.len());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tls_options_apply_to_client() {
+    let addr = start_stub_server().await;
+    let mut cfg = test_config(format!("http://{}:{}", addr.ip(), addr.port()));
+
+    // Accepting invalid certs does not break plain requests
+    cfg.settings.danger_accept_invalid_certs = true;
+    let out = backend::retrieve_formatted(&cfg, &[], "q").await.unwrap();
+    assert!(out.contains("q"));
+
+    // A bad CA path fails fast with an actionable error
+    cfg.settings.root_ca_cert_path = Some("/nonexistent/ca.pem".into());
+    let err = backend::retrieve_formatted(&cfg, &[], "q")
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("root_ca_cert_path"), "{}", err);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pool_settings_apply_to_client() {
+    let defaults = Settings::default();
+    assert_eq!(defaults.pool_max_idle_per_host, None);
+    assert_eq!(defaults.pool_idle_timeout_secs, 90);
+
+    let addr = start_stub_server().await;
+    let mut cfg = test_config(format!("http://{}:{}", addr.ip(), addr.port()));
+    // No idle connections kept and no idle expiry: requests still succeed
+    cfg.settings.pool_max_idle_per_host = Some(0);
+    cfg.settings.pool_idle_timeout_secs = 0;
+    for q in ["a", "b"] {
+        let out = backend::retrieve_formatted(&cfg, &[], q).await.unwrap();
+        assert_eq!(out, format!("OK: {q}"));
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn retrieval_timeout_returns_partial_text() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    // Raw HTTP stub: promises a longer body, sends a prefix, then stalls
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut sock, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut buf = [0u8; 8192];
+                let _ = sock.read(&mut buf).await;
+                let prefix = r#"{"formatted_retrieval": "fn main() {\n    println!(\"hi\");"#;
+                let head = "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: 4096\r\n\r\n";
+                let _ = sock.write_all(head.as_bytes()).await;
+                let _ = sock.write_all(prefix.as_bytes()).await;
+                let _ = sock.flush().await;
+                tokio::time::sleep(std::time::Duration::from_secs(30)).await;
+            });
+        }
+    });
+    let mut cfg = test_config(format!("http://{}", addr));
+    cfg.settings.retrieval_timeout_secs = 1;
+
+    let out = backend::retrieve_formatted(&cfg, &[], "q").await.unwrap();
+    assert!(
+        out.starts_with("fn main() {\n    println!(\"hi\");"),
+        "{out}"
+    );
+    assert!(out.ends_with(backend::PARTIAL_MARKER));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn empty_retrieval_is_signalled_and_message_configurable() {
+    let app = Router::new().route(
+        "/agents/codebase-retrieval",
+        post(|| async {
+            Json(RetrievalResp {
+                formatted_retrieval: "  \n".into(),
+            })
+        }),
+    );
+    let addr = common::serve(app).await;
+    let mut cfg = test_config(format!("http://{}", addr));
+
+    let r = augmcp::service::retrieve(&cfg, "/proj", &[], "q")
+        .await
+        .unwrap();
+    assert!(r.is_empty());
+    assert_eq!(r.into_text(&cfg), backend::DEFAULT_EMPTY_RESULT_MESSAGE);
+
+    cfg.settings.empty_result_message = Some("nothing here".into());
+    let out = backend::retrieve_formatted(&cfg, &[], "q").await.unwrap();
+    assert_eq!(out, "nothing here");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn retrieval_strip_patterns_remove_backend_boilerplate() {
+    let app = Router::new().route(
+        "/agents/codebase-retrieval",
+        post(|| async {
+            Json(RetrievalResp {
+                formatted_retrieval:
+                    "The following code sections were retrieved:\nfn a() {}\n-- end --".into(),
+            })
+        }),
+    );
+    let addr = common::serve(app).await;
+    let mut cfg = test_config(format!("http://{}", addr));
+    cfg.settings.retrieval_strip_patterns = vec![
+        r"(?m)^The following code sections were retrieved:\n".into(),
+        r"\n?-- end --$".into(),
+        "(invalid".into(),
+    ];
+    let out = backend::retrieve_formatted(&cfg, &[], "q").await.unwrap();
+    assert_eq!(out, "fn a() {}");
+
+    // Stripping everything leaves an empty retrieval
+    cfg.settings.retrieval_strip_patterns = vec![r"(?s).*".into()];
+    let r = augmcp::service::retrieve(&cfg, "/proj", &[], "q")
+        .await
+        .unwrap();
+    assert!(r.is_empty());
+}
+
+#[test]
+fn retrieval_max_chars_truncates_with_ellipsis() {
+    let mut cfg = test_config("http://127.0.0.1:1".into());
+    let text = "héllo wörld".to_string();
+    assert_eq!(backend::postprocess_retrieval(&cfg, text.clone()), text);
+
+    cfg.settings.retrieval_max_chars = 6;
+    let out = backend::postprocess_retrieval(&cfg, text.clone());
+    assert_eq!(out, format!("héllo{}", backend::TRUNCATION_MARKER));
+    assert_eq!(out.chars().count(), 6);
+
+    // Text already within the limit is untouched
+    cfg.settings.retrieval_max_chars = text.chars().count();
+    assert_eq!(backend::postprocess_retrieval(&cfg, text.clone()), text);
+}
+
+#[test]
+fn clean_chunk_paths_in_output_strips_only_chunk_suffixes() {
+    let mut cfg = test_config("http://127.0.0.1:1".into());
+    let text = "Path: src/lib.rs#chunk02of10\n\
+                see src/main.rs#chunk3of3, docs/a b.md#chunk1of2.\n\
+                issue #12, src/x.rs#L10, README.md#chunk5of2, a.rs#chunk2of3x, #chunk1of2"
+        .to_string();
+    // Off by default
+    assert_eq!(backend::postprocess_retrieval(&cfg, text.clone()), text);
+
+    cfg.settings.clean_chunk_paths_in_output = true;
+    assert_eq!(
+        backend::postprocess_retrieval(&cfg, text),
+        "Path: src/lib.rs\n\
+         see src/main.rs, docs/a b.md.\n\
+         issue #12, src/x.rs#L10, README.md#chunk5of2, a.rs#chunk2of3x, #chunk1of2"
+    );
+}
+
+#[test]
+fn upload_blob_shape_follows_settings() {
+    let mut cfg = test_config("http://127.0.0.1:1".into());
+    let chunked = BlobUpload {
+        path: "src/lib.rs#chunk02of10".into(),
+        content: "fn a() {}".into(),
+        ..Default::default()
+    };
+    let plain = BlobUpload {
+        path: "notes.unknown".into(),
+        content: "x".into(),
+        ..Default::default()
+    };
+    let shape = |cfg: &Config, b: &BlobUpload| {
+        serde_json::to_value(backend::UploadBlob::new(cfg, b)).unwrap()
+    };
+
+    // Default: just path and content
+    assert_eq!(
+        shape(&cfg, &chunked),
+        serde_json::json!({"path": "src/lib.rs#chunk02of10", "content": "fn a() {}"})
+    );
+
+    cfg.settings.upload_include_language = true;
+    assert_eq!(
+        shape(&cfg, &chunked),
+        serde_json::json!({"path": "src/lib.rs#chunk02of10", "content": "fn a() {}", "language": "rust"})
+    );
+    // Unknown languages are omitted
+    assert_eq!(
+        shape(&cfg, &plain),
+        serde_json::json!({"path": "notes.unknown", "content": "x"})
+    );
+
+    cfg.settings.upload_include_language = false;
+    cfg.settings.upload_strip_chunk_suffix = true;
+    assert_eq!(
+        shape(&cfg, &chunked),
+        serde_json::json!({"path": "src/lib.rs", "content": "fn a() {}", "chunk_index": 2, "chunk_count": 10})
+    );
+    assert_eq!(
+        shape(&cfg, &plain),
+        serde_json::json!({"path": "notes.unknown", "content": "x"})
+    );
+}
+
+#[test]
+fn upload_metrics_describe_the_source_file_when_enabled() {
+    let td = tempfile::tempdir().unwrap();
+    // 3 lines, 12 bytes, split into 2 chunks
+    std::fs::write(td.path().join("a.txt"), "one\ntwo\nsix\n").unwrap();
+    let opts = augmcp::indexer::CollectOptions {
+        text_exts: [".txt".to_string()].into_iter().collect(),
+        max_lines: 2,
+        ..Default::default()
+    };
+    let blobs = augmcp::indexer::collect_blobs_with(td.path(), &opts).unwrap();
+    assert_eq!(blobs.len(), 2);
+    for b in &blobs {
+        // Every chunk carries the whole file's metrics
+        assert_eq!(
+            (b.line_count, b.byte_size),
+            (Some(3), Some(12)),
+            "{}",
+            b.path
+        );
+    }
+
+    let mut cfg = test_config("http://127.0.0.1:1".into());
+    let shape =
+        |cfg: &Config| serde_json::to_value(backend::UploadBlob::new(cfg, &blobs[1])).unwrap();
+    let json = shape(&cfg);
+    assert!(
+        json.get("line_count").is_none() && json.get("byte_size").is_none(),
+        "{json}"
+    );
+    cfg.settings.upload_include_metrics = true;
+    assert_eq!(
+        shape(&cfg),
+        serde_json::json!({"path": "a.txt#chunk2of2", "content": "six\n", "line_count": 3, "byte_size": 12})
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn blobs_dropped_by_backend_are_reported_and_not_persisted() {
+    // Stub confirms every blob of a batch but the last one
+    let app = Router::new().route(
+        "/batch-upload",
+        post(|Json(p): Json<UploadPayload>| async move {
+            let kept = p.blobs.len().saturating_sub(1);
+            let blob_names = p.blobs[..kept]
+                .iter()
+                .map(|b| hash_blob_name(&b.path, &b.content))
+                .collect();
+            Json(UploadResp { blob_names })
+        }),
+    );
+    let addr = common::serve(app).await;
+    let td = tempfile::tempdir().unwrap();
+    let mut cfg = test_config(format!("http://{}", addr));
+    cfg.data_dir = td.path().join("data");
+
+    // 5 blobs, batch_size 2 -> batches [f0 f1] [f2 f3] [f4]
+    let blobs: Vec<BlobUpload> = (0..5)
+        .map(|i| BlobUpload {
+            path: format!("f{i}.txt"),
+            content: format!("c{i}"),
+            ..Default::default()
+        })
+        .collect();
+    let outcome = backend::upload_new_blobs(&cfg, &blobs).await.unwrap();
+    assert_eq!(outcome.blob_names.len(), 2);
+    let name = |i: usize| hash_blob_name(&blobs[i].path, &blobs[i].content);
+    assert_eq!(outcome.unconfirmed, vec![name(1), name(3), name(4)]);
+
+    let proj = td.path().join("proj");
+    std::fs::create_dir_all(&proj).unwrap();
+    for b in &blobs {
+        std::fs::write(proj.join(&b.path), &b.content).unwrap();
+    }
+    let (key, path) = cfg.resolve_project(&proj.to_string_lossy()).unwrap();
+    let report = augmcp::service::index_and_persist(&cfg, &key, &path, false)
+        .await
+        .unwrap();
+    assert_eq!(report.unconfirmed_blobs, 3);
+    assert_eq!(report.total_blobs, 2);
+    assert!(report.summary().contains("unconfirmed_blobs=3"));
+    let stored = &augmcp::service::load_projects(&cfg).0[&key];
+    assert_eq!(stored.len(), 2);
+    assert_eq!(stored, &report.all_blob_names);
+
+    // Unconfirmed blobs are uploaded again on the next index
+    let again = augmcp::service::index_and_persist(&cfg, &key, &path, false)
+        .await
+        .unwrap();
+    assert_eq!(again.new_blobs, 3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn upload_order_controls_send_order() {
+    let received: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(vec![]));
+    let rec = received.clone();
+    let app = Router::new().route(
+        "/batch-upload",
+        post(move |Json(p): Json<UploadPayload>| {
+            let rec = rec.clone();
+            async move {
+                let names = p
+                    .blobs
+                    .into_iter()
+                    .map(|b| {
+                        rec.lock().unwrap().push(b.path.clone());
+                        hash_blob_name(&b.path, &b.content)
+                    })
+                    .collect();
+                Json(UploadResp { blob_names: names })
+            }
+        }),
+    );
+    let addr = common::serve(app).await;
+    let mut cfg = test_config(format!("http://{}", addr));
+
+    // Walk order b, c, a; sizes 2, 3, 1; mtimes old, unknown, new
+    let blob = |path: &str, content: &str, mtime_ms: Option<u64>| BlobUpload {
+        path: path.into(),
+        content: content.into(),
+        mtime_ms,
+        ..Default::default()
+    };
+    let blobs = vec![
+        blob("b.txt", "bb", Some(1)),
+        blob("c.txt", "ccc", None),
+        blob("a.txt", "a", Some(2)),
+    ];
+    for (order, expected) in [
+        (UploadOrder::Walk, ["b.txt", "c.txt", "a.txt"]),
+        (UploadOrder::Path, ["a.txt", "b.txt", "c.txt"]),
+        (UploadOrder::SizeDesc, ["c.txt", "b.txt", "a.txt"]),
+        (UploadOrder::Recency, ["a.txt", "b.txt", "c.txt"]),
+    ] {
+        cfg.settings.upload_order = order;
+        received.lock().unwrap().clear();
+        let outcome = backend::upload_new_blobs(&cfg, &blobs).await.unwrap();
+        assert!(outcome.unconfirmed.is_empty());
+        assert_eq!(*received.lock().unwrap(), expected, "{order:?}");
+
+        received.lock().unwrap().clear();
+        backend::upload_new_blobs_with_progress(&cfg, &blobs, |_| {})
+            .await
+            .unwrap();
+        assert_eq!(
+            *received.lock().unwrap(),
+            expected,
+            "{order:?} with progress"
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn upload_inter_batch_delay_spaces_batches() {
+    let addr = start_stub_server().await;
+    let mut cfg = test_config(format!("http://{}", addr));
+    cfg.settings.upload_inter_batch_delay_ms = 200;
+    // 5 blobs, batch_size 2 -> 3 batches -> 2 pauses
+    let blobs: Vec<BlobUpload> = (0..5)
+        .map(|i| BlobUpload {
+            path: format!("f{i}.txt"),
+            content: format!("c{i}"),
+            ..Default::default()
+        })
+        .collect();
+
+    let start = std::time::Instant::now();
+    backend::upload_new_blobs(&cfg, &blobs).await.unwrap();
+    assert!(start.elapsed() >= std::time::Duration::from_millis(400));
+
+    let start = std::time::Instant::now();
+    let mut batches = 0;
+    backend::upload_new_blobs_with_progress(&cfg, &blobs, |_| batches += 1)
+        .await
+        .unwrap();
+    let elapsed = start.elapsed();
+    assert_eq!(batches, 3);
+    assert!(elapsed >= std::time::Duration::from_millis(400));
+    // No pause after the last batch
+    assert!(
+        elapsed < std::time::Duration::from_millis(600),
+        "{elapsed:?}"
+    );
+}
+
+#[test]
+fn upload_blob_carries_id_when_send_blob_ids() {
+    let mut cfg = test_config("http://127.0.0.1:1".into());
+    let blob = BlobUpload {
+        path: "src/lib.rs#chunk1of2".into(),
+        content: "fn a() {}".into(),
+        ..Default::default()
+    };
+    let payload =
+        |cfg: &Config| serde_json::to_value(backend::UploadBlob::new(cfg, &blob)).unwrap();
+    assert!(payload(&cfg).get("id").is_none());
+
+    cfg.settings.send_blob_ids = true;
+    cfg.settings.upload_strip_chunk_suffix = true;
+    // The id is the blob name of the full (suffixed) path, even when the suffix is stripped
+    assert_eq!(
+        payload(&cfg),
+        serde_json::json!({
+            "id": hash_blob_name("src/lib.rs#chunk1of2", "fn a() {}"),
+            "path": "src/lib.rs",
+            "content": "fn a() {}",
+            "chunk_index": 1,
+            "chunk_count": 2,
+        })
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dialog_is_cut_to_max_dialog_turns() {
+    // Echo the received dialog back as the retrieval text
+    let app = Router::new().route(
+        "/agents/codebase-retrieval",
+        post(|Json(p): Json<serde_json::Value>| async move {
+            Json(RetrievalResp {
+                formatted_retrieval: p["dialog"].to_string(),
+            })
+        }),
+    );
+    let addr = common::serve(app).await;
+    let mut cfg = test_config(format!("http://{}", addr));
+    let dialog: Vec<serde_json::Value> = (0..10)
+        .map(|i| serde_json::json!({"request_message": format!("q{i}"), "response_text": format!("a{i}")}))
+        .collect();
+    let sent = |out: String| -> Vec<serde_json::Value> { serde_json::from_str(&out).unwrap() };
+
+    // Default 0 keeps every turn
+    let out = backend::retrieve_formatted_with_dialog(&cfg, &[], "q", &dialog)
+        .await
+        .unwrap();
+    assert_eq!(sent(out), dialog);
+
+    cfg.settings.max_dialog_turns = 4;
+    let out = backend::retrieve_formatted_with_dialog(&cfg, &[], "q", &dialog)
+        .await
+        .unwrap();
+    assert_eq!(sent(out), dialog[6..]);
+    assert_eq!(backend::bounded_dialog(&cfg, &dialog[..3]), dialog[..3]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn retrieval_meta_reports_call_details() {
+    let addr = start_stub_server().await;
+    let cfg = test_config(format!("http://{}", addr));
+    let names = vec!["b1".to_string(), "b2".to_string()];
+
+    let (text, meta) = backend::retrieve_formatted_with_meta(&cfg, &names, "héllo")
+        .await
+        .unwrap();
+    assert_eq!(text, "OK: héllo");
+    assert_eq!(meta.query_chars, 5);
+    assert_eq!(meta.blobs_sent, 2);
+    assert_eq!(meta.response_chars, 9);
+    // The plain string form is unchanged
+    assert_eq!(
+        backend::retrieve_formatted(&cfg, &names, "héllo")
+            .await
+            .unwrap(),
+        text
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn retrieval_max_request_bytes_splits_or_errors() {
+    // Stub answers each call with one file section per blob plus a section every call shares
+    let calls: Arc<Mutex<Vec<Vec<String>>>> = Arc::new(Mutex::new(Vec::new()));
+    let seen = calls.clone();
+    let app = Router::new().route(
+        "/agents/codebase-retrieval",
+        post(move |Json(p): Json<serde_json::Value>| {
+            let seen = seen.clone();
+            async move {
+                let added: Vec<String> =
+                    serde_json::from_value(p["blobs"]["added_blobs"].clone()).unwrap();
+                let mut text = String::from("Relevant code:\nPath: common.rs\nshared\n");
+                for n in &added {
+                    text.push_str(&format!("Path: {n}.rs\nbody of {n}\n"));
+                }
+                seen.lock().unwrap().push(added);
+                Json(serde_json::json!({ "formatted_retrieval": text, "checkpoint_id": "cp" }))
+            }
+        }),
+    );
+    let addr = common::serve(app).await;
+    let mut cfg = test_config(format!("http://{}", addr));
+    cfg.settings.retrieval_max_request_bytes = 60;
+    let names: Vec<String> = (0..50).map(|i| format!("blob-{i:02}")).collect();
+
+    let text = backend::retrieve_formatted(&cfg, &names, "q")
+        .await
+        .unwrap();
+    let sent = std::mem::take(&mut *calls.lock().unwrap());
+    assert!(sent.len() > 1);
+    for added in &sent {
+        assert!(
+            serde_json::to_string(added).unwrap().len() <= 60,
+            "{added:?}"
+        );
+    }
+    // Every blob is sent exactly once, in order
+    assert_eq!(sent.concat(), names);
+    // One preamble and one shared section; every per-blob section kept
+    assert_eq!(text.matches("Relevant code:").count(), 1);
+    assert_eq!(text.matches("Path: common.rs").count(), 1);
+    assert_eq!(text.matches("shared").count(), 1);
+    for n in &names {
+        assert!(text.contains(&format!("Path: {n}.rs\nbody of {n}")), "{n}");
+    }
+    // A split retrieval keeps no checkpoint
+    let (_, next) = backend::retrieve_with_checkpoint(&cfg, &names, &[], "q", None)
+        .await
+        .unwrap();
+    assert_eq!(next, None);
+
+    cfg.settings.retrieval_overflow_mode = RequestOverflowMode::Error;
+    calls.lock().unwrap().clear();
+    let err = backend::retrieve_formatted(&cfg, &names, "q")
+        .await
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("retrieval_max_request_bytes"), "{err}");
+    // Nothing was sent to the backend
+    assert!(calls.lock().unwrap().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn retry_budget_is_shared_across_batches() {
+    // Every batch fails on its first attempt and succeeds on the retry (after a 1s backoff)
+    let seen: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+    let s = seen.clone();
+    let app = Router::new().route(
+        "/batch-upload",
+        post(move |Json(p): Json<UploadPayload>| {
+            let s = s.clone();
+            async move {
+                let path = p.blobs[0].path.clone();
+                let mut seen = s.lock().unwrap();
+                if !seen.contains(&path) {
+                    seen.push(path);
+                    return Err(axum::http::StatusCode::SERVICE_UNAVAILABLE);
+                }
+                Ok(Json(UploadResp {
+                    blob_names: p
+                        .blobs
+                        .iter()
+                        .map(|b| hash_blob_name(&b.path, &b.content))
+                        .collect(),
+                }))
+            }
+        }),
+    );
+    let addr = common::serve(app).await;
+    let mut cfg = test_config(format!("http://{}", addr));
+    cfg.settings.batch_size = 1;
+    cfg.settings.index_total_retry_budget_secs = 3;
+    let blobs: Vec<BlobUpload> = (0..4)
+        .map(|i| BlobUpload {
+            path: format!("f{i}.txt"),
+            content: format!("{i}\n"),
+            ..Default::default()
+        })
+        .collect();
+
+    let started = std::time::Instant::now();
+    let mut done = 0;
+    let err = backend::upload_new_blobs_with_progress(&cfg, &blobs, |_| done += 1)
+        .await
+        .unwrap_err()
+        .to_string();
+    let elapsed = started.elapsed();
+    // Two batches spend just over 2s of the 3s budget (waits plus retried requests); the third
+    // cannot afford another 1s wait and fails at once
+    assert_eq!(done, 2);
+    assert!(err.contains("retry budget exhausted"), "{err}");
+    assert!(err.contains("chunk 3/4"), "{err}");
+    assert!(
+        elapsed < std::time::Duration::from_millis(2800),
+        "{elapsed:?}"
+    );
+    assert_eq!(seen.lock().unwrap().len(), 3);
+}
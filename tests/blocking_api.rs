@@ -0,0 +1,90 @@
+use augmcp::{
+    blocking,
+    config::{Config, Settings},
+};
+use axum::{Json, Router, routing::post};
+use serde_json::{Value, json};
+use std::{fs, net::SocketAddr};
+
+/// Stub backend on its own thread and runtime, so the test itself stays synchronous.
+fn start_stub_server() -> SocketAddr {
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async move {
+            let app = Router::new()
+                .route(
+                    "/batch-upload",
+                    post(|Json(p): Json<Value>| async move {
+                        let names: Vec<Value> = p["blobs"]
+                            .as_array()
+                            .unwrap()
+                            .iter()
+                            .map(|b| json!(format!("n:{}", b["path"].as_str().unwrap())))
+                            .collect();
+                        Json(json!({ "blob_names": names }))
+                    }),
+                )
+                .route(
+                    "/agents/codebase-retrieval",
+                    post(|Json(p): Json<Value>| async move {
+                        let n = p["blobs"]["added_blobs"].as_array().unwrap().len();
+                        Json(json!({
+                            "formatted_retrieval":
+                                format!("OK: {} ({n})", p["information_request"].as_str().unwrap()),
+                        }))
+                    }),
+                );
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            tx.send(listener.local_addr().unwrap()).unwrap();
+            let _ = axum::serve(listener, app).await;
+        });
+    });
+    rx.recv().unwrap()
+}
+
+#[test]
+fn blocking_api_indexes_and_searches_without_a_runtime() {
+    let addr = start_stub_server();
+    let td = tempfile::tempdir().unwrap();
+    let root_dir = td.path().join("cfg");
+    let cfg = Config {
+        settings: Settings {
+            base_url: format!("http://{addr}"),
+            token: "T".into(),
+            text_extensions: vec![".txt".into()],
+            ..Settings::default()
+        },
+        root_dir: root_dir.clone(),
+        data_dir: td.path().join("data"),
+        settings_path: root_dir.join("settings.toml"),
+    };
+    let proj = td.path().join("proj");
+    fs::create_dir_all(&proj).unwrap();
+    fs::write(proj.join("a.txt"), "A\n").unwrap();
+    fs::write(proj.join("b.txt"), "B\n").unwrap();
+    let (key, path) = cfg.resolve_project(&proj.to_string_lossy()).unwrap();
+
+    let report = blocking::index_blocking(&cfg, &key, &path, false).unwrap();
+    assert_eq!(report.total_blobs, 2);
+    let names = blocking::ensure_indexed_blocking(&cfg, &key, &path, true).unwrap();
+    assert_eq!(names.len(), 2);
+    let out = blocking::search_blocking(&cfg, &key, &path, "where is A", true).unwrap();
+    assert_eq!(out, "OK: where is A (2)");
+    let r = blocking::retrieve_blocking(&cfg, &key, &names, "q").unwrap();
+    assert_eq!(r.into_text(&cfg), "OK: q (2)");
+}
+
+#[test]
+fn blocking_api_refuses_to_run_inside_a_runtime() {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let err = rt
+        .block_on(async { blocking::block_on(async { 1 }) })
+        .unwrap_err();
+    assert!(err.to_string().contains("within a tokio runtime"), "{err}");
+}
@@ -0,0 +1,249 @@
+mod common;
+
+use augmcp::indexer::{BlobUpload, hash_blob_name};
+use axum::{Json, Router, routing::post};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Deserialize)]
+struct UploadPayload {
+    blobs: Vec<BlobUpload>,
+}
+
+/// Stub backend: confirms uploads by their client-side names and answers retrievals with
+/// the query and the number of blobs searched. Uploaded paths are recorded.
+async fn start_stub() -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+    let uploaded = Arc::new(Mutex::new(Vec::new()));
+    let seen = uploaded.clone();
+    let app = Router::new()
+        .route(
+            "/batch-upload",
+            post(move |Json(p): Json<UploadPayload>| {
+                let seen = seen.clone();
+                async move {
+                    let names: Vec<String> = p
+                        .blobs
+                        .iter()
+                        .map(|b| hash_blob_name(&b.path, &b.content))
+                        .collect();
+                    seen.lock()
+                        .unwrap()
+                        .extend(p.blobs.into_iter().map(|b| b.path));
+                    Json(json!({ "blob_names": names }))
+                }
+            }),
+        )
+        .route(
+            "/agents/codebase-retrieval",
+            post(|Json(p): Json<Value>| async move {
+                let blobs = p["blobs"]["added_blobs"].as_array().map_or(0, |a| a.len());
+                Json(json!({
+                    "formatted_retrieval": format!(
+                        "STUB {} over {} blobs",
+                        p["information_request"].as_str().unwrap_or_default(),
+                        blobs
+                    )
+                }))
+            }),
+        );
+    let addr = common::serve(app).await;
+    (addr, uploaded)
+}
+
+/// Run the binary with `args`, piping `stdin`; returns (success, stdout).
+async fn run_cli(
+    home: &std::path::Path,
+    addr: SocketAddr,
+    args: &[&str],
+    stdin: &str,
+) -> (bool, String) {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+    let (home, args, stdin) = (
+        home.to_path_buf(),
+        args.iter().map(|s| s.to_string()).collect::<Vec<_>>(),
+        stdin.to_string(),
+    );
+    tokio::task::spawn_blocking(move || {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_augmcp"))
+            .args(["--base-url", &format!("http://{}", addr), "--token", "t"])
+            .args(&args)
+            .env("HOME", &home)
+            .env("AUGMCP_MAX_LINES_PER_BLOB", "2")
+            .env("RUST_LOG", "info")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(stdin.as_bytes())
+            .unwrap();
+        let out = child.wait_with_output().unwrap();
+        (
+            out.status.success(),
+            String::from_utf8_lossy(&out.stdout).into_owned(),
+        )
+    })
+    .await
+    .unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn search_stdin_queries_piped_text_without_persisting() {
+    let (addr, uploaded) = start_stub().await;
+    let home = tempfile::tempdir().unwrap();
+    let (ok, stdout) = run_cli(
+        home.path(),
+        addr,
+        &["search-stdin", "where is main", "--name", "notes.rs"],
+        "fn main() {\n    run();\n}\nfn run() {}\n",
+    )
+    .await;
+    assert!(ok, "{stdout}");
+    // stdout carries only the retrieval result, logs go to stderr
+    assert_eq!(stdout.trim(), "STUB where is main over 2 blobs");
+    assert_eq!(
+        *uploaded.lock().unwrap(),
+        vec!["notes.rs#chunk1of2", "notes.rs#chunk2of2"]
+    );
+    assert!(
+        !home.path().join(".augmcp/data/projects.json").exists(),
+        "ephemeral content must not be recorded as a project"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn repl_indexes_once_and_answers_each_line() {
+    let (addr, uploaded) = start_stub().await;
+    let home = tempfile::tempdir().unwrap();
+    let proj = tempfile::tempdir().unwrap();
+    std::fs::write(proj.path().join("main.rs"), "fn main() {}\n").unwrap();
+    let proj_path = proj.path().to_string_lossy().into_owned();
+    let (ok, stdout) = run_cli(
+        home.path(),
+        addr,
+        &["repl", "--path", &proj_path],
+        "first query\n\n:reindex\nsecond query\n:quit\nnever sent\n",
+    )
+    .await;
+    assert!(ok, "{stdout}");
+    assert_eq!(
+        stdout.lines().collect::<Vec<_>>(),
+        vec![
+            "STUB first query over 1 blobs",
+            "STUB second query over 1 blobs"
+        ]
+    );
+    // Indexed once; the unchanged file is not uploaded again by :reindex
+    assert_eq!(*uploaded.lock().unwrap(), vec!["main.rs"]);
+}
+
+#[test]
+fn serving_with_placeholder_settings_fails_with_a_config_hint() {
+    use std::io::{BufRead, Write};
+    use std::process::{Command, Stdio};
+    let home = tempfile::tempdir().unwrap();
+    let settings = home.path().join(".augmcp").join("settings.toml");
+    for transport in ["stdio", "http"] {
+        let out = Command::new(env!("CARGO_BIN_EXE_augmcp"))
+            .args(["--transport", transport, "--bind", "127.0.0.1:0"])
+            .env("HOME", home.path())
+            .env_remove("AUGMCP_BASE_URL")
+            .env_remove("AUGMCP_TOKEN")
+            .stdin(Stdio::null())
+            .output()
+            .unwrap();
+        assert!(!out.status.success(), "{transport}");
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(
+            stderr.contains("not configured: base_url and token"),
+            "{stderr}"
+        );
+        assert!(stderr.contains(&settings.display().to_string()), "{stderr}");
+        assert!(stderr.contains("--allow-unconfigured"), "{stderr}");
+    }
+
+    // Opting in starts the server; tools then answer with the same hint instead of a network error
+    let mut child = Command::new(env!("CARGO_BIN_EXE_augmcp"))
+        .args(["--transport", "stdio", "--allow-unconfigured", "--quiet"])
+        .env("HOME", home.path())
+        .env_remove("AUGMCP_BASE_URL")
+        .env_remove("AUGMCP_TOKEN")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let proj = tempfile::tempdir().unwrap();
+    std::fs::write(proj.path().join("a.rs"), "fn a() {}\n").unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    for msg in [
+        json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {
+            "protocolVersion": "2024-11-05", "capabilities": {},
+            "clientInfo": {"name": "test", "version": "0"}}}),
+        json!({"jsonrpc": "2.0", "method": "notifications/initialized"}),
+        json!({"jsonrpc": "2.0", "id": 2, "method": "tools/call", "params": {
+            "name": "search_context",
+            "arguments": {"project_root_path": proj.path().to_string_lossy(), "query": "q"}}}),
+    ] {
+        writeln!(stdin, "{}", msg).unwrap();
+    }
+    let mut lines = std::io::BufReader::new(child.stdout.take().unwrap()).lines();
+    let reply = lines
+        .by_ref()
+        .map(|l| serde_json::from_str::<Value>(&l.unwrap()).unwrap())
+        .find(|v| v["id"] == 2)
+        .unwrap();
+    let _ = child.kill();
+    let _ = child.wait();
+    let text = reply["result"]["content"][0]["text"].as_str().unwrap();
+    assert!(text.contains("augmcp is not configured"), "{text}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn alias_add_dry_run_prints_normalized_path_without_writing() {
+    let (addr, _) = start_stub().await;
+    let home = tempfile::tempdir().unwrap();
+    let proj = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(proj.path().join("sub")).unwrap();
+    let messy = proj.path().join("sub").join("..");
+    let normalized = std::fs::canonicalize(proj.path())
+        .unwrap()
+        .to_string_lossy()
+        .replace('\\', "/");
+    let aliases = home.path().join(".augmcp/aliases.json");
+
+    let (ok, stdout) = run_cli(
+        home.path(),
+        addr,
+        &["alias", "add", "web", &messy.to_string_lossy(), "--dry-run"],
+        "",
+    )
+    .await;
+    assert!(ok, "{stdout}");
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines[0], "alias: web");
+    assert_eq!(lines[1], format!("path: {normalized}"));
+    assert_eq!(lines[2], format!("project_key: {normalized}"));
+    assert!(lines[3].starts_with("dry run: "), "{stdout}");
+    assert!(!aliases.exists(), "dry run must not write aliases.json");
+
+    let (ok, stdout) = run_cli(
+        home.path(),
+        addr,
+        &["alias", "add", "web", &messy.to_string_lossy()],
+        "",
+    )
+    .await;
+    assert!(ok, "{stdout}");
+    let stored: Value = serde_json::from_str(&std::fs::read_to_string(&aliases).unwrap()).unwrap();
+    assert_eq!(stored["web"], normalized.as_str());
+}
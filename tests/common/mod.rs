@@ -0,0 +1,14 @@
+//! Helpers shared by the integration tests (`mod common;`).
+
+use axum::Router;
+use std::net::SocketAddr;
+
+/// Serve `app` on an ephemeral local port in the background; returns its address.
+pub async fn serve(app: Router) -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let _ = axum::serve(listener, app).await;
+    });
+    addr
+}
@@ -4,6 +4,7 @@ use std::{env, fs};
 
 struct EnvGuard(Vec<(String, Option<String>)>);
 impl EnvGuard {
+    #[allow(dead_code)]
     fn set(k: &str, v: &str) -> Self {
         let prev = env::var(k).ok();
         unsafe {
@@ -61,6 +62,7 @@ fn env_overrides_apply() {
         ("AUGMCP_MAX_OUTPUT_LENGTH", "2048"),
         ("AUGMCP_DISABLE_CODEBASE_RETRIEVAL", "true"),
         ("AUGMCP_ENABLE_COMMIT_RETRIEVAL", "true"),
+        ("AUGMCP_NO_FILE_LOG", "1"),
     ]);
 
     let cfg = Config::load_with_overrides(None, None).unwrap();
@@ -73,6 +75,7 @@ fn env_overrides_apply() {
     assert_eq!(cfg.settings.max_output_length, 2048);
     assert!(cfg.settings.disable_codebase_retrieval);
     assert!(cfg.settings.enable_commit_retrieval);
+    assert!(!cfg.settings.file_logging);
 }
 
 #[test]
@@ -86,3 +89,188 @@ fn cli_overrides_take_priority() {
     assert_eq!(cfg.settings.base_url, "http://cli");
     assert_eq!(cfg.settings.token, "CLI");
 }
+
+#[test]
+fn relative_project_paths_resolve_against_base_dir() {
+    let td = tempfile::tempdir().unwrap();
+    let proj = td.path().join("proj");
+    let sibling = td.path().join("sibling");
+    fs::create_dir_all(&proj).unwrap();
+    fs::create_dir_all(&sibling).unwrap();
+
+    let mut cfg = Config {
+        settings: Default::default(),
+        root_dir: td.path().join("cfg"),
+        data_dir: td.path().join("data"),
+        settings_path: td.path().join("cfg/settings.toml"),
+    };
+    cfg.settings.project_base_dir = Some(proj.to_string_lossy().to_string());
+
+    let proj_key = augmcp::config::normalize_path(&proj).unwrap();
+    let sibling_key = augmcp::config::normalize_path(&sibling).unwrap();
+    assert_eq!(cfg.resolve_project_path(".").unwrap(), proj_key);
+    assert_eq!(cfg.resolve_project_path("../sibling").unwrap(), sibling_key);
+    assert_eq!(
+        cfg.resolve_project_path(sibling.to_str().unwrap()).unwrap(),
+        sibling_key
+    );
+}
+
+#[test]
+fn hashed_project_keys_are_stable_and_salted() {
+    let td = tempfile::tempdir().unwrap();
+    let cfg_at = |root: &std::path::Path| {
+        let mut cfg = Config {
+            settings: Default::default(),
+            root_dir: root.join("cfg"),
+            data_dir: root.join("data"),
+            settings_path: root.join("cfg/settings.toml"),
+        };
+        cfg.settings.hash_project_keys = true;
+        cfg
+    };
+    let cfg = cfg_at(td.path());
+    let k1 = cfg.project_key("/home/alice/proj").unwrap();
+    assert!(k1.starts_with(augmcp::config::HASHED_KEY_PREFIX));
+    assert!(!k1.contains("alice"));
+    // Same install (salt persisted under root_dir): same key
+    assert_eq!(
+        cfg_at(td.path()).project_key("/home/alice/proj").unwrap(),
+        k1
+    );
+    assert_ne!(cfg.project_key("/home/alice/other").unwrap(), k1);
+    // Different install: different salt
+    let td2 = tempfile::tempdir().unwrap();
+    assert_ne!(
+        cfg_at(td2.path()).project_key("/home/alice/proj").unwrap(),
+        k1
+    );
+
+    let mut plain = cfg_at(td.path());
+    plain.settings.hash_project_keys = false;
+    assert_eq!(
+        plain.project_key("/home/alice/proj").unwrap(),
+        "/home/alice/proj"
+    );
+}
+
+#[test]
+fn chunk_numbering_is_part_of_the_fingerprint() {
+    let td = tempfile::tempdir().unwrap();
+    let mut cfg = Config {
+        settings: Default::default(),
+        root_dir: td.path().join("cfg"),
+        data_dir: td.path().join("data"),
+        settings_path: td.path().join("cfg/settings.toml"),
+    };
+    let padded = cfg.settings_fingerprint();
+    cfg.settings.chunk_numbering = augmcp::indexer::ChunkNumbering::Plain;
+    assert_ne!(cfg.settings_fingerprint(), padded);
+}
+
+#[test]
+fn path_key_form_strips_verbatim_prefixes() {
+    use augmcp::config::path_key_form;
+    assert_eq!(path_key_form(r"\\server\share\proj"), "//server/share/proj");
+    assert_eq!(
+        path_key_form(r"\\?\UNC\server\share\proj"),
+        "//server/share/proj"
+    );
+    assert_eq!(path_key_form(r"\\?\C:\work\proj"), "C:/work/proj");
+    assert_eq!(path_key_form("/home/u/proj"), "/home/u/proj");
+}
+
+#[cfg(windows)]
+#[test]
+fn long_windows_paths_round_trip_through_collect_and_aliases() {
+    let td = tempfile::tempdir().unwrap();
+    let mut deep = td.path().to_path_buf();
+    while deep.to_string_lossy().len() <= 300 {
+        deep.push("a_rather_long_directory_name");
+    }
+    fs::create_dir_all(&deep).unwrap();
+    fs::write(deep.join("f.txt"), "x\n").unwrap();
+
+    let key = augmcp::config::normalize_path(&deep).unwrap();
+    assert!(!key.starts_with("//?/"), "{key}");
+    assert!(key.len() > 260);
+    assert_eq!(augmcp::config::normalize_path(&key).unwrap(), key);
+    let exts: std::collections::HashSet<String> = [".txt".to_string()].into_iter().collect();
+    let blobs =
+        augmcp::indexer::collect_blobs(std::path::Path::new(&key), &exts, 100, &[]).unwrap();
+    assert_eq!(blobs.len(), 1);
+
+    let cfg = Config {
+        settings: Default::default(),
+        root_dir: td.path().join("cfg"),
+        data_dir: td.path().join("data"),
+        settings_path: td.path().join("cfg/settings.toml"),
+    };
+    let (k1, _) =
+        augmcp::service::resolve_target(&cfg, Some("deep".into()), Some(key.clone())).unwrap();
+    let (k2, p2) = augmcp::service::resolve_target(&cfg, Some("deep".into()), None).unwrap();
+    assert_eq!(
+        (k1.as_str(), k2.as_str(), p2.as_str()),
+        (key.as_str(), key.as_str(), key.as_str())
+    );
+}
+
+#[cfg(windows)]
+#[test]
+fn unc_paths_normalize_to_forward_slash_form() {
+    // Reach the temp dir through the local admin share, e.g. \\localhost\C$\Users\...
+    let td = tempfile::tempdir().unwrap();
+    let local = td.path().to_string_lossy().to_string();
+    let Some((drive, rest)) = local.split_once(":\\") else {
+        return;
+    };
+    let unc = format!(r"\\localhost\{drive}$\{rest}");
+    let Ok(key) = augmcp::config::normalize_path(&unc) else {
+        return; // admin shares disabled on this machine
+    };
+    assert!(key.starts_with("//localhost/"), "{key}");
+    assert_eq!(augmcp::config::normalize_path(&key).unwrap(), key);
+}
+
+#[test]
+#[serial]
+fn project_state_scope_keeps_state_in_the_project() {
+    let td = tempfile::tempdir().unwrap();
+    let td_path = td.path().canonicalize().unwrap();
+    let home = td_path.join("home");
+    let repo = home.join("repo");
+    fs::create_dir_all(home.join(".augmcp")).unwrap();
+    fs::create_dir_all(repo.join(".git")).unwrap();
+    fs::create_dir_all(repo.join("src")).unwrap();
+    fs::write(
+        home.join(".augmcp/settings.toml"),
+        "state_scope = \"project\"\n",
+    )
+    .unwrap();
+    let _home = set_home(home.to_str().unwrap());
+    let prev_cwd = env::current_dir().unwrap();
+    env::set_current_dir(repo.join("src")).unwrap();
+    let loaded = Config::load_with_overrides(None, None);
+    env::set_current_dir(prev_cwd).unwrap();
+    let cfg = loaded.unwrap();
+
+    // The global ~/.augmcp above the repo is skipped; the git root gets the state dir
+    let state = repo.join(".augmcp");
+    assert_eq!(cfg.data_dir, state);
+    assert!(state.is_dir());
+    assert_eq!(cfg.projects_file(), state.join("projects.json"));
+    assert_eq!(cfg.aliases_file(), state.join("aliases.json"));
+    assert_eq!(cfg.settings_path, home.join(".augmcp/settings.toml"));
+    augmcp::indexer::ProjectsIndex::default()
+        .save(&cfg.projects_file())
+        .unwrap();
+    assert!(state.join("projects.json").exists());
+    assert!(!home.join(".augmcp/data/projects.json").exists());
+
+    // An existing .augmcp/ found while searching upward wins over the git root
+    fs::create_dir_all(repo.join("src/.augmcp")).unwrap();
+    assert_eq!(
+        Config::project_state_dir(&repo.join("src"), &home.join(".augmcp")),
+        repo.join("src/.augmcp")
+    );
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use augmcp::{
+    config::{Config, Settings},
+    doctor::{self, CheckStatus},
+};
+use axum::{Router, http::StatusCode, routing::post};
+use std::fs;
+
+async fn start_stub(status: StatusCode) -> String {
+    let app = Router::new().route("/batch-upload", post(move || async move { status }));
+    let addr = common::serve(app).await;
+    format!("http://{}:{}", addr.ip(), addr.port())
+}
+
+fn cfg_at(base_url: String, root: &std::path::Path) -> Config {
+    Config {
+        settings: Settings {
+            base_url,
+            token: "T".into(),
+            text_extensions: vec![".txt".into()],
+            ..Settings::default()
+        },
+        root_dir: root.join("cfg"),
+        data_dir: root.join("data"),
+        settings_path: root.join("cfg/settings.toml"),
+    }
+}
+
+fn status_of(checks: &[doctor::Check], name: &str) -> CheckStatus {
+    checks.iter().find(|c| c.name == name).unwrap().status
+}
+
+#[tokio::test]
+async fn doctor_reports_rejected_token_and_dry_run() {
+    let base = start_stub(StatusCode::UNAUTHORIZED).await;
+    let td = tempfile::tempdir().unwrap();
+    let cfg = cfg_at(base, td.path());
+    fs::create_dir_all(td.path().join("proj")).unwrap();
+    fs::write(td.path().join("proj/a.txt"), "hello\n").unwrap();
+
+    let proj = td.path().join("proj");
+    let checks = doctor::run(&cfg, Some(proj.to_str().unwrap())).await;
+    assert_eq!(status_of(&checks, "settings"), CheckStatus::Warn);
+    assert_eq!(status_of(&checks, "base_url"), CheckStatus::Pass);
+    assert_eq!(status_of(&checks, "backend"), CheckStatus::Fail);
+    assert_eq!(status_of(&checks, "data_dir"), CheckStatus::Pass);
+    assert_eq!(status_of(&checks, "log_dir"), CheckStatus::Pass);
+    let dry = checks.iter().find(|c| c.name == "dry_run").unwrap();
+    assert_eq!(dry.status, CheckStatus::Pass);
+    assert!(dry.detail.contains("1 files, 1 blobs"), "{}", dry.detail);
+    assert!(doctor::has_failures(&checks));
+}
+
+#[tokio::test]
+async fn doctor_passes_with_accepted_token_and_flags_bad_settings_file() {
+    let base = start_stub(StatusCode::OK).await;
+    let td = tempfile::tempdir().unwrap();
+    let cfg = cfg_at(base, td.path());
+    fs::create_dir_all(&cfg.root_dir).unwrap();
+    fs::write(&cfg.settings_path, "batch_size = \"ten\"\n").unwrap();
+
+    let checks = doctor::run(&cfg, None).await;
+    assert_eq!(status_of(&checks, "backend"), CheckStatus::Pass);
+    assert_eq!(status_of(&checks, "settings"), CheckStatus::Fail);
+    assert!(checks.iter().all(|c| c.name != "dry_run"));
+}
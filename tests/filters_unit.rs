@@ -0,0 +1,41 @@
+use augmcp::filters::{ContentFilter, apply_all, parse_filters};
+use augmcp::indexer::{CollectOptions, collect_blobs_with};
+use std::fs;
+
+#[test]
+fn redact_env_values_masks_secret_assignments() {
+    let input = "API_KEY=abc123\nexport GITHUB_TOKEN=\"ghp_x\"\ndb_password: hunter2\nPORT=8080\n";
+    let out = ContentFilter::RedactEnvValues.apply(input);
+    assert_eq!(
+        out,
+        "API_KEY=***\nexport GITHUB_TOKEN=***\ndb_password: ***\nPORT=8080\n"
+    );
+    // Deterministic and idempotent so blob hashes stay stable
+    assert_eq!(ContentFilter::RedactEnvValues.apply(&out), out);
+}
+
+#[test]
+fn strip_block_comments_and_parse_names() {
+    let filters = parse_filters(&[
+        "strip-block-comments".to_string(),
+        "no-such-filter".to_string(),
+    ]);
+    assert_eq!(filters, vec![ContentFilter::StripBlockComments]);
+    let out = apply_all(&filters, "a /* one\ntwo */b\n/**/c\n".to_string());
+    assert_eq!(out, "a b\nc\n");
+}
+
+#[test]
+fn collect_applies_filters_before_upload() {
+    let td = tempfile::tempdir().unwrap();
+    fs::write(td.path().join("app.env.txt"), "SECRET_KEY=s3cr3t\n").unwrap();
+    let opts = CollectOptions {
+        text_exts: [".txt".to_string()].into_iter().collect(),
+        max_lines: 100,
+        content_filters: vec![ContentFilter::RedactEnvValues],
+        ..Default::default()
+    };
+    let blobs = collect_blobs_with(td.path(), &opts).unwrap();
+    assert_eq!(blobs.len(), 1);
+    assert_eq!(blobs[0].content, "SECRET_KEY=***\n");
+}
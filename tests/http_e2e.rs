@@ -1,3 +1,5 @@
+mod common;
+
 use augmcp::{
     AppState, AugServer,
     config::{Config, Settings},
@@ -10,7 +12,6 @@ use axum::{
 };
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use tokio::net::TcpListener;
 use tower::util::ServiceExt;
 
 #[derive(Deserialize)]
@@ -30,7 +31,7 @@ struct RetrievalResp {
     formatted_retrieval: String,
 }
 
-async fn start_slow_stub() -> (String, tokio::task::JoinHandle<()>) {
+async fn start_slow_stub() -> String {
     let app = Router::new()
         .route(
             "/batch-upload",
@@ -53,13 +54,8 @@ async fn start_slow_stub() -> (String, tokio::task::JoinHandle<()>) {
                 })
             }),
         );
-    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
-    let addr = listener.local_addr().unwrap();
-    let base = format!("http://{}:{}", addr.ip(), addr.port());
-    let h = tokio::spawn(async move {
-        let _ = axum::serve(listener, app).await;
-    });
-    (base, h)
+    let addr = common::serve(app).await;
+    format!("http://{}:{}", addr.ip(), addr.port())
 }
 
 fn test_cfg(base_url: String, root: &std::path::Path) -> Config {
@@ -78,6 +74,7 @@ fn test_cfg(base_url: String, root: &std::path::Path) -> Config {
             max_output_length: 0,
             disable_codebase_retrieval: false,
             enable_commit_retrieval: false,
+            ..Settings::default()
         },
         root_dir: root_dir.clone(),
         data_dir: data_dir.clone(),
@@ -87,7 +84,7 @@ fn test_cfg(base_url: String, root: &std::path::Path) -> Config {
 
 #[tokio::test(flavor = "multi_thread")]
 async fn http_index_async_cancel_and_search() {
-    let (base_url, _h) = start_slow_stub().await;
+    let base_url = start_slow_stub().await;
     let td = tempfile::tempdir().unwrap();
     let cfg = test_cfg(base_url, td.path());
 
@@ -114,6 +111,24 @@ async fn http_index_async_cancel_and_search() {
         .unwrap();
     let resp = router.clone().oneshot(req).await.unwrap();
     assert_eq!(resp.status(), StatusCode::OK);
+    let bytes = body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    let task_id = v["task_id"].as_str().expect("async index returns task_id");
+
+    // 按 task_id 查询任务
+    let req = Request::get(format!("/api/tasks/{task_id}"))
+        .body(Body::empty())
+        .unwrap();
+    let resp = router.clone().oneshot(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let bytes = body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["progress"]["task_id"].as_str(), Some(task_id));
+    let req = Request::get("/api/tasks/unknown")
+        .body(Body::empty())
+        .unwrap();
+    let resp = router.clone().oneshot(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
 
     // 查询任务，应该在运行
     let q = format!("/api/tasks?project_root_path={}", proj.to_string_lossy());
@@ -160,3 +175,1026 @@ async fn http_index_async_cancel_and_search() {
     let resp = router.clone().oneshot(req).await.unwrap();
     assert_eq!(resp.status(), StatusCode::OK);
 }
+
+#[tokio::test]
+async fn http_paths_reports_resolved_locations() {
+    let td = tempfile::tempdir().unwrap();
+    let cfg = test_cfg("http://127.0.0.1:1".into(), td.path());
+    let router = augmcp::http_router::build_router(AppState {
+        server: AugServer::new(cfg.clone()),
+        tasks: augmcp::tasks::TaskManager::new(),
+    });
+
+    let req = Request::get("/api/paths").body(Body::empty()).unwrap();
+    let resp = router.oneshot(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let bytes = body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(
+        v["projects_file"].as_str().unwrap(),
+        cfg.projects_file().to_string_lossy()
+    );
+    assert_eq!(
+        v["aliases_file"].as_str().unwrap(),
+        cfg.aliases_file().to_string_lossy()
+    );
+    assert!(v["log_dir"].is_string() && v["settings_path"].is_string());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn read_only_rejects_mutations_and_never_indexes() {
+    let base_url = start_slow_stub().await;
+    let td = tempfile::tempdir().unwrap();
+    let mut cfg = test_cfg(base_url, td.path());
+    let indexed = td.path().join("indexed");
+    let fresh = td.path().join("fresh");
+    for dir in [&indexed, &fresh] {
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::write(dir.join("a.txt"), "a\n").unwrap();
+    }
+    let (key, path) = cfg.resolve_project(&indexed.to_string_lossy()).unwrap();
+    augmcp::service::index_and_persist(&cfg, &key, &path, false)
+        .await
+        .unwrap();
+
+    cfg.settings.read_only = true;
+    let router = augmcp::http_router::build_router(AppState {
+        server: AugServer::new(cfg.clone()),
+        tasks: augmcp::tasks::TaskManager::new(),
+    });
+    let post = |uri: &str, body: serde_json::Value| {
+        Request::post(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+    let result_of = |resp: axum::response::Response| async move {
+        let bytes = body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        v["result"].as_str().unwrap_or_default().to_string()
+    };
+
+    for uri in augmcp::http_router::MUTATING_ROUTES {
+        let uri = uri.replace("{key}", "k");
+        let req = post(&uri, json!({"project_root_path": path}));
+        let resp = router.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN, "{uri}");
+    }
+    // Diff only reads the stored index
+    let req = post("/api/index/diff", json!({"project_root_path": path}));
+    let resp = router.clone().oneshot(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let req = Request::get("/healthz").body(Body::empty()).unwrap();
+    assert_eq!(
+        router.clone().oneshot(req).await.unwrap().status(),
+        StatusCode::OK
+    );
+
+    // Indexed project: searched from cache even when asking to reindex
+    let body = json!({"project_root_path": path, "query": "q", "skip_index_if_indexed": false});
+    let resp = router
+        .clone()
+        .oneshot(post("/api/search", body))
+        .await
+        .unwrap();
+    assert_eq!(result_of(resp).await, "OK: q");
+
+    // Not yet indexed: error instead of uploading
+    let body = json!({"project_root_path": fresh.to_string_lossy(), "query": "q"});
+    let resp = router
+        .clone()
+        .oneshot(post("/api/search", body))
+        .await
+        .unwrap();
+    assert!(result_of(resp).await.contains("read-only"));
+
+    // Binding a new alias is refused
+    let body = json!({"project_root_path": path, "alias": "team", "query": "q"});
+    let resp = router
+        .clone()
+        .oneshot(post("/api/search", body))
+        .await
+        .unwrap();
+    assert!(result_of(resp).await.contains("alias binding is disabled"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bulk_index_reports_each_project_and_continues_past_failures() {
+    let base_url = start_slow_stub().await;
+    let td = tempfile::tempdir().unwrap();
+    let mut cfg = test_cfg(base_url, td.path());
+    cfg.settings.max_concurrent_indexes = 2;
+    let mut paths = vec![];
+    for name in ["a", "b", "c"] {
+        let dir = td.path().join(name);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(format!("{name}.txt")), "x\n").unwrap();
+        paths.push(dir.to_string_lossy().to_string());
+    }
+    let router = augmcp::http_router::build_router(AppState {
+        server: AugServer::new(cfg.clone()),
+        tasks: augmcp::tasks::TaskManager::with_limit(cfg.settings.max_concurrent_indexes),
+    });
+    let bulk = |body: serde_json::Value| {
+        Request::post("/api/index/bulk")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+    let json_of = |resp: axum::response::Response| async move {
+        let bytes = body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+    };
+
+    let body = json!({"projects": [
+        {"project_root_path": paths[0]},
+        {"alias": "missing"},
+        {"project_root_path": paths[1], "force_full": true},
+        {"project_root_path": paths[2]},
+    ]});
+    let v = json_of(router.clone().oneshot(bulk(body)).await.unwrap()).await;
+    let results = v["results"].as_array().unwrap();
+    let statuses: Vec<&str> = results
+        .iter()
+        .map(|r| r["status"].as_str().unwrap())
+        .collect();
+    assert_eq!(statuses, ["success", "error", "success", "success"]);
+    assert!(
+        results[1]["result"]
+            .as_str()
+            .unwrap()
+            .contains("alias not found")
+    );
+    for (r, p) in [(&results[0], &paths[0]), (&results[2], &paths[1])] {
+        let path = cfg.resolve_project(p).unwrap().1;
+        assert_eq!(r["project"].as_str().unwrap(), path);
+        assert!(r["result"].as_str().unwrap().contains("total_blobs=1"));
+    }
+    let projects = augmcp::service::load_projects(&cfg);
+    assert_eq!(projects.0.len(), 3);
+
+    // Async: one task id per project
+    let body = json!({"async": true, "projects": [
+        {"project_root_path": paths[0], "force_full": true},
+        {"project_root_path": paths[1], "force_full": true},
+    ]});
+    let v = json_of(router.clone().oneshot(bulk(body)).await.unwrap()).await;
+    let results = v["results"].as_array().unwrap();
+    assert_eq!(results.len(), 2);
+    assert!(results.iter().all(|r| r["status"] == "accepted"));
+    assert!(results.iter().all(|r| r["task_id"].is_string()));
+    assert_ne!(results[0]["task_id"], results[1]["task_id"]);
+}
+
+#[tokio::test]
+async fn rate_limit_answers_429_past_the_per_ip_budget() {
+    let td = tempfile::tempdir().unwrap();
+    let mut cfg = test_cfg("http://127.0.0.1:1".into(), td.path());
+    cfg.settings.rate_limit_per_min = 3;
+    let router = augmcp::http_router::build_router(AppState {
+        server: AugServer::new(cfg),
+        tasks: augmcp::tasks::TaskManager::new(),
+    });
+    let from = |ip: [u8; 4], uri: &str| {
+        let mut req = Request::get(uri).body(Body::empty()).unwrap();
+        let addr = std::net::SocketAddr::from((ip, 40000));
+        req.extensions_mut()
+            .insert(axum::extract::ConnectInfo(addr));
+        req
+    };
+
+    for _ in 0..3 {
+        let resp = router
+            .clone()
+            .oneshot(from([10, 0, 0, 1], "/api/paths"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+    let resp = router
+        .clone()
+        .oneshot(from([10, 0, 0, 1], "/api/paths"))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    let retry_after: u64 = resp.headers()["retry-after"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((1..=20).contains(&retry_after), "{retry_after}");
+
+    // Other clients have their own bucket; /healthz is never limited
+    let resp = router
+        .clone()
+        .oneshot(from([10, 0, 0, 2], "/api/paths"))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = router
+        .clone()
+        .oneshot(from([10, 0, 0, 1], "/healthz"))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn index_error_names_the_rejected_upload_chunk() {
+    // Backend rejects any batch carrying bad.txt with a JSON error body
+    let app = Router::new().route(
+        "/batch-upload",
+        post(|Json(p): Json<UploadPayload>| async move {
+            if p.blobs.iter().any(|b| b.path == "bad.txt") {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({"error": {"message": "invalid blob content"}})),
+                ));
+            }
+            let names = p.blobs.into_iter().map(|b| b.path).collect();
+            Ok(Json(UploadResp { blob_names: names }))
+        }),
+    );
+    let addr = common::serve(app).await;
+    let td = tempfile::tempdir().unwrap();
+    let cfg = test_cfg(format!("http://{addr}"), td.path());
+    let proj = td.path().join("proj");
+    std::fs::create_dir_all(&proj).unwrap();
+    for name in ["a.txt", "bad.txt", "c.txt"] {
+        std::fs::write(proj.join(name), format!("{name}\n")).unwrap();
+    }
+    let router = augmcp::http_router::build_router(AppState {
+        server: AugServer::new(cfg),
+        tasks: augmcp::tasks::TaskManager::new(),
+    });
+
+    let body = json!({"project_root_path": proj.to_string_lossy()});
+    let req = Request::post("/api/index")
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let resp = router.oneshot(req).await.unwrap();
+    let bytes = body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["status"], "error");
+    // batch_size = 1: one chunk per file, the failing one carries only bad.txt
+    let err = &v["upload_error"];
+    let chunk = err["chunk_index"].as_u64().unwrap();
+    assert!((1..=3).contains(&chunk));
+    assert_eq!(err["chunks_total"], 3);
+    assert_eq!(err["paths"], json!(["bad.txt"]));
+    assert_eq!(err["status"], 400);
+    assert_eq!(err["message"], "invalid blob content");
+    let result = v["result"].as_str().unwrap();
+    assert!(
+        result.contains(&format!("chunk {chunk}/3 [bad.txt]")),
+        "{result}"
+    );
+    assert!(result.contains("400 invalid blob content"), "{result}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn projects_and_tasks_honor_if_none_match() {
+    let base_url = start_slow_stub().await;
+    let td = tempfile::tempdir().unwrap();
+    let cfg = test_cfg(base_url, td.path());
+    let mut paths = vec![];
+    for name in ["a", "b"] {
+        let dir = td.path().join(name);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("x.txt"), "x\n").unwrap();
+        paths.push(cfg.resolve_project(&dir.to_string_lossy()).unwrap());
+    }
+    let (key, path) = &paths[0];
+    augmcp::service::index_and_persist(&cfg, key, path, false)
+        .await
+        .unwrap();
+    let router = augmcp::http_router::build_router(AppState {
+        server: AugServer::new(cfg.clone()),
+        tasks: augmcp::tasks::TaskManager::new(),
+    });
+    let get = |uri: &str, etag: Option<&str>| {
+        let mut req = Request::get(uri);
+        if let Some(tag) = etag {
+            req = req.header("if-none-match", tag);
+        }
+        req.body(Body::empty()).unwrap()
+    };
+
+    let resp = router
+        .clone()
+        .oneshot(get("/api/projects", None))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let etag = resp.headers()["etag"].to_str().unwrap().to_string();
+    assert!(etag.starts_with("W/\""));
+    let bytes = body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["projects"][0]["path"].as_str(), Some(path.as_str()));
+    assert_eq!(v["projects"][0]["blobs"], 1);
+
+    let resp = router
+        .clone()
+        .oneshot(get("/api/projects", Some(&etag)))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(resp.headers()["etag"].to_str().unwrap(), etag);
+
+    // A newly indexed project changes the body and thus the tag
+    augmcp::service::index_and_persist(&cfg, &paths[1].0, &paths[1].1, false)
+        .await
+        .unwrap();
+    let resp = router
+        .clone()
+        .oneshot(get("/api/projects", Some(&etag)))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_ne!(resp.headers()["etag"].to_str().unwrap(), etag);
+
+    let uri = format!("/api/tasks?project_root_path={path}");
+    let resp = router.clone().oneshot(get(&uri, None)).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let etag = resp.headers()["etag"].to_str().unwrap().to_string();
+    let resp = router
+        .clone()
+        .oneshot(get(&uri, Some(&etag)))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn searches_are_recorded_in_project_history() {
+    let base_url = start_slow_stub().await;
+    let td = tempfile::tempdir().unwrap();
+    let mut cfg = test_cfg(base_url, td.path());
+    cfg.settings.record_history = true;
+    let proj = td.path().join("proj");
+    std::fs::create_dir_all(&proj).unwrap();
+    std::fs::write(proj.join("x.txt"), "x\n").unwrap();
+    let (key, _) = cfg.resolve_project(&proj.to_string_lossy()).unwrap();
+    let router = augmcp::http_router::build_router(AppState {
+        server: AugServer::new(cfg.clone()),
+        tasks: augmcp::tasks::TaskManager::new(),
+    });
+
+    for query in ["first", "second"] {
+        let body = json!({"project_root_path": proj.to_string_lossy(), "query": query});
+        let req = Request::post("/api/search")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let resp = router.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    let history = |query: &str| {
+        let uri = format!(
+            "/api/projects/{}/history{}",
+            key.replace('%', "%25").replace('/', "%2F"),
+            query
+        );
+        let router = router.clone();
+        async move {
+            let resp = router
+                .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let bytes = body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+        }
+    };
+    let v = history("").await;
+    let entries = v["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), 2, "{v}");
+    assert_eq!(entries[0]["query"], "first");
+    assert_eq!(entries[1]["query"], "second");
+    assert_eq!(entries[1]["result_len"], "OK: second".len());
+    assert_eq!(entries[1]["blob_count"], 1);
+    assert!(
+        entries[1].get("result").is_none(),
+        "no result body by default"
+    );
+
+    let v = history("?limit=1").await;
+    assert_eq!(v["entries"].as_array().unwrap().len(), 1);
+    assert_eq!(v["entries"][0]["query"], "second");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn max_concurrent_retrievals_queues_or_rejects_extra_searches() {
+    use augmcp::config::RetrievalOverflow;
+    use std::time::{Duration, Instant};
+    const DELAY: Duration = Duration::from_millis(300);
+    let app = Router::new()
+        .route(
+            "/batch-upload",
+            post(|Json(p): Json<UploadPayload>| async move {
+                let names = p.blobs.into_iter().map(|b| b.path).collect();
+                Json(UploadResp { blob_names: names })
+            }),
+        )
+        .route(
+            "/agents/codebase-retrieval",
+            post(|Json(p): Json<RetrievalPayload>| async move {
+                tokio::time::sleep(DELAY).await;
+                Json(RetrievalResp {
+                    formatted_retrieval: format!("OK: {}", p.information_request),
+                })
+            }),
+        );
+    let addr = common::serve(app).await;
+
+    for overflow in [RetrievalOverflow::Queue, RetrievalOverflow::Reject] {
+        let td = tempfile::tempdir().unwrap();
+        let mut cfg = test_cfg(format!("http://{}", addr), td.path());
+        cfg.settings.max_concurrent_retrievals = 1;
+        cfg.settings.retrieval_overflow = overflow;
+        let proj = td.path().join("proj");
+        std::fs::create_dir_all(&proj).unwrap();
+        std::fs::write(proj.join("x.txt"), "x\n").unwrap();
+        let (key, path) = cfg.resolve_project(&proj.to_string_lossy()).unwrap();
+        augmcp::service::index_and_persist(&cfg, &key, &path, false)
+            .await
+            .unwrap();
+        let router = augmcp::http_router::build_router(AppState {
+            server: AugServer::new(cfg.clone()),
+            tasks: augmcp::tasks::TaskManager::new(),
+        });
+        let search = |query: &'static str| {
+            let router = router.clone();
+            let body = json!({"project_root_path": path, "query": query});
+            async move {
+                let req = Request::post("/api/search")
+                    .header("content-type", "application/json")
+                    .body(Body::from(body.to_string()))
+                    .unwrap();
+                let resp = router.oneshot(req).await.unwrap();
+                let bytes = body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+                (
+                    serde_json::from_slice::<serde_json::Value>(&bytes).unwrap(),
+                    Instant::now(),
+                )
+            }
+        };
+
+        let start = Instant::now();
+        let first = tokio::spawn(search("a"));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let (second, second_done) = search("b").await;
+        let (first, first_done) = first.await.unwrap();
+        assert_eq!(first["status"], "success", "{first}");
+        match overflow {
+            RetrievalOverflow::Queue => {
+                // The second search waited for the first retrieval to finish
+                assert_eq!(second["status"], "success", "{second}");
+                assert!(second_done >= first_done, "{second}");
+                assert!(second_done - start >= DELAY * 2);
+            }
+            RetrievalOverflow::Reject => {
+                assert_eq!(second["status"], "busy", "{second}");
+                assert!(second_done - start < DELAY);
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn healthz_is_served_over_unix_socket() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    let td = tempfile::tempdir().unwrap();
+    let cfg = test_cfg("http://127.0.0.1:9".into(), td.path());
+    let router = augmcp::http_router::build_router(AppState {
+        server: AugServer::new(cfg),
+        tasks: augmcp::tasks::TaskManager::new(),
+    });
+    let sock = td.path().join("augmcp.sock");
+    // A stale socket file from an earlier run is replaced
+    drop(std::os::unix::net::UnixListener::bind(&sock).unwrap());
+    let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+    let path = sock.clone();
+    let served = tokio::spawn(async move {
+        augmcp::http_router::serve_unix(router, &path, async {
+            let _ = stop_rx.await;
+        })
+        .await
+    });
+
+    let mut stream = None;
+    for _ in 0..50 {
+        match tokio::net::UnixStream::connect(&sock).await {
+            Ok(s) => {
+                stream = Some(s);
+                break;
+            }
+            Err(_) => tokio::time::sleep(std::time::Duration::from_millis(20)).await,
+        }
+    }
+    let mut stream = stream.expect("socket never accepted connections");
+    stream
+        .write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).await.unwrap();
+    assert!(resp.starts_with("HTTP/1.1 200 OK"), "{resp}");
+    assert!(resp.contains(r#""status":"ok""#), "{resp}");
+
+    stop_tx.send(()).unwrap();
+    served.await.unwrap().unwrap();
+    assert!(!sock.exists());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn timeout_secs_answers_504_for_stalled_backend() {
+    let stall = || async {
+        tokio::time::sleep(std::time::Duration::from_secs(30)).await;
+        Json(json!({}))
+    };
+    let app = Router::new()
+        .route(
+            "/batch-upload",
+            post(move |_: Json<serde_json::Value>| stall()),
+        )
+        .route(
+            "/agents/codebase-retrieval",
+            post(move |_: Json<serde_json::Value>| stall()),
+        );
+    let addr = common::serve(app).await;
+    let td = tempfile::tempdir().unwrap();
+    let cfg = test_cfg(format!("http://{addr}"), td.path());
+    let proj = td.path().join("proj");
+    std::fs::create_dir_all(&proj).unwrap();
+    std::fs::write(proj.join("a.txt"), "a\n").unwrap();
+    let path = proj.to_string_lossy().to_string();
+    let router = augmcp::http_router::build_router(AppState {
+        server: AugServer::new(cfg),
+        tasks: augmcp::tasks::TaskManager::new(),
+    });
+    let post = |uri: &str, body: serde_json::Value| {
+        Request::post(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+
+    for (uri, body) in [
+        (
+            "/api/index",
+            json!({"project_root_path": path, "timeout_secs": 1}),
+        ),
+        (
+            "/api/search",
+            json!({"project_root_path": path, "query": "q", "timeout_secs": 1}),
+        ),
+    ] {
+        let started = std::time::Instant::now();
+        let resp = router.clone().oneshot(post(uri, body)).await.unwrap();
+        assert!(
+            started.elapsed() < std::time::Duration::from_secs(5),
+            "{uri}"
+        );
+        assert_eq!(resp.status(), StatusCode::GATEWAY_TIMEOUT, "{uri}");
+        let bytes = body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(v["status"], "timeout");
+        assert!(
+            v["result"].as_str().unwrap().contains("timeout_secs=1"),
+            "{v}"
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn verify_prunes_and_reuploads_blobs_missing_on_backend() {
+    use std::sync::{Arc, Mutex};
+    let uploaded: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(vec![]));
+    let up = uploaded.clone();
+    let app = Router::new()
+        .route(
+            "/batch-upload",
+            post(move |Json(p): Json<UploadPayload>| {
+                let up = up.clone();
+                async move {
+                    let names = p
+                        .blobs
+                        .iter()
+                        .map(|b| {
+                            up.lock().unwrap().push(b.path.clone());
+                            augmcp::indexer::hash_blob_name(&b.path, &b.content)
+                        })
+                        .collect();
+                    Json(UploadResp { blob_names: names })
+                }
+            }),
+        )
+        .route(
+            "/find-missing",
+            post(|Json(p): Json<serde_json::Value>| async move {
+                // The backend lost b.txt; it also reports a name it was not asked about
+                let lost = augmcp::indexer::hash_blob_name("b.txt", "b\n");
+                let mut missing: Vec<String> = p["blob_names"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .filter_map(|n| n.as_str())
+                    .filter(|n| *n == lost)
+                    .map(String::from)
+                    .collect();
+                missing.push("unrelated".into());
+                Json(json!({ "missing_blob_names": missing }))
+            }),
+        );
+    let addr = common::serve(app).await;
+    let td = tempfile::tempdir().unwrap();
+    let mut cfg = test_cfg(format!("http://{addr}"), td.path());
+    let proj = td.path().join("proj");
+    std::fs::create_dir_all(&proj).unwrap();
+    for f in ["a", "b", "c"] {
+        std::fs::write(proj.join(format!("{f}.txt")), format!("{f}\n")).unwrap();
+    }
+    let (key, path) = cfg.resolve_project(&proj.to_string_lossy()).unwrap();
+    augmcp::service::index_and_persist(&cfg, &key, &path, false)
+        .await
+        .unwrap();
+    uploaded.lock().unwrap().clear();
+    let uri = format!(
+        "/api/projects/{}/verify",
+        key.replace('%', "%25").replace('/', "%2F")
+    );
+    let verify = |cfg: Config| {
+        let uri = uri.clone();
+        async move {
+            let router = augmcp::http_router::build_router(AppState {
+                server: AugServer::new(cfg),
+                tasks: augmcp::tasks::TaskManager::new(),
+            });
+            let resp = router
+                .oneshot(Request::post(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let bytes = body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+        }
+    };
+
+    // read_only: refused before the backend is asked or anything is pruned
+    let mut ro = cfg.clone();
+    ro.settings.read_only = true;
+    ro.settings.verify_endpoint = Some("/find-missing".into());
+    let router = augmcp::http_router::build_router(AppState {
+        server: AugServer::new(ro),
+        tasks: augmcp::tasks::TaskManager::new(),
+    });
+    let req = Request::post(uri.clone()).body(Body::empty()).unwrap();
+    assert_eq!(
+        router.oneshot(req).await.unwrap().status(),
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(augmcp::service::load_projects(&cfg).0[&key].len(), 3);
+
+    // Without verify_endpoint the backend cannot be asked
+    let v = verify(cfg.clone()).await;
+    assert_eq!(v["status"], "not_supported");
+    assert!(uploaded.lock().unwrap().is_empty());
+
+    cfg.settings.verify_endpoint = Some("/find-missing".into());
+    let v = verify(cfg.clone()).await;
+    assert_eq!(v["status"], "success", "{v}");
+    assert_eq!(v["checked"], 3);
+    assert_eq!(
+        v["missing"],
+        json!([augmcp::indexer::hash_blob_name("b.txt", "b\n")])
+    );
+    assert_eq!(v["reuploaded"], 1);
+    assert_eq!(*uploaded.lock().unwrap(), ["b.txt"]);
+    assert_eq!(augmcp::service::load_projects(&cfg).0[&key].len(), 3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn project_meta_is_validated_listed_and_survives_reindex() {
+    let app = Router::new().route(
+        "/batch-upload",
+        post(|Json(p): Json<UploadPayload>| async move {
+            let names = p
+                .blobs
+                .iter()
+                .map(|b| augmcp::indexer::hash_blob_name(&b.path, &b.content))
+                .collect();
+            Json(UploadResp { blob_names: names })
+        }),
+    );
+    let addr = common::serve(app).await;
+    let td = tempfile::tempdir().unwrap();
+    let cfg = test_cfg(format!("http://{addr}"), td.path());
+    let proj = td.path().join("proj");
+    std::fs::create_dir_all(&proj).unwrap();
+    std::fs::write(proj.join("a.txt"), "a\n").unwrap();
+    let (key, path) = cfg.resolve_project(&proj.to_string_lossy()).unwrap();
+    let uri = format!(
+        "/api/projects/{}/meta",
+        key.replace('%', "%25").replace('/', "%2F")
+    );
+    let call = |req: Request<Body>| {
+        let router = augmcp::http_router::build_router(AppState {
+            server: AugServer::new(cfg.clone()),
+            tasks: augmcp::tasks::TaskManager::new(),
+        });
+        async move {
+            let resp = router.oneshot(req).await.unwrap();
+            let status = resp.status();
+            let bytes = body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+            (
+                status,
+                serde_json::from_slice::<serde_json::Value>(&bytes).unwrap(),
+            )
+        }
+    };
+    let set = |body: serde_json::Value| {
+        Request::post(uri.clone())
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+    let meta = json!({ "team": "search", "description": "API gateway" });
+
+    // Only indexed projects can be tagged
+    let (status, v) = call(set(meta.clone())).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(v["result"].as_str().unwrap().contains("not indexed"), "{v}");
+
+    augmcp::service::index_and_persist(&cfg, &key, &path, false)
+        .await
+        .unwrap();
+    // Nested values and non-objects are rejected
+    for bad in [
+        json!({ "team": { "name": "x" } }),
+        json!({ "n": 1 }),
+        json!(["x"]),
+    ] {
+        let (status, v) = call(set(bad.clone())).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{bad}");
+        assert_eq!(v["status"], "error");
+    }
+    let (status, v) = call(set(meta.clone())).await;
+    assert_eq!(status, StatusCode::OK, "{v}");
+    assert_eq!(v["meta"], meta);
+
+    // Re-indexing does not touch the metadata
+    std::fs::write(proj.join("b.txt"), "b\n").unwrap();
+    augmcp::service::index_and_persist(&cfg, &key, &path, true)
+        .await
+        .unwrap();
+    let (_, v) = call(Request::get(uri.clone()).body(Body::empty()).unwrap()).await;
+    assert_eq!(v["meta"], meta);
+    let (_, v) = call(Request::get("/api/projects").body(Body::empty()).unwrap()).await;
+    assert_eq!(v["projects"][0]["meta"], meta);
+    assert_eq!(v["projects"][0]["blobs"], 2);
+
+    // The MCP tools read and replace the same record; {} clears it
+    let server = AugServer::new(cfg.clone());
+    let args = |meta: Option<serde_json::Value>| {
+        rmcp::handler::server::wrapper::Parameters(augmcp::server::ProjectMetaArgs {
+            project_root_path: Some(path.clone()),
+            alias: None,
+            project_key: None,
+            meta: meta.map(|m| serde_json::from_value(m).unwrap()),
+        })
+    };
+    let text = |r: rmcp::model::CallToolResult| r.content[0].as_text().unwrap().text.clone();
+    let got: serde_json::Value =
+        serde_json::from_str(&text(server.get_project_meta(args(None)).await.unwrap())).unwrap();
+    assert_eq!(got["meta"], meta);
+    server
+        .set_project_meta(args(Some(json!({}))))
+        .await
+        .unwrap();
+    assert_eq!(augmcp::service::get_project_meta(&cfg, &key).len(), 0);
+    let (_, v) = call(Request::get("/api/projects").body(Body::empty()).unwrap()).await;
+    assert!(v["projects"][0].get("meta").is_none(), "{v}");
+
+    // read_only: replacing is forbidden, reading still works
+    let mut ro = cfg.clone();
+    ro.settings.read_only = true;
+    let router = augmcp::http_router::build_router(AppState {
+        server: AugServer::new(ro),
+        tasks: augmcp::tasks::TaskManager::new(),
+    });
+    let resp = router.clone().oneshot(set(meta.clone())).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let req = Request::get(uri.clone()).body(Body::empty()).unwrap();
+    assert_eq!(router.oneshot(req).await.unwrap().status(), StatusCode::OK);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn projects_listing_shows_home_relative_paths() {
+    let base_url = start_slow_stub().await;
+    let td = tempfile::tempdir().unwrap();
+    let outside = tempfile::tempdir().unwrap();
+    let mut cfg = test_cfg(base_url, td.path());
+    cfg.settings.display_paths = augmcp::config::DisplayPaths::HomeRelative;
+    let mut paths = vec![];
+    for dir in [td.path().join("dev/proj"), outside.path().to_path_buf()] {
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("x.txt"), "x\n").unwrap();
+        let (key, path) = cfg.resolve_project(&dir.to_string_lossy()).unwrap();
+        augmcp::service::index_and_persist(&cfg, &key, &path, false)
+            .await
+            .unwrap();
+        paths.push(path);
+    }
+    let router = augmcp::http_router::build_router(AppState {
+        server: AugServer::new(cfg.clone()),
+        tasks: augmcp::tasks::TaskManager::new(),
+    });
+    let resp = router
+        .oneshot(Request::get("/api/projects").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let bytes = body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    let mut shown: Vec<&str> = v["projects"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| p["path"].as_str().unwrap())
+        .collect();
+    shown.sort();
+    let mut expected = vec!["~/dev/proj", paths[1].as_str()];
+    expected.sort();
+    // Under the home directory (the parent of ~/.augmcp) paths get `~`; others stay absolute
+    assert_eq!(shown, expected);
+    assert_eq!(cfg.display_path(&td.path().to_string_lossy()), "~");
+    // Only the output changes: the stored keys are still absolute
+    assert!(
+        augmcp::service::load_projects(&cfg)
+            .0
+            .contains_key(&paths[0])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn estimate_matches_the_real_index_file_count() {
+    let base_url = start_slow_stub().await;
+    let td = tempfile::tempdir().unwrap();
+    let cfg = test_cfg(base_url, td.path());
+    let proj = td.path().join("proj");
+    std::fs::create_dir_all(proj.join("sub")).unwrap();
+    std::fs::write(proj.join("a.txt"), "one\ntwo\n").unwrap();
+    std::fs::write(proj.join("sub/b.txt"), "b\n".repeat(30)).unwrap();
+    std::fs::write(proj.join("image.png"), "not text").unwrap();
+    std::fs::write(proj.join("empty.txt"), "").unwrap();
+    let router = augmcp::http_router::build_router(AppState {
+        server: AugServer::new(cfg.clone()),
+        tasks: augmcp::tasks::TaskManager::new(),
+    });
+    let (key, path) = cfg.resolve_project(&proj.to_string_lossy()).unwrap();
+
+    let uri = format!("/api/estimate?path={path}");
+    let resp = router
+        .clone()
+        .oneshot(Request::get(&uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let bytes = body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["status"], "success", "{v}");
+    // Estimating neither uploads nor records anything
+    assert!(augmcp::service::load_projects(&cfg).0.is_empty());
+
+    let report = augmcp::service::index_and_persist(&cfg, &key, &path, false)
+        .await
+        .unwrap();
+    let meta = augmcp::indexer::IndexMeta::load(&cfg.index_meta_file())
+        .unwrap()
+        .0
+        .remove(&key)
+        .unwrap();
+    assert_eq!(meta.files.len(), 2);
+    assert_eq!(v["files"], meta.files.len());
+    assert_eq!(v["total_bytes"], 8 + 60);
+    assert_eq!(v["estimated_blobs"], report.total_blobs);
+
+    let resp = router
+        .oneshot(Request::get("/api/estimate").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let bytes = body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["status"], "error");
+    assert_eq!(v["result"], "provide path or alias");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn merge_unions_blob_lists_and_can_drop_the_source() {
+    let base_url = start_slow_stub().await;
+    let td = tempfile::tempdir().unwrap();
+    let cfg = test_cfg(base_url, td.path());
+    let mut keys = vec![];
+    for (name, own) in [("a", "a.txt"), ("b", "b.txt")] {
+        let dir = td.path().join(name);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(own), format!("{name}\n")).unwrap();
+        // Same relative path and content in both roots: the same blob name
+        std::fs::write(dir.join("shared.txt"), "shared\n").unwrap();
+        let (key, path) = cfg.resolve_project(&dir.to_string_lossy()).unwrap();
+        augmcp::service::index_and_persist(&cfg, &key, &path, false)
+            .await
+            .unwrap();
+        keys.push(key);
+    }
+    let router = augmcp::http_router::build_router(AppState {
+        server: AugServer::new(cfg.clone()),
+        tasks: augmcp::tasks::TaskManager::new(),
+    });
+    let merge = |body: serde_json::Value| {
+        Request::post("/api/projects/merge")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+
+    let resp = router
+        .clone()
+        .oneshot(merge(json!({"src_key": keys[1], "dst_key": keys[0]})))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let bytes = body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["dst_blobs_before"], 2);
+    assert_eq!(v["dst_blobs_after"], 3);
+    let projects = augmcp::service::load_projects(&cfg);
+    assert_eq!(projects.0[&keys[0]].len(), 3);
+    assert_eq!(projects.0[&keys[1]].len(), 2, "source kept by default");
+    // Merged files are attributed in the destination's metadata too
+    let meta = augmcp::indexer::IndexMeta::load(&cfg.index_meta_file()).unwrap();
+    let files: Vec<&String> = meta.0[&keys[0]].files.keys().collect();
+    assert_eq!(files, vec!["a.txt", "b.txt", "shared.txt"]);
+
+    let resp = router
+        .clone()
+        .oneshot(merge(
+            json!({"src_key": keys[1], "dst_key": keys[0], "remove_source": true}),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let projects = augmcp::service::load_projects(&cfg);
+    assert_eq!(projects.0[&keys[0]].len(), 3);
+    assert!(!projects.0.contains_key(&keys[1]));
+
+    let resp = router
+        .oneshot(merge(json!({"src_key": keys[1], "dst_key": keys[0]})))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let bytes = body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(
+        v["result"].as_str().unwrap(),
+        format!("project not indexed: {}", keys[1])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sync_index_waits_for_a_max_concurrent_indexes_slot() {
+    let base_url = start_slow_stub().await;
+    let td = tempfile::tempdir().unwrap();
+    let cfg = test_cfg(base_url, td.path());
+    let proj = td.path().join("proj");
+    std::fs::create_dir_all(&proj).unwrap();
+    std::fs::write(proj.join("a.txt"), "a\n").unwrap();
+    let path = proj.to_string_lossy().to_string();
+    let tasks = augmcp::tasks::TaskManager::with_limit(1);
+    let router = augmcp::http_router::build_router(AppState {
+        server: AugServer::new(cfg),
+        tasks: tasks.clone(),
+    });
+    let index = || {
+        Request::post("/api/index")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                json!({"project_root_path": path, "timeout_secs": 1}).to_string(),
+            ))
+            .unwrap()
+    };
+
+    // 唯一的索引名额被占用时，同步索引排队直至超时
+    let held = tasks.acquire_index_permit("other").await;
+    let resp = router.clone().oneshot(index()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::GATEWAY_TIMEOUT);
+
+    drop(held);
+    let resp = router.clone().oneshot(index()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let bytes = body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["status"], "success", "{v}");
+}
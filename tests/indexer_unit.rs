@@ -1,5 +1,13 @@
-use augmcp::indexer::{ProjectsIndex, collect_blobs, incremental_plan};
-use std::{collections::HashSet, fs, path::Path};
+use augmcp::indexer::{
+    COLLECT_PROGRESS_EVERY, ChunkNumbering, CollectOptions, ExclusionReason, ProjectsIndex,
+    collect_blobs, collect_blobs_with, collect_blobs_with_progress, detect_language,
+    exclude_preset, explain_path_with, incremental_plan, parse_blob_path, root_gitignored,
+    split_chunks,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+};
 
 fn set_to(list: &[&str]) -> HashSet<String> {
     list.iter().map(|s| s.to_string()).collect()
@@ -48,3 +56,700 @@ fn collect_respects_ext_exclude_gitignore_and_splitting() {
     assert_eq!(new_blobs.len(), blobs.len());
     assert_eq!(all.len(), blobs.len());
 }
+
+#[test]
+fn collect_skips_minified_when_avg_line_threshold_set() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path();
+    fs::write(root.join("bundle.min.js"), "var a=1;".repeat(500)).unwrap();
+    fs::write(root.join("app.js"), "let a = 1;\nlet b = 2;\n").unwrap();
+
+    let mut opts = CollectOptions {
+        text_exts: set_to(&[".js"]),
+        max_lines: 100,
+        ..Default::default()
+    };
+    let blobs = collect_blobs_with(root, &opts).unwrap();
+    assert_eq!(blobs.len(), 2, "threshold disabled by default");
+
+    opts.max_avg_line_bytes = 200;
+    let blobs = collect_blobs_with(root, &opts).unwrap();
+    let names: Vec<&str> = blobs.iter().map(|b| b.path.as_str()).collect();
+    assert_eq!(names, vec!["app.js"]);
+}
+
+#[test]
+fn gitattributes_overrides_extension_classification() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path();
+    fs::create_dir_all(root.join("data")).unwrap();
+    fs::write(
+        root.join(".gitattributes"),
+        "# classification\ndata/*.txt -text\nMakefile text\n",
+    )
+    .unwrap();
+    fs::write(root.join("data/dump.txt"), "not really text\n").unwrap();
+    fs::write(root.join("notes.txt"), "hello\n").unwrap();
+    fs::write(root.join("Makefile"), "all:\n\ttrue\n").unwrap();
+
+    let blobs = collect_blobs(root, &set_to(&[".txt"]), 100, &[]).unwrap();
+    let mut names: Vec<&str> = blobs.iter().map(|b| b.path.as_str()).collect();
+    names.sort();
+    assert_eq!(names, vec!["Makefile", "notes.txt"]);
+}
+
+#[test]
+fn collect_reports_progress_by_files_seen() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path();
+    let n = COLLECT_PROGRESS_EVERY + 5;
+    for i in 0..n {
+        fs::write(root.join(format!("f{i}.txt")), "x\n").unwrap();
+    }
+    let opts = CollectOptions {
+        text_exts: set_to(&[".txt"]),
+        max_lines: 100,
+        ..Default::default()
+    };
+    let mut seen = Vec::new();
+    let blobs = collect_blobs_with_progress(root, &opts, |p| seen.push(p.files_seen)).unwrap();
+    assert_eq!(blobs.len(), n);
+    assert_eq!(seen, vec![COLLECT_PROGRESS_EVERY, n]);
+}
+
+#[test]
+fn collect_single_file_root() {
+    let td = tempfile::tempdir().unwrap();
+    let file = td.path().join("lib.rs");
+    fs::write(&file, "pub fn f() {}\n").unwrap();
+    fs::write(td.path().join("other.rs"), "fn g() {}\n").unwrap();
+
+    let blobs = collect_blobs(&file, &set_to(&[".rs"]), 100, &[]).unwrap();
+    assert_eq!(blobs.len(), 1);
+    assert_eq!(blobs[0].path, "lib.rs");
+}
+
+#[test]
+fn per_extension_max_lines_overrides_global() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path();
+    let body: String = (0..20).map(|i| format!("line {i}\n")).collect();
+    fs::write(root.join("README.md"), &body).unwrap();
+    fs::write(root.join("main.rs"), &body).unwrap();
+
+    let opts = CollectOptions {
+        text_exts: set_to(&[".md", ".rs"]),
+        max_lines: 10,
+        max_lines_per_ext: HashMap::from([(".md".to_string(), 100), (".rs".to_string(), 5)]),
+        ..Default::default()
+    };
+    let blobs = collect_blobs_with(root, &opts).unwrap();
+    let md = blobs
+        .iter()
+        .filter(|b| b.path.starts_with("README.md"))
+        .count();
+    let rs = blobs
+        .iter()
+        .filter(|b| b.path.starts_with("main.rs"))
+        .count();
+    assert_eq!(md, 1);
+    assert_eq!(rs, 4);
+}
+
+#[test]
+fn projects_index_migrates_legacy_bare_map() {
+    let td = tempfile::tempdir().unwrap();
+    let file = td.path().join("projects.json");
+    fs::write(&file, r#"{"C:/proj":["h1","h2"],"/home/u/other":[]}"#).unwrap();
+
+    let legacy = ProjectsIndex::load(&file).unwrap();
+    assert_eq!(legacy.0.get("C:/proj").unwrap(), &vec!["h1", "h2"]);
+    assert_eq!(legacy.0.len(), 2);
+
+    assert!(ProjectsIndex::migrate_if_needed(&file).unwrap());
+    let v: serde_json::Value = serde_json::from_str(&fs::read_to_string(&file).unwrap()).unwrap();
+    assert_eq!(v["version"], ProjectsIndex::VERSION);
+    assert_eq!(v["projects"]["C:/proj"], serde_json::json!(["h1", "h2"]));
+
+    assert_eq!(ProjectsIndex::load(&file).unwrap(), legacy);
+    assert!(!ProjectsIndex::migrate_if_needed(&file).unwrap());
+}
+
+#[test]
+fn chunk_decoder_matches_collected_chunks() {
+    let td = tempfile::tempdir().unwrap();
+    let body: String = (0..25).map(|i| format!("l{i}\n")).collect();
+    fs::write(td.path().join("a.txt"), &body).unwrap();
+    let blobs = collect_blobs(td.path(), &set_to(&[".txt"]), 10, &[]).unwrap();
+    let chunks = split_chunks(&body, 10);
+    assert_eq!(chunks.len(), blobs.len());
+    for b in &blobs {
+        let (file, nm) = parse_blob_path(&b.path);
+        let (n, m) = nm.unwrap();
+        assert_eq!(file, "a.txt");
+        assert_eq!(m, 3);
+        assert_eq!(chunks[n - 1], b.content);
+    }
+    assert_eq!(parse_blob_path("src/a#b.rs"), ("src/a#b.rs", None));
+}
+
+#[test]
+fn padded_chunk_numbers_sort_lexically_in_numeric_order() {
+    let td = tempfile::tempdir().unwrap();
+    let body: String = (0..12).map(|i| format!("line{i}\n")).collect();
+    fs::write(td.path().join("big.txt"), &body).unwrap();
+    let mut opts = CollectOptions {
+        text_exts: set_to(&[".txt"]),
+        max_lines: 1,
+        ..Default::default()
+    };
+
+    let names: Vec<String> = collect_blobs_with(td.path(), &opts)
+        .unwrap()
+        .into_iter()
+        .map(|b| b.path)
+        .collect();
+    assert_eq!(names.len(), 12);
+    assert_eq!(names[0], "big.txt#chunk01of12");
+    assert_eq!(names[11], "big.txt#chunk12of12");
+    let mut sorted = names.clone();
+    sorted.sort();
+    assert_eq!(sorted, names, "lexical order must match chunk order");
+    assert_eq!(
+        parse_blob_path("big.txt#chunk02of12"),
+        ("big.txt", Some((2, 12)))
+    );
+
+    opts.chunk_numbering = ChunkNumbering::Plain;
+    let blobs = collect_blobs_with(td.path(), &opts).unwrap();
+    assert_eq!(blobs[1].path, "big.txt#chunk2of12");
+}
+
+#[test]
+fn explain_path_reports_each_exclusion_reason() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path();
+    // .git marks the tempdir as a repository so the ignore layer honors .gitignore
+    for dir in [".git", "src", "dist", "ignored", ".augmcp", "gen"] {
+        fs::create_dir_all(root.join(dir)).unwrap();
+    }
+    fs::write(root.join(".gitignore"), "ignored/\n").unwrap();
+    fs::write(root.join(".gitattributes"), "*.dat binary\n").unwrap();
+    fs::write(root.join("src/lib.rs"), "a\nb\nc\n").unwrap();
+    fs::write(root.join("dist/out.rs"), "x\n").unwrap();
+    fs::write(root.join("ignored/x.rs"), "x\n").unwrap();
+    fs::write(root.join(".augmcp/manifest.rs"), "x\n").unwrap();
+    fs::write(root.join("blob.dat"), "x\n").unwrap();
+    fs::write(root.join("image.png"), "x\n").unwrap();
+    fs::write(root.join("gen/api_generated.rs"), "x\n").unwrap();
+    fs::write(root.join("min.rs"), "x".repeat(500)).unwrap();
+    fs::write(root.join("bad.rs"), "").unwrap();
+
+    let opts = CollectOptions {
+        text_exts: set_to(&[".rs"]),
+        max_lines: 2,
+        exclude_patterns: vec!["node_modules".into(), "dist".into()],
+        generated_patterns: vec!["**/*_generated.rs".into()],
+        max_avg_line_bytes: 100,
+        ..Default::default()
+    };
+    let explain = |rel: &str| explain_path_with(root, rel, &opts).unwrap();
+
+    assert_eq!(
+        explain("src/lib.rs"),
+        ExclusionReason::Included { chunks: 2 }
+    );
+    assert_eq!(
+        explain("./src\\lib.rs"),
+        ExclusionReason::Included { chunks: 2 }
+    );
+    assert_eq!(explain("src/nope.rs"), ExclusionReason::NotFound);
+    assert_eq!(explain("../outside.rs"), ExclusionReason::OutsideProject);
+    assert_eq!(explain("src"), ExclusionReason::Directory);
+    assert_eq!(explain("ignored/x.rs"), ExclusionReason::Gitignored);
+    assert_eq!(explain(".augmcp/manifest.rs"), ExclusionReason::StateDir);
+    assert_eq!(
+        explain("dist/out.rs"),
+        ExclusionReason::ExcludePattern {
+            pattern: "dist".into()
+        }
+    );
+    assert_eq!(explain("blob.dat"), ExclusionReason::GitattributesBinary);
+    assert_eq!(
+        explain("image.png"),
+        ExclusionReason::Extension {
+            extension: ".png".into()
+        }
+    );
+    assert_eq!(
+        explain("gen/api_generated.rs"),
+        ExclusionReason::GeneratedPattern {
+            pattern: "**/*_generated.rs".into()
+        }
+    );
+    assert_eq!(
+        explain("min.rs"),
+        ExclusionReason::Minified {
+            avg_line_bytes: 500,
+            limit: 100
+        }
+    );
+    assert!(explain("bad.rs").is_included());
+
+    // Agrees with a real collection run
+    let collected: HashSet<String> = collect_blobs_with(root, &opts)
+        .unwrap()
+        .into_iter()
+        .map(|b| augmcp::indexer::blob_file_path(&b.path).to_string())
+        .collect();
+    assert_eq!(collected, set_to(&["src/lib.rs", "bad.rs"]));
+}
+
+#[cfg(unix)]
+#[test]
+fn explain_path_reports_unreadable_files() {
+    use std::os::unix::fs::PermissionsExt;
+    let td = tempfile::tempdir().unwrap();
+    let f = td.path().join("locked.rs");
+    fs::write(&f, "x\n").unwrap();
+    fs::set_permissions(&f, fs::Permissions::from_mode(0o000)).unwrap();
+    if fs::read(&f).is_ok() {
+        return; // running as root: permissions are not enforced
+    }
+    let opts = CollectOptions {
+        text_exts: set_to(&[".rs"]),
+        max_lines: 10,
+        ..Default::default()
+    };
+    let reason = explain_path_with(td.path(), "locked.rs", &opts).unwrap();
+    assert!(
+        matches!(reason, ExclusionReason::Unreadable { .. }),
+        "{reason:?}"
+    );
+}
+
+#[test]
+fn force_include_collects_gitignored_and_excluded_files() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path();
+    fs::create_dir_all(root.join(".git")).unwrap();
+    fs::create_dir_all(root.join("docs")).unwrap();
+    fs::write(root.join(".gitignore"), "README.md\ndocs/\nLICENSE\n").unwrap();
+    fs::write(root.join("README.md"), "# readme\n").unwrap();
+    fs::write(root.join("docs/guide.md"), "guide\n").unwrap();
+    fs::write(root.join("docs/other.md"), "other\n").unwrap();
+    fs::write(root.join("LICENSE"), "MIT\n").unwrap();
+    fs::write(root.join("Cargo.toml"), "[package]\n").unwrap();
+    fs::write(root.join("main.rs"), "fn main() {}\n").unwrap();
+
+    let mut opts = CollectOptions {
+        text_exts: set_to(&[".md", ".toml", ".rs"]),
+        max_lines: 100,
+        exclude_patterns: vec!["Cargo.toml".into()],
+        ..Default::default()
+    };
+    let collected = |opts: &CollectOptions| -> HashSet<String> {
+        collect_blobs_with(root, opts)
+            .unwrap()
+            .into_iter()
+            .map(|b| b.path)
+            .collect()
+    };
+    assert_eq!(collected(&opts), set_to(&["main.rs"]));
+
+    opts.force_include = vec![
+        "README.md".into(),
+        "Cargo.toml".into(),
+        "docs/guide.md".into(),
+        "LICENSE".into(),
+    ];
+    assert_eq!(
+        collected(&opts),
+        set_to(&["main.rs", "README.md", "Cargo.toml", "docs/guide.md"])
+    );
+    assert_eq!(
+        explain_path_with(root, "README.md", &opts).unwrap(),
+        ExclusionReason::Included { chunks: 1 }
+    );
+
+    // LICENSE has no extension: only collected when forced files bypass the extension check
+    opts.force_include_any_extension = true;
+    assert!(collected(&opts).contains("LICENSE"));
+}
+
+#[test]
+fn rust_exclude_preset_skips_target_without_user_patterns() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path();
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::create_dir_all(root.join("target/debug/build")).unwrap();
+    fs::write(root.join("src/lib.rs"), "pub fn a() {}\n").unwrap();
+    fs::write(root.join("target/debug/build/out.rs"), "generated\n").unwrap();
+
+    let mut opts = CollectOptions {
+        text_exts: set_to(&[".rs"]),
+        max_lines: 100,
+        ..Default::default()
+    };
+    let paths = |opts: &CollectOptions| -> Vec<String> {
+        let mut p: Vec<_> = collect_blobs_with(root, opts)
+            .unwrap()
+            .into_iter()
+            .map(|b| b.path)
+            .collect();
+        p.sort();
+        p
+    };
+    assert_eq!(paths(&opts), ["src/lib.rs", "target/debug/build/out.rs"]);
+
+    opts.exclude_presets = vec!["Rust".into(), "nope".into()];
+    assert_eq!(paths(&opts), ["src/lib.rs"]);
+    assert_eq!(
+        explain_path_with(root, "target/debug/build/out.rs", &opts).unwrap(),
+        ExclusionReason::ExcludePattern {
+            pattern: "target".into()
+        }
+    );
+    assert!(exclude_preset("node").unwrap().contains(&"node_modules"));
+    assert!(exclude_preset("nope").is_none());
+}
+
+#[test]
+fn only_git_tracked_skips_untracked_files() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path();
+    let git = |args: &[&str]| {
+        let st = std::process::Command::new("git")
+            .arg("-C")
+            .arg(root)
+            .args(args)
+            .status()
+            .unwrap();
+        assert!(st.success(), "git {args:?}");
+    };
+    fs::write(root.join("tracked.txt"), "t\n").unwrap();
+    fs::write(root.join("untracked.txt"), "u\n").unwrap();
+    let mut opts = CollectOptions {
+        text_exts: set_to(&[".txt"]),
+        max_lines: 100,
+        only_git_tracked: true,
+        ..Default::default()
+    };
+    let paths = |opts: &CollectOptions| -> Vec<String> {
+        let mut p: Vec<_> = collect_blobs_with(root, opts)
+            .unwrap()
+            .into_iter()
+            .map(|b| b.path)
+            .collect();
+        p.sort();
+        p
+    };
+    // Not a repository yet: falls back to the normal walk
+    assert_eq!(paths(&opts), ["tracked.txt", "untracked.txt"]);
+
+    git(&["init", "-q"]);
+    git(&["add", "tracked.txt"]);
+    assert_eq!(paths(&opts), ["tracked.txt"]);
+    assert_eq!(
+        explain_path_with(root, "untracked.txt", &opts).unwrap(),
+        ExclusionReason::Untracked
+    );
+
+    opts.only_git_tracked = false;
+    assert_eq!(paths(&opts), ["tracked.txt", "untracked.txt"]);
+}
+
+#[test]
+fn text_extensions_match_whole_file_names() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path();
+    fs::create_dir_all(root.join("sub")).unwrap();
+    for f in [
+        "Makefile",
+        "sub/Makefile",
+        "makefile",
+        "Dockerfile",
+        "CMakeLists.txt",
+        "notes.txt",
+        ".env.local",
+        "lib.rs",
+    ] {
+        fs::write(root.join(f), "x\n").unwrap();
+    }
+    let opts = CollectOptions {
+        text_exts: set_to(&[".rs", "Makefile", "CMakeLists.txt", ".env.local"]),
+        max_lines: 100,
+        ..Default::default()
+    };
+    let mut paths: Vec<_> = collect_blobs_with(root, &opts)
+        .unwrap()
+        .into_iter()
+        .map(|b| b.path)
+        .collect();
+    paths.sort();
+    assert_eq!(
+        paths,
+        [
+            ".env.local",
+            "CMakeLists.txt",
+            "Makefile",
+            "lib.rs",
+            "sub/Makefile"
+        ]
+    );
+}
+
+#[test]
+fn detect_language_uses_extension_then_shebang() {
+    assert_eq!(detect_language("src/lib.rs", "").as_deref(), Some("rust"));
+    assert_eq!(
+        detect_language("tools/gen.PY", "").as_deref(),
+        Some("python")
+    );
+    assert_eq!(
+        detect_language("bin/tool", "#!/usr/bin/env python\nprint(1)\n").as_deref(),
+        Some("python")
+    );
+    assert_eq!(
+        detect_language("bin/run", "#!/usr/bin/env -S python3.11 -u\n").as_deref(),
+        Some("python")
+    );
+    assert_eq!(
+        detect_language("bin/x", "#!/bin/bash\n").as_deref(),
+        Some("shell")
+    );
+    // The extension wins over the shebang; unknown both ways is None
+    assert_eq!(
+        detect_language("a.rs", "#!/usr/bin/env python\n").as_deref(),
+        Some("rust")
+    );
+    assert_eq!(detect_language("LICENSE", "MIT\n"), None);
+
+    // Collected blobs carry it, on every chunk of a split file
+    let td = tempfile::tempdir().unwrap();
+    fs::write(td.path().join("main.rs"), "a\nb\nc\n").unwrap();
+    fs::write(td.path().join("notes.txt"), "n\n").unwrap();
+    let opts = CollectOptions {
+        text_exts: set_to(&[".rs", ".txt"]),
+        max_lines: 2,
+        ..Default::default()
+    };
+    let blobs = collect_blobs_with(td.path(), &opts).unwrap();
+    for b in &blobs {
+        let expected = b.path.starts_with("main.rs").then(|| "rust".to_string());
+        assert_eq!(b.language, expected, "{}", b.path);
+    }
+    assert_eq!(blobs.len(), 3);
+}
+
+#[test]
+fn skip_empty_files_drops_blank_files_and_chunks() {
+    assert!(augmcp::config::Settings::default().skip_empty_files);
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path();
+    fs::write(root.join("empty.txt"), "").unwrap();
+    fs::write(root.join("blank.txt"), "  \n\t\n\r\n").unwrap();
+    fs::write(root.join("one.txt"), "x").unwrap();
+    // The whitespace-only middle chunk is dropped; the others keep their numbering
+    fs::write(root.join("gaps.txt"), "a\n\n \n\nb\n").unwrap();
+
+    let mut opts = CollectOptions {
+        text_exts: set_to(&[".txt"]),
+        max_lines: 2,
+        skip_empty_files: true,
+        ..Default::default()
+    };
+    let mut names: Vec<String> = collect_blobs_with(root, &opts)
+        .unwrap()
+        .into_iter()
+        .map(|b| b.path)
+        .collect();
+    names.sort();
+    assert_eq!(
+        names,
+        vec!["gaps.txt#chunk1of3", "gaps.txt#chunk3of3", "one.txt"]
+    );
+    assert_eq!(
+        explain_path_with(root, "blank.txt", &opts).unwrap(),
+        ExclusionReason::Empty
+    );
+    assert_eq!(
+        explain_path_with(root, "gaps.txt", &opts).unwrap(),
+        ExclusionReason::Included { chunks: 2 }
+    );
+
+    opts.skip_empty_files = false;
+    let files: HashSet<String> = collect_blobs_with(root, &opts)
+        .unwrap()
+        .into_iter()
+        .map(|b| augmcp::indexer::blob_file_path(&b.path).to_string())
+        .collect();
+    assert_eq!(
+        files,
+        set_to(&["empty.txt", "blank.txt", "one.txt", "gaps.txt"])
+    );
+}
+
+#[test]
+fn file_age_filters_skip_recent_and_stale_files() {
+    use std::time::{Duration, SystemTime};
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path();
+    let now = SystemTime::now();
+    for (name, age) in [
+        ("fresh.txt", Duration::ZERO),
+        ("settled.txt", Duration::from_secs(3600)),
+        ("stale.txt", Duration::from_secs(40 * 86_400)),
+    ] {
+        let path = root.join(name);
+        fs::write(&path, "x\n").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(now - age)
+            .unwrap();
+    }
+    let mut opts = CollectOptions {
+        text_exts: set_to(&[".txt"]),
+        max_lines: 10,
+        ..Default::default()
+    };
+    let names = |opts: &CollectOptions| {
+        let mut v: Vec<String> = collect_blobs_with(root, opts)
+            .unwrap()
+            .into_iter()
+            .map(|b| b.path)
+            .collect();
+        v.sort();
+        v
+    };
+    assert_eq!(names(&opts), vec!["fresh.txt", "settled.txt", "stale.txt"]);
+
+    opts.min_file_age_secs = 60;
+    assert_eq!(names(&opts), vec!["settled.txt", "stale.txt"]);
+    assert!(matches!(
+        explain_path_with(root, "fresh.txt", &opts).unwrap(),
+        ExclusionReason::TooRecent {
+            min_age_secs: 60,
+            ..
+        }
+    ));
+
+    opts.max_file_age_days = 30;
+    assert_eq!(names(&opts), vec!["settled.txt"]);
+    assert_eq!(
+        explain_path_with(root, "stale.txt", &opts).unwrap(),
+        ExclusionReason::TooOld {
+            age_days: 40,
+            max_age_days: 30
+        }
+    );
+    assert!(
+        explain_path_with(root, "settled.txt", &opts)
+            .unwrap()
+            .is_included()
+    );
+}
+
+#[test]
+fn root_ignored_by_a_parent_gitignore_is_indexed_only_as_its_own_repo() {
+    let td = tempfile::tempdir().unwrap();
+    let parent = td.path();
+    fs::create_dir(parent.join(".git")).unwrap();
+    fs::write(parent.join(".gitignore"), "sub/*\n").unwrap();
+    let root = parent.join("sub");
+    fs::create_dir(&root).unwrap();
+    fs::write(root.join("a.txt"), "a\n").unwrap();
+    // 根目录自己的 .gitignore 仍然生效
+    fs::write(root.join(".gitignore"), "b.txt\n").unwrap();
+    fs::write(root.join("b.txt"), "b\n").unwrap();
+    let mut opts = CollectOptions {
+        text_exts: set_to(&[".txt"]),
+        max_lines: 10,
+        ..Default::default()
+    };
+    assert!(collect_blobs_with(&root, &opts).unwrap().is_empty());
+    assert!(root_gitignored(&root));
+    assert_eq!(
+        explain_path_with(&root, "a.txt", &opts).unwrap(),
+        ExclusionReason::Gitignored
+    );
+
+    opts.treat_root_as_repo = true;
+    let paths: Vec<String> = collect_blobs_with(&root, &opts)
+        .unwrap()
+        .into_iter()
+        .map(|b| b.path)
+        .collect();
+    assert_eq!(paths, vec!["a.txt"]);
+    assert!(
+        explain_path_with(&root, "a.txt", &opts)
+            .unwrap()
+            .is_included()
+    );
+    assert!(!root_gitignored(parent));
+}
+
+#[test]
+fn augmcpkeep_indexes_listed_files_inside_excluded_directories() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path();
+    fs::create_dir(root.join(".git")).unwrap();
+    fs::create_dir_all(root.join("vendor/lib")).unwrap();
+    fs::create_dir_all(root.join("other")).unwrap();
+    fs::write(root.join("main.rs"), "fn main() {}\n").unwrap();
+    fs::write(root.join("vendor/IMPORTANT.md"), "read me\n").unwrap();
+    fs::write(root.join("vendor/NOTES.md"), "skip me\n").unwrap();
+    fs::write(root.join("vendor/lib/x.rs"), "fn x() {}\n").unwrap();
+    // 同名文件在 keep 文件所在目录之外不受影响
+    fs::write(root.join("other/IMPORTANT.md"), "elsewhere\n").unwrap();
+    fs::write(root.join(".gitignore"), "other/\n").unwrap();
+    fs::write(
+        root.join("vendor/.augmcpkeep"),
+        "# keep the docs that matter\nIMPORTANT.md\n",
+    )
+    .unwrap();
+    let opts = CollectOptions {
+        text_exts: set_to(&[".rs", ".md"]),
+        max_lines: 10,
+        exclude_patterns: vec!["vendor".into()],
+        ..Default::default()
+    };
+    let paths = |opts: &CollectOptions| {
+        let mut v: Vec<String> = collect_blobs_with(root, opts)
+            .unwrap()
+            .into_iter()
+            .map(|b| b.path)
+            .collect();
+        v.sort();
+        v
+    };
+    // Off by default: keep files are not even looked for
+    assert_eq!(paths(&opts), vec!["main.rs"]);
+
+    let opts = CollectOptions {
+        honor_keep_files: true,
+        ..opts
+    };
+    assert_eq!(paths(&opts), vec!["main.rs", "vendor/IMPORTANT.md"]);
+    assert!(
+        explain_path_with(root, "vendor/IMPORTANT.md", &opts)
+            .unwrap()
+            .is_included()
+    );
+    assert_eq!(
+        explain_path_with(root, "vendor/NOTES.md", &opts).unwrap(),
+        ExclusionReason::ExcludePattern {
+            pattern: "vendor".into()
+        }
+    );
+
+    // Also overrides .gitignore: the kept file is picked up by the unfiltered pass
+    fs::write(root.join(".gitignore"), "other/\nvendor/\n").unwrap();
+    let opts = CollectOptions {
+        exclude_patterns: vec![],
+        ..opts
+    };
+    assert_eq!(paths(&opts), vec!["main.rs", "vendor/IMPORTANT.md"]);
+}
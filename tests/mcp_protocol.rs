@@ -0,0 +1,256 @@
+mod common;
+
+use augmcp::{
+    AugServer,
+    config::{Config, Settings},
+    server::{SUPPORTED_PROTOCOL_VERSION, negotiate_protocol_version},
+};
+use rmcp::model::ProtocolVersion;
+use serde_json::{Value, json};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+fn test_cfg(root: &std::path::Path) -> Config {
+    Config {
+        settings: Settings::default(),
+        root_dir: root.join("cfg"),
+        data_dir: root.join("data"),
+        settings_path: root.join("cfg/settings.toml"),
+    }
+}
+
+/// Initialize over stdio-style JSON lines with `client_version`, then list tools.
+async fn handshake(client_version: &str) -> (Value, Value) {
+    let td = tempfile::tempdir().unwrap();
+    let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+    let (server_read, server_write) = tokio::io::split(server_io);
+    tokio::spawn(async move {
+        if let Ok(running) = rmcp::serve_server(
+            AugServer::new(test_cfg(td.path())),
+            (server_read, server_write),
+        )
+        .await
+        {
+            let _ = running.waiting().await;
+        }
+    });
+    let (client_read, mut client_write) = tokio::io::split(client_io);
+    let mut lines = BufReader::new(client_read).lines();
+    let mut send = async |v: Value| {
+        client_write
+            .write_all(format!("{}\n", v).as_bytes())
+            .await
+            .unwrap();
+    };
+
+    send(json!({
+        "jsonrpc": "2.0", "id": 1, "method": "initialize",
+        "params": {
+            "protocolVersion": client_version,
+            "capabilities": {},
+            "clientInfo": {"name": "test", "version": "0"}
+        }
+    }))
+    .await;
+    let init: Value = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+    send(json!({"jsonrpc": "2.0", "method": "notifications/initialized"})).await;
+    send(json!({"jsonrpc": "2.0", "id": 2, "method": "tools/list"})).await;
+    let tools: Value = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+    (init, tools)
+}
+
+#[test]
+fn negotiation_picks_older_of_client_and_server() {
+    let old = ProtocolVersion::V_2024_11_05;
+    assert_eq!(negotiate_protocol_version(&old), old);
+    assert_eq!(
+        negotiate_protocol_version(&SUPPORTED_PROTOCOL_VERSION),
+        SUPPORTED_PROTOCOL_VERSION
+    );
+    let future: ProtocolVersion = serde_json::from_value(json!("2099-01-01")).unwrap();
+    assert_eq!(
+        negotiate_protocol_version(&future),
+        SUPPORTED_PROTOCOL_VERSION
+    );
+}
+
+#[tokio::test]
+async fn tools_listed_under_each_negotiated_version() {
+    let supported = SUPPORTED_PROTOCOL_VERSION.to_string();
+    for (client, expected) in [
+        ("2024-11-05", "2024-11-05"),
+        ("2025-03-26", supported.as_str()),
+        ("2025-06-18", supported.as_str()),
+    ] {
+        let (init, tools) = handshake(client).await;
+        assert_eq!(
+            init["result"]["protocolVersion"], expected,
+            "client {client}"
+        );
+        let mut names: Vec<&str> = tools["result"]["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|t| t["name"].as_str().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                "get_index_status",
+                "get_project_meta",
+                "index_project",
+                "index_project_async",
+                "read_project_file",
+                "search_context",
+                "set_project_meta"
+            ],
+            "client {client}"
+        );
+    }
+}
+
+/// Spawn the binary in stdio mode and return the first stdout line after `initialize`.
+fn stdio_first_line(extra_args: &[&str]) -> String {
+    use std::io::{BufRead, Write};
+    use std::process::{Command, Stdio};
+    let td = tempfile::tempdir().unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_augmcp"))
+        .args(["--transport", "stdio", "--allow-unconfigured"])
+        .args(extra_args)
+        .env("HOME", td.path())
+        .env("RUST_LOG", "info")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let init = json!({
+        "jsonrpc": "2.0", "id": 1, "method": "initialize",
+        "params": {
+            "protocolVersion": "2024-11-05",
+            "capabilities": {},
+            "clientInfo": {"name": "test", "version": "0"}
+        }
+    });
+    writeln!(child.stdin.as_mut().unwrap(), "{}", init).unwrap();
+    let mut line = String::new();
+    std::io::BufReader::new(child.stdout.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let _ = child.kill();
+    let _ = child.wait();
+    line
+}
+
+#[test]
+fn stdio_stdout_carries_only_mcp_frames() {
+    for args in [&[][..], &["--quiet"][..]] {
+        let line = stdio_first_line(args);
+        let v: Value = serde_json::from_str(&line)
+            .unwrap_or_else(|e| panic!("stdout starts with non-MCP output {line:?}: {e}"));
+        assert_eq!(v["id"], 1, "{line}");
+        assert!(v["result"]["serverInfo"].is_object(), "{line}");
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn index_project_sends_progress_notifications_for_a_progress_token() {
+    use axum::{Json, Router, routing::post};
+    let app = Router::new().route(
+        "/batch-upload",
+        post(|Json(p): Json<Value>| async move {
+            let names: Vec<String> = p["blobs"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|b| {
+                    augmcp::indexer::hash_blob_name(
+                        b["path"].as_str().unwrap(),
+                        b["content"].as_str().unwrap(),
+                    )
+                })
+                .collect();
+            Json(json!({ "blob_names": names }))
+        }),
+    );
+    let addr = common::serve(app).await;
+    let td = tempfile::tempdir().unwrap();
+    let proj = td.path().join("proj");
+    std::fs::create_dir_all(&proj).unwrap();
+    for name in ["a", "b", "c", "d"] {
+        std::fs::write(proj.join(format!("{name}.txt")), format!("{name}\n")).unwrap();
+    }
+    let mut cfg = test_cfg(td.path());
+    cfg.settings.base_url = format!("http://{}", addr);
+    cfg.settings.token = "t".into();
+    cfg.settings.batch_size = 1;
+
+    let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+    let (server_read, server_write) = tokio::io::split(server_io);
+    tokio::spawn(async move {
+        if let Ok(running) =
+            rmcp::serve_server(AugServer::new(cfg), (server_read, server_write)).await
+        {
+            let _ = running.waiting().await;
+        }
+    });
+    let (client_read, mut client_write) = tokio::io::split(client_io);
+    let mut lines = BufReader::new(client_read).lines();
+    for v in [
+        json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {
+            "protocolVersion": "2025-03-26", "capabilities": {},
+            "clientInfo": {"name": "test", "version": "0"}}}),
+        json!({"jsonrpc": "2.0", "method": "notifications/initialized"}),
+    ] {
+        client_write
+            .write_all(format!("{}\n", v).as_bytes())
+            .await
+            .unwrap();
+    }
+    lines.next_line().await.unwrap().unwrap();
+
+    // Send index_project (with or without a token) and collect the progress values seen
+    // before its response.
+    let mut call = async |id: u64, meta: Option<Value>, force_full: bool| {
+        let mut params = json!({
+            "name": "index_project",
+            "arguments": {"project_root_path": proj.to_string_lossy(), "force_full": force_full}
+        });
+        if let Some(meta) = meta {
+            params["_meta"] = meta;
+        }
+        let req = json!({"jsonrpc": "2.0", "id": id, "method": "tools/call", "params": params});
+        client_write
+            .write_all(format!("{}\n", req).as_bytes())
+            .await
+            .unwrap();
+        let mut progress = Vec::new();
+        loop {
+            let v: Value =
+                serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+            if v["id"] == id {
+                assert!(
+                    v["result"]["content"][0]["text"]
+                        .as_str()
+                        .unwrap()
+                        .contains("total_blobs"),
+                    "{v}"
+                );
+                return progress;
+            }
+            assert_eq!(v["method"], "notifications/progress", "{v}");
+            assert_eq!(v["params"]["progressToken"], "idx-1");
+            assert_eq!(v["params"]["total"], 100.0);
+            progress.push(v["params"]["progress"].as_f64().unwrap());
+        }
+    };
+
+    let progress = call(2, Some(json!({"progressToken": "idx-1"})), false).await;
+    assert_eq!(progress, vec![25.0, 50.0, 75.0, 100.0]);
+    // Nothing left to upload: a single final notification
+    let progress = call(3, Some(json!({"progressToken": "idx-1"})), false).await;
+    assert_eq!(progress, vec![100.0]);
+    // Without a token the index runs silently
+    let progress = call(4, None, true).await;
+    assert!(progress.is_empty());
+}
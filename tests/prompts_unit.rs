@@ -0,0 +1,20 @@
+use augmcp::prompts;
+use std::collections::HashMap;
+
+#[test]
+fn render_expands_template_and_target() {
+    let mut args = HashMap::new();
+    args.insert("symbol".to_string(), "collect_blobs".to_string());
+    args.insert("alias".to_string(), "myproj".to_string());
+    let text = prompts::render("find-usages", &args).unwrap();
+    assert!(text.contains("`collect_blobs`"));
+    assert!(text.contains("alias=\"myproj\""));
+    assert!(!text.contains('{'));
+}
+
+#[test]
+fn render_rejects_missing_required_and_unknown() {
+    let args = HashMap::new();
+    assert!(prompts::render("explain-file", &args).is_err());
+    assert!(prompts::render("no-such-prompt", &args).is_err());
+}
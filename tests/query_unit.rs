@@ -0,0 +1,36 @@
+use augmcp::backend::{QueryPreprocess, normalize_query};
+
+#[test]
+fn normalize_collapses_whitespace_and_strips_quotes() {
+    assert_eq!(
+        normalize_query("  where is\n\tauth   handled  "),
+        "where is auth handled"
+    );
+    assert_eq!(normalize_query("\"auth middleware\""), "auth middleware");
+    assert_eq!(normalize_query("  ' token refresh '  "), "token refresh");
+    assert_eq!(normalize_query("“login flow”"), "login flow");
+    // Unbalanced or inner quotes are kept
+    assert_eq!(normalize_query("\"auth"), "\"auth");
+    assert_eq!(
+        normalize_query("the \"auth\" module"),
+        "the \"auth\" module"
+    );
+}
+
+#[test]
+fn preprocess_is_off_by_default_and_prepends_context() {
+    let raw = "  \"auth\"  ";
+    assert_eq!(QueryPreprocess::default().apply(raw), raw);
+
+    let prep = QueryPreprocess {
+        normalize: true,
+        prepend_context: Some("Rust web service ".into()),
+    };
+    assert_eq!(prep.apply(raw), "Rust web service\nauth");
+
+    let blank = QueryPreprocess {
+        normalize: false,
+        prepend_context: Some("   ".into()),
+    };
+    assert_eq!(blank.apply("auth"), "auth");
+}
@@ -0,0 +1,557 @@
+mod common;
+
+use augmcp::{
+    AugServer,
+    config::{Config, Settings},
+    indexer::Language,
+    server::{IndexArgs, IndexStatusArgs, ReadFileArgs, SearchArgs},
+};
+use axum::{Json, Router, routing::post};
+use rmcp::handler::server::wrapper::Parameters;
+use std::fs;
+
+fn text_of(res: rmcp::model::CallToolResult) -> String {
+    res.content[0].as_text().unwrap().text.clone()
+}
+
+#[tokio::test]
+async fn read_project_file_returns_file_chunk_and_rejects_traversal() {
+    let td = tempfile::tempdir().unwrap();
+    let proj = td.path().join("proj");
+    fs::create_dir_all(&proj).unwrap();
+    let body: String = (0..5).map(|i| format!("l{i}\n")).collect();
+    fs::write(proj.join("a.txt"), &body).unwrap();
+    fs::write(td.path().join("secret.txt"), "s\n").unwrap();
+    fs::write(proj.join("skip.log"), "excluded\n").unwrap();
+    fs::write(proj.join("a.env"), "TOKEN=abc123\n").unwrap();
+    fs::create_dir_all(td.path().join("data")).unwrap();
+    let cfg = Config {
+        settings: Settings {
+            max_lines_per_blob: 2,
+            text_extensions: vec![".txt".into(), ".log".into(), ".env".into()],
+            exclude_patterns: vec!["*.log".into()],
+            content_filters: vec!["redact-env-values".into()],
+            ..Settings::default()
+        },
+        root_dir: td.path().join("cfg"),
+        data_dir: td.path().join("data"),
+        settings_path: td.path().join("cfg/settings.toml"),
+    };
+    let args_in = |root: &std::path::Path, path: &str, chunk: Option<usize>| {
+        Parameters(ReadFileArgs {
+            project_root_path: Some(root.to_string_lossy().to_string()),
+            alias: None,
+            path: path.into(),
+            chunk,
+        })
+    };
+    let args = |path: &str, chunk: Option<usize>| args_in(&proj, path, chunk);
+
+    // Not indexed: refused, as is any root the caller picks (e.g. "/")
+    let server = AugServer::new(cfg.clone());
+    let refused = server.read_project_file(args("a.txt", None)).await.unwrap();
+    assert!(text_of(refused).starts_with("Error: project not indexed"));
+    let refused = server
+        .read_project_file(args_in(td.path(), "secret.txt", None))
+        .await
+        .unwrap();
+    assert!(text_of(refused).starts_with("Error: project not indexed"));
+
+    let (key, _) = cfg.resolve_project(&proj.to_string_lossy()).unwrap();
+    let mut projects = augmcp::indexer::ProjectsIndex::default();
+    projects.0.insert(key, vec!["n".into()]);
+    projects.save(&cfg.projects_file()).unwrap();
+
+    let whole = server.read_project_file(args("a.txt", None)).await.unwrap();
+    assert_eq!(text_of(whole), body);
+    let second = server
+        .read_project_file(args("a.txt#chunk2of3", None))
+        .await
+        .unwrap();
+    assert_eq!(text_of(second), "l2\nl3\n");
+    let third = server
+        .read_project_file(args("a.txt", Some(3)))
+        .await
+        .unwrap();
+    assert_eq!(text_of(third), "l4\n");
+
+    let out_of_range = server
+        .read_project_file(args("a.txt", Some(4)))
+        .await
+        .unwrap();
+    assert!(text_of(out_of_range).starts_with("Error: chunk 4 out of range"));
+    let traversal = server
+        .read_project_file(args("../secret.txt", None))
+        .await
+        .unwrap();
+    assert!(text_of(traversal).starts_with("Error: file not found"));
+    let missing = server
+        .read_project_file(args("nope.txt", None))
+        .await
+        .unwrap();
+    assert!(text_of(missing).starts_with("Error: file not found"));
+    let excluded = server
+        .read_project_file(args("skip.log", None))
+        .await
+        .unwrap();
+    assert!(text_of(excluded).starts_with("Error: file not indexed"));
+
+    // Whole-file and chunk reads both return the filtered content that was indexed
+    let whole = text_of(server.read_project_file(args("a.env", None)).await.unwrap());
+    let chunk = text_of(
+        server
+            .read_project_file(args("a.env#chunk1of1", None))
+            .await
+            .unwrap(),
+    );
+    assert_eq!(whole, chunk);
+    assert!(!whole.contains("abc123"), "{whole}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn index_project_async_returns_task_id_and_reports_progress() {
+    let app = Router::new().route(
+        "/batch-upload",
+        post(|Json(p): Json<serde_json::Value>| async move {
+            // 慢速上传，保证首次查询时任务仍在运行
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+            let names: Vec<String> = p["blobs"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|b| format!("n:{}", b["path"].as_str().unwrap()))
+                .collect();
+            Json(serde_json::json!({ "blob_names": names }))
+        }),
+    );
+    let addr = common::serve(app).await;
+    let td = tempfile::tempdir().unwrap();
+    let proj = td.path().join("proj");
+    fs::create_dir_all(&proj).unwrap();
+    fs::write(proj.join("a.txt"), "a\n").unwrap();
+    let cfg = Config {
+        settings: Settings {
+            base_url: format!("http://{}", addr),
+            token: "t".into(),
+            ..Settings::default()
+        },
+        root_dir: td.path().join("cfg"),
+        data_dir: td.path().join("data"),
+        settings_path: td.path().join("cfg/settings.toml"),
+    };
+    let server = AugServer::new(cfg.clone());
+    let args = || {
+        Parameters(IndexArgs {
+            project_root_path: Some(proj.to_string_lossy().to_string()),
+            alias: None,
+            force_full: None,
+            project_key: None,
+        })
+    };
+
+    let started: serde_json::Value =
+        serde_json::from_str(&text_of(server.index_project_async(args()).await.unwrap())).unwrap();
+    assert_eq!(started["status"], "accepted");
+    let task_id = started["task_id"].as_str().unwrap().to_string();
+    // A second call while running reports the same task
+    let again: serde_json::Value =
+        serde_json::from_str(&text_of(server.index_project_async(args()).await.unwrap())).unwrap();
+    assert_eq!(again["task_id"], task_id.as_str());
+    assert!(
+        again["result"]
+            .as_str()
+            .unwrap()
+            .contains("already in progress")
+    );
+
+    let status = |id: String| {
+        let server = server.clone();
+        async move {
+            let res = server
+                .get_index_status(Parameters(IndexStatusArgs { task_id: id }))
+                .await
+                .unwrap();
+            text_of(res)
+        }
+    };
+    let mut phase = String::new();
+    for _ in 0..100 {
+        let v: serde_json::Value = serde_json::from_str(&status(task_id.clone()).await).unwrap();
+        phase = v["progress"]["phase"].as_str().unwrap().to_string();
+        if phase == "done" || phase == "failed" {
+            assert_eq!(v["running"], false);
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(phase, "done");
+    let key = augmcp::config::normalize_path(&proj).unwrap();
+    assert_eq!(augmcp::service::load_projects(&cfg).0[&key].len(), 1);
+
+    assert!(
+        status("nope".into())
+            .await
+            .starts_with("Error: unknown task_id")
+    );
+}
+
+#[tokio::test]
+async fn search_context_dedupe_by_file_merges_chunks_of_one_file() {
+    let app = Router::new()
+        .route(
+            "/batch-upload",
+            post(|Json(p): Json<serde_json::Value>| async move {
+                let names: Vec<String> = p["blobs"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|b| {
+                        augmcp::indexer::hash_blob_name(
+                            b["path"].as_str().unwrap(),
+                            b["content"].as_str().unwrap(),
+                        )
+                    })
+                    .collect();
+                Json(serde_json::json!({ "blob_names": names }))
+            }),
+        )
+        .route(
+            "/agents/codebase-retrieval",
+            post(|Json(p): Json<serde_json::Value>| async move {
+                let text = if p["information_request"] == "plain" {
+                    "no recognizable sections".to_string()
+                } else {
+                    [
+                        "The following code sections were retrieved:",
+                        "Path: a.txt#chunk2of2",
+                        "l2",
+                        "Path: b.txt",
+                        "b",
+                        "Path: a.txt#chunk1of2",
+                        "l0",
+                    ]
+                    .join("\n")
+                };
+                Json(serde_json::json!({ "formatted_retrieval": text }))
+            }),
+        );
+    let addr = common::serve(app).await;
+    let td = tempfile::tempdir().unwrap();
+    let proj = td.path().join("proj");
+    fs::create_dir_all(&proj).unwrap();
+    fs::write(proj.join("a.txt"), "l0\nl1\nl2\n").unwrap();
+    fs::write(proj.join("b.txt"), "b\n").unwrap();
+    let cfg = Config {
+        settings: Settings {
+            base_url: format!("http://{}", addr),
+            token: "t".into(),
+            max_lines_per_blob: 2,
+            ..Settings::default()
+        },
+        root_dir: td.path().join("cfg"),
+        data_dir: td.path().join("data"),
+        settings_path: td.path().join("cfg/settings.toml"),
+    };
+    let server = AugServer::new(cfg);
+    let search = |query: &str| {
+        let server = server.clone();
+        let args = SearchArgs {
+            project_root_path: Some(proj.to_string_lossy().to_string()),
+            alias: None,
+            skip_index_if_indexed: None,
+            quick: None,
+            query: Some(query.into()),
+            queries: None,
+            normalize_query: None,
+            prepend_context: None,
+            path_prefix: None,
+            languages: None,
+            project_key: None,
+            dedupe_by_file: Some(true),
+        };
+        async move { server.search_context(Parameters(args)).await.unwrap() }
+    };
+
+    let res = search("grouped").await;
+    let structured = res.structured_content.clone().unwrap();
+    assert_eq!(
+        text_of(res),
+        "The following code sections were retrieved:\n\
+         Path: a.txt\nl0\n...\nl2\n\n\
+         Path: b.txt\nb"
+    );
+    assert_eq!(structured["empty"], false);
+    assert_eq!(
+        structured["files"],
+        serde_json::json!([
+            { "path": "a.txt", "chunks": [1, 2], "snippets": ["l0", "l2"] },
+            { "path": "b.txt", "chunks": [], "snippets": ["b"] },
+        ])
+    );
+
+    // Unrecognized output is returned unchanged, without a files list
+    let res = search("plain").await;
+    assert!(
+        res.structured_content
+            .clone()
+            .unwrap()
+            .get("files")
+            .is_none()
+    );
+    assert_eq!(text_of(res), "no recognizable sections");
+}
+
+#[tokio::test]
+async fn search_context_with_only_a_query_uses_the_default_project() {
+    let app = Router::new()
+        .route(
+            "/batch-upload",
+            post(|Json(p): Json<serde_json::Value>| async move {
+                let names: Vec<String> = p["blobs"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|b| {
+                        augmcp::indexer::hash_blob_name(
+                            b["path"].as_str().unwrap(),
+                            b["content"].as_str().unwrap(),
+                        )
+                    })
+                    .collect();
+                Json(serde_json::json!({ "blob_names": names }))
+            }),
+        )
+        .route(
+            "/agents/codebase-retrieval",
+            post(|Json(p): Json<serde_json::Value>| async move {
+                let n = p["blobs"]["added_blobs"].as_array().unwrap().len();
+                Json(serde_json::json!({ "formatted_retrieval": format!("{n} blobs") }))
+            }),
+        );
+    let addr = common::serve(app).await;
+    let td = tempfile::tempdir().unwrap();
+    let proj = td.path().join("proj");
+    let other = td.path().join("other");
+    fs::create_dir_all(&proj).unwrap();
+    fs::create_dir_all(&other).unwrap();
+    fs::write(proj.join("a.txt"), "a\n").unwrap();
+    fs::write(proj.join("b.txt"), "b\n").unwrap();
+    fs::write(other.join("c.txt"), "c\n").unwrap();
+    let mut cfg = Config {
+        settings: Settings {
+            base_url: format!("http://{}", addr),
+            token: "t".into(),
+            ..Settings::default()
+        },
+        root_dir: td.path().join("cfg"),
+        data_dir: td.path().join("data"),
+        settings_path: td.path().join("cfg/settings.toml"),
+    };
+    let args = |path: Option<&std::path::Path>| SearchArgs {
+        project_root_path: path.map(|p| p.to_string_lossy().to_string()),
+        alias: None,
+        skip_index_if_indexed: None,
+        quick: None,
+        query: Some("q".into()),
+        queries: None,
+        normalize_query: None,
+        prepend_context: None,
+        path_prefix: None,
+        languages: None,
+        project_key: None,
+        dedupe_by_file: None,
+    };
+
+    // No default configured: the call is rejected
+    let server = AugServer::new(cfg.clone());
+    let res = server.search_context(Parameters(args(None))).await.unwrap();
+    assert_eq!(text_of(res), "Error: provide project_root_path or alias");
+
+    cfg.settings.default_project_path = Some(proj.to_string_lossy().to_string());
+    let server = AugServer::new(cfg.clone());
+    let res = server.search_context(Parameters(args(None))).await.unwrap();
+    assert_eq!(text_of(res), "2 blobs");
+    // An explicit path still wins over the default
+    let res = server
+        .search_context(Parameters(args(Some(&other))))
+        .await
+        .unwrap();
+    assert_eq!(text_of(res), "1 blobs");
+
+    // default_alias resolves through aliases.json and takes precedence
+    let mut aliases = augmcp::indexer::Aliases::default();
+    aliases.set(
+        "other".into(),
+        cfg.resolve_project_path(&other.to_string_lossy()).unwrap(),
+    );
+    aliases.save(&cfg.aliases_file()).unwrap();
+    cfg.settings.default_alias = Some("other".into());
+    let server = AugServer::new(cfg);
+    let res = server.search_context(Parameters(args(None))).await.unwrap();
+    assert_eq!(text_of(res), "1 blobs");
+}
+
+#[test]
+fn search_args_schema_lists_language_values() {
+    let schema =
+        serde_json::Value::Object(rmcp::handler::server::common::schema_for_type::<SearchArgs>());
+    let items = &schema["properties"]["languages"]["items"];
+    // Enum definitions may be inlined or referenced from `definitions`
+    let def = match items["$ref"].as_str() {
+        Some(r) => &schema["definitions"][r.rsplit('/').next().unwrap()],
+        None => items,
+    };
+    let values: Vec<&str> = def["enum"]
+        .as_array()
+        .unwrap_or_else(|| panic!("no enum in {def}"))
+        .iter()
+        .map(|v| v.as_str().unwrap())
+        .collect();
+    assert_eq!(
+        values,
+        Language::ALL.iter().map(|l| l.as_str()).collect::<Vec<_>>()
+    );
+    assert!(values.contains(&"rust") && values.contains(&"csharp"));
+    assert!(def["description"].as_str().is_some_and(|d| !d.is_empty()));
+
+    // Values round-trip; parsing stays case-insensitive and rejects unknown names
+    let args: SearchArgs =
+        serde_json::from_value(serde_json::json!({ "query": "q", "languages": ["Rust", "cpp"] }))
+            .unwrap();
+    assert_eq!(args.languages, Some(vec![Language::Rust, Language::Cpp]));
+    assert_eq!(
+        serde_json::to_value(&args).unwrap()["languages"],
+        serde_json::json!(["rust", "cpp"])
+    );
+    let err = serde_json::from_value::<SearchArgs>(
+        serde_json::json!({ "query": "q", "languages": ["cobol"] }),
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("unknown language"), "{err}");
+}
+
+#[tokio::test]
+async fn project_backends_send_each_projects_token() {
+    use axum::http::HeaderMap;
+    use std::sync::{Arc, Mutex};
+    // (route, bearer token, first blob path or query)
+    let seen: Arc<Mutex<Vec<(String, String, String)>>> = Arc::default();
+    let bearer = |h: &HeaderMap| {
+        h.get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .trim_start_matches("Bearer ")
+            .to_string()
+    };
+    let (up, ret) = (seen.clone(), seen.clone());
+    let app = Router::new()
+        .route(
+            "/batch-upload",
+            post(move |h: HeaderMap, Json(p): Json<serde_json::Value>| {
+                let up = up.clone();
+                async move {
+                    let blobs = p["blobs"].as_array().unwrap();
+                    up.lock().unwrap().push((
+                        "upload".into(),
+                        bearer(&h),
+                        blobs[0]["path"].as_str().unwrap().into(),
+                    ));
+                    let names: Vec<String> = blobs
+                        .iter()
+                        .map(|b| {
+                            augmcp::indexer::hash_blob_name(
+                                b["path"].as_str().unwrap(),
+                                b["content"].as_str().unwrap(),
+                            )
+                        })
+                        .collect();
+                    Json(serde_json::json!({ "blob_names": names }))
+                }
+            }),
+        )
+        .route(
+            "/agents/codebase-retrieval",
+            post(move |h: HeaderMap, Json(p): Json<serde_json::Value>| {
+                let ret = ret.clone();
+                async move {
+                    ret.lock().unwrap().push((
+                        "retrieval".into(),
+                        bearer(&h),
+                        p["information_request"].as_str().unwrap().into(),
+                    ));
+                    Json(serde_json::json!({ "formatted_retrieval": "ok" }))
+                }
+            }),
+        );
+    let addr = common::serve(app).await;
+    let td = tempfile::tempdir().unwrap();
+    for name in ["a", "b", "c"] {
+        let dir = td.path().join(name);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(format!("{name}.txt")), format!("{name}\n")).unwrap();
+    }
+    let path_of = |name: &str| td.path().join(name).to_string_lossy().to_string();
+    let mut cfg = Config {
+        settings: Settings {
+            base_url: format!("http://{}", addr),
+            token: "global".into(),
+            ..Settings::default()
+        },
+        root_dir: td.path().join("cfg"),
+        data_dir: td.path().join("data"),
+        settings_path: td.path().join("cfg/settings.toml"),
+    };
+    // a is matched through its alias, b by path; c has no entry and keeps the global token
+    let backend = |token: &str| augmcp::config::ProjectBackend {
+        base_url: None,
+        token: Some(token.into()),
+    };
+    cfg.settings
+        .project_backends
+        .insert("tenant-a".into(), backend("token-a"));
+    cfg.settings
+        .project_backends
+        .insert(path_of("b"), backend("token-b"));
+    let server = AugServer::new(cfg);
+    let args = |alias: Option<&str>, path: Option<String>, query: &str| SearchArgs {
+        project_root_path: path,
+        alias: alias.map(String::from),
+        skip_index_if_indexed: None,
+        quick: None,
+        query: Some(query.into()),
+        queries: None,
+        normalize_query: None,
+        prepend_context: None,
+        path_prefix: None,
+        languages: None,
+        project_key: None,
+        dedupe_by_file: None,
+    };
+
+    for (alias, path, query) in [
+        (Some("tenant-a"), Some(path_of("a")), "qa"),
+        (None, Some(path_of("b")), "qb"),
+        (None, Some(path_of("c")), "qc"),
+        (Some("tenant-a"), None, "qa2"),
+    ] {
+        let res = server
+            .search_context(Parameters(args(alias, path, query)))
+            .await
+            .unwrap();
+        assert_eq!(text_of(res), "ok");
+    }
+    let seen = seen.lock().unwrap().clone();
+    let expect = |route: &str, token: &str, what: &str| (route.into(), token.into(), what.into());
+    assert_eq!(
+        seen,
+        vec![
+            expect("upload", "token-a", "a.txt"),
+            expect("retrieval", "token-a", "qa"),
+            expect("upload", "token-b", "b.txt"),
+            expect("retrieval", "token-b", "qb"),
+            expect("upload", "global", "c.txt"),
+            expect("retrieval", "global", "qc"),
+            expect("retrieval", "token-a", "qa2"),
+        ]
+    );
+}
@@ -1,12 +1,12 @@
+mod common;
+
 use augmcp::{
-    backend,
     config::{Config, Settings},
-    service,
+    service::{self, RetrievalLimiter},
 };
 use axum::{Json, Router, routing::post};
 use serde::{Deserialize, Serialize};
-use std::{fs, net::SocketAddr, path::Path, sync::Arc};
-use tokio::net::TcpListener;
+use std::{fs, net::SocketAddr, path::Path};
 
 #[derive(Deserialize)]
 struct UploadPayload {
@@ -18,6 +18,7 @@ struct UploadResp {
 }
 #[derive(Deserialize)]
 struct RetrievalPayload {
+    #[allow(dead_code)]
     information_request: String,
 }
 #[derive(Serialize)]
@@ -25,7 +26,7 @@ struct RetrievalResp {
     formatted_retrieval: String,
 }
 
-async fn start_stub_server() -> (SocketAddr, tokio::task::JoinHandle<()>) {
+async fn start_stub_server() -> SocketAddr {
     let app = Router::new()
         .route(
             "/batch-upload",
@@ -46,12 +47,7 @@ async fn start_stub_server() -> (SocketAddr, tokio::task::JoinHandle<()>) {
                 })
             }),
         );
-    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
-    let addr = listener.local_addr().unwrap();
-    let handle = tokio::spawn(async move {
-        let _ = axum::serve(listener, app).await;
-    });
-    (addr, handle)
+    common::serve(app).await
 }
 
 fn cfg_with_base(base_url: String, data_root: &Path) -> Config {
@@ -70,6 +66,7 @@ fn cfg_with_base(base_url: String, data_root: &Path) -> Config {
             max_output_length: 0,
             disable_codebase_retrieval: false,
             enable_commit_retrieval: false,
+            ..Settings::default()
         },
         root_dir: root_dir.clone(),
         data_dir: data_dir.clone(),
@@ -79,7 +76,7 @@ fn cfg_with_base(base_url: String, data_root: &Path) -> Config {
 
 #[tokio::test(flavor = "multi_thread")]
 async fn persist_and_incremental_and_concurrent() {
-    let (addr, _h) = start_stub_server().await;
+    let addr = start_stub_server().await;
     let base_url = format!("http://{}:{}", addr.ip(), addr.port());
     let td = tempfile::tempdir().unwrap();
     let cfg = cfg_with_base(base_url, td.path());
@@ -106,14 +103,947 @@ async fn persist_and_incremental_and_concurrent() {
     );
     let ra = ra.unwrap();
     let rb = rb.unwrap();
-    assert!(ra.0 >= 1 && ra.1 >= 1);
-    assert!(rb.0 >= 1 && rb.1 >= 1);
+    assert!(ra.total_blobs >= 1 && ra.new_blobs >= 1);
+    assert!(rb.total_blobs >= 1 && rb.new_blobs >= 1);
 
     // Second index on A with no changes -> new=0
-    let (t, newn, existing, _all) = service::index_and_persist(&cfg, &key_a, &pa, false)
+    let report = service::index_and_persist(&cfg, &key_a, &pa, false)
+        .await
+        .unwrap();
+    assert!(report.total_blobs >= 1);
+    assert_eq!(report.new_blobs, 0, "No changes should yield 0 new blobs");
+    assert!(report.existing_blobs >= 1);
+    assert_eq!(report.uploaded_bytes, 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn manifest_is_trusted_only_with_matching_fingerprint() {
+    let addr = start_stub_server().await;
+    let base_url = format!("http://{}:{}", addr.ip(), addr.port());
+    let td = tempfile::tempdir().unwrap();
+    let proj = td.path().join("proj");
+    fs::create_dir_all(&proj).unwrap();
+    fs::write(proj.join("a.txt"), "A\n").unwrap();
+    let key = augmcp::config::normalize_path(&proj).unwrap();
+    let path = proj.to_string_lossy().to_string();
+
+    // Exporting machine
+    let cfg = cfg_with_base(base_url.clone(), &td.path().join("m1"));
+    let out = service::export_manifest(&cfg, &key, &path).await.unwrap();
+    assert!(out.ends_with(".augmcp/manifest.json"));
+
+    // Teammate with identical settings: nothing to upload, manifest itself not indexed
+    let cfg2 = cfg_with_base(base_url.clone(), &td.path().join("m2"));
+    let report = service::index_and_persist(&cfg2, &key, &path, false)
+        .await
+        .unwrap();
+    assert_eq!(report.total_blobs, 1);
+    assert_eq!(report.new_blobs, 0);
+
+    // Different settings fingerprint: manifest ignored
+    let mut cfg3 = cfg_with_base(base_url, &td.path().join("m3"));
+    cfg3.settings.max_lines_per_blob = 500;
+    let report = service::index_and_persist(&cfg3, &key, &path, false)
+        .await
+        .unwrap();
+    assert_eq!(report.new_blobs, 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn quick_index_matches_full_index_after_changes() {
+    let addr = start_stub_server().await;
+    let base_url = format!("http://{}:{}", addr.ip(), addr.port());
+    let td = tempfile::tempdir().unwrap();
+    let cfg = cfg_with_base(base_url.clone(), &td.path().join("m1"));
+    let proj = td.path().join("proj");
+    fs::create_dir_all(&proj).unwrap();
+    fs::write(proj.join("keep.txt"), "keep\n").unwrap();
+    fs::write(proj.join("edit.txt"), "v1\n").unwrap();
+    fs::write(proj.join("gone.txt"), "bye\n").unwrap();
+    let key = augmcp::config::normalize_path(&proj).unwrap();
+    let path = proj.to_string_lossy().to_string();
+
+    service::index_and_persist(&cfg, &key, &path, false)
+        .await
+        .unwrap();
+    // No changes: cached names come back as-is
+    let unchanged = service::quick_index(&cfg, &key, &path).await.unwrap();
+    assert_eq!(unchanged.len(), 3);
+
+    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    fs::write(proj.join("edit.txt"), "v2\n").unwrap();
+    fs::write(proj.join("new.txt"), "new\n").unwrap();
+    fs::remove_file(proj.join("gone.txt")).unwrap();
+
+    let mut quick = service::quick_index(&cfg, &key, &path).await.unwrap();
+    let cfg_full = cfg_with_base(base_url, &td.path().join("m2"));
+    let mut full = service::index_and_persist(&cfg_full, &key, &path, false)
+        .await
+        .unwrap()
+        .all_blob_names;
+    quick.sort();
+    full.sort();
+    assert_eq!(quick, full);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn renamed_file_is_reported_as_moved_with_detect_moves() {
+    let addr = start_stub_server().await;
+    let base_url = format!("http://{}:{}", addr.ip(), addr.port());
+    let td = tempfile::tempdir().unwrap();
+    let mut cfg = cfg_with_base(base_url, &td.path().join("m1"));
+    cfg.settings.detect_moves = true;
+    let proj = td.path().join("proj");
+    fs::create_dir_all(&proj).unwrap();
+    fs::write(proj.join("old.txt"), "same content\n").unwrap();
+    fs::write(proj.join("other.txt"), "other\n").unwrap();
+    let key = augmcp::config::normalize_path(&proj).unwrap();
+    let path = proj.to_string_lossy().to_string();
+
+    service::index_and_persist(&cfg, &key, &path, false)
+        .await
+        .unwrap();
+    fs::rename(proj.join("old.txt"), proj.join("new.txt")).unwrap();
+
+    let report = service::index_and_persist(&cfg, &key, &path, false)
+        .await
+        .unwrap();
+    assert_eq!(report.moved_blobs, 1);
+    assert_eq!(report.new_blobs, 0);
+    assert_eq!(report.existing_blobs, 1);
+    assert!(report.summary().contains("moved_blobs=1"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn diff_index_reports_added_and_removed_without_persisting() {
+    let addr = start_stub_server().await;
+    let base_url = format!("http://{}:{}", addr.ip(), addr.port());
+    let td = tempfile::tempdir().unwrap();
+    let cfg = cfg_with_base(base_url, &td.path().join("m1"));
+    let proj = td.path().join("proj");
+    fs::create_dir_all(&proj).unwrap();
+    fs::write(proj.join("keep.txt"), "keep\n").unwrap();
+    fs::write(proj.join("edit.txt"), "v1\n").unwrap();
+    fs::write(proj.join("gone.txt"), "bye\n").unwrap();
+    let key = augmcp::config::normalize_path(&proj).unwrap();
+    let path = proj.to_string_lossy().to_string();
+    service::index_and_persist(&cfg, &key, &path, false)
+        .await
+        .unwrap();
+
+    fs::write(proj.join("edit.txt"), "v2\n").unwrap();
+    fs::write(proj.join("new.txt"), "new\n").unwrap();
+    fs::remove_file(proj.join("gone.txt")).unwrap();
+    let before = fs::read_to_string(cfg.projects_file()).unwrap();
+
+    let diff = service::diff_index(&cfg, &key, &path).unwrap();
+    assert_eq!(diff.added, vec!["edit.txt", "new.txt"]);
+    assert_eq!(diff.removed, vec!["edit.txt", "gone.txt"]);
+    assert_eq!(diff.unchanged_count, 1);
+    assert_eq!(fs::read_to_string(cfg.projects_file()).unwrap(), before);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn empty_project_is_remembered_until_ttl() {
+    let addr = start_stub_server().await;
+    let base_url = format!("http://{}:{}", addr.ip(), addr.port());
+    let td = tempfile::tempdir().unwrap();
+    let mut cfg = cfg_with_base(base_url, &td.path().join("m1"));
+    let proj = td.path().join("proj");
+    fs::create_dir_all(&proj).unwrap();
+    fs::write(proj.join("image.bin"), "not indexable").unwrap();
+    let key = augmcp::config::normalize_path(&proj).unwrap();
+    let path = proj.to_string_lossy().to_string();
+
+    let err = service::ensure_indexed(&cfg, &key, &path, true)
+        .await
+        .unwrap_err();
+    assert_eq!(err.to_string(), "project has no indexable files");
+
+    // Within the TTL the walk is skipped, even though a file appeared
+    fs::write(proj.join("a.txt"), "A\n").unwrap();
+    let err = service::ensure_indexed(&cfg, &key, &path, true)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("checked"), "{}", err);
+
+    // TTL expired: the project is walked again
+    cfg.settings.empty_index_ttl_secs = 0;
+    let names = service::ensure_indexed(&cfg, &key, &path, true)
+        .await
+        .unwrap();
+    assert_eq!(names.len(), 1);
+}
+
+#[test]
+fn reset_local_state_clears_data_and_optionally_keeps_aliases() {
+    let td = tempfile::tempdir().unwrap();
+    let cfg = cfg_with_base("http://127.0.0.1:1".into(), td.path());
+    fs::write(cfg.projects_file(), "{}").unwrap();
+    fs::write(cfg.index_meta_file(), "{}").unwrap();
+    fs::write(cfg.project_meta_file(), "{}").unwrap();
+    fs::write(cfg.aliases_file(), "{}").unwrap();
+    fs::write(&cfg.settings_path, "").unwrap();
+    let history = cfg.history_file("some-project");
+    fs::create_dir_all(history.parent().unwrap()).unwrap();
+    fs::write(&history, "{}\n").unwrap();
+
+    let cleared = service::reset_local_state(&cfg, true).unwrap();
+    assert_eq!(
+        cleared,
+        vec![
+            cfg.projects_file(),
+            cfg.index_meta_file(),
+            cfg.project_meta_file(),
+            cfg.history_dir()
+        ]
+    );
+    assert!(!cfg.history_dir().exists());
+    assert!(cfg.aliases_file().exists() && cfg.settings_path.exists());
+
+    let cleared = service::reset_local_state(&cfg, false).unwrap();
+    assert_eq!(cleared, vec![cfg.aliases_file()]);
+    assert!(cfg.settings_path.exists());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn enabling_hashed_keys_migrates_existing_index() {
+    let addr = start_stub_server().await;
+    let base_url = format!("http://{}:{}", addr.ip(), addr.port());
+    let td = tempfile::tempdir().unwrap();
+    let mut cfg = cfg_with_base(base_url, &td.path().join("m1"));
+    let proj = td.path().join("proj");
+    fs::create_dir_all(&proj).unwrap();
+    fs::write(proj.join("a.txt"), "A\n").unwrap();
+    let path = proj.to_string_lossy().to_string();
+
+    let (key, abs) = service::resolve_target(&cfg, Some("p".into()), Some(path)).unwrap();
+    let names = service::ensure_indexed(&cfg, &key, &abs, true)
         .await
         .unwrap();
-    assert!(t >= 1);
-    assert_eq!(newn, 0, "No changes should yield 0 new blobs");
-    assert!(existing >= 1);
+
+    cfg.settings.hash_project_keys = true;
+    assert_eq!(service::migrate_project_keys(&cfg).unwrap(), 1);
+    assert_eq!(service::migrate_project_keys(&cfg).unwrap(), 0);
+    let stored = fs::read_to_string(cfg.projects_file()).unwrap();
+    assert!(
+        !stored.contains(&abs),
+        "plaintext path persisted: {}",
+        stored
+    );
+
+    // Alias still resolves to the real path; the stored index is found under the hashed key
+    let (hkey, hpath) = service::resolve_target(&cfg, Some("p".into()), None).unwrap();
+    assert_eq!(hpath, abs);
+    assert_ne!(hkey, key);
+    let again = service::ensure_indexed(&cfg, &hkey, &hpath, true)
+        .await
+        .unwrap();
+    assert_eq!(again, names);
+    assert_eq!(service::known_projects(&cfg), vec![(hkey, abs)]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn retrieval_sends_only_delta_since_checkpoint() {
+    use std::sync::{Arc, Mutex};
+    let seen: Arc<Mutex<Vec<serde_json::Value>>> = Arc::new(Mutex::new(vec![]));
+    let seen_bg = seen.clone();
+    let app = Router::new()
+        .route(
+            "/batch-upload",
+            post(|Json(p): Json<UploadPayload>| async move {
+                let names = p
+                    .blobs
+                    .into_iter()
+                    .map(|b| format!("n:{}", b.path))
+                    .collect();
+                Json(UploadResp { blob_names: names })
+            }),
+        )
+        .route(
+            "/agents/codebase-retrieval",
+            post(move |Json(p): Json<serde_json::Value>| {
+                let seen = seen_bg.clone();
+                async move {
+                    let mut seen = seen.lock().unwrap();
+                    seen.push(p["blobs"].clone());
+                    Json(serde_json::json!({
+                        "formatted_retrieval": "OK",
+                        "checkpoint_id": format!("cp{}", seen.len()),
+                    }))
+                }
+            }),
+        );
+    let addr = common::serve(app).await;
+    let td = tempfile::tempdir().unwrap();
+    let cfg = cfg_with_base(format!("http://{}", addr), td.path());
+    let proj = td.path().join("proj");
+    fs::create_dir_all(&proj).unwrap();
+    fs::write(proj.join("a.txt"), "A\n").unwrap();
+    fs::write(proj.join("b.txt"), "B\n").unwrap();
+    let key = augmcp::config::normalize_path(&proj).unwrap();
+    let path = proj.to_string_lossy().to_string();
+
+    for _ in 0..2 {
+        service::ensure_index_then_retrieve(
+            &cfg,
+            &RetrievalLimiter::default(),
+            &key,
+            &path,
+            "q",
+            true,
+        )
+        .await
+        .unwrap();
+    }
+    fs::remove_file(proj.join("b.txt")).unwrap();
+    service::ensure_index_then_retrieve(
+        &cfg,
+        &RetrievalLimiter::default(),
+        &key,
+        &path,
+        "q",
+        false,
+    )
+    .await
+    .unwrap();
+
+    let seen = seen.lock().unwrap();
+    let len = |v: &serde_json::Value, k: &str| v[k].as_array().unwrap().len();
+    // First call: no checkpoint yet -> full send
+    assert!(seen[0]["checkpoint_id"].is_null());
+    assert_eq!(len(&seen[0], "added_blobs"), 2);
+    // No changes: nothing added or deleted
+    assert_eq!(seen[1]["checkpoint_id"], "cp1");
+    assert_eq!(len(&seen[1], "added_blobs"), 0);
+    assert_eq!(len(&seen[1], "deleted_blobs"), 0);
+    // Removed file shows up as a deleted blob
+    assert_eq!(seen[2]["checkpoint_id"], "cp2");
+    assert_eq!(len(&seen[2], "added_blobs"), 0);
+    assert_eq!(len(&seen[2], "deleted_blobs"), 1);
+}
+
+#[test]
+fn same_alias_in_different_namespaces_resolves_independently() {
+    let td = tempfile::tempdir().unwrap();
+    let team_a = td.path().join("a");
+    let team_b = td.path().join("b");
+    fs::create_dir_all(&team_a).unwrap();
+    fs::create_dir_all(&team_b).unwrap();
+    let norm_a = augmcp::config::normalize_path(&team_a).unwrap();
+    let norm_b = augmcp::config::normalize_path(&team_b).unwrap();
+    // Two users sharing one aliases.json with different default namespaces
+    let cfg_ns = |ns: &str| {
+        let mut cfg = cfg_with_base("http://127.0.0.1:1".into(), td.path());
+        cfg.settings.alias_namespace = Some(ns.into());
+        cfg
+    };
+    let (cfg_a, cfg_b) = (cfg_ns("alice"), cfg_ns("bob"));
+    let bind = |cfg: &Config, p: &Path| {
+        service::resolve_target(
+            cfg,
+            Some("backend".into()),
+            Some(p.to_string_lossy().into()),
+        )
+        .unwrap()
+    };
+    bind(&cfg_a, &team_a);
+    bind(&cfg_b, &team_b);
+
+    let lookup = |cfg: &Config, alias: &str| service::resolve_target(cfg, Some(alias.into()), None);
+    assert_eq!(lookup(&cfg_a, "backend").unwrap().1, norm_a);
+    assert_eq!(lookup(&cfg_b, "backend").unwrap().1, norm_b);
+    // Qualified aliases bypass the default namespace
+    assert_eq!(lookup(&cfg_a, "bob:backend").unwrap().1, norm_b);
+    // Without a namespace the bare alias was never bound
+    let plain = cfg_with_base("http://127.0.0.1:1".into(), td.path());
+    assert!(lookup(&plain, "backend").is_err());
+    assert_eq!(cfg_a.qualify_alias("backend", Some("bob")), "bob:backend");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn search_falls_back_to_cache_when_project_dir_moved() {
+    let addr = start_stub_server().await;
+    let td = tempfile::tempdir().unwrap();
+    let cfg = cfg_with_base(format!("http://{}", addr), td.path());
+    let proj = td.path().join("proj");
+    fs::create_dir_all(&proj).unwrap();
+    fs::write(proj.join("a.txt"), "A\n").unwrap();
+    let path = proj.to_string_lossy().to_string();
+    let (key, path) = service::resolve_target(&cfg, Some("p".into()), Some(path)).unwrap();
+    let report = service::index_and_persist(&cfg, &key, &path, false)
+        .await
+        .unwrap();
+
+    fs::rename(&proj, td.path().join("moved")).unwrap();
+    // Searches still resolve the alias or old path to the cached key; binding does not
+    for (alias, p) in [(Some("p".to_string()), None), (None, Some(path.clone()))] {
+        let (key2, path2, _) = service::resolve_search_target(&cfg, alias, p, None).unwrap();
+        assert_eq!(
+            (key2.as_str(), path2.as_str()),
+            (key.as_str(), path.as_str())
+        );
+    }
+    assert!(service::resolve_target(&cfg, Some("p".into()), None).is_err());
+    assert!(service::bind_alias(&cfg, "q", &path, false).is_err());
+    assert!(service::resolve_target(&cfg, Some("q".into()), Some(path.clone())).is_err());
+    let aliases = augmcp::indexer::Aliases::load(&cfg.aliases_file()).unwrap();
+    assert!(aliases.resolve(&cfg.qualify_alias("q", None)).is_none());
+    let names = service::ensure_indexed(&cfg, &key, &path, false)
+        .await
+        .unwrap();
+    assert_eq!(names, report.all_blob_names);
+    let out = service::ensure_index_then_retrieve(
+        &cfg,
+        &RetrievalLimiter::default(),
+        &key,
+        &path,
+        "q",
+        false,
+    )
+    .await
+    .unwrap();
+    assert_eq!(out, "OK");
+
+    // Nothing cached: the missing directory is still an error
+    let gone = td.path().join("never").to_string_lossy().to_string();
+    assert!(service::resolve_search_target(&cfg, None, Some(gone), None).is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn index_webhook_is_signed_and_failures_do_not_fail_index() {
+    use std::sync::{Arc, Mutex};
+    let addr = start_stub_server().await;
+    type Received = Vec<(String, Vec<u8>)>;
+    let got: Arc<Mutex<Received>> = Arc::new(Mutex::new(vec![]));
+    let got_bg = got.clone();
+    let hook = Router::new().route(
+        "/hook",
+        post(
+            move |headers: axum::http::HeaderMap, body: axum::body::Bytes| {
+                let got = got_bg.clone();
+                async move {
+                    let sig = headers[augmcp::backend::WEBHOOK_SIGNATURE_HEADER]
+                        .to_str()
+                        .unwrap()
+                        .to_string();
+                    got.lock().unwrap().push((sig, body.to_vec()));
+                }
+            },
+        ),
+    );
+    let hook_addr = common::serve(hook).await;
+
+    let td = tempfile::tempdir().unwrap();
+    let mut cfg = cfg_with_base(format!("http://{}", addr), td.path());
+    cfg.settings.index_webhook_url = Some(format!("http://{}/hook", hook_addr));
+    let proj = td.path().join("proj");
+    fs::create_dir_all(&proj).unwrap();
+    fs::write(proj.join("a.txt"), "A\n").unwrap();
+    let (key, path) = cfg.resolve_project(&proj.to_string_lossy()).unwrap();
+    service::index_and_persist(&cfg, &key, &path, false)
+        .await
+        .unwrap();
+
+    let got = got.lock().unwrap().clone();
+    assert_eq!(got.len(), 1);
+    let (sig, body) = &got[0];
+    assert_eq!(*sig, augmcp::backend::webhook_signature("T", body));
+    let v: serde_json::Value = serde_json::from_slice(body).unwrap();
+    assert_eq!(v["project_key"], key.as_str());
+    assert_eq!(v["path"], path.as_str());
+    assert_eq!(v["total_blobs"], 1);
+    assert_eq!(v["new_blobs"], 1);
+    assert!(v["duration_ms"].is_u64());
+
+    // Backend-only TLS settings do not apply to the webhook client
+    let mut hook_cfg = cfg.clone();
+    hook_cfg.settings.root_ca_cert_path = Some(td.path().join("missing.pem").display().to_string());
+    augmcp::backend::post_webhook(
+        &hook_cfg,
+        &format!("http://{}/hook", hook_addr),
+        b"{}".to_vec(),
+    )
+    .await
+    .unwrap();
+
+    // Unreachable receiver: logged, index still succeeds
+    cfg.settings.index_webhook_url = Some("http://127.0.0.1:1/hook".into());
+    service::index_and_persist(&cfg, &key, &path, true)
+        .await
+        .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn generated_files_are_skipped_and_counted() {
+    let addr = start_stub_server().await;
+    let td = tempfile::tempdir().unwrap();
+    let mut cfg = cfg_with_base(format!("http://{}", addr), td.path());
+    cfg.settings.text_extensions = vec![".rs".into()];
+    cfg.settings.exclude_patterns = vec!["vendor".into()];
+    cfg.settings.generated_patterns = vec!["*_generated.rs".into()];
+    let proj = td.path().join("proj");
+    fs::create_dir_all(proj.join("src")).unwrap();
+    fs::create_dir_all(proj.join("vendor")).unwrap();
+    fs::write(proj.join("src/lib.rs"), "pub fn a() {}\n").unwrap();
+    fs::write(proj.join("src/api_generated.rs"), "pub fn g() {}\n").unwrap();
+    fs::write(proj.join("schema_generated.rs"), "pub fn s() {}\n").unwrap();
+    // Regular excludes are not counted as generated
+    fs::write(proj.join("vendor/dep_generated.rs"), "pub fn v() {}\n").unwrap();
+    let (key, path) = cfg.resolve_project(&proj.to_string_lossy()).unwrap();
+
+    let report = service::index_and_persist(&cfg, &key, &path, false)
+        .await
+        .unwrap();
+    assert_eq!(report.total_blobs, 1);
+    assert_eq!(report.generated_skipped, 2);
+    assert!(report.summary().ends_with(", generated_skipped=2"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn path_prefix_narrows_blobs_to_a_subdirectory() {
+    let addr = start_stub_server().await;
+    let td = tempfile::tempdir().unwrap();
+    let mut cfg = cfg_with_base(format!("http://{}", addr), td.path());
+    cfg.settings.max_lines_per_blob = 1;
+    let proj = td.path().join("proj");
+    fs::create_dir_all(proj.join("services/auth")).unwrap();
+    fs::create_dir_all(proj.join("services/billing")).unwrap();
+    fs::write(proj.join("services/auth/a.txt"), "a1\na2\n").unwrap();
+    fs::write(proj.join("services/billing/b.txt"), "b\n").unwrap();
+    fs::write(proj.join("top.txt"), "t\n").unwrap();
+    let (key, path) = cfg.resolve_project(&proj.to_string_lossy()).unwrap();
+    let names = service::ensure_indexed(&cfg, &key, &path, true)
+        .await
+        .unwrap();
+    assert_eq!(names.len(), 4);
+
+    let auth = service::filter_blobs_by_prefix(&cfg, &key, &names, "services/auth/").unwrap();
+    assert_eq!(auth.len(), 2, "both chunks of a.txt");
+    assert!(auth.iter().all(|n| names.contains(n)));
+    let services = service::filter_blobs_by_prefix(&cfg, &key, &names, "./services").unwrap();
+    assert_eq!(services.len(), 3);
+    assert_eq!(
+        service::filter_blobs_by_prefix(&cfg, &key, &names, "").unwrap(),
+        names
+    );
+    let err = service::filter_blobs_by_prefix(&cfg, &key, &names, "docs/").unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("no indexed files match path_prefix 'docs/'")
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn explicit_project_key_shares_cache_across_mount_points() {
+    let addr = start_stub_server().await;
+    let td = tempfile::tempdir().unwrap();
+    let cfg = cfg_with_base(format!("http://{}", addr), td.path());
+    // Same code mounted at two paths, e.g. /workspace and /app
+    for mount in ["workspace", "app"] {
+        let dir = td.path().join(mount);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("main.txt"), "fn main\n").unwrap();
+    }
+    let mount = |m: &str| Some(td.path().join(m).to_string_lossy().to_string());
+
+    let (key, path) =
+        service::resolve_target_keyed(&cfg, None, mount("workspace"), Some(" myorg/api ")).unwrap();
+    assert_eq!(key, "myorg/api");
+    let first = service::index_and_persist(&cfg, &key, &path, false)
+        .await
+        .unwrap();
+    assert_eq!(first.new_blobs, 1);
+
+    let (key2, path2) =
+        service::resolve_target_keyed(&cfg, None, mount("app"), Some("myorg/api")).unwrap();
+    assert_eq!(key2, key);
+    assert_ne!(path2, path);
+    let second = service::index_and_persist(&cfg, &key2, &path2, false)
+        .await
+        .unwrap();
+    assert_eq!(
+        second.new_blobs, 0,
+        "cache is shared under the explicit key"
+    );
+    assert!(service::load_projects(&cfg).0.contains_key("myorg/api"));
+
+    for bad in ["", "  ", "/abs", "a:b", "a/../b", "a b", "sha256:x"] {
+        assert!(
+            service::resolve_target_keyed(&cfg, None, mount("app"), Some(bad)).is_err(),
+            "{bad:?} should be rejected"
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pinned_paths_are_sent_with_every_retrieval() {
+    use std::sync::{Arc, Mutex};
+    let seen: Arc<Mutex<Vec<serde_json::Value>>> = Arc::new(Mutex::new(vec![]));
+    let seen_bg = seen.clone();
+    let app = Router::new()
+        .route(
+            "/batch-upload",
+            post(|Json(p): Json<UploadPayload>| async move {
+                let names = p
+                    .blobs
+                    .into_iter()
+                    .map(|b| format!("n:{}", b.path))
+                    .collect();
+                Json(UploadResp { blob_names: names })
+            }),
+        )
+        .route(
+            "/agents/codebase-retrieval",
+            post(move |Json(p): Json<serde_json::Value>| {
+                let seen = seen_bg.clone();
+                async move {
+                    let mut seen = seen.lock().unwrap();
+                    seen.push(p["blobs"].clone());
+                    Json(serde_json::json!({
+                        "formatted_retrieval": "OK",
+                        "checkpoint_id": format!("cp{}", seen.len()),
+                    }))
+                }
+            }),
+        );
+    let addr = common::serve(app).await;
+    let td = tempfile::tempdir().unwrap();
+    let mut cfg = cfg_with_base(format!("http://{}", addr), td.path());
+    cfg.settings.pinned_paths = vec!["docs/arch.txt".into(), "missing.txt".into()];
+    let proj = td.path().join("proj");
+    fs::create_dir_all(proj.join("docs")).unwrap();
+    fs::create_dir_all(proj.join("src")).unwrap();
+    fs::write(proj.join("docs/arch.txt"), "layers\n").unwrap();
+    fs::write(proj.join("src/a.txt"), "A\n").unwrap();
+    let (key, path) = cfg.resolve_project(&proj.to_string_lossy()).unwrap();
+    let names = service::ensure_indexed(&cfg, &key, &path, true)
+        .await
+        .unwrap();
+    let pinned = service::pinned_blob_names(&cfg, &key);
+    assert_eq!(pinned.len(), 1, "unindexed pinned paths are skipped");
+
+    let scoped = service::filter_blobs_by_prefix(&cfg, &key, &names, "src/").unwrap();
+    assert!(!scoped.contains(&pinned[0]));
+    for _ in 0..2 {
+        service::retrieve(&cfg, &key, &scoped, "q").await.unwrap();
+    }
+    service::retrieve_many(
+        &cfg,
+        &RetrievalLimiter::default(),
+        &key,
+        scoped.clone(),
+        vec!["q".into()],
+    )
+    .await;
+
+    let seen = seen.lock().unwrap();
+    assert_eq!(seen.len(), 3);
+    for blobs in seen.iter() {
+        let added: Vec<&str> = blobs["added_blobs"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| v.as_str().unwrap())
+            .collect();
+        assert!(added.contains(&pinned[0].as_str()), "{blobs}");
+        assert!(blobs["deleted_blobs"].as_array().unwrap().is_empty());
+    }
+    // The checkpointed call carries only the pinned blob
+    assert_eq!(seen[1]["checkpoint_id"], "cp1");
+    assert_eq!(seen[1]["added_blobs"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn languages_filter_sends_only_matching_blobs() {
+    use std::sync::{Arc, Mutex};
+    let seen: Arc<Mutex<Vec<serde_json::Value>>> = Arc::new(Mutex::new(vec![]));
+    let seen_bg = seen.clone();
+    let app = Router::new()
+        .route(
+            "/batch-upload",
+            post(|Json(p): Json<UploadPayload>| async move {
+                let names = p
+                    .blobs
+                    .into_iter()
+                    .map(|b| format!("n:{}", b.path))
+                    .collect();
+                Json(UploadResp { blob_names: names })
+            }),
+        )
+        .route(
+            "/agents/codebase-retrieval",
+            post(move |Json(p): Json<serde_json::Value>| {
+                let seen = seen_bg.clone();
+                async move {
+                    seen.lock().unwrap().push(p["blobs"].clone());
+                    Json(serde_json::json!({ "formatted_retrieval": "OK" }))
+                }
+            }),
+        );
+    let addr = common::serve(app).await;
+    let td = tempfile::tempdir().unwrap();
+    let mut cfg = cfg_with_base(format!("http://{}", addr), td.path());
+    cfg.settings.text_extensions = [".rs", ".py"].iter().map(|s| s.to_string()).collect();
+    let proj = td.path().join("proj");
+    fs::create_dir_all(&proj).unwrap();
+    fs::write(proj.join("lib.rs"), "fn a() {}\n").unwrap();
+    fs::write(proj.join("main.rs"), "fn main() {}\n").unwrap();
+    fs::write(proj.join("tool.py"), "print(1)\n").unwrap();
+    let (key, path) = cfg.resolve_project(&proj.to_string_lossy()).unwrap();
+    service::ensure_indexed(&cfg, &key, &path, true)
+        .await
+        .unwrap();
+    // Incremental re-index keeps languages of unchanged files
+    fs::write(proj.join("tool.py"), "print(2)\n").unwrap();
+    let names = service::ensure_indexed(&cfg, &key, &path, false)
+        .await
+        .unwrap();
+    assert_eq!(names.len(), 3);
+
+    let langs = vec!["Rust".to_string()];
+    let rust = service::filter_blobs_by_languages(&cfg, &key, &names, &langs).unwrap();
+    service::retrieve(&cfg, &key, &rust, "q").await.unwrap();
+    let seen = seen.lock().unwrap();
+    let mut added: Vec<&str> = seen[0]["added_blobs"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v.as_str().unwrap())
+        .collect();
+    added.sort();
+    let mut expected: Vec<&str> = rust.iter().map(String::as_str).collect();
+    expected.sort();
+    assert_eq!(added, expected);
+    // lib.rs and main.rs; tool.py is left out
+    assert_eq!(rust.len(), 2);
+    let py = service::filter_blobs_by_languages(&cfg, &key, &names, &["python".into()]).unwrap();
+    assert_eq!(py.len(), 1);
+    assert!(!rust.contains(&py[0]));
+
+    assert_eq!(
+        service::filter_blobs_by_languages(&cfg, &key, &names, &[]).unwrap(),
+        names
+    );
+    let err = service::filter_blobs_by_languages(&cfg, &key, &names, &["go".into()]).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("no indexed files match languages [go]"),
+        "{err}"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn search_index_policy_limits_indexing_on_search() {
+    use augmcp::config::SearchIndexPolicy;
+    let addr = start_stub_server().await;
+    let td = tempfile::tempdir().unwrap();
+    let mut cfg = cfg_with_base(format!("http://{}", addr), td.path());
+    let proj = td.path().join("proj");
+    fs::create_dir_all(&proj).unwrap();
+    fs::write(proj.join("a.txt"), "A\n").unwrap();
+    let (key, path) = cfg.resolve_project(&proj.to_string_lossy()).unwrap();
+
+    // never: an unindexed project is an error, even when asking to reindex
+    cfg.settings.search_index_policy = SearchIndexPolicy::Never;
+    for skip in [true, false] {
+        let err = service::ensure_indexed(&cfg, &key, &path, skip)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("not indexed; call index first"));
+    }
+    assert!(service::quick_index(&cfg, &key, &path).await.is_err());
+    service::index_and_persist(&cfg, &key, &path, false)
+        .await
+        .unwrap();
+    fs::write(proj.join("b.txt"), "B\n").unwrap();
+    let names = service::ensure_indexed(&cfg, &key, &path, false)
+        .await
+        .unwrap();
+    assert_eq!(names.len(), 1, "never re-indexes an indexed project");
+
+    // if_missing: skip_index_if_indexed=false cannot widen it to a re-index
+    cfg.settings.search_index_policy = SearchIndexPolicy::IfMissing;
+    let names = service::ensure_indexed(&cfg, &key, &path, false)
+        .await
+        .unwrap();
+    assert_eq!(names.len(), 1);
+    assert_eq!(
+        service::quick_index(&cfg, &key, &path).await.unwrap().len(),
+        1
+    );
+
+    // always: re-indexes, unless the call narrows it with skip_index_if_indexed
+    cfg.settings.search_index_policy = SearchIndexPolicy::Always;
+    let names = service::ensure_indexed(&cfg, &key, &path, false)
+        .await
+        .unwrap();
+    assert_eq!(names.len(), 2);
+    fs::write(proj.join("c.txt"), "C\n").unwrap();
+    let names = service::ensure_indexed(&cfg, &key, &path, true)
+        .await
+        .unwrap();
+    assert_eq!(names.len(), 2);
+}
+
+#[test]
+fn compact_projects_drops_blobs_no_longer_on_disk() {
+    let td = tempfile::tempdir().unwrap();
+    let cfg = cfg_with_base("http://127.0.0.1:9".into(), td.path());
+    let proj = td.path().join("proj");
+    fs::create_dir_all(&proj).unwrap();
+    fs::write(proj.join("a.txt"), "a\n").unwrap();
+    fs::write(proj.join("b.txt"), "b\n").unwrap();
+    let (key, _path) = cfg.resolve_project(&proj.to_string_lossy()).unwrap();
+    let name = |p: &str, c: &str| augmcp::indexer::hash_blob_name(p, c);
+    let live = vec![name("a.txt", "a\n"), name("b.txt", "b\n")];
+    // Stale hashes (deleted file, old content) plus a duplicate
+    let stored = vec![
+        live[0].clone(),
+        name("gone.txt", "g\n"),
+        live[1].clone(),
+        name("a.txt", "old\n"),
+        live[0].clone(),
+    ];
+    let mut projects = augmcp::indexer::ProjectsIndex::default();
+    projects.0.insert(key.clone(), stored);
+    projects.save(&cfg.projects_file()).unwrap();
+
+    let report = service::compact_projects(&cfg).unwrap();
+    assert_eq!(report.entries_removed, 3);
+    assert_eq!(report.compacted.len(), 1);
+    assert_eq!(report.compacted[0].entries_before, 5);
+    assert_eq!(report.compacted[0].entries_after, 2);
+    assert!(report.bytes_reclaimed > 0);
+    assert_eq!(service::load_projects(&cfg).0[&key], live);
+
+    // Already minimal: nothing to do; a missing directory is skipped, not emptied
+    assert_eq!(service::compact_projects(&cfg).unwrap().entries_removed, 0);
+    fs::remove_dir_all(&proj).unwrap();
+    let report = service::compact_projects(&cfg).unwrap();
+    assert_eq!(report.skipped, vec![key.clone()]);
+    assert_eq!(service::load_projects(&cfg).0[&key], live);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn index_ttl_secs_reindexes_stale_index_despite_skip() {
+    let app = Router::new()
+        .route(
+            "/batch-upload",
+            post(|Json(p): Json<UploadPayload>| async move {
+                let names = p
+                    .blobs
+                    .iter()
+                    .map(|b| augmcp::indexer::hash_blob_name(&b.path, &b.content))
+                    .collect();
+                Json(UploadResp { blob_names: names })
+            }),
+        )
+        .route(
+            "/agents/codebase-retrieval",
+            post(|Json(_p): Json<RetrievalPayload>| async move {
+                Json(RetrievalResp {
+                    formatted_retrieval: "OK".to_string(),
+                })
+            }),
+        );
+    let addr = common::serve(app).await;
+    let td = tempfile::tempdir().unwrap();
+    let mut cfg = cfg_with_base(format!("http://{}", addr), td.path());
+    let proj = td.path().join("proj");
+    fs::create_dir_all(&proj).unwrap();
+    fs::write(proj.join("a.txt"), "a\n").unwrap();
+    let (key, path) = cfg.resolve_project(&proj.to_string_lossy()).unwrap();
+    let search = |cfg: Config| {
+        let (key, path) = (key.clone(), path.clone());
+        async move {
+            service::ensure_index_then_retrieve(
+                &cfg,
+                &RetrievalLimiter::default(),
+                &key,
+                &path,
+                "q",
+                true,
+            )
+            .await
+            .unwrap()
+        }
+    };
+    let stored = |cfg: &Config| service::load_projects(cfg).0[&key].len();
+
+    search(cfg.clone()).await;
+    assert_eq!(stored(&cfg), 1);
+    fs::write(proj.join("b.txt"), "b\n").unwrap();
+
+    // Without a TTL the cached index is reused as is
+    search(cfg.clone()).await;
+    assert_eq!(stored(&cfg), 1);
+    // Within the TTL as well
+    cfg.settings.index_ttl_secs = 60;
+    search(cfg.clone()).await;
+    assert_eq!(stored(&cfg), 1);
+
+    // Once the last index is older than the TTL, the skip path re-indexes
+    cfg.settings.index_ttl_secs = 1;
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    search(cfg.clone()).await;
+    assert_eq!(stored(&cfg), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reindex_scheduler_picks_up_changes_and_prunes_missing_projects() {
+    let app = Router::new().route(
+        "/batch-upload",
+        post(|Json(p): Json<UploadPayload>| async move {
+            let names = p
+                .blobs
+                .iter()
+                .map(|b| augmcp::indexer::hash_blob_name(&b.path, &b.content))
+                .collect();
+            Json(UploadResp { blob_names: names })
+        }),
+    );
+    let addr = common::serve(app).await;
+    let td = tempfile::tempdir().unwrap();
+    let mut cfg = cfg_with_base(format!("http://{}", addr), td.path());
+    cfg.settings.reindex_interval_secs = 1;
+    cfg.settings.reindex_prune_missing = true;
+    let live = td.path().join("live");
+    let gone = td.path().join("gone");
+    for (dir, file) in [(&live, "a.txt"), (&gone, "g.txt")] {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join(file), "x\n").unwrap();
+    }
+    let (live_key, live_path) = cfg.resolve_project(&live.to_string_lossy()).unwrap();
+    let (gone_key, gone_path) = cfg.resolve_project(&gone.to_string_lossy()).unwrap();
+    service::index_and_persist(&cfg, &live_key, &live_path, false)
+        .await
+        .unwrap();
+    service::index_and_persist(&cfg, &gone_key, &gone_path, false)
+        .await
+        .unwrap();
+
+    fs::write(live.join("b.txt"), "y\n").unwrap();
+    fs::remove_dir_all(&gone).unwrap();
+    let tasks = augmcp::tasks::TaskManager::with_limit(1);
+    let scheduler = service::spawn_reindex_scheduler(cfg.clone(), tasks).unwrap();
+    let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(10);
+    loop {
+        let projects = service::load_projects(&cfg);
+        if projects.0.get(&live_key).is_some_and(|b| b.len() == 2)
+            && !projects.0.contains_key(&gone_key)
+        {
+            break;
+        }
+        assert!(
+            tokio::time::Instant::now() < deadline,
+            "project was not re-indexed: {:?}",
+            projects.0
+        );
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    scheduler.abort();
+
+    cfg.settings.reindex_interval_secs = 0;
+    assert!(service::spawn_reindex_scheduler(cfg, augmcp::tasks::TaskManager::new()).is_none());
 }
@@ -0,0 +1,74 @@
+use augmcp::tasks::TaskManager;
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+#[tokio::test]
+async fn debounce_coalesces_bursts() {
+    let tasks = TaskManager::new();
+    let runs = Arc::new(AtomicUsize::new(0));
+    for _ in 0..3 {
+        let r = runs.clone();
+        tasks.debounce("proj", Duration::from_millis(100), move || {
+            r.fetch_add(1, Ordering::SeqCst);
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(tasks.has_pending_timer("proj"));
+    assert_eq!(runs.load(Ordering::SeqCst), 0);
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    assert_eq!(runs.load(Ordering::SeqCst), 1);
+    assert!(!tasks.has_pending_timer("proj"));
+}
+
+#[test]
+fn begin_allows_restart_after_finish() {
+    let tasks = TaskManager::new();
+    assert!(tasks.begin("proj"));
+    assert!(!tasks.begin("proj"));
+    tasks.finish("proj");
+    assert!(tasks.begin("proj"));
+}
+
+#[tokio::test]
+async fn index_limit_queues_second_task() {
+    let tasks = TaskManager::with_limit(1);
+    assert!(tasks.begin("a"));
+    assert!(tasks.begin("b"));
+    let permit_a = tasks.acquire_index_permit("a").await;
+    assert!(permit_a.is_some());
+
+    let tasks_bg = tasks.clone();
+    let waiter = tokio::spawn(async move {
+        let _p = tasks_bg.acquire_index_permit("b").await;
+        tasks_bg.set_phase("b", "collecting");
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(tasks.get("b").unwrap().phase, "queued");
+
+    drop(permit_a);
+    waiter.await.unwrap();
+    assert_eq!(tasks.get("b").unwrap().phase, "collecting");
+}
+
+#[tokio::test]
+async fn abort_all_stops_tasks_and_timers() {
+    let tm = TaskManager::new();
+    for key in ["a", "b"] {
+        assert!(tm.begin(key));
+        tm.set_handle(
+            key,
+            tokio::spawn(tokio::time::sleep(Duration::from_secs(30))),
+        );
+    }
+    tm.debounce("c", Duration::from_secs(30), || {});
+
+    assert_eq!(tm.abort_all(), 2);
+    assert!(!tm.is_running("a") && !tm.is_running("b"));
+    assert!(!tm.has_pending_timer("c"));
+    assert_eq!(tm.get("a").unwrap().phase, "aborted");
+}